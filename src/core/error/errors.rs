@@ -59,6 +59,8 @@ pub enum SystemErrorType {
     NoRootCell,
     InvalidOperation,
     NotFound,
+    StorageError,
+    UnsupportedProofType,
 }
 
 impl fmt::Display for SystemErrorType {
@@ -79,6 +81,8 @@ impl fmt::Display for SystemErrorType {
             Self::NoRootCell => write!(f, "No root cell"),
             Self::InvalidOperation => write!(f, "Invalid operation"),
             Self::NotFound => write!(f, "Not found"),
+            Self::StorageError => write!(f, "Storage error"),
+            Self::UnsupportedProofType => write!(f, "Unsupported proof type"),
         }
     }
 }
@@ -120,23 +120,21 @@ impl ChannelContract {
 
     #[wasm_bindgen]
     pub fn update_balance(&mut self, amount: ChannelBalance) -> Result<(), JsValue> {
-        let new_balance = self
-            .balance
-            .checked_add(amount)
-            .ok_or_else(|| JsValue::from_str("Balance overflow"))?;
-        self.balance = new_balance;
-        Ok(())
+        self.try_update_balance(amount)
+            .map_err(|e| JsValue::from_str(&e.to_string()))
     }
 
     #[wasm_bindgen]
     pub fn create_state_boc(&self) -> Result<Box<[u8]>, JsValue> {
-        let boc = self
-            .create_state_boc_internal()
-            .map_err(|e| JsValue::from_str(&e.to_string()))?;
-        let serialized_boc = boc
-            .serialize()
-            .map_err(|e| JsValue::from_str(&e.to_string()))?;
-        Ok(serialized_boc.into_boxed_slice())
+        self.serialize_state_boc()
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    fn serialize_state_boc(&self) -> Result<Box<[u8]>, SystemError> {
+        Ok(self
+            .create_state_boc_internal()?
+            .serialize()?
+            .into_boxed_slice())
     }
 
     fn create_state_boc_internal(&self) -> Result<BOC, SystemError> {
@@ -179,11 +177,8 @@ impl ChannelContract {
 
     #[wasm_bindgen]
     pub fn process_transaction(&mut self, tx: &Transaction) -> Result<Box<[u8]>, JsValue> {
-        self.validate_transaction(tx)
-            .map_err(|e| JsValue::from_str(&e.to_string()))?;
-        self.apply_transaction(tx)
-            .map_err(|e| JsValue::from_str(&e.to_string()))?;
-        self.create_state_boc()
+        self.try_process_transaction(tx)
+            .map_err(|e| JsValue::from_str(&e.to_string()))
     }
 
     fn validate_transaction(&self, tx: &Transaction) -> Result<(), SystemError> {
@@ -348,6 +343,29 @@ impl ChannelContract {
     }
 }
 
+// Native entry points. The `#[wasm_bindgen]` methods above wrap these and convert errors to
+// `JsValue` only at the JavaScript boundary, since `JsValue` cannot be built on native targets.
+impl ChannelContract {
+    /// Adds `amount` to the channel balance.
+    pub fn try_update_balance(&mut self, amount: ChannelBalance) -> Result<(), SystemError> {
+        self.balance = self.balance.checked_add(amount).ok_or_else(|| {
+            SystemError::new(
+                SystemErrorType::InvalidAmount,
+                "Balance overflow".to_string(),
+            )
+        })?;
+        Ok(())
+    }
+
+    /// Validates and applies `tx` and returns the new state BOC. A rejected transaction
+    /// leaves the channel unchanged.
+    pub fn try_process_transaction(&mut self, tx: &Transaction) -> Result<Box<[u8]>, SystemError> {
+        self.validate_transaction(tx)?;
+        self.apply_transaction(tx)?;
+        self.serialize_state_boc()
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SystemErrorType {
     InvalidTransaction,
@@ -473,11 +491,11 @@ mod tests {
     #[test]
     fn test_process_valid_transaction() {
         let mut contract = ChannelContract::new("test_channel");
-        contract.update_balance(1000).unwrap();
+        contract.try_update_balance(1000).unwrap();
 
         let tx = create_test_transaction("test_channel", 1, 1, 400);
 
-        let result = contract.process_transaction(&tx);
+        let result = contract.try_process_transaction(&tx);
         assert!(result.is_ok());
         assert_eq!(contract.balance(), 600);
         assert_eq!(contract.nonce(), 1);
@@ -498,10 +516,16 @@ mod tests {
     #[test]
     fn test_spending_limit() {
         let mut contract = ChannelContract::new("test_channel");
-        contract.update_balance(1000).unwrap();
+        contract.try_update_balance(1000).unwrap();
 
         let tx = create_test_transaction("test_channel", 1, 1, 501);
 
-        assert!(contract.process_transaction(&tx).is_err());
+        let result = contract.try_process_transaction(&tx);
+        assert_eq!(
+            result.unwrap_err().error_type,
+            SystemErrorType::SpendingLimitExceeded
+        );
+        assert_eq!(contract.balance(), 1000);
+        assert!(contract.try_update_balance(u64::MAX).is_err());
     }
 }
@@ -1,11 +1,43 @@
+// ./src/core/hierarchy/client/wallet_extension/channel_manager.rs
+
+// Channel Manager
+// Holds a wallet's channels as the `CommittedChannelState`s their proofs commit to, so a
+// channel's state hash always covers its real id, lock root and authorization key. Channel
+// and wallet ids are raw 32-byte values; anything else is rejected rather than padded.
+//
+// Each channel is authorized by its own `StateKeyChain`, derived from the wallet seed: the
+// state with nonce `n` commits to key `n` of the channel's chain. Updates are transfers that
+// are signed, proven and verified before the new state replaces the old one. Proving runs on
+// a `ProverQueue`, by default the process-wide one, and no channel lock is held while it
+// does: `transfer` returns a `PendingTransfer` that applies the new state when it resolves,
+// and the channel takes no other transfer until then.
+//
+// Channel layout returned by `GetChannel`: `CommittedChannelState::to_bytes`.
+// `Transfer` returns the bincode-encoded `ZkProof` of the update.
+// `VerifyProof` takes a bincode-encoded `ZkProof` of the state transition circuit, whose
+// `merkle_root` is the commitment to the new state.
+
+use crate::core::error::errors::{SystemError, SystemErrorType};
+use crate::core::hierarchy::client::wallet_extension::recovery::RecoveredWallet;
+use crate::core::zkps::plonky2::{check_transfer, Plonky2System};
+use crate::core::zkps::proof::{ProofType, ZkProof};
+use crate::core::zkps::prover_queue::{
+    JobHandle, JobPriority, JobResult, ProverError, ProverQueue,
+};
+use crate::core::zkps::signature::StateKeyChain;
+use crate::core::zkps::state_commitment::CommittedChannelState;
 use sha2::{Digest, Sha256};
-use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, RwLock};
+use std::task::{Context, Poll};
 use wasm_bindgen::prelude::*;
 
 // Type alias for ChannelStore
-type ChannelStore = Arc<RwLock<HashMap<[u8; 32], Arc<RwLock<ChannelContract>>>>>;
+type ChannelStore = Arc<RwLock<HashMap<[u8; 32], Arc<RwLock<CommittedChannelState>>>>>;
+// Channels with a transfer being proven
+type InFlight = Arc<Mutex<HashSet<[u8; 32]>>>;
 
 #[derive(Debug, Clone)]
 pub struct ChannelConfig {
@@ -17,203 +49,451 @@ pub struct ChannelConfig {
 #[wasm_bindgen]
 pub struct ChannelManager {
     channels: ChannelStore,
-    proof_system: Arc<Plonky2SystemHandle>,
+    proof_system: Arc<Plonky2System>,
+    prover: Arc<ProverQueue>,
+    in_flight: InFlight,
+    keys: StateKeyChain,
     wallet_id: [u8; 32],
     spending_limit: u64,
 }
 
 #[wasm_bindgen]
 impl ChannelManager {
+    /// A manager for `wallet_id` whose channel keys derive from the 32-byte `seed`.
     #[wasm_bindgen(constructor)]
-    pub fn new() -> ChannelManager {
-        Self {
-            channels: Arc::new(RwLock::new(HashMap::new())),
-            proof_system: Arc::new(Plonky2SystemHandle::new()),
-            wallet_id: [0; 32],
-            spending_limit: 0,
-        }
+    pub fn new(
+        wallet_id: &[u8],
+        seed: &[u8],
+        spending_limit: u64,
+    ) -> Result<ChannelManager, JsValue> {
+        let wallet_id = parse_id(wallet_id).map_err(to_js_error)?;
+        let seed = parse_id(seed).map_err(to_js_error)?;
+        Self::with_wallet(wallet_id, seed, spending_limit).map_err(to_js_error)
     }
 
     #[wasm_bindgen]
-    pub fn new_with_wallet(wallet_id: &[u8], spending_limit: u64) -> ChannelManager {
-        let mut id = [0u8; 32];
-        id.copy_from_slice(&wallet_id[0..32]);
-        Self {
+    pub async fn dispatch(&self, op_code: u8, params: &[u8]) -> Result<Box<[u8]>, JsValue> {
+        self.dispatch_op(op_code, params)
+            .await
+            .map(Vec::into_boxed_slice)
+            .map_err(to_js_error)
+    }
+}
+
+impl ChannelManager {
+    /// A manager for `wallet_id` whose channel keys derive from `seed`. Fails if the state
+    /// transition circuit cannot be built.
+    pub fn with_wallet(
+        wallet_id: [u8; 32],
+        seed: [u8; 32],
+        spending_limit: u64,
+    ) -> Result<ChannelManager, SystemError> {
+        let proof_system = Plonky2System::shared()
+            .map_err(|e| SystemError::new(SystemErrorType::InvalidProof, e.to_string()))?;
+        Ok(Self {
             channels: Arc::new(RwLock::new(HashMap::new())),
-            proof_system: Arc::new(Plonky2SystemHandle::new()),
-            wallet_id: id,
+            proof_system: Arc::new(proof_system),
+            prover: ProverQueue::shared(),
+            in_flight: Arc::new(Mutex::new(HashSet::new())),
+            keys: StateKeyChain::new(seed),
+            wallet_id,
             spending_limit,
+        })
+    }
+
+    /// Proves transfers on `prover` instead of the process-wide queue.
+    pub fn with_prover(mut self, prover: Arc<ProverQueue>) -> ChannelManager {
+        self.prover = prover;
+        self
+    }
+
+    /// Rebuilds a manager from state recovered off storage-node replicas. Each channel is
+    /// restored with its recorded lock root and the key its nonce derives from `seed`, so
+    /// its state hash matches the one its last proof committed to.
+    pub fn from_recovered(
+        wallet: &RecoveredWallet,
+        seed: [u8; 32],
+        spending_limit: u64,
+    ) -> Result<ChannelManager, SystemError> {
+        let manager = ChannelManager::with_wallet(wallet.wallet_id, seed, spending_limit)?;
+        {
+            let mut channels = manager.channels.write().map_err(|_| lock_error())?;
+            for record in &wallet.channels {
+                if record.wallet_id != wallet.wallet_id {
+                    return Err(SystemError::new(
+                        SystemErrorType::InvalidAddress,
+                        "Recovered channel belongs to another wallet".to_string(),
+                    ));
+                }
+                let channel = CommittedChannelState {
+                    channel_id: record.channel_id,
+                    balance: record.balance,
+                    nonce: record.nonce,
+                    seqno: record.seqno,
+                    lock_root: record.lock_root,
+                    auth_key: manager
+                        .keys
+                        .for_channel(&record.channel_id)
+                        .auth_key(record.nonce),
+                };
+                if channels
+                    .insert(record.channel_id, Arc::new(RwLock::new(channel)))
+                    .is_some()
+                {
+                    return Err(SystemError::new(
+                        SystemErrorType::InvalidOperation,
+                        "Channel recovered twice".to_string(),
+                    ));
+                }
+            }
         }
+        Ok(manager)
     }
 
-    #[wasm_bindgen]
-    pub async fn dispatch(&self, op_code: u8, params: &[u8]) -> Result<Box<[u8]>, JsValue> {
-        let op_code =
-            ChannelOpCode::from_u8(op_code).ok_or_else(|| JsValue::from_str("Invalid op_code"))?;
+    pub fn wallet_id(&self) -> [u8; 32] {
+        self.wallet_id
+    }
+
+    /// The committed state of a channel.
+    pub fn channel_state(
+        &self,
+        channel_id: &[u8; 32],
+    ) -> Result<CommittedChannelState, SystemError> {
+        let channel = self.get_channel(channel_id)?;
+        let state = *channel.read().map_err(|_| lock_error())?;
+        Ok(state)
+    }
+
+    /// Poseidon commitment to a channel's current state, which state transition proofs must
+    /// start from.
+    pub fn state_hash(&self, channel_id: &[u8; 32]) -> Result<[u8; 32], SystemError> {
+        Ok(self.channel_state(channel_id)?.commitment())
+    }
+
+    /// Transfers `amount` out of a channel: the next state is signed with the channel's current
+    /// key and queued for proving. The returned `PendingTransfer` verifies the proof against
+    /// the stored state and applies the new state once it resolves; until then the channel
+    /// stays readable and rejects further transfers.
+    pub fn transfer(
+        &self,
+        channel_id: &[u8; 32],
+        amount: u64,
+        lock_root: [u8; 32],
+    ) -> Result<PendingTransfer, SystemError> {
+        let channel = self.get_channel(channel_id)?;
+        let reservation = Reservation::claim(&self.in_flight, *channel_id)?;
+        let state = *channel.read().map_err(|_| lock_error())?;
+        self.validate_channel(&state)?;
+        // The key signs at most once, so everything the prover would reject is checked first.
+        check_transfer(&state, amount)
+            .map_err(|e| SystemError::new(SystemErrorType::InvalidTransaction, e.to_string()))?;
+
+        // Fails unless the state's key is the one its nonce derives, and checks the balance
+        // before signing.
+        let (next, signature) = self
+            .keys
+            .for_channel(channel_id)
+            .transfer(&state, amount, lock_root)?;
+        let job = self
+            .prover
+            .submit_state_transition(
+                Arc::clone(&self.proof_system),
+                state,
+                next,
+                amount,
+                signature,
+                JobPriority::Normal,
+            )
+            .map_err(prover_error)?;
+        Ok(PendingTransfer {
+            job: Some(job),
+            channel,
+            system: Arc::clone(&self.proof_system),
+            old: state,
+            next,
+            _reservation: reservation,
+        })
+    }
+
+    async fn dispatch_op(&self, op_code: u8, params: &[u8]) -> Result<Vec<u8>, SystemError> {
+        let op_code = ChannelOpCode::from_u8(op_code).ok_or_else(|| {
+            SystemError::new(
+                SystemErrorType::InvalidOperation,
+                "Invalid op_code".to_string(),
+            )
+        })?;
         match op_code {
             ChannelOpCode::GetChannel => {
-                let channel_id = decode_channel_id(params)?;
-                let channel = self.get_channel(&channel_id)?;
-                Ok(serialize_channel(&channel).into_boxed_slice())
+                let channel_id = parse_id(params)?;
+                Ok(self.channel_state(&channel_id)?.to_bytes())
             }
             ChannelOpCode::InitChannel => {
                 let (sender, recipient, initial_balance, config) = decode_create_params(params)?;
                 let channel_id =
                     self.create_channel(sender, recipient, initial_balance, &config)?;
-                Ok(channel_id.to_vec().into_boxed_slice())
+                Ok(channel_id.to_vec())
             }
-            ChannelOpCode::UpdateState => {
-                let (channel_id, new_state) = decode_update_params(params)?;
-                self.update_channel_state(&channel_id, new_state)?;
-                Ok(vec![].into_boxed_slice())
+            ChannelOpCode::Transfer => {
+                let (channel_id, amount, lock_root) = decode_transfer_params(params)?;
+                let proof = self.transfer(&channel_id, amount, lock_root)?.await?;
+                bincode::serialize(&proof)
+                    .map_err(|e| SystemError::new(SystemErrorType::InvalidProof, e.to_string()))
             }
             ChannelOpCode::VerifyProof => {
                 let (channel_id, proof, old_balance, new_balance) = decode_verify_params(params)?;
-                let channel = self.get_channel(&channel_id)?;
-                let channel = channel
-                    .read()
-                    .map_err(|_| JsValue::from_str("InvalidTransaction"))?;
+                let channel = self.channel_state(&channel_id)?;
                 let is_valid = self.verify_proof(&channel, &proof, old_balance, new_balance)?;
-                Ok(vec![is_valid as u8].into_boxed_slice())
+                Ok(vec![is_valid as u8])
             }
         }
     }
 
-    fn get_channel(&self, channel_id: &[u8; 32]) -> Result<Arc<RwLock<ChannelContract>>, JsValue> {
+    fn get_channel(
+        &self,
+        channel_id: &[u8; 32],
+    ) -> Result<Arc<RwLock<CommittedChannelState>>, SystemError> {
         self.channels
             .read()
-            .map_err(|_| JsValue::from_str("InvalidTransaction"))?
+            .map_err(|_| lock_error())?
             .get(channel_id)
             .cloned()
-            .ok_or_else(|| JsValue::from_str("Channel not found"))
+            .ok_or_else(|| {
+                SystemError::new(SystemErrorType::NotFound, "Channel not found".to_string())
+            })
     }
 
-    fn create_channel(
+    /// Opens a channel whose first state is authorized by key 0 of its chain.
+    pub fn create_channel(
         &self,
         sender: [u8; 32],
         recipient: [u8; 32],
         initial_balance: u64,
         _config: &ChannelConfig,
-    ) -> Result<[u8; 32], JsValue> {
+    ) -> Result<[u8; 32], SystemError> {
         let mut hasher = Sha256::new();
         hasher.update(sender);
         hasher.update(recipient);
         hasher.update(initial_balance.to_le_bytes());
-        let hash = hasher.finalize();
-        let mut channel_id = [0u8; 32];
-        channel_id.copy_from_slice(&hash);
-
-        let mut channels = self
-            .channels
-            .write()
-            .map_err(|_| JsValue::from_str("InvalidTransaction"))?;
+        let channel_id: [u8; 32] = hasher.finalize().into();
+
+        let mut channels = self.channels.write().map_err(|_| lock_error())?;
         if channels.contains_key(&channel_id) {
-            return Err(JsValue::from_str("InvalidChannel"));
+            return Err(SystemError::new(
+                SystemErrorType::InvalidOperation,
+                "Channel already exists".to_string(),
+            ));
         }
 
-        let channel = ChannelContract::new(hex::encode(channel_id));
-        let channel = Arc::new(RwLock::new(channel));
+        let channel = CommittedChannelState {
+            channel_id,
+            balance: initial_balance,
+            auth_key: self.keys.for_channel(&channel_id).auth_key(0),
+            ..Default::default()
+        };
+        channels.insert(channel_id, Arc::new(RwLock::new(channel)));
+        Ok(channel_id)
+    }
 
-        {
-            let mut channel_lock = channel
-                .write()
-                .map_err(|_| JsValue::from_str("InvalidTransaction"))?;
-            channel_lock
-                .update_balance(initial_balance)
-                .map_err(|_| JsValue::from_str("InvalidAmount"))?;
+    fn validate_channel(&self, channel: &CommittedChannelState) -> Result<(), SystemError> {
+        if channel.balance > self.spending_limit {
+            return Err(SystemError::new(
+                SystemErrorType::SpendingLimitExceeded,
+                "Spending limit exceeded".to_string(),
+            ));
         }
-
-        channels.insert(channel_id, channel);
-        Ok(channel_id)
+        Ok(())
     }
 
-    fn update_channel_state(
+    /// Verifies a state transition proof out of the channel's committed state, which must be
+    /// within the spending limit.
+    fn verify_proof(
         &self,
-        channel_id: &[u8; 32],
-        new_state: Vec<u8>,
-    ) -> Result<(), JsValue> {
-        let channel = self.get_channel(channel_id)?;
-        let mut channel = channel
-            .write()
-            .map_err(|_| JsValue::from_str("InvalidTransaction"))?;
+        channel: &CommittedChannelState,
+        proof: &ZkProof,
+        old_balance: u64,
+        new_balance: u64,
+    ) -> Result<bool, SystemError> {
+        self.validate_channel(channel)?;
+        Ok(verify_transition(
+            &self.proof_system,
+            channel,
+            proof,
+            old_balance,
+            new_balance,
+        ))
+    }
+}
 
-        if !self.validate_channel(&channel)? {
-            return Err(JsValue::from_str("InvalidChannel"));
-        }
+/// A transfer being proven. Resolves, by `wait` or as a future, to the transfer's proof once
+/// the new state has replaced the one it was signed from. Dropping it abandons the transfer.
+pub struct PendingTransfer {
+    job: Option<JobHandle>,
+    channel: Arc<RwLock<CommittedChannelState>>,
+    system: Arc<Plonky2System>,
+    old: CommittedChannelState,
+    next: CommittedChannelState,
+    _reservation: Reservation,
+}
 
-        if new_state.len() < 8 {
-            return Err(JsValue::from_str("InvalidTransaction"));
+impl PendingTransfer {
+    /// Blocks until the proof is ready, then applies the new state.
+    pub fn wait(mut self) -> Result<ZkProof, SystemError> {
+        let outcome = self
+            .job
+            .take()
+            .map_or(Err(ProverError::Cancelled), JobHandle::wait);
+        self.finish(outcome)
+    }
+
+    fn finish(&self, outcome: JobResult) -> Result<ZkProof, SystemError> {
+        let proof = outcome.map_err(prover_error)?;
+        let proof = self
+            .system
+            .proof_from_bytes(&proof)
+            .map_err(|e| SystemError::new(SystemErrorType::InvalidProof, e.to_string()))?;
+        let proof = ZkProof::from_plonky2(
+            ProofType::StateTransition,
+            self.system.circuit_digest(),
+            &proof,
+            self.next.commitment().to_vec(),
+            current_timestamp(),
+        );
+        if !verify_transition(
+            &self.system,
+            &self.old,
+            &proof,
+            self.old.balance,
+            self.next.balance,
+        ) {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidProof,
+                "Transfer proof does not verify against the channel state".to_string(),
+            ));
         }
 
-        let mut balance_bytes = [0u8; 8];
-        balance_bytes.copy_from_slice(&new_state[0..8]);
-        let balance = u64::from_le_bytes(balance_bytes);
+        let mut state = self.channel.write().map_err(|_| lock_error())?;
+        if *state != self.old {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidOperation,
+                "Channel state changed while the transfer was being proven".to_string(),
+            ));
+        }
+        *state = self.next;
+        Ok(proof)
+    }
+}
 
-        channel
-            .update_balance(balance)
-            .map_err(|_| JsValue::from_str("InvalidAmount"))?;
+impl Future for PendingTransfer {
+    type Output = Result<ZkProof, SystemError>;
 
-        Ok(())
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let Some(job) = self.job.as_mut() else {
+            return Poll::Ready(Err(prover_error(ProverError::Cancelled)));
+        };
+        match Pin::new(job).poll(cx) {
+            Poll::Ready(outcome) => Poll::Ready(self.finish(outcome)),
+            Poll::Pending => Poll::Pending,
+        }
     }
+}
 
-    fn validate_channel(&self, channel: &ChannelContract) -> Result<bool, JsValue> {
-        if channel.balance() > self.spending_limit {
-            return Err(JsValue::from_str("Spending limit exceeded"));
+impl Drop for PendingTransfer {
+    /// Cancels the proving job if the transfer is abandoned before it resolves.
+    fn drop(&mut self) {
+        if let Some(job) = &self.job {
+            job.cancel();
         }
-        Ok(true)
     }
+}
 
-    fn verify_proof(
-        &self,
-        channel: &ChannelContract,
-        _proof: &ZkProof,
-        old_balance: u64,
-        new_balance: u64,
-    ) -> Result<bool, JsValue> {
-        self.validate_channel(channel)?;
+/// Marks a channel as having a transfer in flight until dropped.
+struct Reservation {
+    in_flight: InFlight,
+    channel_id: [u8; 32],
+}
 
-        let mut proof_data = Vec::new();
-        proof_data.extend_from_slice(&old_balance.to_le_bytes());
-        proof_data.extend_from_slice(&channel.nonce().to_le_bytes());
-        proof_data.extend_from_slice(&new_balance.to_le_bytes());
-        proof_data.extend_from_slice(&(channel.nonce() + 1).to_le_bytes());
-        proof_data.extend_from_slice(&new_balance.saturating_sub(old_balance).to_le_bytes());
+impl Reservation {
+    fn claim(in_flight: &InFlight, channel_id: [u8; 32]) -> Result<Self, SystemError> {
+        if !in_flight
+            .lock()
+            .map_err(|_| lock_error())?
+            .insert(channel_id)
+        {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidOperation,
+                "Channel already has a transfer in flight".to_string(),
+            ));
+        }
+        Ok(Self {
+            in_flight: Arc::clone(in_flight),
+            channel_id,
+        })
+    }
+}
 
-        self.proof_system
-            .verify_proof_js(&proof_data)
-            .map_err(|_| JsValue::from_str("InvalidProof"))
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if let Ok(mut in_flight) = self.in_flight.lock() {
+            in_flight.remove(&self.channel_id);
+        }
     }
 }
 
-fn decode_channel_id(params: &[u8]) -> Result<[u8; 32], JsValue> {
-    if params.len() != 32 {
-        return Err(JsValue::from_str("InvalidTransaction"));
+/// Verifies a state transition proof out of `channel`. Proofs of any other type or circuit,
+/// or whose root is not the proven new state, are invalid.
+fn verify_transition(
+    system: &Plonky2System,
+    channel: &CommittedChannelState,
+    proof: &ZkProof,
+    old_balance: u64,
+    new_balance: u64,
+) -> bool {
+    if proof.proof_type != ProofType::StateTransition
+        || proof.circuit_digest != system.circuit_digest()
+    {
+        return false;
     }
-    let mut channel_id = [0u8; 32];
-    channel_id.copy_from_slice(params);
-    Ok(channel_id)
+    let Some(amount) = old_balance.checked_sub(new_balance) else {
+        return false;
+    };
+    let new_commitment = system.verify_from_commitment(
+        &proof.proof_data,
+        &channel.commitment(),
+        [
+            old_balance,
+            channel.nonce,
+            new_balance,
+            channel.nonce + 1,
+            amount,
+        ],
+    );
+    matches!(new_commitment, Ok(root) if proof.merkle_root == root)
+}
+
+/// A 32-byte wallet or channel id.
+fn parse_id(bytes: &[u8]) -> Result<[u8; 32], SystemError> {
+    bytes.try_into().map_err(|_| {
+        SystemError::new(
+            SystemErrorType::InvalidAddress,
+            format!("Ids must be 32 bytes, got {}", bytes.len()),
+        )
+    })
 }
 
+/// `sender (32) | recipient (32) | initial_balance (8) | timeout (8)`
 fn decode_create_params(
     params: &[u8],
-) -> Result<([u8; 32], [u8; 32], u64, ChannelConfig), JsValue> {
-    if params.len() < 80 {
-        return Err(JsValue::from_str("InvalidTransaction"));
+) -> Result<([u8; 32], [u8; 32], u64, ChannelConfig), SystemError> {
+    if params.len() != 80 {
+        return Err(invalid_params(
+            "Channel creation parameters must be 80 bytes",
+        ));
     }
 
-    let mut sender = [0u8; 32];
-    sender.copy_from_slice(&params[0..32]);
-
-    let mut recipient = [0u8; 32];
-    recipient.copy_from_slice(&params[32..64]);
-
-    let mut balance_bytes = [0u8; 8];
-    balance_bytes.copy_from_slice(&params[64..72]);
-    let initial_balance = u64::from_le_bytes(balance_bytes);
-
-    let mut timeout_bytes = [0u8; 8];
-    timeout_bytes.copy_from_slice(&params[72..80]);
-    let timeout = u64::from_le_bytes(timeout_bytes);
+    let sender = parse_id(&params[0..32])?;
+    let recipient = parse_id(&params[32..64])?;
+    let initial_balance = read_u64(params, 64);
+    let timeout = read_u64(params, 72);
 
     let config = ChannelConfig {
         timeout,
@@ -224,103 +504,80 @@ fn decode_create_params(
     Ok((sender, recipient, initial_balance, config))
 }
 
-fn decode_update_params(params: &[u8]) -> Result<([u8; 32], Vec<u8>), JsValue> {
-    if params.len() <= 32 {
-        return Err(JsValue::from_str("InvalidTransaction"));
+/// `channel_id (32) | amount (8) | lock_root (32)`
+fn decode_transfer_params(params: &[u8]) -> Result<([u8; 32], u64, [u8; 32]), SystemError> {
+    if params.len() != 72 {
+        return Err(invalid_params("Transfer parameters must be 72 bytes"));
     }
 
-    let mut channel_id = [0u8; 32];
-    channel_id.copy_from_slice(&params[0..32]);
-    let new_state = params[32..].to_vec();
+    let channel_id = parse_id(&params[0..32])?;
+    let amount = read_u64(params, 32);
+    let lock_root = parse_id(&params[40..72])?;
 
-    Ok((channel_id, new_state))
+    Ok((channel_id, amount, lock_root))
 }
 
-fn decode_verify_params(params: &[u8]) -> Result<([u8; 32], ZkProof, u64, u64), JsValue> {
-    if params.len() < 32 + 64 + 16 {
-        return Err(JsValue::from_str("InvalidProofDataLength"));
+/// `channel_id (32) | old_balance (8) | new_balance (8) | proof (bincode ZkProof)`
+fn decode_verify_params(params: &[u8]) -> Result<([u8; 32], ZkProof, u64, u64), SystemError> {
+    if params.len() <= 48 {
+        return Err(invalid_params("Proof verification parameters too short"));
     }
 
-    let mut channel_id = [0u8; 32];
-    channel_id.copy_from_slice(&params[0..32]);
-
-    let timestamp = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .map_err(|_| JsValue::from_str("InvalidTimestamp"))?
-        .as_secs();
-
-    let public_inputs = Vec::new();
-    let merkle_root = vec![0u8; 32];
-
-    let proof = ZkProof::new(
-        params[32..96].to_vec(),
-        public_inputs,
-        merkle_root,
-        timestamp,
-    );
-
-    let mut old_balance_bytes = [0u8; 8];
-    old_balance_bytes.copy_from_slice(&params[96..104]);
-    let old_balance = u64::from_le_bytes(old_balance_bytes);
-
-    let mut new_balance_bytes = [0u8; 8];
-    new_balance_bytes.copy_from_slice(&params[104..112]);
-    let new_balance = u64::from_le_bytes(new_balance_bytes);
+    let channel_id = parse_id(&params[0..32])?;
+    let old_balance = read_u64(params, 32);
+    let new_balance = read_u64(params, 40);
+    let proof: ZkProof = bincode::deserialize(&params[48..]).map_err(|e| {
+        SystemError::new(
+            SystemErrorType::InvalidProof,
+            format!("Invalid proof encoding: {}", e),
+        )
+    })?;
 
     Ok((channel_id, proof, old_balance, new_balance))
 }
 
-fn serialize_channel(channel: &Arc<RwLock<ChannelContract>>) -> Vec<u8> {
-    let channel = channel.read().unwrap();
-    let mut serialized = Vec::with_capacity(32 + 24);
-
-    if let Ok(id_bytes) = hex::decode(&channel.id()) {
-        serialized.extend_from_slice(&id_bytes);
-    }
-
-    serialized.extend_from_slice(&channel.balance().to_le_bytes());
-    serialized.extend_from_slice(&channel.nonce().to_le_bytes());
-    serialized.extend_from_slice(&channel.seqno().to_le_bytes());
-
-    serialized
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&data[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
 }
 
-#[wasm_bindgen]
-impl ChannelContract {
-    #[wasm_bindgen(constructor)]
-    pub fn new(id: String) -> ChannelContract {
-        ChannelContract {
-            id,
-            balance: 0,
-            nonce: 0,
-            seqno: 0,
-        }
-    }
+fn invalid_params(message: &str) -> SystemError {
+    SystemError::new(SystemErrorType::InvalidTransaction, message.to_string())
+}
 
-    #[wasm_bindgen(getter)]
-    pub fn id(&self) -> String {
-        self.id.clone()
-    }
+fn lock_error() -> SystemError {
+    SystemError::new(
+        SystemErrorType::InvalidOperation,
+        "Channel lock poisoned".to_string(),
+    )
+}
 
-    #[wasm_bindgen(getter)]
-    pub fn balance(&self) -> u64 {
-        self.balance
+fn prover_error(error: ProverError) -> SystemError {
+    match error {
+        ProverError::Failed(error) => error,
+        error => SystemError::new(SystemErrorType::InvalidProof, error.to_string()),
     }
+}
 
-    #[wasm_bindgen(getter)]
-    pub fn nonce(&self) -> u64 {
-        self.nonce
-    }
+fn to_js_error(error: SystemError) -> JsValue {
+    JsValue::from_str(&error.to_string())
+}
 
-    #[wasm_bindgen(getter)]
-    pub fn seqno(&self) -> u64 {
-        self.seqno
-    }
+/// Seconds since the Unix epoch.
+#[cfg(not(target_arch = "wasm32"))]
+fn current_timestamp() -> u64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
 
-    pub fn update_balance(&mut self, amount: u64) -> Result<(), JsValue> {
-        self.balance = amount;
-        Ok(())
-    }
+/// Seconds since the Unix epoch. `SystemTime::now` panics on wasm.
+#[cfg(target_arch = "wasm32")]
+fn current_timestamp() -> u64 {
+    (js_sys::Date::now() / 1000.0) as u64
 }
 
 #[wasm_bindgen]
@@ -328,7 +585,7 @@ impl ChannelContract {
 pub enum ChannelOpCode {
     GetChannel = 0,
     InitChannel = 1,
-    UpdateState = 2,
+    Transfer = 2,
     VerifyProof = 3,
 }
 
@@ -337,46 +594,253 @@ impl ChannelOpCode {
         match value {
             0 => Some(ChannelOpCode::GetChannel),
             1 => Some(ChannelOpCode::InitChannel),
-            2 => Some(ChannelOpCode::UpdateState),
+            2 => Some(ChannelOpCode::Transfer),
             3 => Some(ChannelOpCode::VerifyProof),
             _ => None,
         }
     }
 }
 
-#[derive(Debug)]
-pub struct Plonky2SystemHandle;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::zkps::prover_queue::ProverQueueConfig;
+    use futures::executor::block_on;
 
-impl Plonky2SystemHandle {
-    pub fn new() -> Self {
-        Plonky2SystemHandle
+    const SEED: [u8; 32] = [3u8; 32];
+
+    fn manager() -> ChannelManager {
+        ChannelManager::with_wallet([7u8; 32], SEED, 10_000).unwrap()
     }
 
-    pub fn verify_proof_js(&self, _proof_data: &[u8]) -> Result<bool, JsValue> {
-        Ok(true)
+    fn dispatch(
+        manager: &ChannelManager,
+        op_code: u8,
+        params: &[u8],
+    ) -> Result<Vec<u8>, SystemError> {
+        block_on(manager.dispatch_op(op_code, params))
     }
-}
 
-#[derive(Debug)]
-pub struct ZkProof {
-    proof: Vec<u8>,
-    public_inputs: Vec<u8>,
-    merkle_root: Vec<u8>,
-    timestamp: u64,
-}
+    fn create_params(balance: u64) -> Vec<u8> {
+        let mut params = Vec::new();
+        params.extend_from_slice(&[1u8; 32]);
+        params.extend_from_slice(&[2u8; 32]);
+        params.extend_from_slice(&balance.to_le_bytes());
+        params.extend_from_slice(&60u64.to_le_bytes());
+        params
+    }
 
-impl ZkProof {
-    pub fn new(
-        proof: Vec<u8>,
-        public_inputs: Vec<u8>,
-        merkle_root: Vec<u8>,
-        timestamp: u64,
-    ) -> Self {
-        ZkProof {
-            proof,
-            public_inputs,
-            merkle_root,
-            timestamp,
+    fn open_channel(manager: &ChannelManager, balance: u64) -> [u8; 32] {
+        dispatch(&manager, 1, &create_params(balance))
+            .unwrap()
+            .try_into()
+            .unwrap()
+    }
+
+    fn transfer_params(channel_id: &[u8; 32], amount: u64, lock_root: [u8; 32]) -> Vec<u8> {
+        let mut params = channel_id.to_vec();
+        params.extend_from_slice(&amount.to_le_bytes());
+        params.extend_from_slice(&lock_root);
+        params
+    }
+
+    fn verify_params(
+        channel_id: &[u8; 32],
+        old_balance: u64,
+        new_balance: u64,
+        proof: &ZkProof,
+    ) -> Vec<u8> {
+        let mut params = channel_id.to_vec();
+        params.extend_from_slice(&old_balance.to_le_bytes());
+        params.extend_from_slice(&new_balance.to_le_bytes());
+        params.extend_from_slice(&bincode::serialize(proof).unwrap());
+        params
+    }
+
+    #[test]
+    fn test_channels_commit_to_their_real_state() {
+        let manager = manager();
+        let channel_id = open_channel(&manager, 1_000);
+
+        let state = manager.channel_state(&channel_id).unwrap();
+        assert_eq!(state.channel_id, channel_id);
+        assert_eq!(state.balance, 1_000);
+        assert_eq!(
+            state.auth_key,
+            StateKeyChain::new(SEED)
+                .for_channel(&channel_id)
+                .auth_key(0)
+        );
+        assert_eq!(manager.state_hash(&channel_id).unwrap(), state.commitment());
+        assert_ne!(
+            state.commitment(),
+            state.with_auth_key([0u8; 32]).commitment()
+        );
+        assert_eq!(
+            dispatch(&manager, 0, &channel_id).unwrap(),
+            state.to_bytes()
+        );
+    }
+
+    #[test]
+    fn test_transfer_is_proven_before_it_applies() {
+        let sender = manager();
+        let channel_id = open_channel(&sender, 1_000);
+        // A second copy of the channel, still at the state the proof starts from.
+        let verifier = manager();
+        open_channel(&verifier, 1_000);
+
+        let proof: ZkProof = bincode::deserialize(
+            &dispatch(&sender, 2, &transfer_params(&channel_id, 100, [4u8; 32])).unwrap(),
+        )
+        .unwrap();
+
+        let state = sender.channel_state(&channel_id).unwrap();
+        assert_eq!((state.balance, state.nonce, state.seqno), (900, 1, 1));
+        assert_eq!(state.lock_root, [4u8; 32]);
+        assert_eq!(
+            state.auth_key,
+            StateKeyChain::new(SEED)
+                .for_channel(&channel_id)
+                .auth_key(1)
+        );
+        assert_eq!(proof.merkle_root, state.commitment().to_vec());
+
+        let verify = |params: Vec<u8>| dispatch(&verifier, 3, &params).unwrap();
+        assert_eq!(verify(verify_params(&channel_id, 1_000, 900, &proof)), [1]);
+        assert_eq!(verify(verify_params(&channel_id, 1_000, 800, &proof)), [0]);
+        // The sender's channel has moved on, so the proof no longer starts from it.
+        assert_eq!(
+            dispatch(&sender, 3, &verify_params(&channel_id, 1_000, 900, &proof)).unwrap(),
+            [0]
+        );
+
+        let mislabeled = ZkProof {
+            proof_type: ProofType::MerkleInclusion,
+            ..proof.clone()
+        };
+        assert_eq!(
+            verify(verify_params(&channel_id, 1_000, 900, &mislabeled)),
+            [0]
+        );
+        let wrong_root = ZkProof {
+            merkle_root: vec![0u8; 32],
+            ..proof.clone()
+        };
+        assert_eq!(
+            verify(verify_params(&channel_id, 1_000, 900, &wrong_root)),
+            [0]
+        );
+
+        // The next transfer is signed by the rotated key.
+        sender
+            .transfer(&channel_id, 50, [0u8; 32])
+            .unwrap()
+            .wait()
+            .unwrap();
+        assert_eq!(sender.channel_state(&channel_id).unwrap().balance, 850);
+        assert!(sender
+            .transfer(&channel_id, 10_000, [0u8; 32])
+            .and_then(PendingTransfer::wait)
+            .is_err());
+        assert_eq!(sender.channel_state(&channel_id).unwrap().balance, 850);
+    }
+
+    #[test]
+    fn test_transfers_are_proven_on_the_shared_queue() {
+        let prover = Arc::new(ProverQueue::new(ProverQueueConfig {
+            capacity: 4,
+            workers: 0,
+            default_timeout: None,
+        }));
+        let manager = manager().with_prover(Arc::clone(&prover));
+        let channel_id = open_channel(&manager, 1_000);
+
+        let pending = manager.transfer(&channel_id, 100, [0u8; 32]).unwrap();
+        // Nothing is locked while the job waits: the channel stays readable, but takes no
+        // second transfer signed from the same state.
+        assert_eq!(prover.metrics().queued[JobPriority::Normal as usize], 1);
+        assert_eq!(manager.channel_state(&channel_id).unwrap().balance, 1_000);
+        assert!(manager.transfer(&channel_id, 100, [0u8; 32]).is_err());
+
+        block_on(pending).unwrap();
+        assert_eq!(prover.metrics().completed, 1);
+        assert_eq!(manager.channel_state(&channel_id).unwrap().balance, 900);
+
+        // An abandoned transfer releases the channel and leaves its state alone.
+        drop(manager.transfer(&channel_id, 100, [0u8; 32]).unwrap());
+        assert_eq!(prover.metrics().cancelled, 1);
+        assert_eq!(manager.channel_state(&channel_id).unwrap().balance, 900);
+        manager
+            .transfer(&channel_id, 100, [0u8; 32])
+            .unwrap()
+            .wait()
+            .unwrap();
+        assert_eq!(manager.channel_state(&channel_id).unwrap().balance, 800);
+    }
+
+    #[test]
+    fn test_unprovable_transfers_are_rejected_before_signing() {
+        let prover = Arc::new(ProverQueue::new(ProverQueueConfig {
+            capacity: 4,
+            workers: 0,
+            default_timeout: None,
+        }));
+        let manager = manager().with_prover(Arc::clone(&prover));
+        let channel_id = open_channel(&manager, 1_000);
+
+        // More than half the balance, and more than the balance: nothing is signed or queued.
+        assert!(manager.transfer(&channel_id, 600, [0u8; 32]).is_err());
+        assert!(manager.transfer(&channel_id, 2_000, [0u8; 32]).is_err());
+        let metrics = prover.metrics();
+        assert_eq!(metrics.queued.iter().sum::<usize>(), 0);
+        assert_eq!(metrics.completed + metrics.failed, 0);
+
+        manager
+            .transfer(&channel_id, 500, [0u8; 32])
+            .unwrap()
+            .wait()
+            .unwrap();
+        assert_eq!(manager.channel_state(&channel_id).unwrap().balance, 500);
+    }
+
+    #[test]
+    fn test_rejects_states_not_keyed_by_their_nonce() {
+        let manager = manager();
+        let channel_id = open_channel(&manager, 1_000);
+        let reused = StateKeyChain::new(SEED)
+            .for_channel(&channel_id)
+            .auth_key(0);
+        {
+            let channel = manager.get_channel(&channel_id).unwrap();
+            let mut state = channel.write().unwrap();
+            // Nonce 1 still committing to key 0 would make key 0 sign twice.
+            state.nonce = 1;
+            state.auth_key = reused;
         }
+
+        assert!(manager
+            .transfer(&channel_id, 100, [0u8; 32])
+            .and_then(PendingTransfer::wait)
+            .is_err());
+        assert_eq!(manager.channel_state(&channel_id).unwrap().balance, 1_000);
+    }
+
+    #[test]
+    fn test_rejects_malformed_params() {
+        let manager = manager();
+        assert!(dispatch(&manager, 0, &[1u8; 31]).is_err());
+        assert!(dispatch(&manager, 0, &[1u8; 33]).is_err());
+        assert!(dispatch(&manager, 1, &create_params(1_000)[..72]).is_err());
+        assert!(manager.channel_state(&[9u8; 32]).is_err());
+
+        let channel_id = open_channel(&manager, 1_000);
+        assert!(dispatch(
+            &manager,
+            2,
+            &transfer_params(&channel_id, 100, [0u8; 32])[..40]
+        )
+        .is_err());
+        assert!(dispatch(&manager, 3, &[0u8; 48]).is_err());
     }
 }
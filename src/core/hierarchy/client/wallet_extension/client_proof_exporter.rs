@@ -1,45 +1,43 @@
 // client_proof_exporter.rs
 // This module is responsible for exporting the wallet root and its associated proof in a BOC (Bag of Cells) format for submission to the intermediate layer.
+//
+// The wallet root is the hierarchy tree root over every channel's `ChannelStateRecord`, keyed
+// by channel id. Each submission carries a tree transition proof from the last submitted
+// wallet root to the new one; the intermediate layer checks it against the root it last
+// accepted, and recovery checks recovered channel states against the same tree.
+//
+// BOC layout:
+//   cell 0 (root)  wallet_id (32) | wallet_root (32) | nonce (8) | timestamp (8) | proof_type (1)
+//                  references = [1, 2], merkle_hash = signing hash
+//   cell 1 (proof) proof_type (1) | circuit_digest (32) | merkle_root (32) | timestamp (8) |
+//                  input_count (4) | public_inputs (8 each) | proof_data
+//   cell 2 (sig)   ed25519 signature (64) over the signing hash
 
-use crate::core::error::SystemError;
-use crate::core::types::boc::{Cell, BOC};
-use crate::core::zkps::circuit_builder::ZkCircuitBuilder;
-use crate::core::zkps::proof::ZkProof;
+use crate::core::error::errors::{SystemError, SystemErrorType};
+use crate::core::hierarchy::client::wallet_extension::recovery::ChannelStateRecord;
+use crate::core::smt::store::NodeStore;
+use crate::core::smt::HierarchyTree;
+use crate::core::types::boc::{Cell, CellType, BOC};
+use crate::core::zkps::circuit_cache::circuit_digest;
+use crate::core::zkps::proof::{ProofType, ProofVerifier, ZkProof};
+use crate::core::zkps::tree_transition::{
+    tree_transition_roots, TransitionWitness, TreeTransitionCircuit, SHARED_BATCH_SIZE,
+};
+use ed25519_dalek::{Signer, SigningKey};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 
-/// Enum representing different types of proofs.
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub enum ProofType {
-    StateTransition = 0,
-    BalanceTransfer = 1,
-    MerkleInclusion = 2,
-}
+const HEADER_LEN: usize = 32 + 32 + 8 + 8 + 1;
+const PROOF_HEADER_LEN: usize = 1 + 32 + 32 + 8 + 4;
+const SIGNATURE_LEN: usize = 64;
+
 /// Data structure representing a wallet root and its associated proof.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct WalletRootProof {
     pub wallet_root: [u8; 32],
     pub proof: ZkProof,
     pub metadata: ProofMetadata,
-}
-
-impl WalletRootProof {
-    /// Exports the wallet root and its associated proof in a BOC (Bag of Cells) format for submission to the intermediate layer.
-    pub fn export_proof_boc(&self) -> Result<BOC, SystemError> {
-        let mut data = Vec::new();
-        data.extend_from_slice(&self.wallet_root);
-        data.extend_from_slice(&self.proof.public_inputs);
-        data.extend_from_slice(&self.proof.merkle_root);
-        data.extend_from_slice(&self.proof.proof_data);
-        data.extend_from_slice(&self.metadata.timestamp.to_le_bytes());
-        data.extend_from_slice(&self.metadata.nonce.to_le_bytes());
-        data.extend_from_slice(&self.metadata.wallet_id);
-        data.extend_from_slice(&[self.metadata.proof_type as u8]);
-
-        let mut hasher = Sha256::new();
-        hasher.update(&data);
-        let hash = hasher.finalize();
-    }
+    pub signature: Vec<u8>,
 }
 
 /// Metadata for tracking proof context.
@@ -52,12 +50,420 @@ pub struct ProofMetadata {
 }
 
 impl WalletRootProof {
-    /// Creates a new WalletRootProof with the given wallet root, proof, and metadata.
+    /// Creates a new, unsigned WalletRootProof with the given wallet root, proof, and metadata.
     pub fn new(wallet_root: [u8; 32], proof: ZkProof, metadata: ProofMetadata) -> Self {
         Self {
             wallet_root,
             proof,
             metadata,
+            signature: Vec::new(),
+        }
+    }
+
+    /// Applies `records` to the wallet's channel tree and proves the move to the new wallet
+    /// root. Returns the unsigned submission for that root. At most `SHARED_BATCH_SIZE`
+    /// channels can change per submission.
+    pub fn prove_channel_updates<S: NodeStore>(
+        channels: &mut HierarchyTree<S>,
+        records: &[ChannelStateRecord],
+        wallet_id: [u8; 32],
+        nonce: u64,
+        timestamp: u64,
+    ) -> Result<Self, SystemError> {
+        if records.is_empty() || records.len() > SHARED_BATCH_SIZE {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidOperation,
+                format!(
+                    "A wallet root submission updates 1 to {} channels, got {}",
+                    SHARED_BATCH_SIZE,
+                    records.len()
+                ),
+            ));
+        }
+        if records.iter().any(|record| record.wallet_id != wallet_id) {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidAddress,
+                "Channel state belongs to another wallet".to_string(),
+            ));
+        }
+
+        let updates: Vec<([u8; 32], Vec<u8>)> = records
+            .iter()
+            .map(|record| (record.channel_id, record.encode_state()))
+            .collect();
+        let witness = TransitionWitness::record(channels, &updates)?.padded(SHARED_BATCH_SIZE)?;
+        let circuit = TreeTransitionCircuit::shared();
+        let proof = ZkProof::from_tree_transition(
+            &circuit.prove(&witness)?,
+            circuit_digest(circuit.circuit_data()),
+            timestamp,
+        );
+        let metadata = ProofMetadata {
+            timestamp,
+            nonce,
+            wallet_id,
+            proof_type: ProofType::TreeTransition,
+        };
+        Ok(Self::new(witness.new_root, proof, metadata))
+    }
+
+    /// Verifies the attached proof against a registered tree transition circuit and that it
+    /// ends at the submitted wallet root. Returns the root the proof starts from.
+    pub fn verify_proof(&self, verifier: &ProofVerifier) -> Result<[u8; 32], SystemError> {
+        if self.metadata.proof_type != self.proof.proof_type {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidProof,
+                format!(
+                    "Submission declares a {:?} proof but carries a {:?} proof",
+                    self.metadata.proof_type, self.proof.proof_type
+                ),
+            ));
+        }
+        if self.proof.proof_type != ProofType::TreeTransition {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidProof,
+                format!(
+                    "Wallet roots are proved by tree transitions, not {:?} proofs",
+                    self.proof.proof_type
+                ),
+            ));
+        }
+        verifier.verify(&self.proof)?;
+
+        let (old_root, new_root) =
+            tree_transition_roots(&self.proof.public_inputs).ok_or_else(|| {
+                SystemError::new(
+                    SystemErrorType::InvalidProof,
+                    "Proof is missing its public roots".to_string(),
+                )
+            })?;
+        if new_root != self.wallet_root {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidProof,
+                "Proof is not bound to the submitted wallet root".to_string(),
+            ));
+        }
+        Ok(old_root)
+    }
+
+    /// Signs the submission with the wallet's key.
+    pub fn sign(&mut self, signing_key: &SigningKey) -> Result<(), SystemError> {
+        let signing_hash = self.signing_hash()?;
+        self.signature = signing_key.sign(&signing_hash).to_bytes().to_vec();
+        Ok(())
+    }
+
+    /// Returns the hash covered by the wallet signature. Fails if the proof's merkle root is
+    /// not 32 bytes, since it could not be encoded unambiguously.
+    pub fn signing_hash(&self) -> Result<[u8; 32], SystemError> {
+        let mut hasher = Sha256::new();
+        hasher.update(self.encode_header());
+        hasher.update(self.encode_proof()?);
+        Ok(hasher.finalize().into())
+    }
+
+    /// Exports the wallet root and its associated proof in a BOC (Bag of Cells) format for submission to the intermediate layer.
+    pub fn export_proof_boc(&self) -> Result<BOC, SystemError> {
+        if self.signature.len() != SIGNATURE_LEN {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidSignature,
+                "Wallet root proof must be signed before export".to_string(),
+            ));
+        }
+
+        let mut boc = BOC::new();
+        let root_index = boc.add_cell(Cell::new(
+            self.encode_header(),
+            vec![1, 2],
+            CellType::Ordinary,
+            self.signing_hash()?,
+            None,
+        ));
+
+        let mut proof_cell = Cell::with_data(self.encode_proof()?);
+        proof_cell.update_merkle_hash();
+        boc.add_cell(proof_cell);
+
+        let mut signature_cell = Cell::with_data(self.signature.clone());
+        signature_cell.update_merkle_hash();
+        boc.add_cell(signature_cell);
+
+        boc.add_root(root_index);
+        Ok(boc)
+    }
+
+    /// Parses a wallet root submission BOC produced by `export_proof_boc`.
+    ///
+    /// Only the encoding is checked here; signature, nonce and proof checks are left to the intermediate layer.
+    pub fn import_proof_boc(boc: &BOC) -> Result<Self, SystemError> {
+        let root_cell = boc.get_root_cell().ok_or_else(|| {
+            SystemError::new(
+                SystemErrorType::NoRootCell,
+                "Wallet root BOC has no root cell".to_string(),
+            )
+        })?;
+
+        if root_cell.references.len() != 2 {
+            return Err(invalid_format(
+                "Root cell must reference proof and signature",
+            ));
+        }
+        let proof_cell = boc
+            .get_cell(root_cell.references[0])
+            .ok_or_else(|| invalid_format("Missing proof cell"))?;
+        let signature_cell = boc
+            .get_cell(root_cell.references[1])
+            .ok_or_else(|| invalid_format("Missing signature cell"))?;
+
+        let header = root_cell.get_data();
+        if header.len() != HEADER_LEN {
+            return Err(invalid_format("Invalid header length"));
+        }
+
+        let mut offset = 0;
+        let wallet_id = read_array(header, &mut offset);
+        let wallet_root = read_array(header, &mut offset);
+        let nonce = read_u64(header, &mut offset);
+        let timestamp = read_u64(header, &mut offset);
+        let proof_type = ProofType::try_from(header[offset]).map_err(invalid_format)?;
+
+        let proof = decode_proof(proof_cell.get_data())?;
+
+        if signature_cell.get_data().len() != SIGNATURE_LEN {
+            return Err(invalid_format("Invalid signature length"));
         }
+
+        let submission = Self {
+            wallet_root,
+            proof,
+            metadata: ProofMetadata {
+                timestamp,
+                nonce,
+                wallet_id,
+                proof_type,
+            },
+            signature: signature_cell.get_data().clone(),
+        };
+
+        if root_cell.merkle_hash != submission.signing_hash()? {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidHash,
+                "Root cell hash does not match submission contents".to_string(),
+            ));
+        }
+
+        Ok(submission)
+    }
+
+    fn encode_header(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(HEADER_LEN);
+        data.extend_from_slice(&self.metadata.wallet_id);
+        data.extend_from_slice(&self.wallet_root);
+        data.extend_from_slice(&self.metadata.nonce.to_le_bytes());
+        data.extend_from_slice(&self.metadata.timestamp.to_le_bytes());
+        data.push(self.metadata.proof_type as u8);
+        data
+    }
+
+    fn encode_proof(&self) -> Result<Vec<u8>, SystemError> {
+        let merkle_root: &[u8; 32] =
+            self.proof.merkle_root.as_slice().try_into().map_err(|_| {
+                SystemError::new(
+                    SystemErrorType::InvalidProof,
+                    format!(
+                        "Proof merkle root must be 32 bytes, got {}",
+                        self.proof.merkle_root.len()
+                    ),
+                )
+            })?;
+        let mut data = Vec::with_capacity(
+            PROOF_HEADER_LEN + self.proof.public_inputs.len() * 8 + self.proof.proof_data.len(),
+        );
+        data.push(self.proof.proof_type as u8);
+        data.extend_from_slice(&self.proof.circuit_digest);
+        data.extend_from_slice(merkle_root);
+        data.extend_from_slice(&self.proof.timestamp.to_le_bytes());
+        data.extend_from_slice(&(self.proof.public_inputs.len() as u32).to_le_bytes());
+        for input in &self.proof.public_inputs {
+            data.extend_from_slice(&input.to_le_bytes());
+        }
+        data.extend_from_slice(&self.proof.proof_data);
+        Ok(data)
+    }
+}
+
+fn decode_proof(data: &[u8]) -> Result<ZkProof, SystemError> {
+    if data.len() < PROOF_HEADER_LEN {
+        return Err(invalid_format("Proof cell too short"));
+    }
+
+    let proof_type = ProofType::try_from(data[0]).map_err(invalid_format)?;
+    let mut offset = 1;
+    let circuit_digest = read_array(data, &mut offset);
+    let merkle_root: [u8; 32] = read_array(data, &mut offset);
+    let timestamp = read_u64(data, &mut offset);
+    let input_count = u32::from_le_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ]) as usize;
+    offset += 4;
+
+    if data.len() - offset < input_count * 8 {
+        return Err(invalid_format("Proof cell truncated in public inputs"));
+    }
+    let public_inputs = (0..input_count)
+        .map(|_| read_u64(data, &mut offset))
+        .collect();
+
+    Ok(ZkProof::new(
+        proof_type,
+        circuit_digest,
+        data[offset..].to_vec(),
+        public_inputs,
+        merkle_root.to_vec(),
+        timestamp,
+    ))
+}
+
+fn read_array(data: &[u8], offset: &mut usize) -> [u8; 32] {
+    let mut array = [0u8; 32];
+    array.copy_from_slice(&data[*offset..*offset + 32]);
+    *offset += 32;
+    array
+}
+
+fn read_u64(data: &[u8], offset: &mut usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&data[*offset..*offset + 8]);
+    *offset += 8;
+    u64::from_le_bytes(bytes)
+}
+
+fn invalid_format(message: &str) -> SystemError {
+    SystemError::new(SystemErrorType::InvalidTransaction, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::smt::HierarchyHasher;
+
+    fn test_submission() -> WalletRootProof {
+        let proof = ZkProof::new(
+            ProofType::StateTransition,
+            [4u8; 32],
+            vec![9u8; 48],
+            vec![1000, 900, 100],
+            vec![3u8; 32],
+            42,
+        );
+        let metadata = ProofMetadata {
+            timestamp: 1_700_000_000,
+            nonce: 7,
+            wallet_id: [1u8; 32],
+            proof_type: ProofType::StateTransition,
+        };
+        let mut submission = WalletRootProof::new([3u8; 32], proof, metadata);
+        submission
+            .sign(&SigningKey::from_bytes(&[5u8; 32]))
+            .unwrap();
+        submission
+    }
+
+    #[test]
+    fn test_export_import_roundtrip() {
+        let submission = test_submission();
+        let boc = submission.export_proof_boc().unwrap();
+        assert_eq!(boc.cell_count(), 3);
+
+        let imported = WalletRootProof::import_proof_boc(&boc).unwrap();
+        assert_eq!(imported.wallet_root, submission.wallet_root);
+        assert_eq!(imported.metadata.nonce, 7);
+        assert_eq!(imported.metadata.wallet_id, [1u8; 32]);
+        assert_eq!(imported.proof.public_inputs, vec![1000, 900, 100]);
+        assert_eq!(imported.proof.proof_data, vec![9u8; 48]);
+        assert_eq!(imported.proof.proof_type, ProofType::StateTransition);
+        assert_eq!(imported.proof.circuit_digest, [4u8; 32]);
+        assert_eq!(imported.signature, submission.signature);
+    }
+
+    #[test]
+    fn test_export_requires_signature() {
+        let mut submission = test_submission();
+        submission.signature.clear();
+        assert!(submission.export_proof_boc().is_err());
+    }
+
+    #[test]
+    fn test_rejects_merkle_root_of_wrong_length() {
+        for len in [31, 33] {
+            let mut submission = test_submission();
+            submission.proof.merkle_root = vec![3u8; len];
+            assert!(matches!(
+                submission.sign(&SigningKey::from_bytes(&[5u8; 32])),
+                Err(SystemError {
+                    error_type: SystemErrorType::InvalidProof,
+                    ..
+                })
+            ));
+            assert!(submission.export_proof_boc().is_err());
+        }
+    }
+
+    #[test]
+    fn test_proves_channel_updates_into_wallet_root() {
+        let mut channels = HierarchyTree::new();
+        let empty_root = channels.root();
+        let records = vec![
+            ChannelStateRecord::new([1u8; 32], [2u8; 32], 900, 1, 1, [0u8; 32]),
+            ChannelStateRecord::new([1u8; 32], [3u8; 32], 500, 4, 2, [6u8; 32]),
+        ];
+        let submission =
+            WalletRootProof::prove_channel_updates(&mut channels, &records, [1u8; 32], 1, 42)
+                .unwrap();
+        assert_eq!(submission.wallet_root, channels.root());
+
+        let verifier = ProofVerifier::with_system_circuits().unwrap();
+        assert_eq!(submission.verify_proof(&verifier).unwrap(), empty_root);
+        for record in &records {
+            let proof = channels.prove_compressed(&record.channel_id).unwrap();
+            assert!(proof.verify::<HierarchyHasher>(
+                &submission.wallet_root,
+                &record.channel_id,
+                &record.encode_state()
+            ));
+        }
+
+        let mut rerooted = submission.clone();
+        rerooted.wallet_root = empty_root;
+        assert!(rerooted.verify_proof(&verifier).is_err());
+
+        let foreign = ChannelStateRecord::new([9u8; 32], [4u8; 32], 1, 1, 1, [0u8; 32]);
+        assert!(WalletRootProof::prove_channel_updates(
+            &mut channels,
+            &[foreign],
+            [1u8; 32],
+            2,
+            42
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_import_rejects_tampered_header() {
+        let submission = test_submission();
+        let mut boc = submission.export_proof_boc().unwrap();
+        boc.get_root_cell_mut().unwrap().data[40] ^= 0xff;
+
+        let result = WalletRootProof::import_proof_boc(&boc);
+        assert!(matches!(
+            result,
+            Err(SystemError {
+                error_type: SystemErrorType::InvalidHash,
+                ..
+            })
+        ));
     }
 }
@@ -1,11 +1,14 @@
 // src/core/hierarchy/client/wallet_extension/mod.rs
 pub mod balance;
-//pub mod channel_manager;
-//pub mod client_proof_exporter;
+pub mod channel_manager;
+pub mod client_proof_exporter;
 pub mod grouping;
+pub mod multisig;
+pub mod recovery;
 pub mod sparse_merkle_tree_wasm;
 //pub mod token_wallet;
-//pub mod user;
+pub mod transaction_history;
+pub mod user;
 pub mod wallet_extension_contract;
 pub mod wallet_extension_types;
 //pub mod wallet_utils;
@@ -0,0 +1,744 @@
+// ./src/core/hierarchy/client/wallet_extension/multisig.rs
+
+// Multisignature Control
+// Puts a wallet extension under m-of-n control by a set of ed25519 keys. Channel creation,
+// payments above the policy threshold, channel close and quorum changes are proposed as
+// pending operations, collect signatures until the quorum is met or they expire, and are
+// only then released for execution.
+
+use crate::core::error::errors::{SystemError, SystemErrorType};
+use crate::core::hierarchy::client::wallet_extension::wallet_extension_types::WalletExtension;
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+
+/// An operation that may need multisignature approval.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MultisigOperation {
+    CreateChannel {
+        counterparty: [u8; 32],
+        initial_balance: u64,
+    },
+    Payment {
+        channel_id: [u8; 32],
+        recipient: [u8; 32],
+        amount: u64,
+    },
+    CloseChannel {
+        channel_id: [u8; 32],
+    },
+    UpdateQuorum {
+        signers: Vec<[u8; 32]>,
+        threshold: u32,
+    },
+}
+
+impl MultisigOperation {
+    fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        match self {
+            MultisigOperation::CreateChannel {
+                counterparty,
+                initial_balance,
+            } => {
+                data.push(0);
+                data.extend_from_slice(counterparty);
+                data.extend_from_slice(&initial_balance.to_le_bytes());
+            }
+            MultisigOperation::Payment {
+                channel_id,
+                recipient,
+                amount,
+            } => {
+                data.push(1);
+                data.extend_from_slice(channel_id);
+                data.extend_from_slice(recipient);
+                data.extend_from_slice(&amount.to_le_bytes());
+            }
+            MultisigOperation::CloseChannel { channel_id } => {
+                data.push(2);
+                data.extend_from_slice(channel_id);
+            }
+            MultisigOperation::UpdateQuorum { signers, threshold } => {
+                data.push(3);
+                data.extend_from_slice(&threshold.to_le_bytes());
+                data.extend_from_slice(&(signers.len() as u32).to_le_bytes());
+                for signer in signers {
+                    data.extend_from_slice(signer);
+                }
+            }
+        }
+        data
+    }
+}
+
+/// The signer set and thresholds controlling a wallet.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MultisigPolicy {
+    pub signers: Vec<[u8; 32]>,
+    pub threshold: u32,
+    /// Payments strictly above this amount need a quorum.
+    pub payment_threshold: u64,
+    /// Seconds a pending operation stays open for signatures.
+    pub operation_ttl: u64,
+}
+
+impl MultisigPolicy {
+    pub fn new(
+        signers: Vec<[u8; 32]>,
+        threshold: u32,
+        payment_threshold: u64,
+        operation_ttl: u64,
+    ) -> Result<Self, SystemError> {
+        let policy = Self {
+            signers,
+            threshold,
+            payment_threshold,
+            operation_ttl,
+        };
+        policy.validate()?;
+        Ok(policy)
+    }
+
+    pub fn validate(&self) -> Result<(), SystemError> {
+        if self.threshold == 0 || self.threshold as usize > self.signers.len() {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidOperation,
+                format!(
+                    "Threshold {} is invalid for {} signers",
+                    self.threshold,
+                    self.signers.len()
+                ),
+            ));
+        }
+
+        let unique: HashSet<&[u8; 32]> = self.signers.iter().collect();
+        if unique.len() != self.signers.len() {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidPublicKey,
+                "Duplicate signer in policy".to_string(),
+            ));
+        }
+
+        for signer in &self.signers {
+            VerifyingKey::from_bytes(signer)
+                .map_err(|e| SystemError::new(SystemErrorType::InvalidPublicKey, e.to_string()))?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns whether an operation must collect a quorum before it may run.
+    pub fn requires_quorum(&self, operation: &MultisigOperation) -> bool {
+        match operation {
+            MultisigOperation::Payment { amount, .. } => *amount > self.payment_threshold,
+            _ => true,
+        }
+    }
+
+    fn is_signer(&self, key: &[u8; 32]) -> bool {
+        self.signers.contains(key)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignerApproval {
+    pub signer: [u8; 32],
+    pub signature: Vec<u8>,
+}
+
+/// A proposed operation collecting signatures.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingOperation {
+    pub id: [u8; 32],
+    pub operation: MultisigOperation,
+    pub policy_version: u64,
+    pub created_at: u64,
+    pub expires_at: u64,
+    pub approvals: Vec<SignerApproval>,
+}
+
+impl PendingOperation {
+    pub fn is_expired(&self, now: u64) -> bool {
+        now >= self.expires_at
+    }
+
+    pub fn approval_count(&self) -> usize {
+        self.approvals.len()
+    }
+}
+
+/// Collects and checks signatures for a wallet's quorum-gated operations.
+#[derive(Clone, Debug)]
+pub struct MultisigController {
+    wallet_id: [u8; 32],
+    policy: MultisigPolicy,
+    policy_version: u64,
+    proposal_nonce: u64,
+    pending: HashMap<[u8; 32], PendingOperation>,
+}
+
+impl MultisigController {
+    pub fn new(wallet_id: [u8; 32], policy: MultisigPolicy) -> Result<Self, SystemError> {
+        policy.validate()?;
+        Ok(Self {
+            wallet_id,
+            policy,
+            policy_version: 0,
+            proposal_nonce: 0,
+            pending: HashMap::new(),
+        })
+    }
+
+    pub fn policy(&self) -> &MultisigPolicy {
+        &self.policy
+    }
+
+    pub fn policy_version(&self) -> u64 {
+        self.policy_version
+    }
+
+    /// Hash each signer signs to approve the operation. It binds the wallet, the policy
+    /// version and the proposal id, so approvals cannot be replayed after a quorum change.
+    pub fn signing_hash(&self, pending: &PendingOperation) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(self.wallet_id);
+        hasher.update(pending.id);
+        hasher.update(pending.policy_version.to_le_bytes());
+        hasher.update(pending.expires_at.to_le_bytes());
+        hasher.update(pending.operation.encode());
+        hasher.finalize().into()
+    }
+
+    /// Opens a pending operation and returns its id.
+    pub fn propose(
+        &mut self,
+        operation: MultisigOperation,
+        now: u64,
+    ) -> Result<[u8; 32], SystemError> {
+        if let MultisigOperation::UpdateQuorum { signers, threshold } = &operation {
+            MultisigPolicy {
+                signers: signers.clone(),
+                threshold: *threshold,
+                ..self.policy.clone()
+            }
+            .validate()?;
+        }
+
+        self.proposal_nonce += 1;
+        let mut hasher = Sha256::new();
+        hasher.update(self.wallet_id);
+        hasher.update(self.proposal_nonce.to_le_bytes());
+        hasher.update(operation.encode());
+        let id: [u8; 32] = hasher.finalize().into();
+
+        self.pending.insert(
+            id,
+            PendingOperation {
+                id,
+                operation,
+                policy_version: self.policy_version,
+                created_at: now,
+                expires_at: now.saturating_add(self.policy.operation_ttl),
+                approvals: Vec::new(),
+            },
+        );
+        Ok(id)
+    }
+
+    /// Adds a signer's approval. Returns true once the quorum is reached.
+    pub fn approve(
+        &mut self,
+        id: &[u8; 32],
+        signer: [u8; 32],
+        signature: &[u8],
+        now: u64,
+    ) -> Result<bool, SystemError> {
+        if !self.policy.is_signer(&signer) {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidPublicKey,
+                "Key is not part of the signer set".to_string(),
+            ));
+        }
+
+        let pending = self.live_pending(id, now)?;
+        if pending.approvals.iter().any(|a| a.signer == signer) {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidSignature,
+                "Signer already approved this operation".to_string(),
+            ));
+        }
+
+        let signature = self.verify_approval(pending, &signer, signature)?;
+
+        let threshold = self.policy.threshold as usize;
+        let pending = self.pending.get_mut(id).ok_or_else(not_found)?;
+        pending.approvals.push(SignerApproval {
+            signer,
+            signature: signature.to_bytes().to_vec(),
+        });
+        Ok(pending.approvals.len() >= threshold)
+    }
+
+    /// Releases an operation that has reached its quorum. Quorum changes are applied here,
+    /// which invalidates every other pending operation.
+    pub fn execute(&mut self, id: &[u8; 32], now: u64) -> Result<MultisigOperation, SystemError> {
+        self.approved(id, now)?;
+        let pending = self.pending.remove(id).ok_or_else(not_found)?;
+        if let MultisigOperation::UpdateQuorum { signers, threshold } = &pending.operation {
+            self.policy.signers = signers.clone();
+            self.policy.threshold = *threshold;
+            self.policy_version += 1;
+            self.pending.clear();
+        }
+        Ok(pending.operation)
+    }
+
+    /// Checks whether an operation may run without consuming its approval. Operations below
+    /// the quorum requirement pass directly and return `None`; the rest must name a matching
+    /// approved proposal, whose id is returned so the caller can `execute` it once the
+    /// operation has actually been applied.
+    pub fn authorize(
+        &self,
+        operation: &MultisigOperation,
+        approved_id: Option<&[u8; 32]>,
+        now: u64,
+    ) -> Result<Option<[u8; 32]>, SystemError> {
+        if !self.policy.requires_quorum(operation) {
+            return Ok(None);
+        }
+
+        let id = approved_id.ok_or_else(|| {
+            SystemError::new(
+                SystemErrorType::InvalidSignature,
+                "Operation requires multisignature approval".to_string(),
+            )
+        })?;
+        if &self.approved(id, now)?.operation != operation {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidOperation,
+                "Approved operation does not match the requested operation".to_string(),
+            ));
+        }
+        Ok(Some(*id))
+    }
+
+    pub fn pending(&self, id: &[u8; 32]) -> Option<&PendingOperation> {
+        self.pending.get(id)
+    }
+
+    pub fn pending_operations(&self) -> Vec<&PendingOperation> {
+        self.pending.values().collect()
+    }
+
+    /// Drops expired operations and returns how many were removed.
+    pub fn prune_expired(&mut self, now: u64) -> usize {
+        let before = self.pending.len();
+        self.pending.retain(|_, pending| !pending.is_expired(now));
+        before - self.pending.len()
+    }
+
+    /// Serializes the pending operations so they survive a restart. The policy is not part of
+    /// the output: it is configuration, and is supplied again on restore.
+    pub fn export_pending(&self) -> Result<Vec<u8>, SystemError> {
+        let state = PersistedMultisigState {
+            proposal_nonce: self.proposal_nonce,
+            pending: self.pending.values().cloned().collect(),
+        };
+        serde_json::to_vec(&state)
+            .map_err(|e| SystemError::new(SystemErrorType::InvalidOperation, e.to_string()))
+    }
+
+    /// Restores the pending operations from `export_pending` output under the configured
+    /// `policy` and `policy_version`. The stored data is untrusted: every approval must be a
+    /// valid signature by a configured signer over the operation's signing hash, or the
+    /// restore fails. Expired operations, operations of another policy version and
+    /// operations open for longer than the policy allows are dropped.
+    pub fn restore_pending(
+        wallet_id: [u8; 32],
+        policy: MultisigPolicy,
+        policy_version: u64,
+        data: &[u8],
+        now: u64,
+    ) -> Result<Self, SystemError> {
+        let state: PersistedMultisigState = serde_json::from_slice(data)
+            .map_err(|e| SystemError::new(SystemErrorType::InvalidOperation, e.to_string()))?;
+
+        let mut controller = Self::new(wallet_id, policy)?;
+        controller.policy_version = policy_version;
+        controller.proposal_nonce = state.proposal_nonce;
+
+        let max_expiry = now.saturating_add(controller.policy.operation_ttl);
+        for pending in state.pending {
+            if pending.policy_version != policy_version
+                || pending.is_expired(now)
+                || pending.expires_at > max_expiry
+            {
+                continue;
+            }
+            let mut signers = HashSet::new();
+            for approval in &pending.approvals {
+                if !controller.policy.is_signer(&approval.signer)
+                    || !signers.insert(approval.signer)
+                {
+                    return Err(SystemError::new(
+                        SystemErrorType::InvalidSignature,
+                        "Stored approval is not from a distinct configured signer".to_string(),
+                    ));
+                }
+                controller.verify_approval(&pending, &approval.signer, &approval.signature)?;
+            }
+            controller.pending.insert(pending.id, pending);
+        }
+        Ok(controller)
+    }
+
+    /// Checks `signature` by `signer` over the operation's signing hash.
+    fn verify_approval(
+        &self,
+        pending: &PendingOperation,
+        signer: &[u8; 32],
+        signature: &[u8],
+    ) -> Result<Signature, SystemError> {
+        let key = VerifyingKey::from_bytes(signer)
+            .map_err(|e| SystemError::new(SystemErrorType::InvalidPublicKey, e.to_string()))?;
+        let signature = Signature::from_slice(signature)
+            .map_err(|e| SystemError::new(SystemErrorType::InvalidSignature, e.to_string()))?;
+        key.verify(&self.signing_hash(pending), &signature)
+            .map_err(|_| {
+                SystemError::new(
+                    SystemErrorType::InvalidSignature,
+                    "Approval signature verification failed".to_string(),
+                )
+            })?;
+        Ok(signature)
+    }
+
+    /// Returns a live pending operation that has reached its quorum.
+    fn approved(&self, id: &[u8; 32], now: u64) -> Result<&PendingOperation, SystemError> {
+        let pending = self.live_pending(id, now)?;
+        if pending.approvals.len() < self.policy.threshold as usize {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidSignature,
+                format!(
+                    "Quorum not reached: {} of {} signatures",
+                    pending.approvals.len(),
+                    self.policy.threshold
+                ),
+            ));
+        }
+        Ok(pending)
+    }
+
+    fn live_pending(&self, id: &[u8; 32], now: u64) -> Result<&PendingOperation, SystemError> {
+        let pending = self.pending.get(id).ok_or_else(not_found)?;
+        if pending.is_expired(now) {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidOperation,
+                "Pending operation expired".to_string(),
+            ));
+        }
+        Ok(pending)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct PersistedMultisigState {
+    proposal_nonce: u64,
+    pending: Vec<PendingOperation>,
+}
+
+fn not_found() -> SystemError {
+    SystemError::new(
+        SystemErrorType::NotFound,
+        "Pending operation not found".to_string(),
+    )
+}
+
+impl WalletExtension {
+    /// Places the wallet under m-of-n control.
+    pub fn enable_multisig(&mut self, policy: MultisigPolicy) -> Result<(), SystemError> {
+        if self.multisig.is_some() {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidOperation,
+                "Multisig already enabled; change the quorum through an UpdateQuorum operation"
+                    .to_string(),
+            ));
+        }
+        self.multisig = Some(MultisigController::new(self.wallet_id, policy)?);
+        Ok(())
+    }
+
+    /// Gate for channel creation, payments and channel close. Single-owner wallets pass.
+    /// Returns the approved proposal to pass to `complete_operation` once the operation has
+    /// been applied; the approval stays usable if applying it fails.
+    pub fn authorize_operation(
+        &self,
+        operation: &MultisigOperation,
+        approved_id: Option<&[u8; 32]>,
+        now: u64,
+    ) -> Result<Option<[u8; 32]>, SystemError> {
+        match self.multisig.as_ref() {
+            Some(controller) => controller.authorize(operation, approved_id, now),
+            None => Ok(None),
+        }
+    }
+
+    /// Marks an authorized operation as executed, consuming its approval.
+    pub fn complete_operation(
+        &mut self,
+        approved_id: Option<[u8; 32]>,
+        now: u64,
+    ) -> Result<(), SystemError> {
+        match (self.multisig.as_mut(), approved_id) {
+            (Some(controller), Some(id)) => controller.execute(&id, now).map(|_| ()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    fn keys() -> Vec<SigningKey> {
+        (1..=3u8)
+            .map(|i| SigningKey::from_bytes(&[i; 32]))
+            .collect()
+    }
+
+    fn controller(keys: &[SigningKey]) -> MultisigController {
+        let signers = keys.iter().map(|k| k.verifying_key().to_bytes()).collect();
+        let policy = MultisigPolicy::new(signers, 2, 1_000, 3_600).unwrap();
+        MultisigController::new([7u8; 32], policy).unwrap()
+    }
+
+    fn sign(controller: &MultisigController, id: &[u8; 32], key: &SigningKey) -> Vec<u8> {
+        let hash = controller.signing_hash(controller.pending(id).unwrap());
+        key.sign(&hash).to_bytes().to_vec()
+    }
+
+    #[test]
+    fn test_policy_validation() {
+        let signers: Vec<[u8; 32]> = keys()
+            .iter()
+            .map(|k| k.verifying_key().to_bytes())
+            .collect();
+        assert!(MultisigPolicy::new(signers.clone(), 0, 0, 60).is_err());
+        assert!(MultisigPolicy::new(signers.clone(), 4, 0, 60).is_err());
+        let duplicated = vec![signers[0], signers[0]];
+        assert!(MultisigPolicy::new(duplicated, 1, 0, 60).is_err());
+    }
+
+    #[test]
+    fn test_quorum_collection() {
+        let keys = keys();
+        let mut controller = controller(&keys);
+        let operation = MultisigOperation::CloseChannel {
+            channel_id: [1u8; 32],
+        };
+        let id = controller.propose(operation.clone(), 100).unwrap();
+
+        assert!(controller.authorize(&operation, Some(&id), 101).is_err());
+
+        let sig = sign(&controller, &id, &keys[0]);
+        assert!(!controller
+            .approve(&id, keys[0].verifying_key().to_bytes(), &sig, 101)
+            .unwrap());
+        assert!(controller
+            .approve(&id, keys[0].verifying_key().to_bytes(), &sig, 101)
+            .is_err());
+
+        let sig = sign(&controller, &id, &keys[1]);
+        assert!(controller
+            .approve(&id, keys[1].verifying_key().to_bytes(), &sig, 102)
+            .unwrap());
+
+        assert_eq!(
+            controller.authorize(&operation, Some(&id), 103).unwrap(),
+            Some(id)
+        );
+        assert!(controller.pending(&id).is_some());
+        assert_eq!(controller.execute(&id, 103).unwrap(), operation);
+        assert!(controller.pending(&id).is_none());
+    }
+
+    #[test]
+    fn test_small_payment_skips_quorum() {
+        let keys = keys();
+        let controller = controller(&keys);
+        let small = MultisigOperation::Payment {
+            channel_id: [1u8; 32],
+            recipient: [2u8; 32],
+            amount: 1_000,
+        };
+        let large = MultisigOperation::Payment {
+            channel_id: [1u8; 32],
+            recipient: [2u8; 32],
+            amount: 1_001,
+        };
+        assert_eq!(controller.authorize(&small, None, 100).unwrap(), None);
+        assert!(controller.authorize(&large, None, 100).is_err());
+    }
+
+    #[test]
+    fn test_rejects_outsider_and_expired() {
+        let keys = keys();
+        let mut controller = controller(&keys);
+        let id = controller
+            .propose(
+                MultisigOperation::CloseChannel {
+                    channel_id: [1u8; 32],
+                },
+                100,
+            )
+            .unwrap();
+
+        let outsider = SigningKey::from_bytes(&[9u8; 32]);
+        let sig = sign(&controller, &id, &outsider);
+        assert!(controller
+            .approve(&id, outsider.verifying_key().to_bytes(), &sig, 101)
+            .is_err());
+
+        let sig = sign(&controller, &id, &keys[0]);
+        assert!(controller
+            .approve(&id, keys[0].verifying_key().to_bytes(), &sig, 100 + 3_600)
+            .is_err());
+        assert_eq!(controller.prune_expired(100 + 3_600), 1);
+    }
+
+    #[test]
+    fn test_quorum_update_requires_quorum_and_invalidates_pending() {
+        let keys = keys();
+        let mut controller = controller(&keys);
+        let other = controller
+            .propose(
+                MultisigOperation::CloseChannel {
+                    channel_id: [1u8; 32],
+                },
+                100,
+            )
+            .unwrap();
+
+        let new_signers = vec![
+            keys[0].verifying_key().to_bytes(),
+            keys[1].verifying_key().to_bytes(),
+        ];
+        let update = MultisigOperation::UpdateQuorum {
+            signers: new_signers.clone(),
+            threshold: 2,
+        };
+        let id = controller.propose(update, 100).unwrap();
+        assert!(controller.execute(&id, 101).is_err());
+
+        for key in &keys[..2] {
+            let sig = sign(&controller, &id, key);
+            controller
+                .approve(&id, key.verifying_key().to_bytes(), &sig, 101)
+                .unwrap();
+        }
+        controller.execute(&id, 102).unwrap();
+
+        assert_eq!(controller.policy().signers, new_signers);
+        assert_eq!(controller.policy_version(), 1);
+        assert!(controller.pending(&other).is_none());
+    }
+
+    #[test]
+    fn test_export_restore_pending() {
+        let keys = keys();
+        let mut controller = controller(&keys);
+        let id = controller
+            .propose(
+                MultisigOperation::CreateChannel {
+                    counterparty: [3u8; 32],
+                    initial_balance: 500,
+                },
+                100,
+            )
+            .unwrap();
+        let sig = sign(&controller, &id, &keys[2]);
+        controller
+            .approve(&id, keys[2].verifying_key().to_bytes(), &sig, 101)
+            .unwrap();
+
+        let data = controller.export_pending().unwrap();
+        let policy = controller.policy().clone();
+        let restored =
+            MultisigController::restore_pending([7u8; 32], policy.clone(), 0, &data, 200).unwrap();
+        assert_eq!(restored.pending(&id).unwrap().approval_count(), 1);
+
+        let expired =
+            MultisigController::restore_pending([7u8; 32], policy.clone(), 0, &data, 100 + 3_600)
+                .unwrap();
+        assert!(expired.pending(&id).is_none());
+
+        let superseded =
+            MultisigController::restore_pending([7u8; 32], policy, 1, &data, 200).unwrap();
+        assert!(superseded.pending(&id).is_none());
+    }
+
+    #[test]
+    fn test_restore_rejects_tampered_approvals() {
+        let keys = keys();
+        let mut controller = controller(&keys);
+        let id = controller
+            .propose(
+                MultisigOperation::CloseChannel {
+                    channel_id: [1u8; 32],
+                },
+                100,
+            )
+            .unwrap();
+        let sig = sign(&controller, &id, &keys[0]);
+        controller
+            .approve(&id, keys[0].verifying_key().to_bytes(), &sig, 101)
+            .unwrap();
+        let policy = controller.policy().clone();
+        let restore = |controller: &MultisigController| {
+            MultisigController::restore_pending(
+                [7u8; 32],
+                policy.clone(),
+                0,
+                &controller.export_pending().unwrap(),
+                200,
+            )
+        };
+
+        // An approval copied onto a second signer does not verify for that signer.
+        let mut forged = controller.clone();
+        let pending = forged.pending.get_mut(&id).unwrap();
+        let mut copied = pending.approvals[0].clone();
+        copied.signer = keys[1].verifying_key().to_bytes();
+        pending.approvals.push(copied);
+        assert!(restore(&forged).is_err());
+
+        // An outsider's valid signature is not an approval.
+        let outsider = SigningKey::from_bytes(&[9u8; 32]);
+        let mut foreign = controller.clone();
+        let signature = sign(&foreign, &id, &outsider);
+        foreign
+            .pending
+            .get_mut(&id)
+            .unwrap()
+            .approvals
+            .push(SignerApproval {
+                signer: outsider.verifying_key().to_bytes(),
+                signature,
+            });
+        assert!(restore(&foreign).is_err());
+
+        // A changed operation invalidates the approvals collected for it.
+        let mut swapped = controller.clone();
+        swapped.pending.get_mut(&id).unwrap().operation = MultisigOperation::CloseChannel {
+            channel_id: [2u8; 32],
+        };
+        assert!(restore(&swapped).is_err());
+
+        let restored = restore(&controller).unwrap();
+        assert_eq!(restored.pending(&id).unwrap().approval_count(), 1);
+        assert_eq!(restored.policy(), &policy);
+    }
+}
@@ -0,0 +1,611 @@
+// ./src/core/hierarchy/client/wallet_extension/recovery.rs
+
+// Wallet Recovery
+// Rebuilds a wallet's channel state from storage-node replicas after the device holding it
+// is lost. Replicas are untrusted: the wallet root must be signed by the wallet key, carry a
+// tree transition proof ending at it that verifies against a registered circuit, and be
+// included in the anchored intermediate root, which in turn must be included in the anchored
+// global root. Channel states must be signed by the wallet key and included in that wallet
+// root; the highest valid nonce is kept for each channel. Inclusion proofs are
+// `CompressedProof::to_bytes` encodings over the shared hierarchy tree.
+//
+// A record holds every field of the channel's `CommittedChannelState` except its `auth_key`,
+// which the wallet rederives from its seed and the nonce.
+//
+// Channel state BOC layout:
+//   cell 0 (root)  wallet_id (32) | channel_id (32) | balance (8) | nonce (8) | seqno (8)
+//                  | lock_root (32)
+//                  references = [1]
+//   cell 1 (sig)   ed25519 signature (64) over sha256 of the root cell data
+
+use crate::core::error::errors::{SystemError, SystemErrorType};
+use crate::core::hierarchy::client::wallet_extension::client_proof_exporter::WalletRootProof;
+use crate::core::smt::proof::CompressedProof;
+use crate::core::smt::HierarchyHasher;
+use crate::core::types::boc::{Cell, CellType, BOC};
+use crate::core::zkps::proof::ProofVerifier;
+use async_trait::async_trait;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+const STATE_LEN: usize = 32 + 32 + 8 + 8 + 8 + 32;
+const SIGNATURE_LEN: usize = 64;
+
+/// A signed snapshot of one channel, as replicated to storage nodes.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChannelStateRecord {
+    pub wallet_id: [u8; 32],
+    pub channel_id: [u8; 32],
+    pub balance: u64,
+    pub nonce: u64,
+    pub seqno: u64,
+    pub lock_root: [u8; 32],
+    pub signature: Vec<u8>,
+}
+
+impl ChannelStateRecord {
+    pub fn new(
+        wallet_id: [u8; 32],
+        channel_id: [u8; 32],
+        balance: u64,
+        nonce: u64,
+        seqno: u64,
+        lock_root: [u8; 32],
+    ) -> Self {
+        Self {
+            wallet_id,
+            channel_id,
+            balance,
+            nonce,
+            seqno,
+            lock_root,
+            signature: Vec::new(),
+        }
+    }
+
+    pub fn sign(&mut self, signing_key: &SigningKey) {
+        self.signature = signing_key.sign(&self.signing_hash()).to_bytes().to_vec();
+    }
+
+    pub fn signing_hash(&self) -> [u8; 32] {
+        Sha256::digest(self.encode_state()).into()
+    }
+
+    /// The value committed under `channel_id` in the wallet's state tree.
+    pub fn encode_state(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(STATE_LEN);
+        data.extend_from_slice(&self.wallet_id);
+        data.extend_from_slice(&self.channel_id);
+        data.extend_from_slice(&self.balance.to_le_bytes());
+        data.extend_from_slice(&self.nonce.to_le_bytes());
+        data.extend_from_slice(&self.seqno.to_le_bytes());
+        data.extend_from_slice(&self.lock_root);
+        data
+    }
+
+    pub fn to_boc(&self) -> Result<BOC, SystemError> {
+        if self.signature.len() != SIGNATURE_LEN {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidSignature,
+                "Channel state must be signed before export".to_string(),
+            ));
+        }
+
+        let mut boc = BOC::new();
+        let root_index = boc.add_cell(Cell::new(
+            self.encode_state(),
+            vec![1],
+            CellType::Ordinary,
+            self.signing_hash(),
+            None,
+        ));
+        let mut signature_cell = Cell::with_data(self.signature.clone());
+        signature_cell.update_merkle_hash();
+        boc.add_cell(signature_cell);
+        boc.add_root(root_index);
+        Ok(boc)
+    }
+
+    pub fn from_boc(boc: &BOC) -> Result<Self, SystemError> {
+        let root_cell = boc.get_root_cell().ok_or_else(|| {
+            SystemError::new(
+                SystemErrorType::NoRootCell,
+                "Channel state BOC has no root cell".to_string(),
+            )
+        })?;
+        let data = root_cell.get_data();
+        if data.len() != STATE_LEN || root_cell.references.len() != 1 {
+            return Err(invalid_format("Invalid channel state cell"));
+        }
+        let signature = boc
+            .get_cell(root_cell.references[0])
+            .ok_or_else(|| invalid_format("Missing signature cell"))?
+            .get_data();
+        if signature.len() != SIGNATURE_LEN {
+            return Err(invalid_format("Invalid signature length"));
+        }
+
+        let mut wallet_id = [0u8; 32];
+        wallet_id.copy_from_slice(&data[0..32]);
+        let mut channel_id = [0u8; 32];
+        channel_id.copy_from_slice(&data[32..64]);
+        let mut lock_root = [0u8; 32];
+        lock_root.copy_from_slice(&data[88..120]);
+
+        Ok(Self {
+            wallet_id,
+            channel_id,
+            balance: read_u64(data, 64),
+            nonce: read_u64(data, 72),
+            seqno: read_u64(data, 80),
+            lock_root,
+            signature: signature.clone(),
+        })
+    }
+
+    fn verify_signature(&self, key: &VerifyingKey) -> Result<(), SystemError> {
+        let signature = Signature::from_slice(&self.signature)
+            .map_err(|e| SystemError::new(SystemErrorType::InvalidSignature, e.to_string()))?;
+        key.verify(&self.signing_hash(), &signature).map_err(|_| {
+            SystemError::new(
+                SystemErrorType::InvalidSignature,
+                "Channel state signature verification failed".to_string(),
+            )
+        })
+    }
+}
+
+/// A channel state held by a replica, with its inclusion proof in the wallet tree.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChannelReplica {
+    pub state: BOC,
+    pub inclusion_proof: Vec<u8>,
+}
+
+/// Everything a storage node holds for one wallet.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WalletReplica {
+    /// `WalletRootProof` BOC as exported by the wallet.
+    pub wallet_root: BOC,
+    /// Proof of `wallet_id -> wallet_root` in the intermediate tree.
+    pub intermediate_proof: Vec<u8>,
+    /// Proof of `intermediate_id -> intermediate_root` in the global tree.
+    pub global_proof: Vec<u8>,
+    pub channels: Vec<ChannelReplica>,
+}
+
+/// Roots the recovered state must be anchored to, taken from the intermediate and root layers.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecoveryAnchor {
+    pub intermediate_id: [u8; 32],
+    pub intermediate_root: [u8; 32],
+    pub global_root: [u8; 32],
+}
+
+/// A source of wallet replicas, implemented by storage nodes.
+#[async_trait]
+pub trait ReplicaSource: Send + Sync {
+    async fn fetch_wallet_replica(
+        &self,
+        wallet_id: &[u8; 32],
+    ) -> Result<WalletReplica, SystemError>;
+}
+
+/// The verified state recovered for a wallet.
+#[derive(Clone, Debug)]
+pub struct RecoveredWallet {
+    pub wallet_id: [u8; 32],
+    pub wallet_root: [u8; 32],
+    pub wallet_nonce: u64,
+    pub channels: Vec<ChannelStateRecord>,
+    /// Replicas that failed to respond or whose data did not verify.
+    pub rejected_replicas: usize,
+}
+
+/// Queries every source and rebuilds the wallet's latest verifiable state. Wallet root proofs
+/// are verified against the circuits registered with `verifier`.
+pub async fn recover_wallet(
+    sources: &[&dyn ReplicaSource],
+    wallet_id: [u8; 32],
+    wallet_key: &VerifyingKey,
+    anchor: &RecoveryAnchor,
+    verifier: &ProofVerifier,
+) -> Result<RecoveredWallet, SystemError> {
+    let mut replicas = Vec::new();
+    let mut rejected_replicas = 0;
+    for source in sources {
+        match source.fetch_wallet_replica(&wallet_id).await {
+            Ok(replica) => replicas.push(replica),
+            Err(_) => rejected_replicas += 1,
+        }
+    }
+
+    let mut verified: Vec<(WalletRootProof, &WalletReplica)> = Vec::new();
+    for replica in &replicas {
+        match verify_wallet_root(replica, wallet_id, wallet_key, anchor, verifier) {
+            Ok(root) => verified.push((root, replica)),
+            Err(_) => rejected_replicas += 1,
+        }
+    }
+
+    let (wallet_root, _) = verified
+        .iter()
+        .max_by_key(|(root, _)| root.metadata.nonce)
+        .ok_or_else(|| {
+            SystemError::new(
+                SystemErrorType::NotFound,
+                "No replica holds a wallet root anchored to the given roots".to_string(),
+            )
+        })?;
+    let wallet_root = wallet_root.clone();
+
+    let mut channels: HashMap<[u8; 32], ChannelStateRecord> = HashMap::new();
+    for (root, replica) in &verified {
+        if root.wallet_root != wallet_root.wallet_root {
+            continue;
+        }
+        for channel in &replica.channels {
+            let record = match verify_channel(channel, wallet_id, wallet_key, &wallet_root) {
+                Ok(record) => record,
+                Err(_) => continue,
+            };
+            let newer = channels
+                .get(&record.channel_id)
+                .map_or(true, |current| record.nonce > current.nonce);
+            if newer {
+                channels.insert(record.channel_id, record);
+            }
+        }
+    }
+
+    let mut channels: Vec<ChannelStateRecord> = channels.into_values().collect();
+    channels.sort_by(|a, b| a.channel_id.cmp(&b.channel_id));
+
+    Ok(RecoveredWallet {
+        wallet_id,
+        wallet_root: wallet_root.wallet_root,
+        wallet_nonce: wallet_root.metadata.nonce,
+        channels,
+        rejected_replicas,
+    })
+}
+
+fn verify_wallet_root(
+    replica: &WalletReplica,
+    wallet_id: [u8; 32],
+    wallet_key: &VerifyingKey,
+    anchor: &RecoveryAnchor,
+    verifier: &ProofVerifier,
+) -> Result<WalletRootProof, SystemError> {
+    let root = WalletRootProof::import_proof_boc(&replica.wallet_root)?;
+    if root.metadata.wallet_id != wallet_id {
+        return Err(SystemError::new(
+            SystemErrorType::InvalidAddress,
+            "Replica wallet root belongs to another wallet".to_string(),
+        ));
+    }
+
+    let signature = Signature::from_slice(&root.signature)
+        .map_err(|e| SystemError::new(SystemErrorType::InvalidSignature, e.to_string()))?;
+    wallet_key
+        .verify(&root.signing_hash()?, &signature)
+        .map_err(|_| {
+            SystemError::new(
+                SystemErrorType::InvalidSignature,
+                "Wallet root signature verification failed".to_string(),
+            )
+        })?;
+    root.verify_proof(verifier)?;
+
+    verify_inclusion(
+        &wallet_id,
+        &root.wallet_root,
+        &replica.intermediate_proof,
+        &anchor.intermediate_root,
+    )?;
+    verify_inclusion(
+        &anchor.intermediate_id,
+        &anchor.intermediate_root,
+        &replica.global_proof,
+        &anchor.global_root,
+    )?;
+
+    Ok(root)
+}
+
+fn verify_channel(
+    channel: &ChannelReplica,
+    wallet_id: [u8; 32],
+    wallet_key: &VerifyingKey,
+    wallet_root: &WalletRootProof,
+) -> Result<ChannelStateRecord, SystemError> {
+    let record = ChannelStateRecord::from_boc(&channel.state)?;
+    if record.wallet_id != wallet_id {
+        return Err(SystemError::new(
+            SystemErrorType::InvalidAddress,
+            "Channel state belongs to another wallet".to_string(),
+        ));
+    }
+    record.verify_signature(wallet_key)?;
+    verify_inclusion(
+        &record.channel_id,
+        &record.encode_state(),
+        &channel.inclusion_proof,
+        &wallet_root.wallet_root,
+    )?;
+    Ok(record)
+}
+
+fn verify_inclusion(
+    key: &[u8; 32],
+    value: &[u8],
+    proof: &[u8],
+    expected_root: &[u8; 32],
+) -> Result<(), SystemError> {
+    let proof = CompressedProof::from_bytes::<HierarchyHasher>(proof)?;
+    if !proof.verify::<HierarchyHasher>(expected_root, key, value) {
+        return Err(SystemError::new(
+            SystemErrorType::InvalidProof,
+            "Merkle proof does not match the anchored root".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&data[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+fn invalid_format(message: &str) -> SystemError {
+    SystemError::new(SystemErrorType::InvalidTransaction, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::hierarchy::client::wallet_extension::channel_manager::{
+        ChannelConfig, ChannelManager,
+    };
+    use crate::core::smt::HierarchyTree;
+    use futures::executor::block_on;
+    use std::sync::OnceLock;
+
+    const WALLET_ID: [u8; 32] = [1u8; 32];
+    const INTERMEDIATE_ID: [u8; 32] = [2u8; 32];
+
+    struct MockNode(Option<WalletReplica>);
+
+    #[async_trait]
+    impl ReplicaSource for MockNode {
+        async fn fetch_wallet_replica(
+            &self,
+            _wallet_id: &[u8; 32],
+        ) -> Result<WalletReplica, SystemError> {
+            self.0.clone().ok_or_else(|| {
+                SystemError::new(SystemErrorType::NotFound, "No replica".to_string())
+            })
+        }
+    }
+
+    fn signed_channel(key: &SigningKey, channel: u8, nonce: u64) -> ChannelStateRecord {
+        let mut record =
+            ChannelStateRecord::new(WALLET_ID, [channel; 32], 1000 - nonce, nonce, 1, [4u8; 32]);
+        record.sign(key);
+        record
+    }
+
+    /// Builds a replica whose wallet root commits to `record` and returns it with its anchor
+    /// and the wallet tree it was built from.
+    fn replica(
+        key: &SigningKey,
+        record: &ChannelStateRecord,
+    ) -> (WalletReplica, RecoveryAnchor, HierarchyTree) {
+        let mut wallet_tree = HierarchyTree::new();
+        let mut root_proof = WalletRootProof::prove_channel_updates(
+            &mut wallet_tree,
+            std::slice::from_ref(record),
+            WALLET_ID,
+            record.nonce,
+            1_700_000_000,
+        )
+        .unwrap();
+        root_proof.sign(key).unwrap();
+        let wallet_root = root_proof.wallet_root;
+
+        let mut intermediate_tree = HierarchyTree::new();
+        intermediate_tree
+            .update(&[9u8; 32], b"other wallet")
+            .unwrap();
+        let intermediate_root = intermediate_tree.update(&WALLET_ID, &wallet_root).unwrap();
+        let mut global_tree = HierarchyTree::new();
+        let global_root = global_tree
+            .update(&INTERMEDIATE_ID, &intermediate_root)
+            .unwrap();
+
+        let replica = WalletReplica {
+            wallet_root: root_proof.export_proof_boc().unwrap(),
+            intermediate_proof: intermediate_tree
+                .prove_compressed(&WALLET_ID)
+                .unwrap()
+                .to_bytes(),
+            global_proof: global_tree
+                .prove_compressed(&INTERMEDIATE_ID)
+                .unwrap()
+                .to_bytes(),
+            channels: vec![ChannelReplica {
+                state: record.to_boc().unwrap(),
+                inclusion_proof: wallet_tree
+                    .prove_compressed(&record.channel_id)
+                    .unwrap()
+                    .to_bytes(),
+            }],
+        };
+        let anchor = RecoveryAnchor {
+            intermediate_id: INTERMEDIATE_ID,
+            intermediate_root,
+            global_root,
+        };
+        (replica, anchor, wallet_tree)
+    }
+
+    fn verifier() -> &'static ProofVerifier {
+        static VERIFIER: OnceLock<ProofVerifier> = OnceLock::new();
+        VERIFIER.get_or_init(|| ProofVerifier::with_system_circuits().unwrap())
+    }
+
+    #[test]
+    fn test_channel_state_boc_roundtrip() {
+        let key = SigningKey::from_bytes(&[5u8; 32]);
+        let record = signed_channel(&key, 3, 7);
+        let decoded = ChannelStateRecord::from_boc(&record.to_boc().unwrap()).unwrap();
+        assert_eq!(decoded, record);
+    }
+
+    #[test]
+    fn test_recovers_anchored_state_and_skips_stale_replica() {
+        let key = SigningKey::from_bytes(&[5u8; 32]);
+        let (stale, _, _) = replica(&key, &signed_channel(&key, 3, 4));
+        let (latest, anchor, _) = replica(&key, &signed_channel(&key, 3, 9));
+
+        let nodes = [
+            MockNode(Some(stale)),
+            MockNode(None),
+            MockNode(Some(latest)),
+        ];
+        let sources: Vec<&dyn ReplicaSource> =
+            nodes.iter().map(|n| n as &dyn ReplicaSource).collect();
+
+        let recovered = block_on(recover_wallet(
+            &sources,
+            WALLET_ID,
+            &key.verifying_key(),
+            &anchor,
+            verifier(),
+        ))
+        .unwrap();
+        assert_eq!(recovered.wallet_nonce, 9);
+        assert_eq!(recovered.channels.len(), 1);
+        assert_eq!(recovered.channels[0].nonce, 9);
+        assert_eq!(recovered.channels[0].balance, 991);
+        assert_eq!(recovered.rejected_replicas, 2);
+    }
+
+    #[test]
+    fn test_rejects_foreign_signatures() {
+        let key = SigningKey::from_bytes(&[5u8; 32]);
+        let attacker = SigningKey::from_bytes(&[6u8; 32]);
+        let (forged, anchor, _) = replica(&attacker, &signed_channel(&attacker, 3, 9));
+
+        let node = MockNode(Some(forged));
+        let sources: Vec<&dyn ReplicaSource> = vec![&node];
+        let result = block_on(recover_wallet(
+            &sources,
+            WALLET_ID,
+            &key.verifying_key(),
+            &anchor,
+            verifier(),
+        ));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_rejects_wallet_root_without_a_verifying_proof() {
+        let key = SigningKey::from_bytes(&[5u8; 32]);
+        let (mut replica, anchor, _) = replica(&key, &signed_channel(&key, 3, 9));
+        let mut root = WalletRootProof::import_proof_boc(&replica.wallet_root).unwrap();
+        root.proof.proof_data = vec![9u8; 48];
+        root.sign(&key).unwrap();
+        replica.wallet_root = root.export_proof_boc().unwrap();
+
+        let node = MockNode(Some(replica));
+        let sources: Vec<&dyn ReplicaSource> = vec![&node];
+        let result = block_on(recover_wallet(
+            &sources,
+            WALLET_ID,
+            &key.verifying_key(),
+            &anchor,
+            verifier(),
+        ));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_drops_channel_not_in_wallet_root() {
+        let key = SigningKey::from_bytes(&[5u8; 32]);
+        let (mut replica, anchor, wallet_tree) = replica(&key, &signed_channel(&key, 3, 9));
+        replica.channels.push(ChannelReplica {
+            state: signed_channel(&key, 8, 50).to_boc().unwrap(),
+            inclusion_proof: wallet_tree.prove_compressed(&[8u8; 32]).unwrap().to_bytes(),
+        });
+
+        let node = MockNode(Some(replica));
+        let sources: Vec<&dyn ReplicaSource> = vec![&node];
+        let recovered = block_on(recover_wallet(
+            &sources,
+            WALLET_ID,
+            &key.verifying_key(),
+            &anchor,
+            verifier(),
+        ))
+        .unwrap();
+        assert_eq!(recovered.channels.len(), 1);
+        assert_eq!(recovered.channels[0].channel_id, [3u8; 32]);
+    }
+
+    #[test]
+    fn test_recovered_wallet_resumes_channels() {
+        let key = SigningKey::from_bytes(&[5u8; 32]);
+        let seed = [6u8; 32];
+        let config = ChannelConfig {
+            timeout: 60,
+            min_balance: 0,
+            max_balance: u64::MAX,
+        };
+
+        // The lost device moved its channel on before replicating it.
+        let device = ChannelManager::with_wallet(WALLET_ID, seed, 10_000).unwrap();
+        let channel_id = device
+            .create_channel([1u8; 32], [2u8; 32], 1_000, &config)
+            .unwrap();
+        block_on(device.transfer(&channel_id, 100, [4u8; 32]).unwrap()).unwrap();
+        let state = device.channel_state(&channel_id).unwrap();
+
+        let mut record = ChannelStateRecord::new(
+            WALLET_ID,
+            channel_id,
+            state.balance,
+            state.nonce,
+            state.seqno,
+            state.lock_root,
+        );
+        record.sign(&key);
+        let (replica, anchor, _) = replica(&key, &record);
+        let node = MockNode(Some(replica));
+        let sources: Vec<&dyn ReplicaSource> = vec![&node];
+        let recovered = block_on(recover_wallet(
+            &sources,
+            WALLET_ID,
+            &key.verifying_key(),
+            &anchor,
+            verifier(),
+        ))
+        .unwrap();
+
+        let restored = ChannelManager::from_recovered(&recovered, seed, 10_000).unwrap();
+        assert_eq!(restored.channel_state(&channel_id).unwrap(), state);
+        block_on(restored.transfer(&channel_id, 50, [0u8; 32]).unwrap()).unwrap();
+        assert_eq!(restored.channel_state(&channel_id).unwrap().balance, 850);
+
+        // Another seed rebuilds a state that no proof of the channel starts from.
+        let foreign = ChannelManager::from_recovered(&recovered, [7u8; 32], 10_000).unwrap();
+        assert_ne!(foreign.state_hash(&channel_id).unwrap(), state.commitment());
+
+        let mut mixed = recovered.clone();
+        mixed.channels[0].wallet_id = [9u8; 32];
+        assert!(ChannelManager::from_recovered(&mixed, seed, 10_000).is_err());
+    }
+}
@@ -0,0 +1,521 @@
+// ./src/core/hierarchy/client/wallet_extension/transaction_history.rs
+
+// Transaction History
+// Wallet-level ledger of every payment made or received through the wallet's channels.
+// Entries are recorded as channels process transactions and can be queried page by page,
+// filtered by channel, group, time range and status, and exported as CSV or JSON.
+
+use crate::core::error::errors::{SystemError, SystemErrorType};
+use crate::core::hierarchy::client::wallet_extension::grouping::GroupingManager;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PaymentStatus {
+    Pending,
+    Confirmed,
+    Failed,
+}
+
+impl PaymentStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PaymentStatus::Pending => "pending",
+            PaymentStatus::Confirmed => "confirmed",
+            PaymentStatus::Failed => "failed",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PaymentDirection {
+    Outgoing,
+    Incoming,
+}
+
+impl PaymentDirection {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PaymentDirection::Outgoing => "outgoing",
+            PaymentDirection::Incoming => "incoming",
+        }
+    }
+}
+
+/// A single payment in the wallet ledger.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LedgerEntry {
+    pub id: [u8; 32],
+    pub channel_id: [u8; 32],
+    pub counterparty: [u8; 32],
+    pub asset: String,
+    pub amount: u64,
+    pub fee: u64,
+    pub direction: PaymentDirection,
+    pub status: PaymentStatus,
+    pub nonce: u64,
+    pub balance_after: u64,
+    /// Number of times the payment was submitted; failed payments can be retried.
+    pub attempts: u32,
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+impl LedgerEntry {
+    /// Creates a pending entry. The id is derived from the channel, direction and nonce,
+    /// so the same channel update is recorded once; retries of a failed update reuse it.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        channel_id: [u8; 32],
+        counterparty: [u8; 32],
+        asset: &str,
+        amount: u64,
+        fee: u64,
+        direction: PaymentDirection,
+        nonce: u64,
+        timestamp: u64,
+    ) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(channel_id);
+        hasher.update([direction as u8]);
+        hasher.update(nonce.to_le_bytes());
+
+        Self {
+            id: hasher.finalize().into(),
+            channel_id,
+            counterparty,
+            asset: asset.to_string(),
+            amount,
+            fee,
+            direction,
+            status: PaymentStatus::Pending,
+            nonce,
+            balance_after: 0,
+            attempts: 1,
+            created_at: timestamp,
+            updated_at: timestamp,
+        }
+    }
+}
+
+/// Criteria for selecting ledger entries. Unset fields match everything.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct HistoryFilter {
+    pub channel_id: Option<[u8; 32]>,
+    pub group: Option<String>,
+    pub from_timestamp: Option<u64>,
+    pub to_timestamp: Option<u64>,
+    pub status: Option<PaymentStatus>,
+}
+
+/// One page of query results, newest entries first.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HistoryPage {
+    pub entries: Vec<LedgerEntry>,
+    pub page: usize,
+    pub page_size: usize,
+    pub total: usize,
+}
+
+impl HistoryPage {
+    pub fn has_next(&self) -> bool {
+        (self.page + 1) * self.page_size < self.total
+    }
+}
+
+/// Aggregated figures for one channel.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChannelSummary {
+    pub transaction_count: u64,
+    pub total_sent: u64,
+    pub total_received: u64,
+    pub total_fees: u64,
+    pub balance: u64,
+}
+
+/// Wallet-level payment ledger.
+#[derive(Clone)]
+pub struct TransactionLedger {
+    entries: Vec<LedgerEntry>,
+    index: HashMap<[u8; 32], usize>,
+    groups: GroupingManager,
+}
+
+impl TransactionLedger {
+    pub fn new(groups: GroupingManager) -> Self {
+        Self {
+            entries: Vec::new(),
+            index: HashMap::new(),
+            groups,
+        }
+    }
+
+    /// Records a new entry and returns its id. If an earlier attempt at the same channel
+    /// update failed, that entry is reopened as pending with the retry's details instead.
+    pub fn record(&mut self, entry: LedgerEntry) -> Result<[u8; 32], SystemError> {
+        if let Some(&position) = self.index.get(&entry.id) {
+            let existing = &mut self.entries[position];
+            if existing.status != PaymentStatus::Failed {
+                return Err(SystemError::new(
+                    SystemErrorType::InvalidTransaction,
+                    "Payment already recorded".to_string(),
+                ));
+            }
+            *existing = LedgerEntry {
+                attempts: existing.attempts + 1,
+                created_at: existing.created_at,
+                ..entry
+            };
+            return Ok(existing.id);
+        }
+
+        let id = entry.id;
+        self.index.insert(id, self.entries.len());
+        self.entries.push(entry);
+        Ok(id)
+    }
+
+    /// Moves an entry to a new status, recording the balance the channel settled at.
+    pub fn update_status(
+        &mut self,
+        id: &[u8; 32],
+        status: PaymentStatus,
+        balance_after: u64,
+        timestamp: u64,
+    ) -> Result<(), SystemError> {
+        let position = *self.index.get(id).ok_or_else(|| {
+            SystemError::new(SystemErrorType::NotFound, "Payment not found".to_string())
+        })?;
+
+        let entry = &mut self.entries[position];
+        if entry.status != PaymentStatus::Pending {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidOperation,
+                "Payment already finalized".to_string(),
+            ));
+        }
+
+        entry.status = status;
+        entry.balance_after = balance_after;
+        entry.updated_at = timestamp;
+        Ok(())
+    }
+
+    pub fn get(&self, id: &[u8; 32]) -> Option<&LedgerEntry> {
+        self.index.get(id).map(|&position| &self.entries[position])
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the requested page (0-based) of entries matching the filter.
+    pub fn query(
+        &self,
+        filter: &HistoryFilter,
+        page: usize,
+        page_size: usize,
+    ) -> Result<HistoryPage, SystemError> {
+        if page_size == 0 {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidOperation,
+                "Page size must be greater than zero".to_string(),
+            ));
+        }
+
+        let matching = self.select(filter)?;
+        let total = matching.len();
+        let entries = matching
+            .into_iter()
+            .skip(page.saturating_mul(page_size))
+            .take(page_size)
+            .cloned()
+            .collect();
+
+        Ok(HistoryPage {
+            entries,
+            page,
+            page_size,
+            total,
+        })
+    }
+
+    /// Summarizes confirmed activity on a channel.
+    pub fn channel_summary(&self, channel_id: &[u8; 32]) -> ChannelSummary {
+        let mut summary = ChannelSummary::default();
+        for entry in self
+            .entries
+            .iter()
+            .filter(|e| &e.channel_id == channel_id && e.status == PaymentStatus::Confirmed)
+        {
+            summary.transaction_count += 1;
+            summary.total_fees = summary.total_fees.saturating_add(entry.fee);
+            match entry.direction {
+                PaymentDirection::Outgoing => {
+                    summary.total_sent = summary.total_sent.saturating_add(entry.amount)
+                }
+                PaymentDirection::Incoming => {
+                    summary.total_received = summary.total_received.saturating_add(entry.amount)
+                }
+            }
+            summary.balance = entry.balance_after;
+        }
+        summary
+    }
+
+    /// Exports the entries matching the filter as CSV, newest first.
+    pub fn export_csv(&self, filter: &HistoryFilter) -> Result<String, SystemError> {
+        let mut csv = String::from(
+            "id,channel_id,counterparty,asset,amount,fee,direction,status,nonce,balance_after,attempts,created_at,updated_at\n",
+        );
+        for entry in self.select(filter)? {
+            csv.push_str(&format!(
+                "{},{},{},{},{},{},{},{},{},{},{},{},{}\n",
+                hex::encode(entry.id),
+                hex::encode(entry.channel_id),
+                hex::encode(entry.counterparty),
+                escape_csv(&entry.asset),
+                entry.amount,
+                entry.fee,
+                entry.direction.as_str(),
+                entry.status.as_str(),
+                entry.nonce,
+                entry.balance_after,
+                entry.attempts,
+                entry.created_at,
+                entry.updated_at,
+            ));
+        }
+        Ok(csv)
+    }
+
+    /// Exports the entries matching the filter as a JSON array, newest first.
+    pub fn export_json(&self, filter: &HistoryFilter) -> Result<String, SystemError> {
+        let entries = self.select(filter)?;
+        serde_json::to_string(&entries)
+            .map_err(|e| SystemError::new(SystemErrorType::InvalidOperation, e.to_string()))
+    }
+
+    fn select(&self, filter: &HistoryFilter) -> Result<Vec<&LedgerEntry>, SystemError> {
+        let group_channels: Option<HashSet<[u8; 32]>> = match &filter.group {
+            Some(group) => Some(self.groups.get_group_channels(group)?.into_iter().collect()),
+            None => None,
+        };
+
+        let mut matching: Vec<&LedgerEntry> = self
+            .entries
+            .iter()
+            .filter(|e| filter.channel_id.map_or(true, |id| e.channel_id == id))
+            .filter(|e| {
+                group_channels
+                    .as_ref()
+                    .map_or(true, |channels| channels.contains(&e.channel_id))
+            })
+            .filter(|e| {
+                filter
+                    .from_timestamp
+                    .map_or(true, |from| e.created_at >= from)
+            })
+            .filter(|e| filter.to_timestamp.map_or(true, |to| e.created_at <= to))
+            .filter(|e| filter.status.map_or(true, |status| e.status == status))
+            .collect();
+
+        // Entries are appended in processing order, so a stable sort keeps ties newest-last;
+        // reverse afterwards to list the newest first.
+        matching.sort_by_key(|e| e.created_at);
+        matching.reverse();
+        Ok(matching)
+    }
+}
+
+fn escape_csv(value: &str) -> String {
+    if value.contains(',') || value.contains('"') || value.contains('\n') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn confirmed_entry(
+        ledger: &mut TransactionLedger,
+        channel_id: [u8; 32],
+        nonce: u64,
+        amount: u64,
+        timestamp: u64,
+    ) -> [u8; 32] {
+        let entry = LedgerEntry::new(
+            channel_id,
+            [9u8; 32],
+            "TON",
+            amount,
+            1,
+            PaymentDirection::Outgoing,
+            nonce,
+            timestamp,
+        );
+        let id = ledger.record(entry).unwrap();
+        ledger
+            .update_status(&id, PaymentStatus::Confirmed, 1000 - amount, timestamp)
+            .unwrap();
+        id
+    }
+
+    #[test]
+    fn test_record_and_paginate() {
+        let mut ledger = TransactionLedger::new(GroupingManager::new());
+        for nonce in 1..=5 {
+            confirmed_entry(&mut ledger, [1u8; 32], nonce, 10, 100 + nonce);
+        }
+
+        let filter = HistoryFilter::default();
+        let first = ledger.query(&filter, 0, 2).unwrap();
+        assert_eq!(first.total, 5);
+        assert_eq!(first.entries.len(), 2);
+        assert_eq!(first.entries[0].created_at, 105);
+        assert!(first.has_next());
+
+        let last = ledger.query(&filter, 2, 2).unwrap();
+        assert_eq!(last.entries.len(), 1);
+        assert_eq!(last.entries[0].created_at, 101);
+        assert!(!last.has_next());
+    }
+
+    #[test]
+    fn test_duplicate_entry_rejected() {
+        let mut ledger = TransactionLedger::new(GroupingManager::new());
+        let entry = LedgerEntry::new(
+            [1u8; 32],
+            [2u8; 32],
+            "TON",
+            10,
+            0,
+            PaymentDirection::Outgoing,
+            1,
+            100,
+        );
+        assert!(ledger.record(entry.clone()).is_ok());
+        assert!(ledger.record(entry).is_err());
+    }
+
+    #[test]
+    fn test_failed_entry_reopened_on_retry() {
+        let mut ledger = TransactionLedger::new(GroupingManager::new());
+        let entry = |amount, timestamp| {
+            LedgerEntry::new(
+                [1u8; 32],
+                [2u8; 32],
+                "TON",
+                amount,
+                0,
+                PaymentDirection::Outgoing,
+                1,
+                timestamp,
+            )
+        };
+        let id = ledger.record(entry(10, 100)).unwrap();
+        ledger
+            .update_status(&id, PaymentStatus::Failed, 1000, 101)
+            .unwrap();
+
+        assert_eq!(ledger.record(entry(8, 200)).unwrap(), id);
+        assert_eq!(ledger.len(), 1);
+        let retried = ledger.get(&id).unwrap();
+        assert_eq!(retried.status, PaymentStatus::Pending);
+        assert_eq!(retried.amount, 8);
+        assert_eq!(retried.attempts, 2);
+        assert_eq!(retried.created_at, 100);
+        assert_eq!(retried.updated_at, 200);
+
+        ledger
+            .update_status(&id, PaymentStatus::Confirmed, 992, 201)
+            .unwrap();
+        assert!(ledger.record(entry(8, 300)).is_err());
+    }
+
+    #[test]
+    fn test_filters() {
+        let groups = GroupingManager::new();
+        groups.create_group("savings").unwrap();
+        groups.add_channel_to_group("savings", &[2u8; 32]).unwrap();
+
+        let mut ledger = TransactionLedger::new(groups);
+        confirmed_entry(&mut ledger, [1u8; 32], 1, 10, 100);
+        confirmed_entry(&mut ledger, [2u8; 32], 1, 20, 200);
+        let pending = LedgerEntry::new(
+            [2u8; 32],
+            [9u8; 32],
+            "TON",
+            30,
+            0,
+            PaymentDirection::Outgoing,
+            2,
+            300,
+        );
+        ledger.record(pending).unwrap();
+
+        let by_channel = HistoryFilter {
+            channel_id: Some([1u8; 32]),
+            ..Default::default()
+        };
+        assert_eq!(ledger.query(&by_channel, 0, 10).unwrap().total, 1);
+
+        let by_group = HistoryFilter {
+            group: Some("savings".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(ledger.query(&by_group, 0, 10).unwrap().total, 2);
+
+        let by_time = HistoryFilter {
+            from_timestamp: Some(150),
+            to_timestamp: Some(250),
+            ..Default::default()
+        };
+        assert_eq!(ledger.query(&by_time, 0, 10).unwrap().total, 1);
+
+        let by_status = HistoryFilter {
+            status: Some(PaymentStatus::Pending),
+            ..Default::default()
+        };
+        assert_eq!(ledger.query(&by_status, 0, 10).unwrap().total, 1);
+
+        let unknown_group = HistoryFilter {
+            group: Some("missing".to_string()),
+            ..Default::default()
+        };
+        assert!(ledger.query(&unknown_group, 0, 10).is_err());
+    }
+
+    #[test]
+    fn test_channel_summary_and_export() {
+        let mut ledger = TransactionLedger::new(GroupingManager::new());
+        confirmed_entry(&mut ledger, [1u8; 32], 1, 10, 100);
+        confirmed_entry(&mut ledger, [1u8; 32], 2, 20, 200);
+
+        let summary = ledger.channel_summary(&[1u8; 32]);
+        assert_eq!(summary.transaction_count, 2);
+        assert_eq!(summary.total_sent, 30);
+        assert_eq!(summary.total_fees, 2);
+        assert_eq!(summary.balance, 980);
+
+        let csv = ledger.export_csv(&HistoryFilter::default()).unwrap();
+        assert_eq!(csv.lines().count(), 3);
+        assert!(csv
+            .lines()
+            .nth(1)
+            .unwrap()
+            .contains(",TON,20,1,outgoing,confirmed,"));
+
+        let json = ledger.export_json(&HistoryFilter::default()).unwrap();
+        let parsed: Vec<LedgerEntry> = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.len(), 2);
+    }
+}
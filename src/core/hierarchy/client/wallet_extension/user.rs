@@ -1,20 +1,176 @@
 // ./src/core/hierarchy/client/wallet_extension/user.rs
-use crate::core::types::boc::BOC;
+use crate::core::error::errors::{SystemError, SystemErrorType};
+use crate::core::hierarchy::client::channel::channel_contract::{ChannelContract, Transaction};
+use crate::core::hierarchy::client::wallet_extension::grouping::GroupingManager;
+use crate::core::hierarchy::client::wallet_extension::transaction_history::{
+    ChannelSummary, HistoryFilter, HistoryPage, LedgerEntry, PaymentDirection, PaymentStatus,
+    TransactionLedger,
+};
 use std::collections::HashSet;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsValue;
-use web_sys::{Blob, Url};
 
 // This is a struct that represents a user (private)
 #[wasm_bindgen]
 pub struct User {
-    pub name: String,
-    pub channels: HashSet<[u8; 32]>,
+    name: String,
+    channels: HashSet<[u8; 32]>,
+    groups: GroupingManager,
+    ledger: TransactionLedger,
 }
 
 impl User {
     pub fn new(name: String, channels: HashSet<[u8; 32]>) -> Self {
-        Self { name, channels }
+        let groups = GroupingManager::new();
+        Self {
+            name,
+            channels,
+            ledger: TransactionLedger::new(groups.clone()),
+            groups,
+        }
+    }
+
+    pub fn add_channel(&mut self, channel_id: [u8; 32]) {
+        self.channels.insert(channel_id);
+    }
+
+    pub fn channel_ids(&self) -> Vec<[u8; 32]> {
+        self.channels.iter().cloned().collect()
+    }
+
+    /// Channel groups used by the group history filter.
+    pub fn groups(&self) -> &GroupingManager {
+        &self.groups
+    }
+
+    pub fn ledger(&self) -> &TransactionLedger {
+        &self.ledger
+    }
+
+    /// Processes an outgoing payment on one of the user's channels and records it in the ledger.
+    /// Failed payments are recorded as well, with the channel balance left unchanged, and a
+    /// retry of the same channel update reopens the failed entry.
+    pub fn process_channel_transaction(
+        &mut self,
+        channel_id: [u8; 32],
+        channel: &mut ChannelContract,
+        tx: &Transaction,
+        counterparty: [u8; 32],
+        asset: &str,
+        fee: u64,
+    ) -> Result<Box<[u8]>, SystemError> {
+        let entry_id = self.record_pending(
+            channel_id,
+            counterparty,
+            asset,
+            tx.amount(),
+            fee,
+            PaymentDirection::Outgoing,
+            tx.nonce(),
+        )?;
+
+        match channel.try_process_transaction(tx) {
+            Ok(state_boc) => {
+                self.ledger.update_status(
+                    &entry_id,
+                    PaymentStatus::Confirmed,
+                    channel.balance(),
+                    current_timestamp(),
+                )?;
+                Ok(state_boc)
+            }
+            Err(e) => {
+                self.ledger.update_status(
+                    &entry_id,
+                    PaymentStatus::Failed,
+                    channel.balance(),
+                    current_timestamp(),
+                )?;
+                Err(SystemError::new(
+                    SystemErrorType::InvalidTransaction,
+                    e.to_string(),
+                ))
+            }
+        }
+    }
+
+    /// Records a payment received from a counterparty once the channel has accepted it.
+    #[allow(clippy::too_many_arguments)]
+    pub fn record_incoming_payment(
+        &mut self,
+        channel_id: [u8; 32],
+        counterparty: [u8; 32],
+        asset: &str,
+        amount: u64,
+        fee: u64,
+        nonce: u64,
+        balance_after: u64,
+    ) -> Result<[u8; 32], SystemError> {
+        let entry_id = self.record_pending(
+            channel_id,
+            counterparty,
+            asset,
+            amount,
+            fee,
+            PaymentDirection::Incoming,
+            nonce,
+        )?;
+        self.ledger.update_status(
+            &entry_id,
+            PaymentStatus::Confirmed,
+            balance_after,
+            current_timestamp(),
+        )?;
+        Ok(entry_id)
+    }
+
+    pub fn query_history(
+        &self,
+        filter: &HistoryFilter,
+        page: usize,
+        page_size: usize,
+    ) -> Result<HistoryPage, SystemError> {
+        self.ledger.query(filter, page, page_size)
+    }
+
+    pub fn channel_summary(&self, channel_id: &[u8; 32]) -> Result<ChannelSummary, SystemError> {
+        self.ensure_channel(channel_id)?;
+        Ok(self.ledger.channel_summary(channel_id))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn record_pending(
+        &mut self,
+        channel_id: [u8; 32],
+        counterparty: [u8; 32],
+        asset: &str,
+        amount: u64,
+        fee: u64,
+        direction: PaymentDirection,
+        nonce: u64,
+    ) -> Result<[u8; 32], SystemError> {
+        self.ensure_channel(&channel_id)?;
+        let entry = LedgerEntry::new(
+            channel_id,
+            counterparty,
+            asset,
+            amount,
+            fee,
+            direction,
+            nonce,
+            current_timestamp(),
+        );
+        self.ledger.record(entry)
+    }
+
+    fn ensure_channel(&self, channel_id: &[u8; 32]) -> Result<(), SystemError> {
+        if !self.channels.contains(channel_id) {
+            return Err(SystemError::new(
+                SystemErrorType::NotFound,
+                "Channel not found".to_string(),
+            ));
+        }
+        Ok(())
     }
 }
 
@@ -24,38 +180,183 @@ impl User {
         self.name.clone()
     }
 
-    pub fn get_channels(&self) -> HashSet<[u8; 32]> {
-        self.channels.clone()
-    }
-
     pub fn get_channel_count(&self) -> usize {
         self.channels.len()
     }
 
-    pub fn get_channel_ids(&self) -> Vec<[u8; 32]> {
-        self.channels.iter().cloned().collect()
+    pub fn get_channel_ids(&self) -> Result<JsValue, JsValue> {
+        serde_wasm_bindgen::to_value(&self.channel_ids()).map_err(to_js_error)
     }
 
-    pub fn get_channel_names(&self) -> Vec<String> {
-        self.channels
-            .iter()
-            .map(|channel_id| self.get_channel_name(channel_id))
-            .collect()
+    pub fn get_channel_balance(&self, channel_id: &[u8]) -> Result<u64, JsValue> {
+        let channel_id = parse_channel_id(channel_id)?;
+        self.channel_summary(&channel_id)
+            .map(|summary| summary.balance)
+            .map_err(to_js_error)
     }
 
-    pub fn get_channel_name(&self, channel_id: &[u8; 32]) -> String {
-        let channel = self.channels.get(channel_id).unwrap();
-        format!("{:?}", channel)
+    pub fn get_channel_transaction_count(&self, channel_id: &[u8]) -> Result<u64, JsValue> {
+        let channel_id = parse_channel_id(channel_id)?;
+        self.channel_summary(&channel_id)
+            .map(|summary| summary.transaction_count)
+            .map_err(to_js_error)
     }
 
-    pub fn get_channel_balance(&self, channel_id: &[u8; 32]) -> u64 {
-        let channel = self.channels.get(channel_id).unwrap();
-        format!("{:?}", channel)
+    /// Returns a page of history; `filter` is a `HistoryFilter` object or undefined.
+    #[wasm_bindgen(js_name = queryHistory)]
+    pub fn query_history_js(
+        &self,
+        filter: JsValue,
+        page: usize,
+        page_size: usize,
+    ) -> Result<JsValue, JsValue> {
+        let filter = parse_filter(filter)?;
+        let page = self
+            .query_history(&filter, page, page_size)
+            .map_err(to_js_error)?;
+        serde_wasm_bindgen::to_value(&page).map_err(to_js_error)
+    }
+
+    #[wasm_bindgen(js_name = exportHistoryCsv)]
+    pub fn export_history_csv(&self, filter: JsValue) -> Result<String, JsValue> {
+        let filter = parse_filter(filter)?;
+        self.ledger.export_csv(&filter).map_err(to_js_error)
+    }
+
+    #[wasm_bindgen(js_name = exportHistoryJson)]
+    pub fn export_history_json(&self, filter: JsValue) -> Result<String, JsValue> {
+        let filter = parse_filter(filter)?;
+        self.ledger.export_json(&filter).map_err(to_js_error)
+    }
+}
+
+fn parse_channel_id(bytes: &[u8]) -> Result<[u8; 32], JsValue> {
+    bytes
+        .try_into()
+        .map_err(|_| JsValue::from_str("Channel id must be 32 bytes long"))
+}
+
+fn parse_filter(filter: JsValue) -> Result<HistoryFilter, JsValue> {
+    if filter.is_undefined() || filter.is_null() {
+        return Ok(HistoryFilter::default());
+    }
+    serde_wasm_bindgen::from_value(filter).map_err(to_js_error)
+}
+
+fn to_js_error<E: std::fmt::Display>(error: E) -> JsValue {
+    JsValue::from_str(&format!("Error: {}", error))
+}
+
+/// Seconds since the Unix epoch.
+#[cfg(not(target_arch = "wasm32"))]
+fn current_timestamp() -> u64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Seconds since the Unix epoch. `SystemTime::now` panics on wasm.
+#[cfg(target_arch = "wasm32")]
+fn current_timestamp() -> u64 {
+    (js_sys::Date::now() / 1000.0) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHANNEL_ID: [u8; 32] = [1u8; 32];
+    const COUNTERPARTY: [u8; 32] = [2u8; 32];
+
+    fn user_with_channel(balance: u64) -> (User, ChannelContract) {
+        let user = User::new("alice".to_string(), HashSet::from([CHANNEL_ID]));
+        let mut channel = ChannelContract::new("channel");
+        channel.try_update_balance(balance).unwrap();
+        (user, channel)
+    }
+
+    fn pay(user: &mut User, channel: &mut ChannelContract, tx: &Transaction) -> bool {
+        user.process_channel_transaction(CHANNEL_ID, channel, tx, COUNTERPARTY, "TON", 1)
+            .is_ok()
+    }
+
+    #[test]
+    fn test_records_confirmed_and_incoming_payments() {
+        let (mut user, mut channel) = user_with_channel(1000);
+        assert!(pay(
+            &mut user,
+            &mut channel,
+            &Transaction::new("channel", 1, 1, 100)
+        ));
+        user.record_incoming_payment(CHANNEL_ID, COUNTERPARTY, "TON", 50, 0, 1, 950)
+            .unwrap();
+
+        let summary = user.channel_summary(&CHANNEL_ID).unwrap();
+        assert_eq!(summary.transaction_count, 2);
+        assert_eq!(summary.total_sent, 100);
+        assert_eq!(summary.total_received, 50);
+        assert_eq!(summary.balance, 950);
+        assert_eq!(user.ledger().len(), 2);
+    }
+
+    #[test]
+    fn test_failed_payment_can_be_retried() {
+        let (mut user, mut channel) = user_with_channel(1000);
+
+        // Above the spending limit: recorded as failed, balance unchanged
+        assert!(!pay(
+            &mut user,
+            &mut channel,
+            &Transaction::new("channel", 1, 1, 600)
+        ));
+        let failed = HistoryFilter {
+            status: Some(PaymentStatus::Failed),
+            ..Default::default()
+        };
+        let page = user.query_history(&failed, 0, 10).unwrap();
+        assert_eq!(page.total, 1);
+        assert_eq!(page.entries[0].balance_after, 1000);
+
+        // The same channel update succeeds on retry and reuses the entry
+        assert!(pay(
+            &mut user,
+            &mut channel,
+            &Transaction::new("channel", 1, 1, 200)
+        ));
+        assert_eq!(user.ledger().len(), 1);
+        let entry = &user
+            .query_history(&HistoryFilter::default(), 0, 10)
+            .unwrap()
+            .entries[0];
+        assert_eq!(entry.status, PaymentStatus::Confirmed);
+        assert_eq!(entry.amount, 200);
+        assert_eq!(entry.attempts, 2);
+        assert_eq!(entry.balance_after, 800);
+
+        // A confirmed update cannot be recorded again
+        assert!(!pay(
+            &mut user,
+            &mut channel,
+            &Transaction::new("channel", 1, 1, 200)
+        ));
+        assert_eq!(channel.balance(), 800);
     }
 
-    pub fn get_channel_transaction_count(&self, channel_id: &[u8; 32]) -> u64 {
-        let channel = self.channels.get(channel_id).unwrap();
-        format!("{:?}", channel)
+    #[test]
+    fn test_rejects_unknown_channel() {
+        let (mut user, mut channel) = user_with_channel(1000);
+        let result = user.process_channel_transaction(
+            [9u8; 32],
+            &mut channel,
+            &Transaction::new("channel", 1, 1, 100),
+            COUNTERPARTY,
+            "TON",
+            0,
+        );
+        assert!(result.is_err());
+        assert!(user.ledger().is_empty());
+        assert_eq!(channel.balance(), 1000);
     }
 }
-// This is a function that returns the name of the user
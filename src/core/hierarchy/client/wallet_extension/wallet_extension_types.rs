@@ -1,14 +1,18 @@
-use crate::core::error::errors::Error;
-use crate::core::hierarchy::client::channel::channel_contract::ChannelContract;
+use crate::core::error::errors::{Error, SystemError, SystemErrorType};
+use crate::core::hierarchy::client::channel::channel_contract::{self, ChannelContract};
+use crate::core::hierarchy::client::wallet_extension::multisig::{
+    MultisigController, MultisigOperation,
+};
 use crate::core::hierarchy::client::wallet_extension::sparse_merkle_tree_wasm::SparseMerkleTreeWasm;
 use crate::core::hierarchy::client::wallet_extension::wallet_extension_contract::ByteArray32;
 use crate::core::types::boc::BOC;
 use crate::core::types::WalletExtensionStateChangeOp;
 use crate::core::zkps::plonky2::Plonky2SystemHandle;
-use crate::core::zkps::proof::ZkProof;
+use crate::core::zkps::proof::{ProofType, ZkProof};
 use serde::{Deserialize, Serialize};
 
 use ed25519_dalek::Signature;
+use sha2::{Digest, Sha256};
 use std::collections::HashMap;
 use std::fmt;
 use std::sync::{Arc, RwLock};
@@ -147,7 +151,14 @@ impl Default for ChannelClosureRequest {
             channel_id: [0; 32],
             final_balance: 0,
             boc: Vec::new(),
-            proof: ZkProof::new(Vec::new(), Vec::new(), Vec::new(), 0),
+            proof: ZkProof::new(
+                ProofType::StateTransition,
+                [0u8; 32],
+                Vec::new(),
+                Vec::new(),
+                Vec::new(),
+                0,
+            ),
             signature: Vec::new(),
             timestamp: 0,
             merkle_proof: Vec::new(),
@@ -167,6 +178,9 @@ pub struct WalletExtension {
     pub root_hash: [u8; 32],
     pub balance: u64,
     pub encrypted_states: HashMap<[u8; 32], Vec<u8>>,
+    pub multisig: Option<MultisigController>,
+    /// Counterparty each channel pays out to, keyed by channel id.
+    pub counterparties: HashMap<[u8; 32], [u8; 32]>,
 }
 
 impl fmt::Debug for WalletExtension {
@@ -181,10 +195,180 @@ impl fmt::Debug for WalletExtension {
             .field("root_hash", &self.root_hash)
             .field("balance", &self.balance)
             .field("encrypted_states", &"<encrypted_states>")
+            .field("multisig", &self.multisig)
+            .field("counterparties", &self.counterparties)
             .finish()
     }
 }
 
+impl WalletExtension {
+    /// A single-owner wallet holding `balance` outside its channels.
+    pub fn new(wallet_id: [u8; 32], balance: u64) -> Self {
+        Self {
+            wallet_id,
+            channels: HashMap::new(),
+            total_locked_balance: 0,
+            rebalance_config: RebalanceConfig::default(),
+            proof_system: Arc::new(PlonkySystemHandleWrapper::default()),
+            state_tree: Arc::new(SparseMerkleTreeWasm::new()),
+            root_hash: [0u8; 32],
+            balance,
+            encrypted_states: HashMap::new(),
+            multisig: None,
+            counterparties: HashMap::new(),
+        }
+    }
+
+    /// Opens a channel to `counterparty` funded from the wallet balance and returns its id.
+    /// Multisig wallets need `approved_id` to name an approved matching proposal.
+    pub fn create_channel(
+        &mut self,
+        counterparty: [u8; 32],
+        initial_balance: u64,
+        approved_id: Option<&[u8; 32]>,
+        now: u64,
+    ) -> Result<[u8; 32], SystemError> {
+        let mut hasher = Sha256::new();
+        hasher.update(self.wallet_id);
+        hasher.update(counterparty);
+        hasher.update(initial_balance.to_le_bytes());
+        hasher.update(now.to_le_bytes());
+        let channel_id: [u8; 32] = hasher.finalize().into();
+        if self.channels.contains_key(&channel_id) {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidOperation,
+                "Channel already exists".to_string(),
+            ));
+        }
+        if initial_balance > self.balance {
+            return Err(SystemError::new(
+                SystemErrorType::InsufficientBalance,
+                "Wallet balance does not cover the channel deposit".to_string(),
+            ));
+        }
+
+        let approval = self.authorize_operation(
+            &MultisigOperation::CreateChannel {
+                counterparty,
+                initial_balance,
+            },
+            approved_id,
+            now,
+        )?;
+
+        let mut channel = ChannelContract::new(&hex::encode(channel_id));
+        channel
+            .try_update_balance(initial_balance)
+            .map_err(|_| invalid_amount())?;
+        self.complete_operation(approval, now)?;
+        self.channels
+            .insert(channel_id, Arc::new(RwLock::new(channel)));
+        self.counterparties.insert(channel_id, counterparty);
+        self.balance -= initial_balance;
+        self.total_locked_balance += initial_balance;
+        Ok(channel_id)
+    }
+
+    /// Pays `tx.amount()` to `recipient` over one of the wallet's channels and returns the new
+    /// channel state BOC. `tx` must be sent by the channel and `recipient` must be the
+    /// channel's counterparty. Payments above the multisig payment threshold need an approved
+    /// matching proposal, which is consumed only once the channel accepts the transaction.
+    pub fn process_payment(
+        &mut self,
+        channel_id: &[u8; 32],
+        recipient: [u8; 32],
+        tx: &channel_contract::Transaction,
+        approved_id: Option<&[u8; 32]>,
+        now: u64,
+    ) -> Result<Box<[u8]>, SystemError> {
+        let channel = self.channel(channel_id)?;
+        if tx.sender() != hex::encode(channel_id) {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidTransaction,
+                "Transaction is not sent by this channel".to_string(),
+            ));
+        }
+        if self.counterparties.get(channel_id) != Some(&recipient) {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidTransaction,
+                "Recipient is not the channel counterparty".to_string(),
+            ));
+        }
+        let approval = self.authorize_operation(
+            &MultisigOperation::Payment {
+                channel_id: *channel_id,
+                recipient,
+                amount: tx.amount(),
+            },
+            approved_id,
+            now,
+        )?;
+
+        let state_boc = channel
+            .write()
+            .map_err(|_| lock_error())?
+            .try_process_transaction(tx)
+            .map_err(|e| SystemError::new(SystemErrorType::InvalidTransaction, e.to_string()))?;
+        self.complete_operation(approval, now)?;
+        self.total_locked_balance = self.total_locked_balance.saturating_sub(tx.amount());
+        Ok(state_boc)
+    }
+
+    /// Closes a channel and returns its remaining balance to the wallet. Multisig wallets need
+    /// an approved matching proposal.
+    pub fn close_channel(
+        &mut self,
+        channel_id: &[u8; 32],
+        approved_id: Option<&[u8; 32]>,
+        now: u64,
+    ) -> Result<u64, SystemError> {
+        let final_balance = self
+            .channel(channel_id)?
+            .read()
+            .map_err(|_| lock_error())?
+            .balance();
+        let approval = self.authorize_operation(
+            &MultisigOperation::CloseChannel {
+                channel_id: *channel_id,
+            },
+            approved_id,
+            now,
+        )?;
+
+        self.complete_operation(approval, now)?;
+        self.channels.remove(channel_id);
+        self.counterparties.remove(channel_id);
+        self.total_locked_balance = self.total_locked_balance.saturating_sub(final_balance);
+        self.balance += final_balance;
+        Ok(final_balance)
+    }
+
+    fn channel(&self, channel_id: &[u8; 32]) -> Result<Arc<RwLock<ChannelContract>>, SystemError> {
+        self.channels
+            .get(channel_id)
+            .cloned()
+            .ok_or_else(channel_not_found)
+    }
+}
+
+fn channel_not_found() -> SystemError {
+    SystemError::new(SystemErrorType::NotFound, "Channel not found".to_string())
+}
+
+fn invalid_amount() -> SystemError {
+    SystemError::new(
+        SystemErrorType::InvalidAmount,
+        "Channel balance overflow".to_string(),
+    )
+}
+
+fn lock_error() -> SystemError {
+    SystemError::new(
+        SystemErrorType::InvalidOperation,
+        "Channel lock poisoned".to_string(),
+    )
+}
+
 pub struct WalletExtensionStateChange {
     pub op: WalletExtensionStateChangeOp,
     pub channel_id: [u8; 32],
@@ -245,8 +429,232 @@ impl Default for StateTransition {
         Self {
             old_state: PrivateChannelState::default(),
             new_state: PrivateChannelState::default(),
-            proof: ZkProof::new(Vec::new(), Vec::new(), Vec::new(), 0),
+            proof: ZkProof::new(
+                ProofType::StateTransition,
+                [0u8; 32],
+                Vec::new(),
+                Vec::new(),
+                Vec::new(),
+                0,
+            ),
             timestamp: 0,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::hierarchy::client::wallet_extension::multisig::MultisigPolicy;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    fn keys() -> Vec<SigningKey> {
+        (1..=3u8)
+            .map(|i| SigningKey::from_bytes(&[i; 32]))
+            .collect()
+    }
+
+    /// A 2-of-3 wallet whose payments above 100 need a quorum.
+    fn multisig_wallet(keys: &[SigningKey]) -> WalletExtension {
+        let mut wallet = WalletExtension::new([7u8; 32], 10_000);
+        let signers = keys.iter().map(|k| k.verifying_key().to_bytes()).collect();
+        wallet
+            .enable_multisig(MultisigPolicy::new(signers, 2, 100, 3_600).unwrap())
+            .unwrap();
+        wallet
+    }
+
+    /// Proposes `operation` and approves it with `signers`.
+    fn approve(
+        wallet: &mut WalletExtension,
+        operation: MultisigOperation,
+        signers: &[SigningKey],
+    ) -> [u8; 32] {
+        let controller = wallet.multisig.as_mut().unwrap();
+        let id = controller.propose(operation, 100).unwrap();
+        for key in signers {
+            let hash = controller.signing_hash(controller.pending(&id).unwrap());
+            let signature = key.sign(&hash).to_bytes();
+            controller
+                .approve(&id, key.verifying_key().to_bytes(), &signature, 100)
+                .unwrap();
+        }
+        id
+    }
+
+    fn open_channel(wallet: &mut WalletExtension, keys: &[SigningKey]) -> [u8; 32] {
+        let create = MultisigOperation::CreateChannel {
+            counterparty: [2u8; 32],
+            initial_balance: 1_000,
+        };
+        let id = approve(wallet, create, &keys[..2]);
+        wallet
+            .create_channel([2u8; 32], 1_000, Some(&id), 101)
+            .unwrap()
+    }
+
+    fn payment(channel_id: &[u8; 32], amount: u64) -> channel_contract::Transaction {
+        channel_contract::Transaction::new(&hex::encode(channel_id), 1, 1, amount)
+    }
+
+    #[test]
+    fn test_single_owner_wallet_needs_no_approval() {
+        let mut wallet = WalletExtension::new([7u8; 32], 10_000);
+        let channel_id = wallet.create_channel([2u8; 32], 1_000, None, 100).unwrap();
+        assert_eq!(wallet.balance, 9_000);
+        assert_eq!(wallet.total_locked_balance, 1_000);
+
+        wallet
+            .process_payment(
+                &channel_id,
+                [2u8; 32],
+                &payment(&channel_id, 400),
+                None,
+                101,
+            )
+            .unwrap();
+        assert_eq!(wallet.close_channel(&channel_id, None, 102).unwrap(), 600);
+        assert_eq!(wallet.balance, 9_600);
+        assert_eq!(wallet.total_locked_balance, 0);
+    }
+
+    #[test]
+    fn test_channel_creation_and_close_require_quorum() {
+        let keys = keys();
+        let mut wallet = multisig_wallet(&keys);
+        assert!(wallet.create_channel([2u8; 32], 1_000, None, 101).is_err());
+        assert!(wallet.channels.is_empty());
+
+        let channel_id = open_channel(&mut wallet, &keys);
+        assert!(wallet.close_channel(&channel_id, None, 102).is_err());
+
+        let close = MultisigOperation::CloseChannel { channel_id };
+        let id = approve(&mut wallet, close, &keys[1..]);
+        assert_eq!(
+            wallet.close_channel(&channel_id, Some(&id), 102).unwrap(),
+            1_000
+        );
+    }
+
+    #[test]
+    fn test_under_signed_payment_rejected() {
+        let keys = keys();
+        let mut wallet = multisig_wallet(&keys);
+        let channel_id = open_channel(&mut wallet, &keys);
+        let large = MultisigOperation::Payment {
+            channel_id,
+            recipient: [2u8; 32],
+            amount: 400,
+        };
+
+        // One of two required signatures
+        let id = approve(&mut wallet, large.clone(), &keys[..1]);
+        let result = wallet.process_payment(
+            &channel_id,
+            [2u8; 32],
+            &payment(&channel_id, 400),
+            Some(&id),
+            101,
+        );
+        assert!(matches!(
+            result,
+            Err(SystemError {
+                error_type: SystemErrorType::InvalidSignature,
+                ..
+            })
+        ));
+        assert!(wallet
+            .process_payment(
+                &channel_id,
+                [2u8; 32],
+                &payment(&channel_id, 400),
+                None,
+                101
+            )
+            .is_err());
+        assert_eq!(wallet.total_locked_balance, 1_000);
+
+        // Payments up to the threshold pass without approval
+        wallet
+            .process_payment(
+                &channel_id,
+                [2u8; 32],
+                &payment(&channel_id, 100),
+                None,
+                101,
+            )
+            .unwrap();
+        assert_eq!(wallet.total_locked_balance, 900);
+    }
+
+    #[test]
+    fn test_rejected_payment_keeps_its_approval() {
+        let keys = keys();
+        let mut wallet = multisig_wallet(&keys);
+        let channel_id = open_channel(&mut wallet, &keys);
+        let large = MultisigOperation::Payment {
+            channel_id,
+            recipient: [2u8; 32],
+            amount: 400,
+        };
+        let id = approve(&mut wallet, large, &keys[..2]);
+
+        // A stale nonce is rejected by the channel; the quorum is not spent.
+        let stale = channel_contract::Transaction::new(&hex::encode(channel_id), 0, 1, 400);
+        assert!(wallet
+            .process_payment(&channel_id, [2u8; 32], &stale, Some(&id), 101)
+            .is_err());
+        assert!(wallet.multisig.as_ref().unwrap().pending(&id).is_some());
+        assert_eq!(wallet.total_locked_balance, 1_000);
+
+        wallet
+            .process_payment(
+                &channel_id,
+                [2u8; 32],
+                &payment(&channel_id, 400),
+                Some(&id),
+                101,
+            )
+            .unwrap();
+        assert!(wallet.multisig.as_ref().unwrap().pending(&id).is_none());
+        assert_eq!(wallet.total_locked_balance, 600);
+    }
+
+    #[test]
+    fn test_payment_must_match_its_approval() {
+        let keys = keys();
+        let mut wallet = multisig_wallet(&keys);
+        let channel_id = open_channel(&mut wallet, &keys);
+        let large = MultisigOperation::Payment {
+            channel_id,
+            recipient: [2u8; 32],
+            amount: 400,
+        };
+        let id = approve(&mut wallet, large, &keys[..2]);
+
+        let amount = wallet.process_payment(
+            &channel_id,
+            [2u8; 32],
+            &payment(&channel_id, 500),
+            Some(&id),
+            101,
+        );
+        let recipient = wallet.process_payment(
+            &channel_id,
+            [3u8; 32],
+            &payment(&channel_id, 400),
+            Some(&id),
+            101,
+        );
+        let sender = wallet.process_payment(
+            &channel_id,
+            [2u8; 32],
+            &payment(&[9u8; 32], 400),
+            Some(&id),
+            101,
+        );
+        assert!(amount.is_err() && recipient.is_err() && sender.is_err());
+        assert!(wallet.multisig.as_ref().unwrap().pending(&id).is_some());
+        assert_eq!(wallet.total_locked_balance, 1_000);
+    }
+}
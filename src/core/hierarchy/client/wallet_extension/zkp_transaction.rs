@@ -4,10 +4,8 @@ use crate::core::error::errors::{SystemError, SystemErrorType};
 
 use crate::core::types::boc::*;
 use crate::core::types::ovp_ops::*;
-use crate::core::zkps::circuit_builder::*;
 use crate::core::zkps::plonky2::*;
 use crate::core::zkps::proof::*;
-use crate::core::zkps::zkp::*;
 use crate::core::zkps::zkp_interface::*;
 use wasm_bindgen::prelude::*;
 
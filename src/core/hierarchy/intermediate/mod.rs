@@ -9,3 +9,4 @@ pub mod destination_contract;
 pub mod intermediate_contract_types;
 pub mod sparse_merkle_tree_i;
 pub mod state_tracking_i;
+pub mod wallet_root_i;
@@ -0,0 +1,439 @@
+// ./src/core/hierarchy/intermediate/wallet_root_i.rs
+
+// Wallet Root Import
+// Accepts wallet root submissions exported by the client (`WalletRootProof::export_proof_boc`),
+// validates them against the wallet's registered key and last accepted nonce, verifies the
+// attached tree transition proof against the circuit registry, and applies the new wallet
+// root to the intermediate tree under the wallet's id. The proof must start from the wallet
+// root accepted last, or from the empty tree for a wallet's first submission.
+
+use crate::core::error::errors::{SystemError, SystemErrorType};
+use crate::core::hierarchy::client::wallet_extension::client_proof_exporter::WalletRootProof;
+use crate::core::hierarchy::intermediate::sparse_merkle_tree_i::SparseMerkleTreeI;
+use crate::core::smt::tree::default_hashes;
+use crate::core::smt::HierarchyHasher;
+use crate::core::types::boc::BOC;
+use crate::core::zkps::proof::ProofVerifier;
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use std::collections::HashMap;
+
+/// Tracks registered wallets and applies their root submissions to the intermediate tree.
+pub struct WalletRootManagerI {
+    tree: SparseMerkleTreeI,
+    verifier: ProofVerifier,
+    wallet_keys: HashMap<[u8; 32], VerifyingKey>,
+    wallet_nonces: HashMap<[u8; 32], u64>,
+    /// Root of a wallet with no channels, where a wallet's first proof starts.
+    empty_wallet_root: [u8; 32],
+}
+
+impl WalletRootManagerI {
+    /// A manager applying roots to `tree` whose proofs must verify against a circuit
+    /// registered with `verifier`.
+    pub fn new(tree: SparseMerkleTreeI, verifier: ProofVerifier) -> Self {
+        Self {
+            tree,
+            verifier,
+            wallet_keys: HashMap::new(),
+            wallet_nonces: HashMap::new(),
+            empty_wallet_root: default_hashes::<HierarchyHasher>()[0],
+        }
+    }
+
+    /// Registers a wallet's ed25519 public key. Submissions are accepted from nonce 1 onwards.
+    pub fn register_wallet(
+        &mut self,
+        wallet_id: [u8; 32],
+        public_key: &[u8; 32],
+    ) -> Result<(), SystemError> {
+        if self.wallet_keys.contains_key(&wallet_id) {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidOperation,
+                "Wallet already registered".to_string(),
+            ));
+        }
+
+        let key = VerifyingKey::from_bytes(public_key)
+            .map_err(|e| SystemError::new(SystemErrorType::InvalidPublicKey, e.to_string()))?;
+
+        self.wallet_keys.insert(wallet_id, key);
+        self.wallet_nonces.insert(wallet_id, 0);
+        Ok(())
+    }
+
+    /// Parses, validates and applies a wallet root submission BOC.
+    /// Returns the new intermediate root.
+    pub fn import_wallet_root(&mut self, boc: &BOC) -> Result<[u8; 32], SystemError> {
+        let submission = WalletRootProof::import_proof_boc(boc)?;
+        self.validate_submission(&submission)?;
+
+        let wallet_id = submission.metadata.wallet_id;
+        self.tree.update(&wallet_id, &submission.wallet_root)?;
+        self.wallet_nonces
+            .insert(wallet_id, submission.metadata.nonce);
+
+        Ok(self.tree.root())
+    }
+
+    /// Checks the submission's signature, nonce and proof without applying it. The proof must
+    /// be a registered tree transition from the wallet's current root to the submitted root.
+    pub fn validate_submission(&self, submission: &WalletRootProof) -> Result<(), SystemError> {
+        let wallet_id = &submission.metadata.wallet_id;
+        let key = self.wallet_keys.get(wallet_id).ok_or_else(|| {
+            SystemError::new(SystemErrorType::NotFound, "Unknown wallet".to_string())
+        })?;
+
+        let last_nonce = self.wallet_nonces.get(wallet_id).copied().unwrap_or(0);
+        if submission.metadata.nonce <= last_nonce {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidNonce,
+                format!(
+                    "Stale wallet root nonce {} (last accepted {})",
+                    submission.metadata.nonce, last_nonce
+                ),
+            ));
+        }
+
+        let signature = Signature::from_slice(&submission.signature)
+            .map_err(|e| SystemError::new(SystemErrorType::InvalidSignature, e.to_string()))?;
+        key.verify(&submission.signing_hash()?, &signature)
+            .map_err(|_| {
+                SystemError::new(
+                    SystemErrorType::InvalidSignature,
+                    "Wallet root signature verification failed".to_string(),
+                )
+            })?;
+
+        let old_root = submission.verify_proof(&self.verifier)?;
+        if old_root != self.wallet_root(wallet_id)? {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidProof,
+                "Proof does not start from the last accepted wallet root".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Finalizes an epoch, recording the current intermediate root so the wallet roots it
+    /// commits to stay queryable.
+    pub fn finalize_epoch(&mut self, epoch: u64) -> Result<[u8; 32], SystemError> {
+        self.tree.commit_epoch(epoch)
+    }
+
+    /// Returns the last accepted root of a wallet, or the empty wallet root if none was.
+    pub fn wallet_root(&self, wallet_id: &[u8; 32]) -> Result<[u8; 32], SystemError> {
+        match self.tree.get(wallet_id)? {
+            Some(root) => root.as_slice().try_into().map_err(|_| {
+                SystemError::new(
+                    SystemErrorType::InvalidHash,
+                    "Stored wallet root is not 32 bytes".to_string(),
+                )
+            }),
+            None => Ok(self.empty_wallet_root),
+        }
+    }
+
+    /// Returns the last accepted nonce for a wallet.
+    pub fn wallet_nonce(&self, wallet_id: &[u8; 32]) -> Option<u64> {
+        self.wallet_nonces.get(wallet_id).copied()
+    }
+
+    /// Returns the intermediate tree the wallet roots are applied to.
+    pub fn tree(&self) -> &SparseMerkleTreeI {
+        &self.tree
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::hierarchy::client::wallet_extension::client_proof_exporter::ProofMetadata;
+    use crate::core::hierarchy::client::wallet_extension::recovery::ChannelStateRecord;
+    use crate::core::smt::HierarchyTree;
+    use crate::core::zkps::plonky2::Plonky2System;
+    use crate::core::zkps::proof::{ProofType, ZkProof};
+    use crate::core::zkps::signature::StateKeyChain;
+    use crate::core::zkps::state_commitment::CommittedChannelState;
+    use ed25519_dalek::SigningKey;
+    use std::sync::OnceLock;
+
+    const WALLET_ID: [u8; 32] = [1u8; 32];
+
+    fn channel(balance: u64, nonce: u64) -> ChannelStateRecord {
+        ChannelStateRecord::new(WALLET_ID, [7u8; 32], balance, nonce, 1, [0u8; 32])
+    }
+
+    /// A proof moving the wallet's channel tree from empty to holding `channel(1000, 0)`,
+    /// proved once.
+    fn first_proof() -> ZkProof {
+        static PROOF: OnceLock<ZkProof> = OnceLock::new();
+        PROOF
+            .get_or_init(|| {
+                WalletRootProof::prove_channel_updates(
+                    &mut HierarchyTree::new(),
+                    &[channel(1000, 0)],
+                    WALLET_ID,
+                    1,
+                    42,
+                )
+                .unwrap()
+                .proof
+            })
+            .clone()
+    }
+
+    fn wallet_root() -> [u8; 32] {
+        first_proof().merkle_root.try_into().unwrap()
+    }
+
+    fn submission_with_proof(
+        signing_key: &SigningKey,
+        nonce: u64,
+        proof: ZkProof,
+    ) -> WalletRootProof {
+        let metadata = ProofMetadata {
+            timestamp: 1_700_000_000,
+            nonce,
+            wallet_id: WALLET_ID,
+            proof_type: proof.proof_type,
+        };
+        let root = proof.merkle_root.as_slice().try_into().unwrap();
+        let mut submission = WalletRootProof::new(root, proof, metadata);
+        submission.sign(signing_key).unwrap();
+        submission
+    }
+
+    fn signed_submission(signing_key: &SigningKey, nonce: u64) -> WalletRootProof {
+        submission_with_proof(signing_key, nonce, first_proof())
+    }
+
+    fn manager_with_wallet(signing_key: &SigningKey) -> WalletRootManagerI {
+        let mut manager = WalletRootManagerI::new(
+            SparseMerkleTreeI::new(),
+            ProofVerifier::with_system_circuits().unwrap(),
+        );
+        manager
+            .register_wallet(WALLET_ID, signing_key.verifying_key().as_bytes())
+            .unwrap();
+        manager
+    }
+
+    #[test]
+    fn test_valid_submission() {
+        let signing_key = SigningKey::from_bytes(&[5u8; 32]);
+        let manager = manager_with_wallet(&signing_key);
+        let submission = signed_submission(&signing_key, 1);
+
+        assert!(manager.validate_submission(&submission).is_ok());
+    }
+
+    #[test]
+    fn test_rejects_proof_of_unregistered_circuit() {
+        let signing_key = SigningKey::from_bytes(&[5u8; 32]);
+        let mut manager = WalletRootManagerI::new(SparseMerkleTreeI::new(), ProofVerifier::new());
+        manager
+            .register_wallet(WALLET_ID, signing_key.verifying_key().as_bytes())
+            .unwrap();
+
+        let result = manager.validate_submission(&signed_submission(&signing_key, 1));
+        assert!(matches!(
+            result,
+            Err(SystemError {
+                error_type: SystemErrorType::NotFound,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_rejects_invalid_or_mislabeled_proof() {
+        let signing_key = SigningKey::from_bytes(&[5u8; 32]);
+        let manager = manager_with_wallet(&signing_key);
+
+        let mut forged = first_proof();
+        forged.proof_data = vec![9u8; 48];
+        let submission = submission_with_proof(&signing_key, 1, forged);
+        assert!(manager.validate_submission(&submission).is_err());
+
+        let mut mislabeled = signed_submission(&signing_key, 1);
+        mislabeled.metadata.proof_type = ProofType::MerkleInclusion;
+        mislabeled.sign(&signing_key).unwrap();
+        assert!(matches!(
+            manager.validate_submission(&mislabeled),
+            Err(SystemError {
+                error_type: SystemErrorType::InvalidProof,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_rejects_stale_nonce() {
+        let signing_key = SigningKey::from_bytes(&[5u8; 32]);
+        let manager = manager_with_wallet(&signing_key);
+        let submission = signed_submission(&signing_key, 0);
+
+        let result = manager.validate_submission(&submission);
+        assert!(matches!(
+            result,
+            Err(SystemError {
+                error_type: SystemErrorType::InvalidNonce,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_rejects_foreign_signature() {
+        let signing_key = SigningKey::from_bytes(&[5u8; 32]);
+        let manager = manager_with_wallet(&signing_key);
+        let submission = signed_submission(&SigningKey::from_bytes(&[6u8; 32]), 1);
+
+        let result = manager.validate_submission(&submission);
+        assert!(matches!(
+            result,
+            Err(SystemError {
+                error_type: SystemErrorType::InvalidSignature,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_rejects_proof_that_is_not_a_wallet_tree_transition() {
+        let signing_key = SigningKey::from_bytes(&[5u8; 32]);
+        let manager = manager_with_wallet(&signing_key);
+
+        let keys = StateKeyChain::new([7u8; 32]);
+        let old = CommittedChannelState {
+            channel_id: [7u8; 32],
+            balance: 1000,
+            auth_key: keys.auth_key(0),
+            ..Default::default()
+        };
+        let (new, signature) = keys.transfer(&old, 100, [0u8; 32]).unwrap();
+        let system = Plonky2System::shared().unwrap();
+        let proof_bytes = system.generate_proof(&old, &new, 100, &signature).unwrap();
+        let transition = ZkProof::from_plonky2(
+            ProofType::StateTransition,
+            system.circuit_digest(),
+            &system.proof_from_bytes(&proof_bytes).unwrap(),
+            new.commitment().to_vec(),
+            42,
+        );
+
+        let submission = submission_with_proof(&signing_key, 1, transition);
+        assert!(matches!(
+            manager.validate_submission(&submission),
+            Err(SystemError {
+                error_type: SystemErrorType::InvalidProof,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_submissions_chain_from_the_accepted_root() {
+        let signing_key = SigningKey::from_bytes(&[5u8; 32]);
+        let mut manager = manager_with_wallet(&signing_key);
+        manager
+            .import_wallet_root(
+                &signed_submission(&signing_key, 1)
+                    .export_proof_boc()
+                    .unwrap(),
+            )
+            .unwrap();
+        assert_eq!(manager.wallet_root(&WALLET_ID).unwrap(), wallet_root());
+
+        let mut channels = HierarchyTree::new();
+        channels
+            .update(&[7u8; 32], &channel(1000, 0).encode_state())
+            .unwrap();
+        let mut next = WalletRootProof::prove_channel_updates(
+            &mut channels,
+            &[channel(900, 1)],
+            WALLET_ID,
+            2,
+            43,
+        )
+        .unwrap();
+        next.sign(&signing_key).unwrap();
+        let root = manager
+            .import_wallet_root(&next.export_proof_boc().unwrap())
+            .unwrap();
+        assert_eq!(manager.wallet_root(&WALLET_ID).unwrap(), channels.root());
+        assert_eq!(root, manager.tree().root());
+
+        // A proof from the empty wallet no longer starts at the accepted root.
+        let replayed = signed_submission(&signing_key, 3);
+        assert!(matches!(
+            manager.validate_submission(&replayed),
+            Err(SystemError {
+                error_type: SystemErrorType::InvalidProof,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_import_applies_wallet_root() {
+        let signing_key = SigningKey::from_bytes(&[5u8; 32]);
+        let mut manager = manager_with_wallet(&signing_key);
+        let boc = signed_submission(&signing_key, 1)
+            .export_proof_boc()
+            .unwrap();
+
+        let root = manager.import_wallet_root(&boc).unwrap();
+        assert_eq!(root, manager.tree().root());
+        assert_eq!(manager.wallet_nonce(&WALLET_ID), Some(1));
+        assert_eq!(
+            manager.tree().get(&WALLET_ID).unwrap(),
+            Some(wallet_root().to_vec())
+        );
+
+        let proof = manager.tree().prove(&WALLET_ID).unwrap();
+        assert!(SparseMerkleTreeI::verify(
+            &root,
+            &WALLET_ID,
+            &wallet_root(),
+            &proof
+        ));
+        assert!(manager.import_wallet_root(&boc).is_err());
+    }
+
+    #[test]
+    fn test_finalized_epochs_stay_queryable() {
+        let signing_key = SigningKey::from_bytes(&[5u8; 32]);
+        let mut manager = manager_with_wallet(&signing_key);
+        let boc = signed_submission(&signing_key, 1)
+            .export_proof_boc()
+            .unwrap();
+        let before = manager.finalize_epoch(1).unwrap();
+        manager.import_wallet_root(&boc).unwrap();
+        let after = manager.finalize_epoch(2).unwrap();
+
+        let past = manager.tree().get_at_epoch(1, &WALLET_ID).unwrap();
+        assert_eq!(past.value, None);
+        assert!(past.verify::<HierarchyHasher>(&before));
+        let present = manager.tree().get_at_epoch(2, &WALLET_ID).unwrap();
+        assert_eq!(present.value, Some(wallet_root().to_vec()));
+        assert!(present.verify::<HierarchyHasher>(&after));
+        assert_eq!(manager.tree().changed_keys(1, 2).unwrap(), vec![WALLET_ID]);
+    }
+
+    #[test]
+    fn test_rejects_unknown_wallet() {
+        let signing_key = SigningKey::from_bytes(&[5u8; 32]);
+        let mut manager = WalletRootManagerI::new(
+            SparseMerkleTreeI::new(),
+            ProofVerifier::with_system_circuits().unwrap(),
+        );
+        let boc = signed_submission(&signing_key, 1)
+            .export_proof_boc()
+            .unwrap();
+
+        assert!(manager.import_wallet_root(&boc).is_err());
+        assert_eq!(manager.wallet_nonce(&WALLET_ID), None);
+    }
+}
@@ -1,5 +1,5 @@
 // ./src/core/hierarchy/mod.rs
 
-mod client;
+pub mod client;
 pub mod intermediate;
 pub mod root;
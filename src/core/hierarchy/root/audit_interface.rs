@@ -1,17 +1,107 @@
 // src/core/global/audit_interface.rs
 
-pub struct AuditInterface;
+use crate::core::error::errors::SystemError;
+use crate::core::hierarchy::root::root_contract::RootContract;
+use crate::core::smt::history::HistoricalValue;
 
-impl AuditInterface {
-    /// Returns the current global root.
-    pub fn query_global_root() -> [u8; 32] {
-        // Implementation to return global root
-        [0; 32] // Placeholder return value
+/// Read-only, point-in-time view of the root contract for auditors and dispute resolution.
+pub struct AuditInterface<'a> {
+    root_contract: &'a RootContract,
+}
+
+impl<'a> AuditInterface<'a> {
+    pub fn new(root_contract: &'a RootContract) -> Self {
+        Self { root_contract }
+    }
+
+    /// Returns the global root of the latest finalized epoch, or the live root before the first.
+    pub fn query_global_root(&self) -> [u8; 32] {
+        let tree = self.root_contract.global_tree();
+        tree.epoch_roots()
+            .last()
+            .map(|(_, root)| *root)
+            .unwrap_or_else(|| tree.root())
+    }
+
+    /// Returns the global roots of all retained finalized epochs, oldest first.
+    pub fn query_root_history(&self) -> Vec<[u8; 32]> {
+        self.root_contract
+            .global_tree()
+            .epoch_roots()
+            .into_iter()
+            .map(|(_, root)| root)
+            .collect()
+    }
+
+    /// Returns the global root finalized for an epoch.
+    pub fn query_epoch_root(&self, epoch: u64) -> Option<[u8; 32]> {
+        self.root_contract.global_tree().epoch_root(epoch)
+    }
+
+    /// Returns the value of a global tree key at an epoch, with a proof against that epoch's
+    /// global root.
+    pub fn query_value_at(&self, epoch: u64, key: &[u8]) -> Result<HistoricalValue, SystemError> {
+        self.root_contract.global_tree().get_at_epoch(epoch, key)
+    }
+
+    /// Returns an intermediate contract's root as finalized at an epoch.
+    pub fn query_intermediate_root(
+        &self,
+        epoch: u64,
+        contract: &[u8; 32],
+    ) -> Result<HistoricalValue, SystemError> {
+        self.query_value_at(epoch, contract)
+    }
+
+    /// Returns the intermediate contracts whose root changed between two epochs.
+    pub fn query_changed_keys(
+        &self,
+        from_epoch: u64,
+        to_epoch: u64,
+    ) -> Result<Vec<[u8; 32]>, SystemError> {
+        self.root_contract
+            .global_tree()
+            .changed_keys(from_epoch, to_epoch)
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::smt::HierarchyHasher;
+    use plonky2::hash::merkle_proofs::MerkleProof;
+
+    fn submit(contract: &mut RootContract, addr: u8, root: u8) {
+        contract
+            .process_intermediate_root([addr; 32], [root; 32], MerkleProof { siblings: vec![] })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_point_in_time_queries() {
+        let mut contract = RootContract::new(10);
+        submit(&mut contract, 1, 11);
+        submit(&mut contract, 2, 21);
+        contract.try_submit_global_root(10).unwrap();
+        submit(&mut contract, 2, 22);
+        contract.try_submit_global_root(20).unwrap();
+        submit(&mut contract, 3, 31);
+
+        let audit = AuditInterface::new(&contract);
+        let history = audit.query_root_history();
+        assert_eq!(history.len(), 2);
+        assert_eq!(audit.query_global_root(), history[1]);
+        assert_ne!(audit.query_global_root(), contract.global_tree().root());
+
+        let first = audit.query_intermediate_root(1, &[2u8; 32]).unwrap();
+        assert_eq!(first.value, Some([21u8; 32].to_vec()));
+        assert!(first.verify::<HierarchyHasher>(&audit.query_epoch_root(1).unwrap()));
+
+        let absent = audit.query_value_at(2, &[3u8; 32]).unwrap();
+        assert_eq!(absent.value, None);
+        assert!(absent.verify::<HierarchyHasher>(&history[1]));
 
-    /// Allows for querying the history of global roots or proofs.
-    pub fn query_root_history() -> Vec<[u8; 32]> {
-        // Implementation of history query
-        Vec::new() // Placeholder return value
+        assert_eq!(audit.query_changed_keys(1, 2).unwrap(), vec![[2u8; 32]]);
+        assert!(audit.query_value_at(3, &[1u8; 32]).is_err());
     }
 }
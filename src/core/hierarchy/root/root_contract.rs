@@ -3,6 +3,7 @@ use crate::core::error::errors::{SystemError, SystemErrorType};
 use crate::core::hierarchy::client::wallet_extension::wallet_extension_types::Transaction;
 use crate::core::hierarchy::root::sparse_merkle_tree_r::SparseMerkleTreeR;
 use crate::core::types::boc::{get_refs, Cell, CellType, BOC};
+use crate::core::zkps::verifier_export::{ExportedProof, ExportedVerifierKey};
 use plonky2::field::goldilocks_field::GoldilocksField;
 use plonky2::hash::merkle_proofs::MerkleProof;
 use plonky2::hash::poseidon::PoseidonHash;
@@ -22,6 +23,7 @@ pub struct RootContract {
     verify_global_state: bool,
     verify_root_state: bool,
     submit_settlement: bool,
+    settlement_verifier_key: Option<ExportedVerifierKey>,
 }
 
 impl RootContract {
@@ -40,9 +42,58 @@ impl RootContract {
             verify_global_state: false,
             verify_root_state: false,
             submit_settlement: true,
+            settlement_verifier_key: None,
         }
     }
 
+    /// Sets the verifier key settlement proofs must verify under.
+    pub fn set_settlement_verifier_key(&mut self, key: ExportedVerifierKey) {
+        self.settlement_verifier_key = Some(key);
+    }
+
+    /// Hash of the settlement verifier key, if one is set.
+    pub fn settlement_verifier_key_hash(&self) -> Option<[u8; 32]> {
+        self.settlement_verifier_key.as_ref().map(|key| key.hash())
+    }
+
+    /// Verifies a root submission's exported proof (`RootSubmission::zkp`) under the settlement
+    /// verifier key; `verifier_key_hash` is the hash the submission references.
+    pub fn verify_settlement_proof(
+        &self,
+        verifier_key_hash: &[u8; 32],
+        proof: &[u8],
+    ) -> Result<(), SystemError> {
+        let key = self.settlement_verifier_key.as_ref().ok_or_else(|| {
+            SystemError::new(
+                SystemErrorType::NotFound,
+                "No settlement verifier key is set".to_string(),
+            )
+        })?;
+        if *verifier_key_hash != key.hash() {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidProof,
+                "Submission references a different verifier key".to_string(),
+            ));
+        }
+        key.verify(&ExportedProof::from_bytes(proof)?)
+    }
+
+    /// Settles an intermediate root submitted with an exported settlement proof. The proof
+    /// must verify under the settlement verifier key the submission references before the
+    /// root enters the global tree.
+    pub fn process_settlement_submission(
+        &mut self,
+        contract_addr: Address,
+        root: Hash,
+        verifier_key_hash: &[u8; 32],
+        zkp: &[u8],
+    ) -> Result<(), SystemError> {
+        self.verify_settlement_proof(verifier_key_hash, zkp)?;
+        self.intermediate_roots.insert(contract_addr, root);
+        self.global_tree.update(&contract_addr, &root)?;
+        Ok(())
+    }
+
     pub fn process_intermediate_root(
         &mut self,
         contract_addr: Address,
@@ -50,19 +101,21 @@ impl RootContract {
         _proof: MerkleProof<GoldilocksField, PoseidonHash>,
     ) -> Result<(), SystemError> {
         self.intermediate_roots.insert(contract_addr, root);
-        self.global_tree.update_global_tree(&contract_addr, &root)?;
+        self.global_tree.update(&contract_addr, &root)?;
         Ok(())
     }
 
+    /// Finalizes the next epoch once the epoch duration has passed. Fails, leaving the epoch
+    /// open, if the global tree cannot commit it.
     pub fn try_submit_global_root(
         &mut self,
         now: u64,
-    ) -> Option<(Hash, MerkleProof<GoldilocksField, PoseidonHash>)> {
+    ) -> Result<Option<(Hash, MerkleProof<GoldilocksField, PoseidonHash>)>, SystemError> {
         if now - self.last_submission < self.epoch_duration {
-            return None;
+            return Ok(None);
         }
 
-        let root = self.global_tree.get_global_root_hash();
+        let root = self.global_tree.commit_epoch(self.epoch + 1)?;
         let proof = MerkleProof { siblings: vec![] };
 
         self.epoch += 1;
@@ -71,9 +124,19 @@ impl RootContract {
         self.submit_settlement = true;
 
         if !self.verify_global_state {
-            return None;
+            return Ok(None);
         }
-        Some((root, proof))
+        Ok(Some((root, proof)))
+    }
+
+    /// Returns the number of the last finalized epoch.
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Returns the global tree, which keeps the roots of finalized epochs.
+    pub fn global_tree(&self) -> &SparseMerkleTreeR {
+        &self.global_tree
     }
 
     pub fn verify_transaction(
@@ -110,7 +173,7 @@ impl RootContract {
             None,
         ));
 
-        let _tree_boc = self.global_tree.serialize_global_state()?;
+        let _tree_boc = self.global_tree.serialize_state()?;
         boc.add_cell(Cell::new(
             vec![],
             vec![],
@@ -184,9 +247,25 @@ impl RootContract {
             verify_global_state: false,
             verify_root_state: false,
             submit_settlement,
+            settlement_verifier_key: None,
         })
     }
 }
 
 type Hash = [u8; 32];
 type Address = [u8; 32];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_failed_epoch_commit_is_reported() {
+        let mut contract = RootContract::new(10);
+        contract.global_tree.commit_epoch(5).unwrap();
+
+        assert!(contract.try_submit_global_root(10).is_err());
+        assert_eq!(contract.epoch(), 0);
+        assert!(contract.try_submit_global_root(5).unwrap().is_none());
+    }
+}
@@ -7,6 +7,7 @@
 //pub mod conversion;
 pub mod error;
 pub mod hierarchy;
+pub mod smt;
 //pub mod tokens;
 //pub mod state;
 pub mod storage_node;
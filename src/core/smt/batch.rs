@@ -0,0 +1,434 @@
+// ./src/core/smt/batch.rs
+
+// Batch Updates
+// Applies many key updates in one pass. Keys are sorted so updates sharing a prefix share the
+// internal nodes above their split point, and each of those nodes is hashed once. Disjoint
+// subtrees near the root are hashed in parallel. The result is the same root as applying the
+// updates one by one, together with a multi-proof covering every updated key.
+//
+// Multi-proof siblings are listed depth first, left before right, and are exactly the roots of
+// the subtrees that contain none of the proven keys. Those subtrees are untouched by the batch,
+// so the same multi-proof verifies the old values against the old root and the new values
+// against the new root.
+
+use crate::core::error::errors::{SystemError, SystemErrorType};
+use crate::core::smt::hasher::TreeHasher;
+use crate::core::smt::store::NodeStore;
+use crate::core::smt::tree::{key_bit, Node, SparseMerkleTree, EMPTY_LEAF, TREE_DEPTH};
+use serde::{Deserialize, Serialize};
+
+/// Subtrees with fewer updates than this are hashed on the current thread.
+const PARALLEL_THRESHOLD: usize = 64;
+
+/// Only the top levels are split across threads; below that each branch is already large.
+const PARALLEL_DEPTH: usize = 8;
+
+/// Outcome of a batch update.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BatchUpdate {
+    pub old_root: [u8; 32],
+    pub new_root: [u8; 32],
+    pub proof: MultiProof,
+}
+
+/// One proof for several keys against a single root.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MultiProof {
+    /// Proven keys, sorted and without duplicates.
+    pub keys: Vec<[u8; 32]>,
+    /// Roots of the subtrees off the proven paths, depth first, left before right.
+    pub siblings: Vec<[u8; 32]>,
+}
+
+type Subtree = ([u8; 32], Vec<([u8; 32], Node)>);
+
+impl<H: TreeHasher, S: NodeStore> SparseMerkleTree<H, S> {
+    /// Applies all updates and returns the new root with a multi-proof for the updated keys.
+    /// An empty value removes the key; when a key appears more than once the last value wins.
+    pub fn batch_update(
+        &mut self,
+        updates: &[([u8; 32], Vec<u8>)],
+    ) -> Result<BatchUpdate, SystemError> {
+        let updates = normalize(updates);
+        let old_root = self.root();
+        let keys: Vec<[u8; 32]> = updates.iter().map(|(key, _)| *key).collect();
+
+        let (new_root, nodes) = self.update_subtree(old_root, 0, &updates)?;
+        self.commit(new_root, nodes)?;
+
+        let proof = self.prove_many_at(&new_root, &keys)?;
+        Ok(BatchUpdate {
+            old_root,
+            new_root,
+            proof,
+        })
+    }
+
+    /// Builds a multi-proof for `keys` against the current root.
+    pub fn prove_many(&self, keys: &[[u8; 32]]) -> Result<MultiProof, SystemError> {
+        self.prove_many_at(&self.root(), keys)
+    }
+
+    /// Builds a multi-proof for `keys` against the given root.
+    pub fn prove_many_at(
+        &self,
+        root: &[u8; 32],
+        keys: &[[u8; 32]],
+    ) -> Result<MultiProof, SystemError> {
+        let mut keys = keys.to_vec();
+        keys.sort_unstable();
+        keys.dedup();
+
+        let mut siblings = Vec::new();
+        if !keys.is_empty() {
+            self.collect_siblings(*root, 0, &keys, &mut siblings)?;
+        }
+        Ok(MultiProof { keys, siblings })
+    }
+
+    fn update_subtree(
+        &self,
+        hash: [u8; 32],
+        depth: usize,
+        updates: &[([u8; 32], Vec<u8>)],
+    ) -> Result<Subtree, SystemError> {
+        if updates.is_empty() {
+            return Ok((hash, Vec::new()));
+        }
+
+        if depth == TREE_DEPTH {
+            let (key, value) = &updates[0];
+            if value.is_empty() {
+                return Ok((EMPTY_LEAF, Vec::new()));
+            }
+            let leaf_hash = H::hash_leaf(key, value);
+            let leaf = Node::Leaf {
+                key: *key,
+                value: value.clone(),
+            };
+            return Ok((leaf_hash, vec![(leaf_hash, leaf)]));
+        }
+
+        let (left, right) = self.children(&hash, depth)?;
+        let split = updates.partition_point(|(key, _)| !key_bit(key, depth));
+        let (left_updates, right_updates) = updates.split_at(split);
+
+        let parallel = depth < PARALLEL_DEPTH
+            && updates.len() >= PARALLEL_THRESHOLD
+            && !left_updates.is_empty()
+            && !right_updates.is_empty();
+        let (left, right) = if parallel {
+            join(
+                || self.update_subtree(left, depth + 1, left_updates),
+                || self.update_subtree(right, depth + 1, right_updates),
+            )
+        } else {
+            (
+                self.update_subtree(left, depth + 1, left_updates),
+                self.update_subtree(right, depth + 1, right_updates),
+            )
+        };
+        let (left, mut nodes) = left?;
+        let (right, right_nodes) = right?;
+        nodes.extend(right_nodes);
+
+        let node_hash = H::hash_node(&left, &right);
+        if node_hash != self.default_at(depth) {
+            nodes.push((node_hash, Node::Internal { left, right }));
+        }
+        Ok((node_hash, nodes))
+    }
+
+    fn collect_siblings(
+        &self,
+        hash: [u8; 32],
+        depth: usize,
+        keys: &[[u8; 32]],
+        siblings: &mut Vec<[u8; 32]>,
+    ) -> Result<(), SystemError> {
+        if depth == TREE_DEPTH {
+            return Ok(());
+        }
+
+        let (left, right) = self.children(&hash, depth)?;
+        let split = keys.partition_point(|key| !key_bit(key, depth));
+        let (left_keys, right_keys) = keys.split_at(split);
+
+        if left_keys.is_empty() {
+            siblings.push(left);
+        } else {
+            self.collect_siblings(left, depth + 1, left_keys, siblings)?;
+        }
+        if right_keys.is_empty() {
+            siblings.push(right);
+        } else {
+            self.collect_siblings(right, depth + 1, right_keys, siblings)?;
+        }
+        Ok(())
+    }
+}
+
+impl MultiProof {
+    /// Recomputes the root from the proven keys' values, given in `keys` order.
+    /// An empty value stands for an absent key. Returns None for a malformed proof.
+    pub fn compute_root<H: TreeHasher>(&self, values: &[Vec<u8>]) -> Option<[u8; 32]> {
+        if self.keys.is_empty() || values.len() != self.keys.len() {
+            return None;
+        }
+        if self.keys.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return None;
+        }
+
+        let leaves: Vec<([u8; 32], &[u8])> = self
+            .keys
+            .iter()
+            .zip(values)
+            .map(|(key, value)| (*key, value.as_slice()))
+            .collect();
+        let mut siblings = self.siblings.iter();
+        let root = compute_subtree::<H>(0, &leaves, &mut siblings)?;
+        if siblings.next().is_some() {
+            return None;
+        }
+        Some(root)
+    }
+
+    /// Checks that each key holds the matching value under `root`.
+    pub fn verify<H: TreeHasher>(&self, root: &[u8; 32], values: &[Vec<u8>]) -> bool {
+        self.compute_root::<H>(values).as_ref() == Some(root)
+    }
+
+    /// Encoded as key count (4) | keys (32 each) | sibling count (4) | siblings (32 each).
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(8 + (self.keys.len() + self.siblings.len()) * 32);
+        data.extend_from_slice(&(self.keys.len() as u32).to_le_bytes());
+        for key in &self.keys {
+            data.extend_from_slice(key);
+        }
+        data.extend_from_slice(&(self.siblings.len() as u32).to_le_bytes());
+        for sibling in &self.siblings {
+            data.extend_from_slice(sibling);
+        }
+        data
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, SystemError> {
+        let mut offset = 0;
+        let keys = read_hashes(data, &mut offset)?;
+        let siblings = read_hashes(data, &mut offset)?;
+        if offset != data.len() {
+            return Err(invalid_proof("Trailing bytes after multi-proof"));
+        }
+        Ok(Self { keys, siblings })
+    }
+}
+
+fn compute_subtree<'a, H: TreeHasher>(
+    depth: usize,
+    leaves: &[([u8; 32], &[u8])],
+    siblings: &mut impl Iterator<Item = &'a [u8; 32]>,
+) -> Option<[u8; 32]> {
+    if depth == TREE_DEPTH {
+        let (key, value) = leaves[0];
+        return Some(if value.is_empty() {
+            EMPTY_LEAF
+        } else {
+            H::hash_leaf(&key, value)
+        });
+    }
+
+    let split = leaves.partition_point(|(key, _)| !key_bit(key, depth));
+    let (left_leaves, right_leaves) = leaves.split_at(split);
+
+    let left = if left_leaves.is_empty() {
+        *siblings.next()?
+    } else {
+        compute_subtree::<H>(depth + 1, left_leaves, siblings)?
+    };
+    let right = if right_leaves.is_empty() {
+        *siblings.next()?
+    } else {
+        compute_subtree::<H>(depth + 1, right_leaves, siblings)?
+    };
+    Some(H::hash_node(&left, &right))
+}
+
+/// Sorts updates by key, keeping the last value given for each key.
+fn normalize(updates: &[([u8; 32], Vec<u8>)]) -> Vec<([u8; 32], Vec<u8>)> {
+    let mut indexed: Vec<(usize, &([u8; 32], Vec<u8>))> = updates.iter().enumerate().collect();
+    indexed.sort_by(|(i, a), (j, b)| a.0.cmp(&b.0).then(j.cmp(i)));
+    indexed.dedup_by(|(_, a), (_, b)| a.0 == b.0);
+    indexed
+        .into_iter()
+        .map(|(_, update)| update.clone())
+        .collect()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn join<A, B, RA, RB>(a: A, b: B) -> (RA, RB)
+where
+    A: FnOnce() -> RA + Send,
+    B: FnOnce() -> RB + Send,
+    RA: Send,
+    RB: Send,
+{
+    rayon::join(a, b)
+}
+
+#[cfg(target_arch = "wasm32")]
+fn join<A, B, RA, RB>(a: A, b: B) -> (RA, RB)
+where
+    A: FnOnce() -> RA + Send,
+    B: FnOnce() -> RB + Send,
+    RA: Send,
+    RB: Send,
+{
+    (a(), b())
+}
+
+fn read_hashes(data: &[u8], offset: &mut usize) -> Result<Vec<[u8; 32]>, SystemError> {
+    if data.len() < *offset + 4 {
+        return Err(invalid_proof("Multi-proof truncated"));
+    }
+    let mut count_bytes = [0u8; 4];
+    count_bytes.copy_from_slice(&data[*offset..*offset + 4]);
+    let count = u32::from_le_bytes(count_bytes) as usize;
+    *offset += 4;
+
+    let end = count
+        .checked_mul(32)
+        .and_then(|len| offset.checked_add(len))
+        .filter(|end| *end <= data.len())
+        .ok_or_else(|| invalid_proof("Multi-proof truncated"))?;
+    let hashes = data[*offset..end]
+        .chunks(32)
+        .map(|chunk| {
+            let mut hash = [0u8; 32];
+            hash.copy_from_slice(chunk);
+            hash
+        })
+        .collect();
+    *offset = end;
+    Ok(hashes)
+}
+
+fn invalid_proof(message: &str) -> SystemError {
+    SystemError::new(SystemErrorType::InvalidProof, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::smt::hasher::{PoseidonHasher, Sha256Hasher};
+    use sha2::{Digest, Sha256};
+
+    fn key(i: u32) -> [u8; 32] {
+        Sha256::digest(i.to_le_bytes()).into()
+    }
+
+    fn updates(range: std::ops::Range<u32>, tag: u8) -> Vec<([u8; 32], Vec<u8>)> {
+        range.map(|i| (key(i), vec![tag, i as u8])).collect()
+    }
+
+    fn check_matches_sequential<H: TreeHasher>(count: u32) {
+        let mut sequential = SparseMerkleTree::<H>::new();
+        let mut batched = SparseMerkleTree::<H>::new();
+
+        let first = updates(0..count, 1);
+        for (k, v) in &first {
+            sequential.update(k, v).unwrap();
+        }
+        batched.batch_update(&first).unwrap();
+        assert_eq!(batched.root(), sequential.root());
+
+        let mut second = updates(count / 2..count + count / 2, 2);
+        second.push((key(0), Vec::new()));
+        for (k, v) in &second {
+            sequential.update(k, v).unwrap();
+        }
+        let result = batched.batch_update(&second).unwrap();
+        assert_eq!(result.new_root, sequential.root());
+        assert_eq!(batched.get(&key(0)).unwrap(), None);
+        assert_eq!(
+            batched.get(&key(count)).unwrap(),
+            sequential.get(&key(count)).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_batch_matches_sequential() {
+        check_matches_sequential::<Sha256Hasher>(300);
+        check_matches_sequential::<PoseidonHasher>(40);
+    }
+
+    #[test]
+    fn test_duplicate_keys_last_write_wins() {
+        let mut tree = SparseMerkleTree::<Sha256Hasher>::new();
+        tree.batch_update(&[
+            (key(1), b"first".to_vec()),
+            (key(2), b"other".to_vec()),
+            (key(1), b"second".to_vec()),
+        ])
+        .unwrap();
+        assert_eq!(tree.get(&key(1)).unwrap(), Some(b"second".to_vec()));
+    }
+
+    #[test]
+    fn test_multi_proof_covers_old_and_new_state() {
+        let mut tree = SparseMerkleTree::<Sha256Hasher>::new();
+        tree.batch_update(&updates(0..50, 1)).unwrap();
+
+        let batch = vec![
+            (key(3), b"three".to_vec()),
+            (key(7), Vec::new()),
+            (key(99), b"new key".to_vec()),
+        ];
+        let result = tree.batch_update(&batch).unwrap();
+        let proof = &result.proof;
+
+        let new_values: Vec<Vec<u8>> = proof
+            .keys
+            .iter()
+            .map(|k| batch.iter().find(|(bk, _)| bk == k).unwrap().1.clone())
+            .collect();
+        assert!(proof.verify::<Sha256Hasher>(&result.new_root, &new_values));
+
+        let old_values: Vec<Vec<u8>> = proof
+            .keys
+            .iter()
+            .map(|k| {
+                (0..50u32)
+                    .find(|i| key(*i) == *k)
+                    .map_or(Vec::new(), |i| vec![1, i as u8])
+            })
+            .collect();
+        assert!(proof.verify::<Sha256Hasher>(&result.old_root, &old_values));
+        assert!(!proof.verify::<Sha256Hasher>(&result.new_root, &old_values));
+
+        let decoded = MultiProof::from_bytes(&proof.to_bytes()).unwrap();
+        assert_eq!(&decoded, proof);
+    }
+
+    #[test]
+    fn test_multi_proof_rejects_tampering() {
+        let mut tree = SparseMerkleTree::<Sha256Hasher>::new();
+        let result = tree.batch_update(&updates(0..20, 1)).unwrap();
+        let values: Vec<Vec<u8>> = result
+            .proof
+            .keys
+            .iter()
+            .map(|k| tree.get(k).unwrap().unwrap())
+            .collect();
+        assert!(result
+            .proof
+            .verify::<Sha256Hasher>(&result.new_root, &values));
+
+        let mut extra = result.proof.clone();
+        extra.siblings.push([7u8; 32]);
+        assert!(!extra.verify::<Sha256Hasher>(&result.new_root, &values));
+
+        let mut unsorted = result.proof.clone();
+        unsorted.keys.swap(0, 1);
+        assert!(!unsorted.verify::<Sha256Hasher>(&result.new_root, &values));
+    }
+}
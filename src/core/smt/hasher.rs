@@ -0,0 +1,202 @@
+// ./src/core/smt/hasher.rs
+
+// Tree Hashers
+// The hash functions a `SparseMerkleTree` can be instantiated with. Leaves and internal nodes
+// are hashed separately so a leaf can never be passed off as a node. All hashers map into
+// 32-byte digests; the empty leaf is all zeroes for every hasher.
+
+use blake2::{Blake2s, Digest as _};
+use plonky2::field::goldilocks_field::GoldilocksField;
+use plonky2::field::types::{Field, PrimeField64};
+use plonky2::hash::hash_types::HashOut;
+use plonky2::hash::poseidon::PoseidonHash;
+use plonky2::plonk::config::Hasher;
+use sha2::{Digest, Sha256};
+
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+
+/// First input element of every Poseidon leaf hash.
+pub const LEAF_DOMAIN: u64 = 0;
+/// First input element of every Poseidon node hash.
+pub const NODE_DOMAIN: u64 = 1;
+
+/// Number of key/value bytes packed into one Goldilocks element.
+/// Seven bytes always fit below the field modulus.
+const BYTES_PER_ELEMENT: usize = 7;
+
+/// Hash function used for leaves and internal nodes of a sparse Merkle tree.
+pub trait TreeHasher: Send + Sync + 'static {
+    fn hash_leaf(key: &[u8; 32], value: &[u8]) -> [u8; 32];
+    fn hash_node(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32];
+}
+
+/// SHA-256 with one-byte leaf/node prefixes.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Sha256Hasher;
+
+impl TreeHasher for Sha256Hasher {
+    fn hash_leaf(key: &[u8; 32], value: &[u8]) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update([LEAF_PREFIX]);
+        hasher.update(key);
+        hasher.update(value);
+        hasher.finalize().into()
+    }
+
+    fn hash_node(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update([NODE_PREFIX]);
+        hasher.update(left);
+        hasher.update(right);
+        hasher.finalize().into()
+    }
+}
+
+/// Blake2s-256 with one-byte leaf/node prefixes.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Blake2Hasher;
+
+impl TreeHasher for Blake2Hasher {
+    fn hash_leaf(key: &[u8; 32], value: &[u8]) -> [u8; 32] {
+        let mut hasher = Blake2s::new();
+        hasher.update([LEAF_PREFIX]);
+        hasher.update(key);
+        hasher.update(value);
+        let mut output = [0u8; 32];
+        output.copy_from_slice(&hasher.finalize());
+        output
+    }
+
+    fn hash_node(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+        let mut hasher = Blake2s::new();
+        hasher.update([NODE_PREFIX]);
+        hasher.update(left);
+        hasher.update(right);
+        let mut output = [0u8; 32];
+        output.copy_from_slice(&hasher.finalize());
+        output
+    }
+}
+
+/// plonky2 Poseidon over Goldilocks.
+///
+/// A digest is four field elements stored as little-endian u64 limbs. Internal nodes hash
+/// `NODE_DOMAIN` followed by the eight limbs of both children with `hash_no_pad`, which is
+/// exactly what `poseidon_node` computes in-circuit. Leaves hash `LEAF_DOMAIN` followed by
+/// the key and value packed seven bytes per element, the value prefixed with its length. The
+/// domain element keeps a leaf from ever hashing the same inputs as a node.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PoseidonHasher;
+
+impl PoseidonHasher {
+    pub fn to_hash_out(bytes: &[u8; 32]) -> HashOut<GoldilocksField> {
+        let mut elements = [GoldilocksField::ZERO; 4];
+        for (element, chunk) in elements.iter_mut().zip(bytes.chunks(8)) {
+            let mut limb = [0u8; 8];
+            limb.copy_from_slice(chunk);
+            *element = GoldilocksField::from_noncanonical_u64(u64::from_le_bytes(limb));
+        }
+        HashOut { elements }
+    }
+
+    pub fn from_hash_out(hash: HashOut<GoldilocksField>) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        for (chunk, element) in bytes.chunks_mut(8).zip(hash.elements.iter()) {
+            chunk.copy_from_slice(&element.to_canonical_u64().to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Packs bytes into field elements, seven bytes per element.
+    pub fn pack_bytes(bytes: &[u8]) -> Vec<GoldilocksField> {
+        bytes
+            .chunks(BYTES_PER_ELEMENT)
+            .map(|chunk| {
+                let mut limb = [0u8; 8];
+                limb[..chunk.len()].copy_from_slice(chunk);
+                GoldilocksField::from_canonical_u64(u64::from_le_bytes(limb))
+            })
+            .collect()
+    }
+}
+
+impl TreeHasher for PoseidonHasher {
+    fn hash_leaf(key: &[u8; 32], value: &[u8]) -> [u8; 32] {
+        let mut inputs = vec![GoldilocksField::from_canonical_u64(LEAF_DOMAIN)];
+        inputs.extend(Self::pack_bytes(key));
+        inputs.push(GoldilocksField::from_canonical_u64(value.len() as u64));
+        inputs.extend(Self::pack_bytes(value));
+        Self::from_hash_out(PoseidonHash::hash_no_pad(&inputs))
+    }
+
+    fn hash_node(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+        let mut inputs = Vec::with_capacity(9);
+        inputs.push(GoldilocksField::from_canonical_u64(NODE_DOMAIN));
+        inputs.extend_from_slice(&Self::to_hash_out(left).elements);
+        inputs.extend_from_slice(&Self::to_hash_out(right).elements);
+        Self::from_hash_out(PoseidonHash::hash_no_pad(&inputs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_domain_separation<H: TreeHasher>() {
+        let a = [1u8; 32];
+        let b = [2u8; 32];
+        assert_ne!(H::hash_node(&a, &b), H::hash_node(&b, &a));
+        assert_ne!(H::hash_leaf(&a, &[1]), H::hash_leaf(&a, &[2]));
+        assert_ne!(H::hash_leaf(&a, &[0]), H::hash_leaf(&a, &[0, 0]));
+        assert_eq!(H::hash_node(&a, &b), H::hash_node(&a, &b));
+        assert_ne!(H::hash_leaf(&a, &b), H::hash_node(&a, &b));
+    }
+
+    #[test]
+    fn test_hashers_are_deterministic_and_separated() {
+        check_domain_separation::<Sha256Hasher>();
+        check_domain_separation::<Blake2Hasher>();
+        check_domain_separation::<PoseidonHasher>();
+    }
+
+    #[test]
+    fn test_poseidon_hash_out_roundtrip() {
+        let hash = PoseidonHash::hash_no_pad(&[GoldilocksField::from_canonical_u64(7)]);
+        let bytes = PoseidonHasher::from_hash_out(hash);
+        assert_eq!(PoseidonHasher::to_hash_out(&bytes), hash);
+    }
+
+    #[test]
+    fn test_poseidon_node_matches_field_hash() {
+        let left = PoseidonHash::hash_no_pad(&[GoldilocksField::ONE]);
+        let right = PoseidonHash::hash_no_pad(&[GoldilocksField::TWO]);
+        let mut inputs = vec![GoldilocksField::from_canonical_u64(NODE_DOMAIN)];
+        inputs.extend_from_slice(&left.elements);
+        inputs.extend_from_slice(&right.elements);
+        let expected = PoseidonHash::hash_no_pad(&inputs);
+        let actual = PoseidonHasher::hash_node(
+            &PoseidonHasher::from_hash_out(left),
+            &PoseidonHasher::from_hash_out(right),
+        );
+        assert_eq!(PoseidonHasher::to_hash_out(&actual), expected);
+    }
+
+    #[test]
+    fn test_poseidon_leaf_is_not_a_node_over_the_same_limbs() {
+        // A 32-byte key and a ten-byte value pack into eight elements, as many as a node hashes
+        let key = [3u8; 32];
+        let value = [4u8; 10];
+        let mut packed = PoseidonHasher::pack_bytes(&key);
+        packed.push(GoldilocksField::from_canonical_u64(value.len() as u64));
+        packed.extend(PoseidonHasher::pack_bytes(&value));
+        assert_eq!(packed.len(), 8);
+
+        let left = PoseidonHasher::from_hash_out(HashOut::from_partial(&packed[..4]));
+        let right = PoseidonHasher::from_hash_out(HashOut::from_partial(&packed[4..]));
+        assert_ne!(
+            PoseidonHasher::hash_leaf(&key, &value),
+            PoseidonHasher::hash_node(&left, &right)
+        );
+    }
+}
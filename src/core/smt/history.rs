@@ -0,0 +1,175 @@
+// ./src/core/smt/history.rs
+
+// Historical Queries
+// Point-in-time reads against committed versions. A historical read returns the value a key
+// held at a version together with a proof against that version's root: an inclusion proof
+// when the key was set, a non-membership proof when it was not. Changed keys between two
+// versions are found by walking both roots together and skipping every subtree they share.
+
+use crate::core::error::errors::{SystemError, SystemErrorType};
+use crate::core::smt::hasher::TreeHasher;
+use crate::core::smt::proof::{MerkleProof, NonMembershipProof};
+use crate::core::smt::store::NodeStore;
+use crate::core::smt::tree::{SparseMerkleTree, EMPTY_LEAF, TREE_DEPTH};
+use serde::{Deserialize, Serialize};
+
+/// Proof backing a historical read.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HistoricalProof {
+    Included(MerkleProof),
+    Absent(NonMembershipProof),
+}
+
+/// The value of a key at a committed version, with a proof against that version's root.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoricalValue {
+    pub version: u64,
+    pub root: [u8; 32],
+    pub key: [u8; 32],
+    pub value: Option<Vec<u8>>,
+    pub proof: HistoricalProof,
+}
+
+impl HistoricalValue {
+    /// Checks the proof against `root`, which the caller must take from a trusted record of
+    /// the version's root rather than from this value.
+    pub fn verify<H: TreeHasher>(&self, root: &[u8; 32]) -> bool {
+        if root != &self.root {
+            return false;
+        }
+        match (&self.value, &self.proof) {
+            (Some(value), HistoricalProof::Included(proof)) => {
+                proof.verify::<H>(root, &self.key, value)
+            }
+            (None, HistoricalProof::Absent(proof)) => proof.verify::<H>(root, &self.key),
+            _ => false,
+        }
+    }
+}
+
+impl<H: TreeHasher, S: NodeStore> SparseMerkleTree<H, S> {
+    /// Returns the value of `key` at a committed version, with a proof against its root.
+    pub fn get_at_version(
+        &self,
+        version: u64,
+        key: &[u8; 32],
+    ) -> Result<HistoricalValue, SystemError> {
+        let root = self.committed_root(version)?;
+        let value = self.get_at(&root, key)?;
+        let proof = match value {
+            Some(_) => HistoricalProof::Included(self.prove_at(&root, key)?),
+            None => HistoricalProof::Absent(self.prove_absence_at(&root, key)?),
+        };
+        Ok(HistoricalValue {
+            version,
+            root,
+            key: *key,
+            value,
+            proof,
+        })
+    }
+
+    /// Keys whose value differs between two committed versions, in key order.
+    pub fn changed_keys(
+        &self,
+        from_version: u64,
+        to_version: u64,
+    ) -> Result<Vec<[u8; 32]>, SystemError> {
+        let from = self.committed_root(from_version)?;
+        let to = self.committed_root(to_version)?;
+        self.diff(&from, &to)
+    }
+
+    /// Keys whose value differs between two stored roots, in key order.
+    pub fn diff(
+        &self,
+        old_root: &[u8; 32],
+        new_root: &[u8; 32],
+    ) -> Result<Vec<[u8; 32]>, SystemError> {
+        let mut changed = Vec::new();
+        // Right children are pushed first so keys come out in ascending order.
+        let mut stack = vec![(*old_root, *new_root, 0usize)];
+        while let Some((old, new, depth)) = stack.pop() {
+            if old == new {
+                continue;
+            }
+            if depth == TREE_DEPTH {
+                let leaf = if new != EMPTY_LEAF { new } else { old };
+                changed.push(self.leaf_key(&leaf)?);
+                continue;
+            }
+            let (old_left, old_right) = self.children(&old, depth)?;
+            let (new_left, new_right) = self.children(&new, depth)?;
+            stack.push((old_right, new_right, depth + 1));
+            stack.push((old_left, new_left, depth + 1));
+        }
+        Ok(changed)
+    }
+
+    fn committed_root(&self, version: u64) -> Result<[u8; 32], SystemError> {
+        self.version_root(version).ok_or_else(|| {
+            SystemError::new(
+                SystemErrorType::NotFound,
+                format!("Version {} is not retained", version),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::smt::hasher::Sha256Hasher;
+
+    fn key(byte: u8) -> [u8; 32] {
+        let mut key = [0u8; 32];
+        key[0] = byte;
+        key
+    }
+
+    fn committed_tree() -> SparseMerkleTree<Sha256Hasher> {
+        let mut tree = SparseMerkleTree::<Sha256Hasher>::new();
+        tree.update(&key(1), b"a").unwrap();
+        tree.update(&key(2), b"b").unwrap();
+        tree.commit_version(1).unwrap();
+        tree.update(&key(2), b"c").unwrap();
+        tree.update(&key(3), b"d").unwrap();
+        tree.commit_version(2).unwrap();
+        tree.remove(&key(1)).unwrap();
+        tree.update(&key(3), b"d").unwrap();
+        tree.commit_version(3).unwrap();
+        tree
+    }
+
+    #[test]
+    fn test_value_at_version_with_proof() {
+        let tree = committed_tree();
+        let epoch_one = tree.version_root(1).unwrap();
+
+        let set = tree.get_at_version(1, &key(2)).unwrap();
+        assert_eq!(set.value, Some(b"b".to_vec()));
+        assert!(set.verify::<Sha256Hasher>(&epoch_one));
+        assert!(!set.verify::<Sha256Hasher>(&tree.root()));
+
+        let unset = tree.get_at_version(1, &key(3)).unwrap();
+        assert_eq!(unset.value, None);
+        assert!(unset.verify::<Sha256Hasher>(&epoch_one));
+
+        let mut forged = set.clone();
+        forged.value = Some(b"c".to_vec());
+        assert!(!forged.verify::<Sha256Hasher>(&epoch_one));
+        assert!(tree.get_at_version(7, &key(2)).is_err());
+    }
+
+    #[test]
+    fn test_changed_keys_between_versions() {
+        let tree = committed_tree();
+        assert_eq!(tree.changed_keys(1, 2).unwrap(), vec![key(2), key(3)]);
+        assert_eq!(tree.changed_keys(2, 3).unwrap(), vec![key(1)]);
+        assert_eq!(
+            tree.changed_keys(1, 3).unwrap(),
+            vec![key(1), key(2), key(3)]
+        );
+        assert!(tree.changed_keys(3, 3).unwrap().is_empty());
+    }
+}
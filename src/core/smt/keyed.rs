@@ -0,0 +1,185 @@
+// ./src/core/smt/keyed.rs
+
+// Keyed Hierarchy Tree
+// The tree the intermediate and root layers keep: a `HierarchyTree` keyed by 32-byte
+// identifiers, wallet ids at the intermediate layer and intermediate contract addresses at
+// the root. Keys arrive as byte slices and are rejected unless they are exactly 32 bytes.
+// Roots can be committed per epoch and queried while they are retained.
+
+use crate::core::error::errors::{SystemError, SystemErrorType};
+use crate::core::smt::batch::BatchUpdate;
+use crate::core::smt::history::HistoricalValue;
+use crate::core::smt::proof::{CompressedProof, MerkleProof, NonMembershipProof};
+use crate::core::smt::store::{MemoryNodeStore, NodeStore};
+use crate::core::smt::{HierarchyHasher, HierarchyTree};
+use crate::core::types::boc::BOC;
+use crate::core::zkps::tree_transition::TransitionWitness;
+
+/// A hierarchy tree keyed by 32-byte identifiers.
+#[derive(Clone, Default)]
+pub struct KeyedTree<S: NodeStore = MemoryNodeStore> {
+    tree: HierarchyTree<S>,
+}
+
+impl KeyedTree {
+    /// Create a new Sparse Merkle Tree
+    pub fn new() -> Self {
+        Self {
+            tree: HierarchyTree::new(),
+        }
+    }
+
+    /// Verify an inclusion proof against a root
+    pub fn verify(root: &[u8; 32], key: &[u8], value: &[u8], proof: &MerkleProof) -> bool {
+        match to_key(key) {
+            Ok(key) => proof.verify::<HierarchyHasher>(root, &key, value),
+            Err(_) => false,
+        }
+    }
+
+    /// Verify a compressed inclusion proof against a root
+    pub fn verify_compressed(
+        root: &[u8; 32],
+        key: &[u8],
+        value: &[u8],
+        proof: &CompressedProof,
+    ) -> bool {
+        match to_key(key) {
+            Ok(key) => proof.verify::<HierarchyHasher>(root, &key, value),
+            Err(_) => false,
+        }
+    }
+
+    /// Verify a non-membership proof against a root
+    pub fn verify_absence(root: &[u8; 32], key: &[u8], proof: &NonMembershipProof) -> bool {
+        match to_key(key) {
+            Ok(key) => proof.verify::<HierarchyHasher>(root, &key),
+            Err(_) => false,
+        }
+    }
+
+    /// Restore the tree state from a BOC produced by `serialize_state`
+    pub fn deserialize_state(boc: &BOC) -> Result<Self, SystemError> {
+        Ok(Self {
+            tree: HierarchyTree::from_boc(boc)?,
+        })
+    }
+}
+
+impl<S: NodeStore> KeyedTree<S> {
+    /// Open a tree over a node store at its latest committed epoch
+    pub fn open(store: S) -> Result<Self, SystemError> {
+        Ok(Self {
+            tree: HierarchyTree::<S>::open(store)?,
+        })
+    }
+
+    /// Open a tree over a node store at the root committed for an earlier epoch
+    pub fn open_at_epoch(store: S, epoch: u64) -> Result<Self, SystemError> {
+        Ok(Self {
+            tree: HierarchyTree::<S>::open_at_version(store, epoch)?,
+        })
+    }
+
+    /// Keep only the given number of most recent epochs queryable
+    pub fn with_retention(self, epochs: u64) -> Self {
+        Self {
+            tree: self.tree.with_retention(epochs),
+        }
+    }
+
+    /// Commit the current root for an epoch, pruning epochs outside the retention window
+    pub fn commit_epoch(&mut self, epoch: u64) -> Result<[u8; 32], SystemError> {
+        self.tree.commit_version(epoch)
+    }
+
+    /// Return the root committed for an epoch, if it is still retained
+    pub fn epoch_root(&self, epoch: u64) -> Option<[u8; 32]> {
+        self.tree.version_root(epoch)
+    }
+
+    /// Return every retained epoch and its committed root, oldest first
+    pub fn epoch_roots(&self) -> Vec<(u64, [u8; 32])> {
+        self.tree
+            .versions()
+            .iter()
+            .map(|(epoch, root)| (*epoch, *root))
+            .collect()
+    }
+
+    /// Get the value a key held at an epoch, with a proof against that epoch's root
+    pub fn get_at_epoch(&self, epoch: u64, key: &[u8]) -> Result<HistoricalValue, SystemError> {
+        self.tree.get_at_version(epoch, &to_key(key)?)
+    }
+
+    /// List the keys whose value changed between two epochs
+    pub fn changed_keys(
+        &self,
+        from_epoch: u64,
+        to_epoch: u64,
+    ) -> Result<Vec<[u8; 32]>, SystemError> {
+        self.tree.changed_keys(from_epoch, to_epoch)
+    }
+
+    /// Update a leaf in the Merkle tree
+    pub fn update(&mut self, key: &[u8], value: &[u8]) -> Result<(), SystemError> {
+        self.tree.update(&to_key(key)?, value)?;
+        Ok(())
+    }
+
+    /// Apply many child roots at once; returns the old and new roots with a multi-proof
+    pub fn batch_update(
+        &mut self,
+        updates: &[([u8; 32], Vec<u8>)],
+    ) -> Result<BatchUpdate, SystemError> {
+        self.tree.batch_update(updates)
+    }
+
+    /// Apply child roots in order and record the witness for a tree transition proof
+    pub fn apply_with_witness(
+        &mut self,
+        updates: &[([u8; 32], Vec<u8>)],
+    ) -> Result<TransitionWitness, SystemError> {
+        TransitionWitness::record(&mut self.tree, updates)
+    }
+
+    /// Get the value stored for a key
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, SystemError> {
+        self.tree.get(&to_key(key)?)
+    }
+
+    /// Generate an inclusion proof for a key
+    pub fn prove(&self, key: &[u8]) -> Result<MerkleProof, SystemError> {
+        self.tree.prove(&to_key(key)?)
+    }
+
+    /// Generate an inclusion proof with the default siblings left out
+    pub fn prove_compressed(&self, key: &[u8]) -> Result<CompressedProof, SystemError> {
+        self.tree.prove_compressed(&to_key(key)?)
+    }
+
+    /// Generate a proof that a key has no leaf
+    pub fn prove_absence(&self, key: &[u8]) -> Result<NonMembershipProof, SystemError> {
+        self.tree.prove_absence(&to_key(key)?)
+    }
+
+    /// Return the current root hash of the tree
+    pub fn root(&self) -> [u8; 32] {
+        self.tree.root()
+    }
+
+    /// Serialize the tree state to a BOC format
+    pub fn serialize_state(&self) -> Result<BOC, SystemError> {
+        self.tree.to_boc()
+    }
+}
+
+/// Keys are 32-byte identifiers at every level.
+fn to_key(key: &[u8]) -> Result<[u8; 32], SystemError> {
+    key.try_into().map_err(|_| {
+        SystemError::new(
+            SystemErrorType::InvalidAddress,
+            format!("Tree keys must be 32 bytes, got {}", key.len()),
+        )
+    })
+}
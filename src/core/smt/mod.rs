@@ -0,0 +1,22 @@
+// ./src/core/smt/mod.rs
+
+// Sparse Merkle Tree
+// The generic sparse Merkle tree shared by the client, intermediate and root layers.
+
+pub mod batch;
+pub mod hasher;
+pub mod history;
+pub mod keyed;
+pub mod proof;
+pub mod store;
+pub mod tree;
+
+use hasher::PoseidonHasher;
+use store::MemoryNodeStore;
+
+/// The hasher every hierarchy level commits with, so roots and proofs agree across layers
+/// and can be checked in-circuit.
+pub type HierarchyHasher = PoseidonHasher;
+
+/// The tree type used by every hierarchy level, in memory unless another store is given.
+pub type HierarchyTree<S = MemoryNodeStore> = tree::SparseMerkleTree<HierarchyHasher, S>;
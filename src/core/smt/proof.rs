@@ -0,0 +1,350 @@
+// ./src/core/smt/proof.rs
+
+// Merkle Proofs
+// An inclusion proof is the list of sibling hashes on the path from the root to a key's leaf.
+// A non-membership proof stops where the key's path enters an empty subtree: every sibling
+// below that point is a default hash, so only about log2(N) siblings are carried.
+// Both are serialized as key (32) | sibling count (2) | siblings (32 each), root side first.
+//
+// A compressed inclusion proof drops the siblings that are empty-subtree defaults, which is
+// most of them in a sparse tree. It carries a 256-bit bitmap (bit d, most significant bit
+// first, set when sibling d is not the default) and only the marked siblings:
+//   binary  key (32) | bitmap (32) | marked siblings (32 each), root side first
+//   BOC     cell 0 (root)  key | bitmap, references = [1]
+//           cell 1         marked siblings
+// Both encodings are canonical: a marked sibling equal to its default is rejected.
+
+use crate::core::error::errors::{SystemError, SystemErrorType};
+use crate::core::smt::hasher::TreeHasher;
+use crate::core::smt::tree::{default_hashes, key_bit, EMPTY_LEAF, TREE_DEPTH};
+use crate::core::types::boc::{Cell, CellType, BOC};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    pub key: [u8; 32],
+    /// `siblings[d]` is the sibling of the path node at depth `d + 1`.
+    pub siblings: Vec<[u8; 32]>,
+}
+
+impl MerkleProof {
+    pub fn new(key: [u8; 32], siblings: Vec<[u8; 32]>) -> Self {
+        Self { key, siblings }
+    }
+
+    /// Recomputes the root from a leaf hash. Returns None for a malformed proof.
+    pub fn compute_root<H: TreeHasher>(&self, leaf_hash: [u8; 32]) -> Option<[u8; 32]> {
+        if self.siblings.len() != TREE_DEPTH {
+            return None;
+        }
+        let mut current = leaf_hash;
+        for depth in (0..TREE_DEPTH).rev() {
+            let sibling = &self.siblings[depth];
+            current = if key_bit(&self.key, depth) {
+                H::hash_node(sibling, &current)
+            } else {
+                H::hash_node(&current, sibling)
+            };
+        }
+        Some(current)
+    }
+
+    /// Checks that `key` holds `value` under `root`.
+    pub fn verify<H: TreeHasher>(&self, root: &[u8; 32], key: &[u8; 32], value: &[u8]) -> bool {
+        if &self.key != key || value.is_empty() {
+            return false;
+        }
+        self.compute_root::<H>(H::hash_leaf(key, value)).as_ref() == Some(root)
+    }
+
+    /// Drops the default siblings, keeping a bitmap of the ones that remain.
+    pub fn compress<H: TreeHasher>(&self) -> Result<CompressedProof, SystemError> {
+        CompressedProof::from_proof(self, &default_hashes::<H>())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encode_path(&self.key, &self.siblings)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, SystemError> {
+        let (key, siblings) = decode_path(data)?;
+        if siblings.len() != TREE_DEPTH {
+            return Err(invalid_proof("Invalid Merkle proof length"));
+        }
+        Ok(Self { key, siblings })
+    }
+}
+
+/// Proof that a key has no leaf under a root.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NonMembershipProof {
+    pub key: [u8; 32],
+    /// Siblings from the root down to the first empty subtree on the key's path.
+    pub siblings: Vec<[u8; 32]>,
+}
+
+impl NonMembershipProof {
+    pub fn new(key: [u8; 32], siblings: Vec<[u8; 32]>) -> Self {
+        Self { key, siblings }
+    }
+
+    /// Depth of the empty subtree the key falls into.
+    pub fn depth(&self) -> usize {
+        self.siblings.len()
+    }
+
+    /// Checks that `key` is absent under `root`.
+    pub fn verify<H: TreeHasher>(&self, root: &[u8; 32], key: &[u8; 32]) -> bool {
+        if &self.key != key || self.siblings.len() > TREE_DEPTH {
+            return false;
+        }
+
+        let mut current = EMPTY_LEAF;
+        for _ in self.siblings.len()..TREE_DEPTH {
+            current = H::hash_node(&current, &current);
+        }
+        for depth in (0..self.siblings.len()).rev() {
+            let sibling = &self.siblings[depth];
+            current = if key_bit(key, depth) {
+                H::hash_node(sibling, &current)
+            } else {
+                H::hash_node(&current, sibling)
+            };
+        }
+        &current == root
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encode_path(&self.key, &self.siblings)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, SystemError> {
+        let (key, siblings) = decode_path(data)?;
+        if siblings.len() > TREE_DEPTH {
+            return Err(invalid_proof("Invalid non-membership proof length"));
+        }
+        Ok(Self { key, siblings })
+    }
+}
+
+/// Inclusion proof that carries only the non-default siblings.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompressedProof {
+    pub key: [u8; 32],
+    /// Bit `d` (most significant bit first) is set when sibling `d` is not the default.
+    pub bitmap: [u8; 32],
+    /// The non-default siblings, root side first.
+    pub siblings: Vec<[u8; 32]>,
+}
+
+impl CompressedProof {
+    /// Compresses a full proof given the tree's per-depth default hashes.
+    pub fn from_proof(proof: &MerkleProof, defaults: &[[u8; 32]]) -> Result<Self, SystemError> {
+        if proof.siblings.len() != TREE_DEPTH || defaults.len() != TREE_DEPTH + 1 {
+            return Err(invalid_proof("Invalid Merkle proof length"));
+        }
+        let mut bitmap = [0u8; 32];
+        let mut siblings = Vec::new();
+        for (depth, sibling) in proof.siblings.iter().enumerate() {
+            if sibling != &defaults[depth + 1] {
+                bitmap[depth / 8] |= 0x80 >> (depth % 8);
+                siblings.push(*sibling);
+            }
+        }
+        Ok(Self {
+            key: proof.key,
+            bitmap,
+            siblings,
+        })
+    }
+
+    /// Restores the full proof.
+    pub fn decompress<H: TreeHasher>(&self) -> Result<MerkleProof, SystemError> {
+        let defaults = default_hashes::<H>();
+        let mut marked = self.siblings.iter();
+        let mut siblings = Vec::with_capacity(TREE_DEPTH);
+        for depth in 0..TREE_DEPTH {
+            if key_bit(&self.bitmap, depth) {
+                let sibling = marked
+                    .next()
+                    .ok_or_else(|| invalid_proof("Bitmap marks more siblings than given"))?;
+                siblings.push(*sibling);
+            } else {
+                siblings.push(defaults[depth + 1]);
+            }
+        }
+        if marked.next().is_some() {
+            return Err(invalid_proof("Bitmap marks fewer siblings than given"));
+        }
+        Ok(MerkleProof::new(self.key, siblings))
+    }
+
+    /// Recomputes the root from a leaf hash, filling in defaults on the way up.
+    /// Returns None for a malformed proof.
+    pub fn compute_root<H: TreeHasher>(&self, leaf_hash: [u8; 32]) -> Option<[u8; 32]> {
+        if self.siblings.len() != self.marked_count() {
+            return None;
+        }
+        let mut marked = self.siblings.iter().rev();
+        let mut current = leaf_hash;
+        let mut default = EMPTY_LEAF;
+        for depth in (0..TREE_DEPTH).rev() {
+            let sibling = if key_bit(&self.bitmap, depth) {
+                *marked.next()?
+            } else {
+                default
+            };
+            current = if key_bit(&self.key, depth) {
+                H::hash_node(&sibling, &current)
+            } else {
+                H::hash_node(&current, &sibling)
+            };
+            default = H::hash_node(&default, &default);
+        }
+        Some(current)
+    }
+
+    /// Checks that `key` holds `value` under `root`. Works against the wallet, intermediate
+    /// and global roots alike, since every level uses the same tree.
+    pub fn verify<H: TreeHasher>(&self, root: &[u8; 32], key: &[u8; 32], value: &[u8]) -> bool {
+        if &self.key != key || value.is_empty() {
+            return false;
+        }
+        self.compute_root::<H>(H::hash_leaf(key, value)).as_ref() == Some(root)
+    }
+
+    /// Number of siblings the bitmap marks as present.
+    pub fn marked_count(&self) -> usize {
+        self.bitmap
+            .iter()
+            .map(|byte| byte.count_ones() as usize)
+            .sum()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(64 + self.siblings.len() * 32);
+        data.extend_from_slice(&self.key);
+        data.extend_from_slice(&self.bitmap);
+        for sibling in &self.siblings {
+            data.extend_from_slice(sibling);
+        }
+        data
+    }
+
+    /// Parses the canonical binary encoding. Hasher-specific so that marked siblings equal to
+    /// their default, which have a shorter encoding, are rejected.
+    pub fn from_bytes<H: TreeHasher>(data: &[u8]) -> Result<Self, SystemError> {
+        if data.len() < 64 {
+            return Err(invalid_proof("Compressed proof too short"));
+        }
+        let proof = Self {
+            key: data[0..32].try_into().unwrap(),
+            bitmap: data[32..64].try_into().unwrap(),
+            siblings: decode_hashes(&data[64..])?,
+        };
+        proof.check_canonical::<H>()?;
+        Ok(proof)
+    }
+
+    pub fn to_boc(&self) -> BOC {
+        let mut boc = BOC::new();
+        let mut header = Vec::with_capacity(64);
+        header.extend_from_slice(&self.key);
+        header.extend_from_slice(&self.bitmap);
+        let mut root = Cell::new(header, vec![1], CellType::MerkleProof, [0u8; 32], None);
+        root.update_merkle_hash();
+        let root_index = boc.add_cell(root);
+
+        let mut siblings = Cell::with_data(self.siblings.concat());
+        siblings.update_merkle_hash();
+        boc.add_cell(siblings);
+        boc.add_root(root_index);
+        boc
+    }
+
+    pub fn from_boc<H: TreeHasher>(boc: &BOC) -> Result<Self, SystemError> {
+        let root = boc.get_root_cell().ok_or_else(|| {
+            SystemError::new(
+                SystemErrorType::NoRootCell,
+                "Proof BOC has no root cell".to_string(),
+            )
+        })?;
+        let header = root.get_data();
+        if header.len() != 64 || root.references.len() != 1 {
+            return Err(invalid_proof("Invalid compressed proof root cell"));
+        }
+        let siblings = boc
+            .get_cell(root.references[0])
+            .ok_or_else(|| invalid_proof("Missing sibling cell"))?;
+
+        let proof = Self {
+            key: header[0..32].try_into().unwrap(),
+            bitmap: header[32..64].try_into().unwrap(),
+            siblings: decode_hashes(siblings.get_data())?,
+        };
+        proof.check_canonical::<H>()?;
+        Ok(proof)
+    }
+
+    fn check_canonical<H: TreeHasher>(&self) -> Result<(), SystemError> {
+        if self.siblings.len() != self.marked_count() {
+            return Err(invalid_proof(
+                "Compressed proof sibling count does not match bitmap",
+            ));
+        }
+        let defaults = default_hashes::<H>();
+        let mut marked = self.siblings.iter();
+        for depth in 0..TREE_DEPTH {
+            if key_bit(&self.bitmap, depth) && marked.next() == Some(&defaults[depth + 1]) {
+                return Err(invalid_proof("Compressed proof includes a default sibling"));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn encode_path(key: &[u8; 32], siblings: &[[u8; 32]]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(34 + siblings.len() * 32);
+    data.extend_from_slice(key);
+    data.extend_from_slice(&(siblings.len() as u16).to_le_bytes());
+    for sibling in siblings {
+        data.extend_from_slice(sibling);
+    }
+    data
+}
+
+fn decode_path(data: &[u8]) -> Result<([u8; 32], Vec<[u8; 32]>), SystemError> {
+    if data.len() < 34 {
+        return Err(invalid_proof("Merkle proof too short"));
+    }
+    let mut key = [0u8; 32];
+    key.copy_from_slice(&data[0..32]);
+    let count = u16::from_le_bytes([data[32], data[33]]) as usize;
+    if data.len() != 34 + count * 32 {
+        return Err(invalid_proof(
+            "Merkle proof length does not match sibling count",
+        ));
+    }
+
+    Ok((key, decode_hashes(&data[34..])?))
+}
+
+fn decode_hashes(data: &[u8]) -> Result<Vec<[u8; 32]>, SystemError> {
+    if data.len() % 32 != 0 {
+        return Err(invalid_proof(
+            "Sibling data is not a whole number of hashes",
+        ));
+    }
+    Ok(data
+        .chunks(32)
+        .map(|chunk| {
+            let mut sibling = [0u8; 32];
+            sibling.copy_from_slice(chunk);
+            sibling
+        })
+        .collect())
+}
+
+fn invalid_proof(message: &str) -> SystemError {
+    SystemError::new(SystemErrorType::InvalidProof, message.to_string())
+}
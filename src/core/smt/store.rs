@@ -0,0 +1,594 @@
+// ./src/core/smt/store.rs
+
+// Node Storage
+// Tree nodes live in a `NodeStore`, keyed by hash, each with a reference count: one for every
+// stored parent pointing at it and one for every committed version whose root it is. The tree
+// maintains the counts; a store only has to keep nodes, counts and the version table.
+//
+// `MemoryNodeStore` keeps everything in maps. `FileNodeStore` appends every change to a local
+// log file and keeps only the node index (offset, length, count) and the version table in
+// memory. The log is replayed on open; a torn record at the tail is discarded, and `compact`
+// rewrites the log with only live records.
+
+use crate::core::error::errors::{SystemError, SystemErrorType};
+use crate::core::smt::tree::Node;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Backing storage for tree nodes and committed versions.
+pub trait NodeStore: Send + Sync {
+    /// Returns the node stored under `hash`.
+    fn get_node(&self, hash: &[u8; 32]) -> Result<Option<Node>, SystemError>;
+
+    /// Stores a node with a reference count of zero. Storing an existing hash is a no-op.
+    fn put_node(&mut self, hash: [u8; 32], node: Node) -> Result<(), SystemError>;
+
+    /// Deletes a node and its reference count.
+    fn delete_node(&mut self, hash: &[u8; 32]) -> Result<(), SystemError>;
+
+    /// Returns the node's reference count, or None if the node is not stored.
+    fn ref_count(&self, hash: &[u8; 32]) -> Result<Option<u64>, SystemError>;
+
+    /// Sets the reference count of a stored node.
+    fn set_ref_count(&mut self, hash: &[u8; 32], count: u64) -> Result<(), SystemError>;
+
+    /// Records the root committed for a version.
+    fn put_version(&mut self, version: u64, root: [u8; 32]) -> Result<(), SystemError>;
+
+    /// Forgets a version's root.
+    fn delete_version(&mut self, version: u64) -> Result<(), SystemError>;
+
+    /// Returns every recorded version and its root.
+    fn versions(&self) -> Result<BTreeMap<u64, [u8; 32]>, SystemError>;
+
+    /// Makes all changes so far durable.
+    fn flush(&mut self) -> Result<(), SystemError> {
+        Ok(())
+    }
+}
+
+/// Node store held entirely in memory.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryNodeStore {
+    nodes: HashMap<[u8; 32], (Node, u64)>,
+    versions: BTreeMap<u64, [u8; 32]>,
+}
+
+impl MemoryNodeStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of stored nodes.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+}
+
+impl NodeStore for MemoryNodeStore {
+    fn get_node(&self, hash: &[u8; 32]) -> Result<Option<Node>, SystemError> {
+        Ok(self.nodes.get(hash).map(|(node, _)| node.clone()))
+    }
+
+    fn put_node(&mut self, hash: [u8; 32], node: Node) -> Result<(), SystemError> {
+        self.nodes.entry(hash).or_insert((node, 0));
+        Ok(())
+    }
+
+    fn delete_node(&mut self, hash: &[u8; 32]) -> Result<(), SystemError> {
+        self.nodes.remove(hash);
+        Ok(())
+    }
+
+    fn ref_count(&self, hash: &[u8; 32]) -> Result<Option<u64>, SystemError> {
+        Ok(self.nodes.get(hash).map(|(_, count)| *count))
+    }
+
+    fn set_ref_count(&mut self, hash: &[u8; 32], count: u64) -> Result<(), SystemError> {
+        match self.nodes.get_mut(hash) {
+            Some(entry) => {
+                entry.1 = count;
+                Ok(())
+            }
+            None => Err(unknown_node()),
+        }
+    }
+
+    fn put_version(&mut self, version: u64, root: [u8; 32]) -> Result<(), SystemError> {
+        self.versions.insert(version, root);
+        Ok(())
+    }
+
+    fn delete_version(&mut self, version: u64) -> Result<(), SystemError> {
+        self.versions.remove(&version);
+        Ok(())
+    }
+
+    fn versions(&self) -> Result<BTreeMap<u64, [u8; 32]>, SystemError> {
+        Ok(self.versions.clone())
+    }
+}
+
+const PUT_NODE: u8 = 0;
+const DELETE_NODE: u8 = 1;
+const SET_REFS: u8 = 2;
+const PUT_VERSION: u8 = 3;
+const DELETE_VERSION: u8 = 4;
+
+/// Appended records are buffered up to this size before being written out.
+const FLUSH_THRESHOLD: usize = 1 << 20;
+
+#[derive(Clone, Copy, Debug)]
+struct IndexEntry {
+    offset: u64,
+    len: u32,
+    refs: u64,
+}
+
+struct LogFile {
+    file: File,
+    /// Length of the log already written to `file`.
+    flushed: u64,
+    /// Records appended since the last write, starting at offset `flushed`.
+    buffer: Vec<u8>,
+}
+
+impl LogFile {
+    fn end(&self) -> u64 {
+        self.flushed + self.buffer.len() as u64
+    }
+
+    fn read(&mut self, offset: u64, len: usize) -> Result<Vec<u8>, SystemError> {
+        if offset >= self.flushed {
+            let start = (offset - self.flushed) as usize;
+            return self
+                .buffer
+                .get(start..start + len)
+                .map(|data| data.to_vec())
+                .ok_or_else(|| corrupt_log("Node offset past end of log"));
+        }
+        let mut data = vec![0u8; len];
+        self.file.seek(SeekFrom::Start(offset)).map_err(io_error)?;
+        self.file.read_exact(&mut data).map_err(io_error)?;
+        Ok(data)
+    }
+
+    fn write_out(&mut self) -> Result<(), SystemError> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        self.file
+            .seek(SeekFrom::Start(self.flushed))
+            .map_err(io_error)?;
+        self.file.write_all(&self.buffer).map_err(io_error)?;
+        self.file.sync_data().map_err(io_error)?;
+        self.flushed += self.buffer.len() as u64;
+        self.buffer.clear();
+        Ok(())
+    }
+}
+
+/// Node store persisted to an append-only log file on the local disk.
+pub struct FileNodeStore {
+    path: PathBuf,
+    log: Mutex<LogFile>,
+    index: HashMap<[u8; 32], IndexEntry>,
+    versions: BTreeMap<u64, [u8; 32]>,
+}
+
+impl FileNodeStore {
+    /// Opens the log at `path`, creating it if needed, and replays it.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, SystemError> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(io_error)?;
+
+        let mut index = HashMap::new();
+        let mut versions = BTreeMap::new();
+        let valid_len = replay(&file, &mut index, &mut versions)?;
+        if file.metadata().map_err(io_error)?.len() > valid_len {
+            file.set_len(valid_len).map_err(io_error)?;
+        }
+
+        Ok(Self {
+            path,
+            log: Mutex::new(LogFile {
+                file,
+                flushed: valid_len,
+                buffer: Vec::new(),
+            }),
+            index,
+            versions,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Number of live nodes.
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Rewrites the log with only live nodes, counts and versions.
+    pub fn compact(&mut self) -> Result<(), SystemError> {
+        self.flush()?;
+
+        let tmp_path = self.path.with_extension("compact");
+        let mut writer = BufWriter::new(File::create(&tmp_path).map_err(io_error)?);
+        let mut offset = 0u64;
+        let mut index = HashMap::with_capacity(self.index.len());
+
+        let log = self.log.get_mut().map_err(|_| lock_poisoned())?;
+        for (hash, entry) in &self.index {
+            let data = log.read(entry.offset, entry.len as usize)?;
+            let mut record = Vec::with_capacity(data.len() + 37 + 41);
+            let data_offset = offset + 37;
+            encode_put(&mut record, hash, &data);
+            if entry.refs > 0 {
+                encode_refs(&mut record, hash, entry.refs);
+            }
+            writer.write_all(&record).map_err(io_error)?;
+            offset += record.len() as u64;
+            index.insert(
+                *hash,
+                IndexEntry {
+                    offset: data_offset,
+                    ..*entry
+                },
+            );
+        }
+        for (version, root) in &self.versions {
+            let mut record = Vec::with_capacity(41);
+            encode_version(&mut record, *version, root);
+            writer.write_all(&record).map_err(io_error)?;
+            offset += record.len() as u64;
+        }
+
+        let file = writer.into_inner().map_err(|e| io_error(e.into_error()))?;
+        file.sync_all().map_err(io_error)?;
+        drop(file);
+        fs::rename(&tmp_path, &self.path).map_err(io_error)?;
+
+        log.file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&self.path)
+            .map_err(io_error)?;
+        log.flushed = offset;
+        log.buffer.clear();
+        self.index = index;
+        Ok(())
+    }
+
+    fn append(&mut self, record: &[u8]) -> Result<u64, SystemError> {
+        let log = self.log.get_mut().map_err(|_| lock_poisoned())?;
+        let offset = log.end();
+        log.buffer.extend_from_slice(record);
+        if log.buffer.len() >= FLUSH_THRESHOLD {
+            log.write_out()?;
+        }
+        Ok(offset)
+    }
+}
+
+impl NodeStore for FileNodeStore {
+    fn get_node(&self, hash: &[u8; 32]) -> Result<Option<Node>, SystemError> {
+        let entry = match self.index.get(hash) {
+            Some(entry) => *entry,
+            None => return Ok(None),
+        };
+        let data = self
+            .log
+            .lock()
+            .map_err(|_| lock_poisoned())?
+            .read(entry.offset, entry.len as usize)?;
+        Node::from_bytes(&data).map(Some)
+    }
+
+    fn put_node(&mut self, hash: [u8; 32], node: Node) -> Result<(), SystemError> {
+        if self.index.contains_key(&hash) {
+            return Ok(());
+        }
+        let data = node.to_bytes();
+        let mut record = Vec::with_capacity(data.len() + 37);
+        encode_put(&mut record, &hash, &data);
+        let offset = self.append(&record)? + 37;
+        self.index.insert(
+            hash,
+            IndexEntry {
+                offset,
+                len: data.len() as u32,
+                refs: 0,
+            },
+        );
+        Ok(())
+    }
+
+    fn delete_node(&mut self, hash: &[u8; 32]) -> Result<(), SystemError> {
+        if self.index.remove(hash).is_none() {
+            return Ok(());
+        }
+        let mut record = Vec::with_capacity(33);
+        record.push(DELETE_NODE);
+        record.extend_from_slice(hash);
+        self.append(&record)?;
+        Ok(())
+    }
+
+    fn ref_count(&self, hash: &[u8; 32]) -> Result<Option<u64>, SystemError> {
+        Ok(self.index.get(hash).map(|entry| entry.refs))
+    }
+
+    fn set_ref_count(&mut self, hash: &[u8; 32], count: u64) -> Result<(), SystemError> {
+        match self.index.get_mut(hash) {
+            Some(entry) => entry.refs = count,
+            None => return Err(unknown_node()),
+        }
+        let mut record = Vec::with_capacity(41);
+        encode_refs(&mut record, hash, count);
+        self.append(&record)?;
+        Ok(())
+    }
+
+    fn put_version(&mut self, version: u64, root: [u8; 32]) -> Result<(), SystemError> {
+        let mut record = Vec::with_capacity(41);
+        encode_version(&mut record, version, &root);
+        self.append(&record)?;
+        self.versions.insert(version, root);
+        Ok(())
+    }
+
+    fn delete_version(&mut self, version: u64) -> Result<(), SystemError> {
+        if self.versions.remove(&version).is_none() {
+            return Ok(());
+        }
+        let mut record = Vec::with_capacity(9);
+        record.push(DELETE_VERSION);
+        record.extend_from_slice(&version.to_le_bytes());
+        self.append(&record)?;
+        Ok(())
+    }
+
+    fn versions(&self) -> Result<BTreeMap<u64, [u8; 32]>, SystemError> {
+        Ok(self.versions.clone())
+    }
+
+    fn flush(&mut self) -> Result<(), SystemError> {
+        self.log.get_mut().map_err(|_| lock_poisoned())?.write_out()
+    }
+}
+
+impl Drop for FileNodeStore {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+fn encode_put(record: &mut Vec<u8>, hash: &[u8; 32], data: &[u8]) {
+    record.push(PUT_NODE);
+    record.extend_from_slice(hash);
+    record.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    record.extend_from_slice(data);
+}
+
+fn encode_refs(record: &mut Vec<u8>, hash: &[u8; 32], count: u64) {
+    record.push(SET_REFS);
+    record.extend_from_slice(hash);
+    record.extend_from_slice(&count.to_le_bytes());
+}
+
+fn encode_version(record: &mut Vec<u8>, version: u64, root: &[u8; 32]) {
+    record.push(PUT_VERSION);
+    record.extend_from_slice(&version.to_le_bytes());
+    record.extend_from_slice(root);
+}
+
+/// Replays the log into `index` and `versions`, returning the length of the valid prefix.
+fn replay(
+    file: &File,
+    index: &mut HashMap<[u8; 32], IndexEntry>,
+    versions: &mut BTreeMap<u64, [u8; 32]>,
+) -> Result<u64, SystemError> {
+    let mut reader = BufReader::new(file);
+    reader.seek(SeekFrom::Start(0)).map_err(io_error)?;
+    let mut offset = 0u64;
+
+    loop {
+        let mut tag = [0u8; 1];
+        match reader.read_exact(&mut tag) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(offset),
+            Err(e) => return Err(io_error(e)),
+        }
+
+        let read = match tag[0] {
+            PUT_NODE => {
+                let mut header = [0u8; 36];
+                if !read_or_eof(&mut reader, &mut header)? {
+                    return Ok(offset);
+                }
+                let hash: [u8; 32] = header[..32].try_into().unwrap();
+                let len = u32::from_le_bytes(header[32..].try_into().unwrap());
+                let mut data = vec![0u8; len as usize];
+                if !read_or_eof(&mut reader, &mut data)? {
+                    return Ok(offset);
+                }
+                index.entry(hash).or_insert(IndexEntry {
+                    offset: offset + 37,
+                    len,
+                    refs: 0,
+                });
+                37 + len as u64
+            }
+            DELETE_NODE => {
+                let mut hash = [0u8; 32];
+                if !read_or_eof(&mut reader, &mut hash)? {
+                    return Ok(offset);
+                }
+                index.remove(&hash);
+                33
+            }
+            SET_REFS => {
+                let mut body = [0u8; 40];
+                if !read_or_eof(&mut reader, &mut body)? {
+                    return Ok(offset);
+                }
+                let hash: [u8; 32] = body[..32].try_into().unwrap();
+                if let Some(entry) = index.get_mut(&hash) {
+                    entry.refs = u64::from_le_bytes(body[32..].try_into().unwrap());
+                }
+                41
+            }
+            PUT_VERSION => {
+                let mut body = [0u8; 40];
+                if !read_or_eof(&mut reader, &mut body)? {
+                    return Ok(offset);
+                }
+                let version = u64::from_le_bytes(body[..8].try_into().unwrap());
+                versions.insert(version, body[8..].try_into().unwrap());
+                41
+            }
+            DELETE_VERSION => {
+                let mut body = [0u8; 8];
+                if !read_or_eof(&mut reader, &mut body)? {
+                    return Ok(offset);
+                }
+                versions.remove(&u64::from_le_bytes(body));
+                9
+            }
+            _ => return Err(corrupt_log("Unknown record in node log")),
+        };
+        offset += read;
+    }
+}
+
+/// Fills `buf`, returning false if the log ends first.
+fn read_or_eof<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<bool, SystemError> {
+    match reader.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(io_error(e)),
+    }
+}
+
+fn io_error(e: std::io::Error) -> SystemError {
+    SystemError::new(SystemErrorType::StorageError, e.to_string())
+}
+
+fn corrupt_log(message: &str) -> SystemError {
+    SystemError::new(SystemErrorType::StorageError, message.to_string())
+}
+
+fn lock_poisoned() -> SystemError {
+    SystemError::new(
+        SystemErrorType::StorageError,
+        "Node log lock poisoned".to_string(),
+    )
+}
+
+fn unknown_node() -> SystemError {
+    SystemError::new(SystemErrorType::NotFound, "Node is not stored".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::smt::hasher::Sha256Hasher;
+    use crate::core::smt::tree::SparseMerkleTree;
+
+    type FileTree = SparseMerkleTree<Sha256Hasher, FileNodeStore>;
+
+    fn log_path(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("smt-store-{}-{}.log", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn key(byte: u8) -> [u8; 32] {
+        [byte; 32]
+    }
+
+    #[test]
+    fn test_file_store_survives_reopen() {
+        let path = log_path("reopen");
+        let (old_root, new_root) = {
+            let mut tree = FileTree::open(FileNodeStore::open(&path).unwrap()).unwrap();
+            tree.update(&key(1), b"one").unwrap();
+            let old_root = tree.commit_version(1).unwrap();
+            tree.update(&key(1), b"uno").unwrap();
+            tree.update(&key(2), b"two").unwrap();
+            (old_root, tree.commit_version(2).unwrap())
+        };
+
+        let tree = FileTree::open(FileNodeStore::open(&path).unwrap()).unwrap();
+        assert_eq!(tree.root(), new_root);
+        assert_eq!(tree.get(&key(1)).unwrap(), Some(b"uno".to_vec()));
+
+        let old = FileTree::open_at(FileNodeStore::open(&path).unwrap(), old_root).unwrap();
+        assert_eq!(old.get(&key(1)).unwrap(), Some(b"one".to_vec()));
+        assert_eq!(old.get(&key(2)).unwrap(), None);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_torn_tail_is_discarded() {
+        let path = log_path("torn");
+        let root = {
+            let mut tree = FileTree::open(FileNodeStore::open(&path).unwrap()).unwrap();
+            tree.update(&key(3), b"three").unwrap();
+            tree.commit_version(1).unwrap()
+        };
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[PUT_NODE, 1, 2, 3]).unwrap();
+        drop(file);
+
+        let tree = FileTree::open(FileNodeStore::open(&path).unwrap()).unwrap();
+        assert_eq!(tree.root(), root);
+        assert_eq!(tree.get(&key(3)).unwrap(), Some(b"three".to_vec()));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_compact_keeps_live_nodes() {
+        let path = log_path("compact");
+        let mut tree = FileTree::open(FileNodeStore::open(&path).unwrap())
+            .unwrap()
+            .with_retention(1);
+        for i in 1..=4u8 {
+            tree.update(&key(i), &[i]).unwrap();
+            tree.commit_version(i as u64).unwrap();
+        }
+        let root = tree.root();
+        let before = fs::metadata(&path).unwrap().len();
+
+        drop(tree);
+
+        let mut store = FileNodeStore::open(&path).unwrap();
+        store.compact().unwrap();
+        assert!(fs::metadata(&path).unwrap().len() < before);
+
+        let tree = FileTree::open(store).unwrap();
+        assert_eq!(tree.root(), root);
+        assert_eq!(tree.get(&key(2)).unwrap(), Some(vec![2]));
+        fs::remove_file(&path).unwrap();
+    }
+}
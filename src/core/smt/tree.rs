@@ -0,0 +1,903 @@
+// ./src/core/smt/tree.rs
+
+// Sparse Merkle Tree
+// A 256-level sparse Merkle tree keyed by 32-byte keys, generic over the hash function.
+// Keys are walked most significant bit first; a set bit descends to the right. Empty subtrees
+// hash to precomputed per-level defaults, so only non-empty paths are stored. Nodes are stored
+// by hash, which lets any root the tree has had be read back as long as its nodes are kept.
+//
+// Nodes live in a `NodeStore`. Committing a version pins the current root and drops nodes that
+// were written since the last commit but are no longer reachable; pruning a version unpins its
+// root and deletes every node that only it referenced. Without commits nothing is ever dropped.
+
+use crate::core::error::errors::{SystemError, SystemErrorType};
+use crate::core::smt::hasher::TreeHasher;
+use crate::core::smt::proof::{CompressedProof, MerkleProof, NonMembershipProof};
+use crate::core::smt::store::{MemoryNodeStore, NodeStore};
+use crate::core::types::boc::{Cell, CellType, BOC};
+use std::collections::{BTreeMap, HashSet};
+use std::marker::PhantomData;
+
+/// Number of levels between the root and the leaves.
+pub const TREE_DEPTH: usize = 256;
+
+/// Hash of an empty leaf.
+pub const EMPTY_LEAF: [u8; 32] = [0u8; 32];
+
+const LEAF_TAG: u8 = 0;
+const INTERNAL_TAG: u8 = 1;
+
+/// A stored tree node, addressed by its hash.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Node {
+    Internal { left: [u8; 32], right: [u8; 32] },
+    Leaf { key: [u8; 32], value: Vec<u8> },
+}
+
+impl Node {
+    /// Encoded as 0 | key | value for a leaf, or 1 | left | right for an internal node.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        match self {
+            Node::Internal { left, right } => {
+                data.push(INTERNAL_TAG);
+                data.extend_from_slice(left);
+                data.extend_from_slice(right);
+            }
+            Node::Leaf { key, value } => {
+                data.push(LEAF_TAG);
+                data.extend_from_slice(key);
+                data.extend_from_slice(value);
+            }
+        }
+        data
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, SystemError> {
+        match data.first() {
+            Some(&INTERNAL_TAG) if data.len() == 65 => {
+                let mut left = [0u8; 32];
+                let mut right = [0u8; 32];
+                left.copy_from_slice(&data[1..33]);
+                right.copy_from_slice(&data[33..65]);
+                Ok(Node::Internal { left, right })
+            }
+            Some(&LEAF_TAG) if data.len() > 33 => {
+                let mut key = [0u8; 32];
+                key.copy_from_slice(&data[1..33]);
+                Ok(Node::Leaf {
+                    key,
+                    value: data[33..].to_vec(),
+                })
+            }
+            _ => Err(invalid_cell("Invalid node cell")),
+        }
+    }
+
+    /// Recomputes the node's hash from its contents.
+    pub fn hash<H: TreeHasher>(&self) -> [u8; 32] {
+        match self {
+            Node::Internal { left, right } => H::hash_node(left, right),
+            Node::Leaf { key, value } => H::hash_leaf(key, value),
+        }
+    }
+}
+
+/// Returns the bit of `key` that selects the child at `depth`.
+pub fn key_bit(key: &[u8; 32], depth: usize) -> bool {
+    (key[depth / 8] >> (7 - (depth % 8))) & 1 == 1
+}
+
+/// Hashes of empty subtrees; `default_hashes::<H>()[d]` is the empty node at depth `d`.
+pub fn default_hashes<H: TreeHasher>() -> Vec<[u8; 32]> {
+    let mut defaults = vec![EMPTY_LEAF; TREE_DEPTH + 1];
+    for depth in (0..TREE_DEPTH).rev() {
+        defaults[depth] = H::hash_node(&defaults[depth + 1], &defaults[depth + 1]);
+    }
+    defaults
+}
+
+pub struct SparseMerkleTree<H: TreeHasher, S: NodeStore = MemoryNodeStore> {
+    root: [u8; 32],
+    store: S,
+    /// Committed versions and their roots, mirrored from the store.
+    versions: BTreeMap<u64, [u8; 32]>,
+    /// Number of most recent versions kept when committing; None keeps all of them.
+    retention: Option<u64>,
+    /// Nodes written since the last commit, checked for reachability when committing.
+    pending: HashSet<[u8; 32]>,
+    defaults: Vec<[u8; 32]>,
+    _hasher: PhantomData<H>,
+}
+
+impl<H: TreeHasher, S: NodeStore + Default> Default for SparseMerkleTree<H, S> {
+    fn default() -> Self {
+        Self::with_root(S::default(), BTreeMap::new(), None)
+    }
+}
+
+impl<H: TreeHasher, S: NodeStore + Clone> Clone for SparseMerkleTree<H, S> {
+    fn clone(&self) -> Self {
+        Self {
+            root: self.root,
+            store: self.store.clone(),
+            versions: self.versions.clone(),
+            retention: self.retention,
+            pending: self.pending.clone(),
+            defaults: self.defaults.clone(),
+            _hasher: PhantomData,
+        }
+    }
+}
+
+impl<H: TreeHasher> SparseMerkleTree<H> {
+    /// Creates an empty tree held in memory.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Rebuilds an in-memory tree from `to_boc` output, re-hashing every node.
+    pub fn from_boc(boc: &BOC) -> Result<Self, SystemError> {
+        Self::from_boc_in(boc, MemoryNodeStore::new())
+    }
+}
+
+impl<H: TreeHasher, S: NodeStore> SparseMerkleTree<H, S> {
+    /// Opens a tree over `store` at its latest committed version, or empty if it has none.
+    pub fn open(store: S) -> Result<Self, SystemError> {
+        let versions = store.versions()?;
+        let root = versions.values().next_back().copied();
+        Ok(Self::with_root(store, versions, root))
+    }
+
+    /// Opens a tree over `store` at a historical root. The root's nodes must still be stored.
+    pub fn open_at(store: S, root: [u8; 32]) -> Result<Self, SystemError> {
+        let versions = store.versions()?;
+        let tree = Self::with_root(store, versions, Some(root));
+        if root != tree.defaults[0] && tree.store.get_node(&root)?.is_none() {
+            return Err(SystemError::new(
+                SystemErrorType::NotFound,
+                "Root is not stored; it may have been pruned".to_string(),
+            ));
+        }
+        Ok(tree)
+    }
+
+    /// Opens a tree over `store` at the root committed for `version`.
+    pub fn open_at_version(store: S, version: u64) -> Result<Self, SystemError> {
+        let root = store
+            .versions()?
+            .get(&version)
+            .copied()
+            .ok_or_else(|| unknown_version(version))?;
+        Self::open_at(store, root)
+    }
+
+    fn with_root(store: S, versions: BTreeMap<u64, [u8; 32]>, root: Option<[u8; 32]>) -> Self {
+        let defaults = default_hashes::<H>();
+        Self {
+            root: root.unwrap_or(defaults[0]),
+            store,
+            versions,
+            retention: None,
+            pending: HashSet::new(),
+            defaults,
+            _hasher: PhantomData,
+        }
+    }
+
+    /// Keeps only the `epochs` most recent versions; older ones are pruned on each commit.
+    pub fn with_retention(mut self, epochs: u64) -> Self {
+        self.retention = Some(epochs.max(1));
+        self
+    }
+
+    pub fn root(&self) -> [u8; 32] {
+        self.root
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// Committed versions and their roots.
+    pub fn versions(&self) -> &BTreeMap<u64, [u8; 32]> {
+        &self.versions
+    }
+
+    /// Root committed for `version`, if it is still retained.
+    pub fn version_root(&self, version: u64) -> Option<[u8; 32]> {
+        self.versions.get(&version).copied()
+    }
+
+    /// Most recent committed version.
+    pub fn latest_version(&self) -> Option<u64> {
+        self.versions.keys().next_back().copied()
+    }
+
+    /// Records the current root as `version`, which must be newer than every committed
+    /// version. Drops unreachable nodes written since the last commit, prunes versions
+    /// outside the retention window and flushes the store.
+    pub fn commit_version(&mut self, version: u64) -> Result<[u8; 32], SystemError> {
+        if let Some(latest) = self.latest_version() {
+            if version <= latest {
+                return Err(SystemError::new(
+                    SystemErrorType::InvalidSequence,
+                    format!("Version {} is not newer than {}", version, latest),
+                ));
+            }
+        }
+
+        let root = self.root;
+        self.retain(&root)?;
+        self.store.put_version(version, root)?;
+        self.versions.insert(version, root);
+
+        let pending: Vec<[u8; 32]> = self.pending.drain().collect();
+        for hash in pending {
+            if self.store.ref_count(&hash)? == Some(0) {
+                self.release(hash)?;
+            }
+        }
+
+        if let Some(epochs) = self.retention {
+            self.prune_versions((version + 1).saturating_sub(epochs))?;
+        }
+        self.store.flush()?;
+        Ok(root)
+    }
+
+    /// Forgets every version older than `before`, except the latest, and deletes the nodes
+    /// only they referenced. Returns the number of versions pruned.
+    pub fn prune_versions(&mut self, before: u64) -> Result<usize, SystemError> {
+        let latest = match self.latest_version() {
+            Some(latest) => latest,
+            None => return Ok(0),
+        };
+        let pruned: Vec<(u64, [u8; 32])> = self
+            .versions
+            .range(..before.min(latest))
+            .map(|(version, root)| (*version, *root))
+            .collect();
+
+        for (version, root) in &pruned {
+            self.store.delete_version(*version)?;
+            self.versions.remove(version);
+            self.unpin(*root)?;
+        }
+        Ok(pruned.len())
+    }
+
+    /// Makes all changes so far durable.
+    pub fn flush(&mut self) -> Result<(), SystemError> {
+        self.store.flush()
+    }
+
+    /// Root of the tree with no leaves set.
+    pub fn empty_root(&self) -> [u8; 32] {
+        self.defaults[0]
+    }
+
+    /// Returns the value stored under `key`, if any.
+    pub fn get(&self, key: &[u8; 32]) -> Result<Option<Vec<u8>>, SystemError> {
+        self.get_at(&self.root, key)
+    }
+
+    /// Returns the value stored under `key` in the tree with the given root.
+    pub fn get_at(&self, root: &[u8; 32], key: &[u8; 32]) -> Result<Option<Vec<u8>>, SystemError> {
+        let (_, leaf_hash) = self.walk(root, key)?;
+        if leaf_hash == EMPTY_LEAF {
+            return Ok(None);
+        }
+        match self.store.get_node(&leaf_hash)? {
+            Some(Node::Leaf { key: stored, value }) if stored == *key => Ok(Some(value)),
+            _ => Err(missing_node()),
+        }
+    }
+
+    /// Sets `key` to `value` and returns the new root. An empty value removes the key.
+    pub fn update(&mut self, key: &[u8; 32], value: &[u8]) -> Result<[u8; 32], SystemError> {
+        let (siblings, _) = self.walk(&self.root, key)?;
+
+        let mut current = if value.is_empty() {
+            EMPTY_LEAF
+        } else {
+            let leaf_hash = H::hash_leaf(key, value);
+            self.put_node(
+                leaf_hash,
+                Node::Leaf {
+                    key: *key,
+                    value: value.to_vec(),
+                },
+            )?;
+            leaf_hash
+        };
+
+        for depth in (0..TREE_DEPTH).rev() {
+            let sibling = siblings[depth];
+            let (left, right) = if key_bit(key, depth) {
+                (sibling, current)
+            } else {
+                (current, sibling)
+            };
+            current = H::hash_node(&left, &right);
+            if current != self.defaults[depth] {
+                self.put_node(current, Node::Internal { left, right })?;
+            }
+        }
+
+        self.root = current;
+        Ok(current)
+    }
+
+    /// Removes `key` from the tree and returns the new root.
+    pub fn remove(&mut self, key: &[u8; 32]) -> Result<[u8; 32], SystemError> {
+        self.update(key, &[])
+    }
+
+    /// Builds an inclusion proof for `key` against the current root.
+    pub fn prove(&self, key: &[u8; 32]) -> Result<MerkleProof, SystemError> {
+        self.prove_at(&self.root, key)
+    }
+
+    /// Builds an inclusion proof for `key` against the given root.
+    pub fn prove_at(&self, root: &[u8; 32], key: &[u8; 32]) -> Result<MerkleProof, SystemError> {
+        let (siblings, _) = self.walk(root, key)?;
+        Ok(MerkleProof::new(*key, siblings))
+    }
+
+    /// Builds a compressed inclusion proof for `key` against the current root.
+    pub fn prove_compressed(&self, key: &[u8; 32]) -> Result<CompressedProof, SystemError> {
+        self.prove_compressed_at(&self.root, key)
+    }
+
+    /// Builds a compressed inclusion proof for `key` against the given root.
+    pub fn prove_compressed_at(
+        &self,
+        root: &[u8; 32],
+        key: &[u8; 32],
+    ) -> Result<CompressedProof, SystemError> {
+        CompressedProof::from_proof(&self.prove_at(root, key)?, &self.defaults)
+    }
+
+    /// Builds a proof that `key` has no leaf under the current root.
+    pub fn prove_absence(&self, key: &[u8; 32]) -> Result<NonMembershipProof, SystemError> {
+        self.prove_absence_at(&self.root, key)
+    }
+
+    /// Builds a proof that `key` has no leaf under the given root. Fails if the key is set.
+    pub fn prove_absence_at(
+        &self,
+        root: &[u8; 32],
+        key: &[u8; 32],
+    ) -> Result<NonMembershipProof, SystemError> {
+        let mut siblings = Vec::new();
+        let mut current = *root;
+
+        for depth in 0..TREE_DEPTH {
+            if current == self.defaults[depth] {
+                return Ok(NonMembershipProof::new(*key, siblings));
+            }
+            let (left, right) = self.children(&current, depth)?;
+            if key_bit(key, depth) {
+                siblings.push(left);
+                current = right;
+            } else {
+                siblings.push(right);
+                current = left;
+            }
+        }
+
+        if current == EMPTY_LEAF {
+            return Ok(NonMembershipProof::new(*key, siblings));
+        }
+        Err(SystemError::new(
+            SystemErrorType::InvalidOperation,
+            "Key is present in the tree".to_string(),
+        ))
+    }
+
+    /// Checks a proof that `key` holds `value` under `root`.
+    pub fn verify(root: &[u8; 32], key: &[u8; 32], value: &[u8], proof: &MerkleProof) -> bool {
+        proof.verify::<H>(root, key, value)
+    }
+
+    /// Hash of the empty subtree at `depth`.
+    pub(super) fn default_at(&self, depth: usize) -> [u8; 32] {
+        self.defaults[depth]
+    }
+
+    /// Stores computed nodes, children before parents, and moves the tree to `root`.
+    pub(super) fn commit(
+        &mut self,
+        root: [u8; 32],
+        nodes: Vec<([u8; 32], Node)>,
+    ) -> Result<(), SystemError> {
+        for (hash, node) in nodes {
+            self.put_node(hash, node)?;
+        }
+        self.root = root;
+        Ok(())
+    }
+
+    /// Stores a node whose children are already stored, counting its references to them.
+    fn put_node(&mut self, hash: [u8; 32], node: Node) -> Result<(), SystemError> {
+        if self.store.ref_count(&hash)?.is_some() {
+            return Ok(());
+        }
+        if let Node::Internal { left, right } = &node {
+            self.retain(left)?;
+            self.retain(right)?;
+        }
+        self.store.put_node(hash, node)?;
+        self.pending.insert(hash);
+        Ok(())
+    }
+
+    /// Adds a reference to a stored node. Default subtrees are not stored and are skipped.
+    fn retain(&mut self, hash: &[u8; 32]) -> Result<(), SystemError> {
+        if let Some(count) = self.store.ref_count(hash)? {
+            self.store.set_ref_count(hash, count + 1)?;
+        }
+        Ok(())
+    }
+
+    /// Drops a reference to a stored node, releasing it when none remain.
+    fn unpin(&mut self, hash: [u8; 32]) -> Result<(), SystemError> {
+        match self.store.ref_count(&hash)? {
+            Some(count) if count > 1 => self.store.set_ref_count(&hash, count - 1),
+            Some(_) => {
+                self.store.set_ref_count(&hash, 0)?;
+                self.release(hash)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Deletes an unreferenced node and every descendant left unreferenced by it. The current
+    /// root is kept and re-checked at the next commit.
+    fn release(&mut self, hash: [u8; 32]) -> Result<(), SystemError> {
+        let mut stack = vec![hash];
+        while let Some(hash) = stack.pop() {
+            if hash == self.root {
+                self.pending.insert(hash);
+                continue;
+            }
+            let node = match self.store.get_node(&hash)? {
+                Some(node) => node,
+                None => continue,
+            };
+            self.store.delete_node(&hash)?;
+            self.pending.remove(&hash);
+
+            if let Node::Internal { left, right } = node {
+                for child in [left, right] {
+                    match self.store.ref_count(&child)? {
+                        Some(count) if count > 1 => self.store.set_ref_count(&child, count - 1)?,
+                        Some(_) => {
+                            self.store.set_ref_count(&child, 0)?;
+                            stack.push(child);
+                        }
+                        None => {}
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Walks from `root` to the leaf of `key`, returning the siblings along the path
+    /// (root side first) and the leaf hash.
+    fn walk(
+        &self,
+        root: &[u8; 32],
+        key: &[u8; 32],
+    ) -> Result<(Vec<[u8; 32]>, [u8; 32]), SystemError> {
+        let mut siblings = Vec::with_capacity(TREE_DEPTH);
+        let mut current = *root;
+
+        for depth in 0..TREE_DEPTH {
+            let (left, right) = self.children(&current, depth)?;
+            if key_bit(key, depth) {
+                siblings.push(left);
+                current = right;
+            } else {
+                siblings.push(right);
+                current = left;
+            }
+        }
+
+        Ok((siblings, current))
+    }
+
+    pub(super) fn children(
+        &self,
+        hash: &[u8; 32],
+        depth: usize,
+    ) -> Result<([u8; 32], [u8; 32]), SystemError> {
+        if *hash == self.defaults[depth] {
+            let child = self.defaults[depth + 1];
+            return Ok((child, child));
+        }
+        match self.store.get_node(hash)? {
+            Some(Node::Internal { left, right }) => Ok((left, right)),
+            _ => Err(missing_node()),
+        }
+    }
+
+    /// Key of a stored leaf.
+    pub(super) fn leaf_key(&self, hash: &[u8; 32]) -> Result<[u8; 32], SystemError> {
+        match self.store.get_node(hash)? {
+            Some(Node::Leaf { key, .. }) => Ok(key),
+            _ => Err(missing_node()),
+        }
+    }
+
+    /// Serializes the nodes reachable from the current root.
+    ///
+    /// Cell 0 holds the root hash; every other cell is one node, tagged 0 for a leaf
+    /// (key | value) or 1 for an internal node (left | right), with its hash as merkle_hash.
+    pub fn to_boc(&self) -> Result<BOC, SystemError> {
+        let mut boc = BOC::new();
+        let root_index = boc.add_cell(Cell::new(
+            self.root.to_vec(),
+            vec![],
+            CellType::Ordinary,
+            self.root,
+            None,
+        ));
+        boc.add_root(root_index);
+
+        let mut stack = vec![(self.root, 0usize)];
+        while let Some((hash, depth)) = stack.pop() {
+            if hash == self.defaults[depth] {
+                continue;
+            }
+            let node = self.store.get_node(&hash)?.ok_or_else(missing_node)?;
+            if let Node::Internal { left, right } = &node {
+                stack.push((*left, depth + 1));
+                stack.push((*right, depth + 1));
+            }
+            boc.add_cell(Cell::new(
+                node.to_bytes(),
+                vec![],
+                CellType::Ordinary,
+                hash,
+                None,
+            ));
+        }
+
+        Ok(boc)
+    }
+
+    /// Rebuilds a tree from `to_boc` output into `store`, re-hashing every node.
+    pub fn from_boc_in(boc: &BOC, store: S) -> Result<Self, SystemError> {
+        let root_cell = boc.get_root_cell().ok_or_else(|| {
+            SystemError::new(
+                SystemErrorType::NoRootCell,
+                "Tree BOC has no root cell".to_string(),
+            )
+        })?;
+        let root: [u8; 32] = root_cell
+            .get_data()
+            .as_slice()
+            .try_into()
+            .map_err(|_| invalid_cell("Invalid root cell"))?;
+
+        let mut tree = Self::open(store)?;
+        // Nodes follow their parents in the BOC, so store them in reverse.
+        for index in (0..boc.cell_count()).rev() {
+            let cell = boc
+                .get_cell(index)
+                .ok_or_else(|| invalid_cell("Missing cell"))?;
+            if std::ptr::eq(cell, root_cell) {
+                continue;
+            }
+            let node = Node::from_bytes(cell.get_data())?;
+            let hash = node.hash::<H>();
+            if hash != cell.merkle_hash {
+                return Err(SystemError::new(
+                    SystemErrorType::InvalidHash,
+                    "Node hash does not match its contents".to_string(),
+                ));
+            }
+            tree.put_node(hash, node)?;
+        }
+
+        tree.root = root;
+        tree.walk_all()?;
+        Ok(tree)
+    }
+
+    /// Checks that every node reachable from the root is present.
+    fn walk_all(&self) -> Result<(), SystemError> {
+        let mut stack = vec![(self.root, 0usize)];
+        while let Some((hash, depth)) = stack.pop() {
+            if depth == TREE_DEPTH {
+                if hash != EMPTY_LEAF
+                    && !matches!(self.store.get_node(&hash)?, Some(Node::Leaf { .. }))
+                {
+                    return Err(missing_node());
+                }
+                continue;
+            }
+            if hash == self.defaults[depth] {
+                continue;
+            }
+            let (left, right) = self.children(&hash, depth)?;
+            stack.push((left, depth + 1));
+            stack.push((right, depth + 1));
+        }
+        Ok(())
+    }
+}
+
+fn missing_node() -> SystemError {
+    SystemError::new(
+        SystemErrorType::NotFound,
+        "Node not found in path".to_string(),
+    )
+}
+
+fn invalid_cell(message: &str) -> SystemError {
+    SystemError::new(SystemErrorType::InvalidTransaction, message.to_string())
+}
+
+fn unknown_version(version: u64) -> SystemError {
+    SystemError::new(
+        SystemErrorType::NotFound,
+        format!("Version {} is not retained", version),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::smt::hasher::{Blake2Hasher, PoseidonHasher, Sha256Hasher};
+
+    fn key(byte: u8) -> [u8; 32] {
+        let mut key = [0u8; 32];
+        key[0] = byte;
+        key[31] = byte;
+        key
+    }
+
+    fn check_update_get_prove<H: TreeHasher>() {
+        let mut tree = SparseMerkleTree::<H>::new();
+        let empty_root = tree.root();
+        assert_eq!(empty_root, default_hashes::<H>()[0]);
+
+        tree.update(&key(1), b"one").unwrap();
+        tree.update(&key(0x80), b"two").unwrap();
+        let root = tree.update(&key(1), b"uno").unwrap();
+
+        assert_eq!(tree.get(&key(1)).unwrap(), Some(b"uno".to_vec()));
+        assert_eq!(tree.get(&key(0x80)).unwrap(), Some(b"two".to_vec()));
+        assert_eq!(tree.get(&key(2)).unwrap(), None);
+
+        let proof = tree.prove(&key(0x80)).unwrap();
+        assert!(SparseMerkleTree::<H>::verify(
+            &root,
+            &key(0x80),
+            b"two",
+            &proof
+        ));
+        assert!(!SparseMerkleTree::<H>::verify(
+            &root,
+            &key(0x80),
+            b"uno",
+            &proof
+        ));
+        assert!(!SparseMerkleTree::<H>::verify(
+            &root,
+            &key(1),
+            b"two",
+            &proof
+        ));
+
+        tree.remove(&key(1)).unwrap();
+        tree.remove(&key(0x80)).unwrap();
+        assert_eq!(tree.root(), empty_root);
+    }
+
+    #[test]
+    fn test_update_get_prove_all_hashers() {
+        check_update_get_prove::<Sha256Hasher>();
+        check_update_get_prove::<Blake2Hasher>();
+        check_update_get_prove::<PoseidonHasher>();
+    }
+
+    #[test]
+    fn test_root_is_independent_of_insertion_order() {
+        let entries: Vec<([u8; 32], Vec<u8>)> =
+            (1..=8u8).map(|i| (key(i * 29), vec![i; 3])).collect();
+
+        let mut forward = SparseMerkleTree::<Sha256Hasher>::new();
+        for (k, v) in &entries {
+            forward.update(k, v).unwrap();
+        }
+        let mut backward = SparseMerkleTree::<Sha256Hasher>::new();
+        for (k, v) in entries.iter().rev() {
+            backward.update(k, v).unwrap();
+        }
+        assert_eq!(forward.root(), backward.root());
+    }
+
+    #[test]
+    fn test_reads_historical_root() {
+        let mut tree = SparseMerkleTree::<Sha256Hasher>::new();
+        let old_root = tree.update(&key(3), b"old").unwrap();
+        tree.update(&key(3), b"new").unwrap();
+
+        assert_eq!(
+            tree.get_at(&old_root, &key(3)).unwrap(),
+            Some(b"old".to_vec())
+        );
+        let proof = tree.prove_at(&old_root, &key(3)).unwrap();
+        assert!(proof.verify::<Sha256Hasher>(&old_root, &key(3), b"old"));
+    }
+
+    fn check_non_membership<H: TreeHasher>() {
+        let mut tree = SparseMerkleTree::<H>::new();
+        let empty = tree.prove_absence(&key(9)).unwrap();
+        assert_eq!(empty.depth(), 0);
+        assert!(empty.verify::<H>(&tree.root(), &key(9)));
+
+        for i in 1..=16u8 {
+            tree.update(&key(i * 13), &[i]).unwrap();
+        }
+        let root = tree.root();
+
+        let proof = tree.prove_absence(&key(9)).unwrap();
+        assert!(proof.depth() < 16);
+        assert!(proof.verify::<H>(&root, &key(9)));
+        assert!(!proof.verify::<H>(&root, &key(13)));
+        assert!(!proof.verify::<H>(&empty_root_of::<H>(), &key(9)));
+        assert!(tree.prove_absence(&key(13)).is_err());
+
+        let decoded = NonMembershipProof::from_bytes(&proof.to_bytes()).unwrap();
+        assert!(decoded.verify::<H>(&root, &key(9)));
+    }
+
+    fn empty_root_of<H: TreeHasher>() -> [u8; 32] {
+        default_hashes::<H>()[0]
+    }
+
+    #[test]
+    fn test_non_membership_all_hashers() {
+        check_non_membership::<Sha256Hasher>();
+        check_non_membership::<Blake2Hasher>();
+        check_non_membership::<PoseidonHasher>();
+    }
+
+    #[test]
+    fn test_removed_key_proves_absent() {
+        let mut tree = SparseMerkleTree::<Sha256Hasher>::new();
+        tree.update(&key(1), b"one").unwrap();
+        tree.update(&key(2), b"two").unwrap();
+        let root = tree.remove(&key(1)).unwrap();
+
+        let proof = tree.prove_absence(&key(1)).unwrap();
+        assert!(proof.verify::<Sha256Hasher>(&root, &key(1)));
+    }
+
+    #[test]
+    fn test_boc_roundtrip() {
+        let mut tree = SparseMerkleTree::<PoseidonHasher>::new();
+        tree.update(&key(5), b"five").unwrap();
+        tree.update(&key(200), b"two hundred").unwrap();
+
+        let restored =
+            SparseMerkleTree::<PoseidonHasher>::from_boc(&tree.to_boc().unwrap()).unwrap();
+        assert_eq!(restored.root(), tree.root());
+        assert_eq!(
+            restored.get(&key(200)).unwrap(),
+            Some(b"two hundred".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_boc_rejects_tampered_node() {
+        let mut tree = SparseMerkleTree::<Sha256Hasher>::new();
+        tree.update(&key(5), b"five").unwrap();
+
+        let mut boc = tree.to_boc().unwrap();
+        let last = boc.cell_count() - 1;
+        let cell = boc.get_cell_mut(last).unwrap();
+        let len = cell.data.len();
+        cell.data[len - 1] ^= 0xff;
+
+        assert!(SparseMerkleTree::<Sha256Hasher>::from_boc(&boc).is_err());
+    }
+
+    fn check_compressed_proof<H: TreeHasher>() {
+        let mut tree = SparseMerkleTree::<H>::new();
+        for i in 1..=32u8 {
+            tree.update(&key(i * 7), &[i; 4]).unwrap();
+        }
+        let root = tree.root();
+        let proof = tree.prove_compressed(&key(14)).unwrap();
+        assert!(proof.siblings.len() < 16);
+        assert!(proof.verify::<H>(&root, &key(14), &[2; 4]));
+        assert!(!proof.verify::<H>(&root, &key(14), &[3; 4]));
+        assert_eq!(
+            proof.decompress::<H>().unwrap(),
+            tree.prove(&key(14)).unwrap()
+        );
+        assert_eq!(
+            tree.prove(&key(14)).unwrap().compress::<H>().unwrap(),
+            proof
+        );
+
+        let decoded = CompressedProof::from_bytes::<H>(&proof.to_bytes()).unwrap();
+        assert!(decoded.verify::<H>(&root, &key(14), &[2; 4]));
+        let decoded = CompressedProof::from_boc::<H>(&proof.to_boc()).unwrap();
+        assert_eq!(decoded, proof);
+    }
+
+    #[test]
+    fn test_compressed_proof_all_hashers() {
+        check_compressed_proof::<Sha256Hasher>();
+        check_compressed_proof::<Blake2Hasher>();
+        check_compressed_proof::<PoseidonHasher>();
+    }
+
+    #[test]
+    fn test_compressed_proof_rejects_non_canonical_encoding() {
+        let mut tree = SparseMerkleTree::<Sha256Hasher>::new();
+        tree.update(&key(1), b"one").unwrap();
+        tree.update(&key(2), b"two").unwrap();
+        let proof = tree.prove_compressed(&key(1)).unwrap();
+
+        let mut padded = proof.clone();
+        padded.bitmap[31] |= 1;
+        padded
+            .siblings
+            .push(default_hashes::<Sha256Hasher>()[TREE_DEPTH]);
+        assert!(CompressedProof::from_bytes::<Sha256Hasher>(&padded.to_bytes()).is_err());
+
+        let mut bytes = proof.to_bytes();
+        bytes.truncate(bytes.len() - 32);
+        assert!(CompressedProof::from_bytes::<Sha256Hasher>(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_pruned_versions_release_their_nodes() {
+        let mut tree = SparseMerkleTree::<Sha256Hasher>::new().with_retention(2);
+        tree.update(&key(1), b"a").unwrap();
+        let first = tree.commit_version(1).unwrap();
+        tree.update(&key(1), b"b").unwrap();
+        tree.update(&key(2), b"c").unwrap();
+        let second = tree.commit_version(2).unwrap();
+        tree.update(&key(1), b"d").unwrap();
+        tree.commit_version(3).unwrap();
+
+        assert_eq!(tree.versions().len(), 2);
+        assert_eq!(tree.version_root(1), None);
+        assert!(tree.get_at(&first, &key(1)).is_err());
+        assert_eq!(tree.get_at(&second, &key(1)).unwrap(), Some(b"b".to_vec()));
+
+        tree.prune_versions(3).unwrap();
+        let reachable = tree.to_boc().unwrap().cell_count() - 1;
+        assert_eq!(tree.store().len(), reachable);
+    }
+
+    #[test]
+    fn test_open_at_version() {
+        let mut tree = SparseMerkleTree::<Sha256Hasher>::new();
+        tree.update(&key(4), b"old").unwrap();
+        let old_root = tree.commit_version(10).unwrap();
+        tree.update(&key(4), b"new").unwrap();
+        tree.commit_version(11).unwrap();
+        assert!(tree.commit_version(11).is_err());
+
+        let store = tree.store().clone();
+        let old = SparseMerkleTree::<Sha256Hasher>::open_at_version(store.clone(), 10).unwrap();
+        assert_eq!(old.root(), old_root);
+        assert_eq!(old.get(&key(4)).unwrap(), Some(b"old".to_vec()));
+
+        let latest = SparseMerkleTree::<Sha256Hasher>::open(store.clone()).unwrap();
+        assert_eq!(latest.get(&key(4)).unwrap(), Some(b"new".to_vec()));
+        assert!(SparseMerkleTree::<Sha256Hasher>::open_at_version(store, 9).is_err());
+    }
+}
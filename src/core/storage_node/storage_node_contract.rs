@@ -1,7 +1,11 @@
 use crate::core::error::errors::{SystemError, SystemErrorType};
+use crate::core::hierarchy::client::wallet_extension::recovery::{
+    ChannelReplica, ChannelStateRecord, ReplicaSource, WalletReplica,
+};
 use crate::core::storage_node::battery::BatteryChargingSystem;
 use crate::core::types::boc::BOC;
 use crate::core::zkps::proof::ZkProof;
+use async_trait::async_trait;
 use futures::lock::Mutex;
 use sha2::{Digest, Sha256};
 use std::collections::{HashMap, HashSet};
@@ -45,6 +49,7 @@ pub struct StorageNode {
     pub stake: u64,
     pub stored_bocs: Arc<Mutex<HashMap<[u8; 32], BOC>>>,
     pub stored_proofs: Arc<Mutex<HashMap<[u8; 32], ZkProof>>>,
+    pub wallet_replicas: Arc<Mutex<HashMap<[u8; 32], WalletReplica>>>,
     pub battery_system: Arc<Mutex<BatteryChargingSystem>>,
     pub config: StorageNodeConfig,
     peers: Arc<Mutex<HashSet<[u8; 32]>>>,
@@ -71,6 +76,7 @@ impl StorageNode {
             stake: initial_stake,
             stored_bocs: Arc::new(Mutex::new(HashMap::new())),
             stored_proofs: Arc::new(Mutex::new(HashMap::new())),
+            wallet_replicas: Arc::new(Mutex::new(HashMap::new())),
             battery_system: Arc::new(Mutex::new(battery_charging_system)),
             config,
             peers: Arc::new(Mutex::new(peers)),
@@ -122,6 +128,55 @@ impl StorageNode {
         })
     }
 
+    /// Replaces the wallet root held for a wallet along with its inclusion proofs.
+    /// Channel states already held are kept so recovery can still pick the newest one.
+    pub async fn store_wallet_root(
+        &self,
+        wallet_id: [u8; 32],
+        wallet_root: BOC,
+        intermediate_proof: Vec<u8>,
+        global_proof: Vec<u8>,
+    ) -> Result<(), SystemError> {
+        let mut replicas = self.wallet_replicas.lock().await;
+        let channels = replicas
+            .remove(&wallet_id)
+            .map(|replica| replica.channels)
+            .unwrap_or_default();
+        replicas.insert(
+            wallet_id,
+            WalletReplica {
+                wallet_root,
+                intermediate_proof,
+                global_proof,
+                channels,
+            },
+        );
+        Ok(())
+    }
+
+    /// Stores a signed channel state for a wallet whose root is already held.
+    pub async fn store_channel_state(
+        &self,
+        wallet_id: [u8; 32],
+        channel: ChannelReplica,
+    ) -> Result<(), SystemError> {
+        let record = ChannelStateRecord::from_boc(&channel.state)?;
+        if record.wallet_id != wallet_id {
+            return Err(SystemError {
+                error_type: SystemErrorType::InvalidAddress,
+                message: "Channel state belongs to another wallet".to_string(),
+            });
+        }
+
+        let mut replicas = self.wallet_replicas.lock().await;
+        let replica = replicas.get_mut(&wallet_id).ok_or_else(|| SystemError {
+            error_type: SystemErrorType::NotFound,
+            message: "No wallet root stored for wallet".to_string(),
+        })?;
+        replica.channels.push(channel);
+        Ok(())
+    }
+
     pub async fn add_peer(&self, peer_id: [u8; 32]) -> Result<(), SystemError> {
         let mut peers = self.peers.lock().await;
         peers.insert(peer_id);
@@ -148,6 +203,20 @@ impl StorageNode {
     }
 }
 
+#[async_trait]
+impl ReplicaSource for StorageNode {
+    async fn fetch_wallet_replica(
+        &self,
+        wallet_id: &[u8; 32],
+    ) -> Result<WalletReplica, SystemError> {
+        let replicas = self.wallet_replicas.lock().await;
+        replicas.get(wallet_id).cloned().ok_or_else(|| SystemError {
+            error_type: SystemErrorType::NotFound,
+            message: "Wallet replica not found".to_string(),
+        })
+    }
+}
+
 impl StorageNodeConfig {
     pub fn new(
         battery_config: BatteryConfig,
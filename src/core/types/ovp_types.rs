@@ -1,4 +1,6 @@
 // src/core/types/ovp_types.rs
+use crate::core::error::errors::SystemError;
+use crate::core::hierarchy::root::root_contract::RootContract;
 use crate::core::storage_node::epidemic::{BatteryPropagation, SynchronizationManager};
 use crate::core::storage_node::storage_node::StorageNode;
 use crate::core::types::ovp_ops::ChannelOpCode;
@@ -382,7 +384,28 @@ pub struct RootSubmission {
     pub boc: BOC,
     pub zk_proof: ZkProof,
     pub timestamp: u64,
+    /// Proof in the `verifier_export` layout.
     pub(crate) zkp: Vec<u8>,
+    /// Hash of the exported verifier key `zkp` verifies under.
+    pub verifier_key_hash: [u8; 32],
+}
+
+impl RootSubmission {
+    /// Submits `root` for `contract_addr` to the root contract, which settles it only if `zkp`
+    /// verifies under the verifier key `verifier_key_hash` names.
+    pub fn process(
+        &self,
+        contract: &mut RootContract,
+        contract_addr: [u8; 32],
+        root: [u8; 32],
+    ) -> Result<(), SystemError> {
+        contract.process_settlement_submission(
+            contract_addr,
+            root,
+            &self.verifier_key_hash,
+            &self.zkp,
+        )
+    }
 }
 
 // Slice of CellData
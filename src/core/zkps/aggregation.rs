@@ -0,0 +1,768 @@
+// ./src/core/zkps/aggregation.rs
+
+// Recursive Proof Aggregation
+// Folds many proofs into one (blueprint 38.1) that always verifies under the same key, so
+// the root can pin a single aggregation key however many proofs an epoch holds.
+//
+// Child circuits (state transitions, tree transitions, ...) are registered by their verifier
+// data. Every child proof is first wrapped by a leaf circuit for its child circuit, which
+// verifies it against the child's key and exposes the digest of its public inputs. Leaf
+// proofs and aggregates share one shape, so a single node circuit folds any two of them:
+// each input verifies under a key the prover supplies, which the node constrains to be one of
+// the leaf keys or the node's own key. Proofs are paired level by level; the odd proof at the
+// end of a level, and a lone proof, are folded with an empty input, so the result is always
+// a node proof.
+//
+// Public inputs of every proof of the shared shape are the 32-byte digest of the public
+// inputs it covers, the number of child proofs it covers and, for aggregates, the node
+// circuit's own key. A leaf's digest is the Poseidon hash of its child's public inputs; a
+// node's is Poseidon(left digest | right digest), or the left digest when the right input is
+// empty. `aggregate_digest` recomputes the same value from the children's public inputs, so a
+// verifier can tell exactly which statements an aggregate proves. The node cannot check its
+// own key, so verifiers must check that an aggregate carries the key it verified under.
+//
+// The leaf and node circuits are built on first use and depend only on the set of registered
+// child circuits: registering another child circuit rebuilds them under a new key.
+
+use crate::core::error::errors::{SystemError, SystemErrorType};
+use crate::core::smt::hasher::PoseidonHasher;
+use crate::core::zkps::gadgets::hash::{poseidon, poseidon_pair};
+use plonky2::field::goldilocks_field::GoldilocksField;
+use plonky2::field::types::Field;
+use plonky2::gates::gate::GateRef;
+use plonky2::gates::noop::NoopGate;
+use plonky2::hash::hash_types::{HashOut, HashOutTarget};
+use plonky2::hash::poseidon::PoseidonHash;
+use plonky2::iop::target::{BoolTarget, Target};
+use plonky2::iop::witness::{PartialWitness, WitnessWrite};
+use plonky2::plonk::circuit_builder::CircuitBuilder;
+use plonky2::plonk::circuit_data::{
+    CircuitConfig, CircuitData, CommonCircuitData, VerifierCircuitData, VerifierCircuitTarget,
+    VerifierOnlyCircuitData,
+};
+use plonky2::plonk::config::{Hasher, PoseidonGoldilocksConfig};
+use plonky2::plonk::proof::{ProofWithPublicInputs, ProofWithPublicInputsTarget};
+use std::collections::{BTreeMap, HashMap};
+
+const D: usize = 2;
+type F = GoldilocksField;
+type C = PoseidonGoldilocksConfig;
+
+/// Public inputs of an aggregate that make up its statement: the covered digest and the
+/// number of proofs it covers. The node circuit's own key follows them.
+pub const AGGREGATE_STATEMENT_INPUTS: usize = 5;
+
+/// Rounds of rebuilding the leaf and node circuits until they share one shape.
+const MAX_SHAPE_ROUNDS: usize = 8;
+
+/// A proof of a registered circuit, identified by that circuit's digest.
+#[derive(Clone, Debug)]
+pub struct ChildProof {
+    pub circuit: [u8; 32],
+    pub proof: ProofWithPublicInputs<F, C, D>,
+}
+
+/// One proof covering `proof_count` child proofs.
+#[derive(Clone, Debug)]
+pub struct AggregateProof {
+    /// Digest of the circuit that produced `proof`.
+    pub circuit: [u8; 32],
+    pub proof_count: usize,
+    pub proof: ProofWithPublicInputs<F, C, D>,
+}
+
+impl AggregateProof {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.proof.to_bytes()
+    }
+}
+
+/// Wraps proofs of one child circuit into the shared shape.
+struct LeafCircuit {
+    data: CircuitData<F, C, D>,
+    proof: ProofWithPublicInputsTarget<D>,
+}
+
+impl LeafCircuit {
+    fn build(child: &VerifierCircuitData<F, C, D>, shape: &Shape) -> Self {
+        let config = CircuitConfig::standard_recursion_config();
+        let key_len = key_len(&config);
+        let mut builder = CircuitBuilder::<F, D>::new(config);
+        let proof = builder.add_virtual_proof_with_pis(&child.common);
+        let key = builder.constant_verifier_data(&child.verifier_only);
+        builder.verify_proof::<C>(&proof, &key, &child.common);
+
+        let digest = poseidon(&mut builder, proof.public_inputs.clone());
+        builder.register_public_inputs(&digest.elements);
+        let one = builder.one();
+        builder.register_public_input(one);
+        let zero = builder.zero();
+        for _ in 0..key_len {
+            builder.register_public_input(zero);
+        }
+
+        shape.pad(&mut builder);
+        Self {
+            data: builder.build::<C>(),
+            proof,
+        }
+    }
+
+    fn prove(
+        &self,
+        child: &ProofWithPublicInputs<F, C, D>,
+    ) -> Result<ProofWithPublicInputs<F, C, D>, SystemError> {
+        let mut pw = PartialWitness::new();
+        pw.set_proof_with_pis_target(&self.proof, child)
+            .map_err(proving_error)?;
+        self.data.prove(pw).map_err(proving_error)
+    }
+}
+
+/// One input of the node circuit: a proof of the shared shape and the key it verifies under.
+struct SlotTargets {
+    proof: ProofWithPublicInputsTarget<D>,
+    key: VerifierCircuitTarget,
+    is_aggregate: BoolTarget,
+    leaves: Vec<BoolTarget>,
+    is_empty: Option<BoolTarget>,
+}
+
+impl SlotTargets {
+    /// Verifies a proof of `shape` under a key that must be the node's own key or one of
+    /// `leaf_keys`. An optional slot may instead be marked empty, in which case its proof and
+    /// key are unconstrained and must be ignored by the caller.
+    fn add(
+        builder: &mut CircuitBuilder<F, D>,
+        shape: &CommonCircuitData<F, D>,
+        own_key: &VerifierCircuitTarget,
+        leaf_keys: &[Vec<F>],
+        optional: bool,
+    ) -> Self {
+        let proof = builder.add_virtual_proof_with_pis(shape);
+        let key = builder.add_virtual_verifier_data(shape.config.fri_config.cap_height);
+        builder.verify_proof::<C>(&proof, &key, shape);
+
+        let is_aggregate = builder.add_virtual_bool_target_safe();
+        let leaves: Vec<BoolTarget> = leaf_keys
+            .iter()
+            .map(|_| builder.add_virtual_bool_target_safe())
+            .collect();
+        let is_empty = optional.then(|| builder.add_virtual_bool_target_safe());
+        let mut kinds = is_aggregate.target;
+        for flag in leaves.iter().chain(is_empty.iter()) {
+            kinds = builder.add(kinds, flag.target);
+        }
+        let one = builder.one();
+        builder.connect(kinds, one);
+
+        // The key is the node's own key or the flagged leaf's
+        let active = match is_empty {
+            Some(is_empty) => builder.not(is_empty).target,
+            None => one,
+        };
+        let own = key_targets(own_key);
+        for (i, element) in key_targets(&key).into_iter().enumerate() {
+            let mut expected = builder.mul(is_aggregate.target, own[i]);
+            for (flag, leaf_key) in leaves.iter().zip(leaf_keys) {
+                expected = builder.mul_const_add(leaf_key[i], flag.target, expected);
+            }
+            let difference = builder.sub(element, expected);
+            let difference = builder.mul(difference, active);
+            builder.assert_zero(difference);
+        }
+
+        // An inner aggregate must itself have been made under the node's own key
+        let inner_key = &proof.public_inputs[AGGREGATE_STATEMENT_INPUTS..];
+        for (&inner, &own) in inner_key.iter().zip(&own) {
+            let difference = builder.sub(inner, own);
+            let difference = builder.mul(difference, is_aggregate.target);
+            builder.assert_zero(difference);
+        }
+
+        Self {
+            proof,
+            key,
+            is_aggregate,
+            leaves,
+            is_empty,
+        }
+    }
+
+    fn digest(&self) -> HashOutTarget {
+        HashOutTarget::from_vec(self.proof.public_inputs[..4].to_vec())
+    }
+
+    fn count(&self) -> Target {
+        self.proof.public_inputs[4]
+    }
+
+    fn set(
+        &self,
+        pw: &mut PartialWitness<F>,
+        input: &Folded,
+        key: &VerifierOnlyCircuitData<C, D>,
+        empty: bool,
+    ) -> Result<(), SystemError> {
+        pw.set_proof_with_pis_target(&self.proof, &input.proof)
+            .map_err(proving_error)?;
+        pw.set_verifier_data_target(&self.key, key)
+            .map_err(proving_error)?;
+        pw.set_bool_target(self.is_aggregate, !empty && input.leaf.is_none())
+            .map_err(proving_error)?;
+        for (index, flag) in self.leaves.iter().enumerate() {
+            pw.set_bool_target(*flag, !empty && input.leaf == Some(index))
+                .map_err(proving_error)?;
+        }
+        if let Some(is_empty) = self.is_empty {
+            pw.set_bool_target(is_empty, empty).map_err(proving_error)?;
+        }
+        Ok(())
+    }
+}
+
+/// Folds two proofs of the shared shape, or one and an empty input, into an aggregate.
+struct NodeCircuit {
+    data: CircuitData<F, C, D>,
+    own_key: VerifierCircuitTarget,
+    left: SlotTargets,
+    right: SlotTargets,
+}
+
+impl NodeCircuit {
+    fn build(common: &CommonCircuitData<F, D>, leaf_keys: &[Vec<F>], shape: &Shape) -> Self {
+        let mut builder = CircuitBuilder::<F, D>::new(common.config.clone());
+        let own_key = builder.add_virtual_verifier_data(common.config.fri_config.cap_height);
+        let left = SlotTargets::add(&mut builder, common, &own_key, leaf_keys, false);
+        let right = SlotTargets::add(&mut builder, common, &own_key, leaf_keys, true);
+        let empty = right.is_empty.expect("the right input is optional");
+
+        let left_digest = left.digest();
+        let both = poseidon_pair(&mut builder, left_digest, right.digest());
+        for (single, pair) in left_digest.elements.into_iter().zip(both.elements) {
+            let digest = builder.select(empty, single, pair);
+            builder.register_public_input(digest);
+        }
+        let present = builder.not(empty);
+        let count = builder.mul_add(present.target, right.count(), left.count());
+        builder.register_public_input(count);
+        builder.register_public_inputs(&key_targets(&own_key));
+
+        shape.pad(&mut builder);
+        Self {
+            data: builder.build::<C>(),
+            own_key,
+            left,
+            right,
+        }
+    }
+}
+
+/// Degree and gate set the leaf and node circuits are padded to, so they share one shape.
+#[derive(Default)]
+struct Shape {
+    degree_bits: usize,
+    gates: Vec<GateRef<F, D>>,
+}
+
+impl Shape {
+    fn pad(&self, builder: &mut CircuitBuilder<F, D>) {
+        for gate in &self.gates {
+            builder.add_gate_to_gate_set(gate.clone());
+        }
+        if self.degree_bits > 0 {
+            while builder.num_gates() <= 1 << (self.degree_bits - 1) {
+                builder.add_gate(NoopGate, vec![]);
+            }
+        }
+    }
+
+    fn widen(&mut self, common: &CommonCircuitData<F, D>) {
+        self.degree_bits = self.degree_bits.max(common.degree_bits());
+        for gate in &common.gates {
+            if !self.gates.contains(gate) {
+                self.gates.push(gate.clone());
+            }
+        }
+    }
+}
+
+/// The leaf circuits of the registered children, in digest order, and the node circuit.
+struct AggregationCircuit {
+    node: NodeCircuit,
+    digest: [u8; 32],
+    leaves: Vec<LeafCircuit>,
+    leaf_index: HashMap<[u8; 32], usize>,
+}
+
+impl AggregationCircuit {
+    /// Builds the leaf and node circuits, widening all of them to the largest degree and the
+    /// union of their gates until they come out with the same common data.
+    fn build(
+        children: &BTreeMap<[u8; 32], VerifierCircuitData<F, C, D>>,
+    ) -> Result<Self, SystemError> {
+        if children.is_empty() {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidOperation,
+                "No circuits registered for aggregation".to_string(),
+            ));
+        }
+
+        let mut shape = Shape::default();
+        for _ in 0..MAX_SHAPE_ROUNDS {
+            let leaves: Vec<LeafCircuit> = children
+                .values()
+                .map(|child| LeafCircuit::build(child, &shape))
+                .collect();
+            let common = leaves[0].data.common.clone();
+            let leaf_keys: Vec<Vec<F>> = leaves
+                .iter()
+                .map(|leaf| key_elements(&leaf.data.verifier_only))
+                .collect();
+            let node = NodeCircuit::build(&common, &leaf_keys, &shape);
+
+            let commons = leaves
+                .iter()
+                .map(|leaf| &leaf.data.common)
+                .chain([&node.data.common]);
+            if commons.clone().all(|other| *other == common) {
+                return Ok(Self {
+                    digest: PoseidonHasher::from_hash_out(node.data.verifier_only.circuit_digest),
+                    node,
+                    leaves,
+                    leaf_index: children.keys().enumerate().map(|(i, k)| (*k, i)).collect(),
+                });
+            }
+            for other in commons {
+                shape.widen(other);
+            }
+        }
+        Err(SystemError::new(
+            SystemErrorType::InvalidOperation,
+            "Aggregation circuits did not settle on one shape".to_string(),
+        ))
+    }
+
+    fn wrap(&self, child: &ChildProof) -> Result<Folded, SystemError> {
+        let index = self.leaf_index[&child.circuit];
+        Ok(Folded {
+            proof: self.leaves[index].prove(&child.proof)?,
+            leaf: Some(index),
+        })
+    }
+
+    fn fold(&self, left: &Folded, right: Option<&Folded>) -> Result<Folded, SystemError> {
+        let node = &self.node;
+        let mut pw = PartialWitness::new();
+        pw.set_verifier_data_target(&node.own_key, &node.data.verifier_only)
+            .map_err(proving_error)?;
+        node.left.set(&mut pw, left, self.key(left), false)?;
+        match right {
+            Some(right) => node.right.set(&mut pw, right, self.key(right), false)?,
+            None => node.right.set(&mut pw, left, self.key(left), true)?,
+        }
+        Ok(Folded {
+            proof: node.data.prove(pw).map_err(proving_error)?,
+            leaf: None,
+        })
+    }
+
+    fn key(&self, input: &Folded) -> &VerifierOnlyCircuitData<C, D> {
+        match input.leaf {
+            Some(index) => &self.leaves[index].data.verifier_only,
+            None => &self.node.data.verifier_only,
+        }
+    }
+}
+
+/// A proof of the shared shape: a wrapped child proof of the given leaf, or an aggregate.
+struct Folded {
+    proof: ProofWithPublicInputs<F, C, D>,
+    leaf: Option<usize>,
+}
+
+/// Registry of child circuits and the aggregation circuit built over them.
+#[derive(Default)]
+pub struct ProofAggregator {
+    children: BTreeMap<[u8; 32], VerifierCircuitData<F, C, D>>,
+    circuit: Option<AggregationCircuit>,
+}
+
+impl ProofAggregator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a child circuit and returns its digest. A new child circuit changes the
+    /// aggregation circuit's key.
+    pub fn register_circuit(&mut self, verifier: VerifierCircuitData<F, C, D>) -> [u8; 32] {
+        let digest = PoseidonHasher::from_hash_out(verifier.verifier_only.circuit_digest);
+        if !self.children.contains_key(&digest) {
+            self.children.insert(digest, verifier);
+            self.circuit = None;
+        }
+        digest
+    }
+
+    /// Parses a serialized proof of a registered circuit.
+    pub fn child_proof(&self, circuit: [u8; 32], bytes: &[u8]) -> Result<ChildProof, SystemError> {
+        let common = &self.child(&circuit)?.common;
+        let proof = ProofWithPublicInputs::<F, C, D>::from_bytes(bytes.to_vec(), common)
+            .map_err(|e| SystemError::new(SystemErrorType::InvalidProof, e.to_string()))?;
+        Ok(ChildProof { circuit, proof })
+    }
+
+    /// Folds the proofs, in order, into one proof.
+    pub fn aggregate(&mut self, proofs: Vec<ChildProof>) -> Result<AggregateProof, SystemError> {
+        if proofs.is_empty() {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidOperation,
+                "No proofs to aggregate".to_string(),
+            ));
+        }
+        for child in &proofs {
+            self.child(&child.circuit)?
+                .verify(child.proof.clone())
+                .map_err(proving_error)?;
+        }
+
+        let circuit = self.aggregation_circuit()?;
+        let mut level = proofs
+            .iter()
+            .map(|child| circuit.wrap(child))
+            .collect::<Result<Vec<_>, _>>()?;
+        loop {
+            level = level
+                .chunks(2)
+                .map(|pair| circuit.fold(&pair[0], pair.get(1)))
+                .collect::<Result<Vec<_>, _>>()?;
+            if level.len() == 1 {
+                break;
+            }
+        }
+
+        let root = level.pop().expect("one proof remains");
+        Ok(AggregateProof {
+            circuit: circuit.digest,
+            proof_count: proofs.len(),
+            proof: root.proof,
+        })
+    }
+
+    /// Verifies an aggregate proof and returns the digest of the public inputs it covers.
+    pub fn verify(&self, aggregate: &AggregateProof) -> Result<[u8; 32], SystemError> {
+        let circuit = match &self.circuit {
+            Some(circuit) if circuit.digest == aggregate.circuit => circuit,
+            _ => {
+                return Err(SystemError::new(
+                    SystemErrorType::NotFound,
+                    "Aggregate was not made by this aggregator's circuit".to_string(),
+                ))
+            }
+        };
+        let public_inputs = &aggregate.proof.public_inputs;
+        check_aggregate_key(public_inputs, &circuit.node.data.verifier_only)?;
+        if public_inputs[4] != F::from_canonical_usize(aggregate.proof_count) {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidProof,
+                "Aggregate covers a different number of proofs".to_string(),
+            ));
+        }
+        circuit
+            .node
+            .data
+            .verify(aggregate.proof.clone())
+            .map_err(proving_error)?;
+        Ok(PoseidonHasher::from_hash_out(HashOut::from_partial(
+            &public_inputs[..4],
+        )))
+    }
+
+    /// Verifies an aggregate proof and checks that it covers exactly these child public
+    /// inputs, in order.
+    pub fn verify_children(
+        &self,
+        aggregate: &AggregateProof,
+        children: &[Vec<F>],
+    ) -> Result<(), SystemError> {
+        let digest = self.verify(aggregate)?;
+        if aggregate.proof_count != children.len() || digest != aggregate_digest(children) {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidProof,
+                "Aggregate proof does not cover the expected public inputs".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Verifier data of the aggregation circuit, building it if needed, for registering it
+    /// with a `ProofVerifier`.
+    pub fn aggregation_verifier_data(
+        &mut self,
+    ) -> Result<VerifierCircuitData<F, C, D>, SystemError> {
+        Ok(self.aggregation_circuit()?.node.data.verifier_data())
+    }
+
+    /// Verifier data of a registered child circuit or of the aggregation circuit.
+    pub fn verifier_data(
+        &self,
+        circuit: &[u8; 32],
+    ) -> Result<VerifierCircuitData<F, C, D>, SystemError> {
+        match &self.circuit {
+            Some(aggregation) if aggregation.digest == *circuit => {
+                Ok(aggregation.node.data.verifier_data())
+            }
+            _ => self.child(circuit).cloned(),
+        }
+    }
+
+    fn child(&self, digest: &[u8; 32]) -> Result<&VerifierCircuitData<F, C, D>, SystemError> {
+        self.children.get(digest).ok_or_else(|| {
+            SystemError::new(
+                SystemErrorType::NotFound,
+                "Proof circuit is not registered".to_string(),
+            )
+        })
+    }
+
+    fn aggregation_circuit(&mut self) -> Result<&AggregationCircuit, SystemError> {
+        if self.circuit.is_none() {
+            self.circuit = Some(AggregationCircuit::build(&self.children)?);
+        }
+        Ok(self.circuit.as_ref().expect("aggregation circuit is built"))
+    }
+}
+
+/// Checks that an aggregate's public inputs carry `key`, the key it was verified under.
+pub fn check_aggregate_key(
+    public_inputs: &[F],
+    key: &VerifierOnlyCircuitData<C, D>,
+) -> Result<(), SystemError> {
+    if public_inputs.get(AGGREGATE_STATEMENT_INPUTS..) != Some(key_elements(key).as_slice()) {
+        return Err(SystemError::new(
+            SystemErrorType::InvalidProof,
+            "Aggregate was not made under the aggregation circuit's key".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Digest of the public inputs covered by aggregating `children` in order, using the same
+/// pairing as `ProofAggregator::aggregate`.
+pub fn aggregate_digest(children: &[Vec<F>]) -> [u8; 32] {
+    let mut level: Vec<HashOut<F>> = children
+        .iter()
+        .map(|inputs| PoseidonHash::hash_no_pad(inputs))
+        .collect();
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => {
+                    let mut inputs = left.elements.to_vec();
+                    inputs.extend_from_slice(&right.elements);
+                    PoseidonHash::hash_no_pad(&inputs)
+                }
+                [single] => *single,
+                _ => unreachable!(),
+            })
+            .collect();
+    }
+    level
+        .first()
+        .map(|hash| PoseidonHasher::from_hash_out(*hash))
+        .unwrap_or([0u8; 32])
+}
+
+/// The statement public inputs of an aggregate of `children`: the covered digest and the
+/// number of proofs.
+pub fn aggregate_statement(children: &[Vec<F>]) -> Vec<F> {
+    let mut inputs = PoseidonHasher::to_hash_out(&aggregate_digest(children))
+        .elements
+        .to_vec();
+    inputs.push(F::from_canonical_usize(children.len()));
+    inputs
+}
+
+/// Length of a verifier key as public inputs: the circuit digest, then the Merkle cap.
+fn key_len(config: &CircuitConfig) -> usize {
+    4 + 4 * config.fri_config.num_cap_elements()
+}
+
+fn key_elements(key: &VerifierOnlyCircuitData<C, D>) -> Vec<F> {
+    let mut elements = key.circuit_digest.elements.to_vec();
+    elements.extend(key.constants_sigmas_cap.flatten());
+    elements
+}
+
+fn key_targets(key: &VerifierCircuitTarget) -> Vec<Target> {
+    let mut targets = key.circuit_digest.elements.to_vec();
+    for hash in &key.constants_sigmas_cap.0 {
+        targets.extend_from_slice(&hash.elements);
+    }
+    targets
+}
+
+fn proving_error<E: std::fmt::Display>(e: E) -> SystemError {
+    SystemError::new(SystemErrorType::InvalidProof, e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use plonky2::field::types::Field;
+    use plonky2::iop::target::Target;
+
+    /// Proves knowledge of x with x * x = y for public y, plus a public tag.
+    struct SquareCircuit {
+        data: CircuitData<F, C, D>,
+        x: Target,
+        y: Target,
+        tag: Target,
+    }
+
+    impl SquareCircuit {
+        fn new(extra_public: usize) -> Self {
+            let mut builder =
+                CircuitBuilder::<F, D>::new(CircuitConfig::standard_recursion_config());
+            let x = builder.add_virtual_target();
+            let y = builder.add_virtual_public_input();
+            let tag = builder.add_virtual_public_input();
+            let square = builder.mul(x, x);
+            builder.connect(square, y);
+            for _ in 0..extra_public {
+                let zero = builder.zero();
+                builder.register_public_input(zero);
+            }
+            Self {
+                data: builder.build::<C>(),
+                x,
+                y,
+                tag,
+            }
+        }
+
+        fn prove(&self, x: u64, tag: u64) -> ProofWithPublicInputs<F, C, D> {
+            let mut pw = PartialWitness::new();
+            pw.set_target(self.x, F::from_canonical_u64(x)).unwrap();
+            pw.set_target(self.y, F::from_canonical_u64(x * x)).unwrap();
+            pw.set_target(self.tag, F::from_canonical_u64(tag)).unwrap();
+            self.data.prove(pw).unwrap()
+        }
+    }
+
+    #[test]
+    fn test_aggregates_mixed_circuits_into_one_proof() {
+        let square = SquareCircuit::new(0);
+        let padded = SquareCircuit::new(2);
+        let mut aggregator = ProofAggregator::new();
+        let square_id = aggregator.register_circuit(square.data.verifier_data());
+        let padded_id = aggregator.register_circuit(padded.data.verifier_data());
+
+        let children = vec![
+            ChildProof {
+                circuit: square_id,
+                proof: square.prove(3, 1),
+            },
+            ChildProof {
+                circuit: square_id,
+                proof: square.prove(4, 2),
+            },
+            ChildProof {
+                circuit: padded_id,
+                proof: padded.prove(5, 3),
+            },
+        ];
+        let inputs: Vec<Vec<F>> = children
+            .iter()
+            .map(|child| child.proof.public_inputs.clone())
+            .collect();
+
+        let aggregate = aggregator.aggregate(children).unwrap();
+        assert_eq!(aggregate.proof_count, 3);
+        assert_eq!(
+            aggregate.proof.public_inputs.len(),
+            AGGREGATE_STATEMENT_INPUTS + key_len(&CircuitConfig::standard_recursion_config())
+        );
+        assert_eq!(
+            aggregate.proof.public_inputs[..5],
+            aggregate_statement(&inputs)
+        );
+        assert_eq!(
+            aggregator.verify(&aggregate).unwrap(),
+            aggregate_digest(&inputs)
+        );
+        aggregator.verify_children(&aggregate, &inputs).unwrap();
+
+        let mut reordered = inputs.clone();
+        reordered.swap(0, 1);
+        assert!(aggregator.verify_children(&aggregate, &reordered).is_err());
+    }
+
+    #[test]
+    fn test_every_aggregate_verifies_under_one_key() {
+        let square = SquareCircuit::new(0);
+        let padded = SquareCircuit::new(2);
+        let mut aggregator = ProofAggregator::new();
+        let square_id = aggregator.register_circuit(square.data.verifier_data());
+        let padded_id = aggregator.register_circuit(padded.data.verifier_data());
+        let key = aggregator.aggregation_verifier_data().unwrap();
+
+        // A lone proof is still folded into an aggregate
+        let single = ChildProof {
+            circuit: square_id,
+            proof: square.prove(3, 1),
+        };
+        let inputs = vec![single.proof.public_inputs.clone()];
+        let one = aggregator.aggregate(vec![single]).unwrap();
+        assert_ne!(one.circuit, square_id);
+        aggregator.verify_children(&one, &inputs).unwrap();
+
+        let pair = vec![
+            ChildProof {
+                circuit: padded_id,
+                proof: padded.prove(4, 2),
+            },
+            ChildProof {
+                circuit: square_id,
+                proof: square.prove(5, 3),
+            },
+        ];
+        let two = aggregator.aggregate(pair).unwrap();
+        assert_eq!(two.circuit, one.circuit);
+        for aggregate in [&one, &two] {
+            key.verify(aggregate.proof.clone()).unwrap();
+            check_aggregate_key(&aggregate.proof.public_inputs, &key.verifier_only).unwrap();
+        }
+
+        // The key an aggregate carries must be the one it is checked against
+        let mut rekeyed = two.clone();
+        rekeyed.proof.public_inputs[AGGREGATE_STATEMENT_INPUTS] += F::ONE;
+        assert!(aggregator.verify(&rekeyed).is_err());
+        let mut recounted = two.clone();
+        recounted.proof_count = 3;
+        assert!(aggregator.verify(&recounted).is_err());
+
+        // A new child circuit changes the aggregation key
+        aggregator.register_circuit(SquareCircuit::new(1).data.verifier_data());
+        assert!(aggregator.verify(&two).is_err());
+        assert_ne!(
+            aggregator
+                .aggregation_verifier_data()
+                .unwrap()
+                .verifier_only,
+            key.verifier_only
+        );
+    }
+
+    #[test]
+    fn test_rejects_unregistered_circuit() {
+        let square = SquareCircuit::new(0);
+        let mut aggregator = ProofAggregator::new();
+        let proof = ChildProof {
+            circuit: [7u8; 32],
+            proof: square.prove(2, 0),
+        };
+        assert!(aggregator.aggregate(vec![proof]).is_err());
+    }
+}
@@ -0,0 +1,314 @@
+// ./src/core/zkps/circuit_cache.rs
+
+// Circuit Cache
+// Building a circuit dominates proving latency, so each circuit is built once per process and
+// shared. Circuits are looked up by name and stored by circuit digest; a name is bound to the
+// first circuit built or loaded for it.
+//
+// Serialized circuits are the circuit digest followed by the circuit data (common data plus
+// prover-only and verifier-only data). Loading recomputes the digest from the constants and
+// sigmas cap and the common data, the way `CircuitBuilder::build` derives it, so the digest
+// stored in the verifier-only data is not taken on trust. The bytes are rejected unless the
+// recomputed digest matches the header and the digest the caller pinned. This lets built
+// circuits be written to disk or shipped to the browser instead of rebuilt.
+
+use crate::core::error::errors::{SystemError, SystemErrorType};
+use crate::core::smt::hasher::PoseidonHasher;
+use plonky2::field::goldilocks_field::GoldilocksField;
+use plonky2::field::types::Field;
+use plonky2::hash::poseidon::PoseidonHash;
+use plonky2::plonk::circuit_data::CircuitData;
+use plonky2::plonk::config::{Hasher, PoseidonGoldilocksConfig};
+use plonky2::util::serialization::{DefaultGateSerializer, DefaultGeneratorSerializer};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex, OnceLock};
+
+const D: usize = 2;
+type F = GoldilocksField;
+type C = PoseidonGoldilocksConfig;
+
+/// Digest identifying a circuit, taken from its verifier-only data.
+pub fn circuit_digest(data: &CircuitData<F, C, D>) -> [u8; 32] {
+    PoseidonHasher::from_hash_out(data.verifier_only.circuit_digest)
+}
+
+/// Digest of a circuit recomputed from its constants/sigmas cap and degree, as
+/// `CircuitBuilder::build` derives it with the default, empty domain separator.
+fn recompute_digest(data: &CircuitData<F, C, D>) -> [u8; 32] {
+    let mut inputs = data.verifier_only.constants_sigmas_cap.flatten();
+    inputs.extend(PoseidonHash::hash_pad(&[]).elements);
+    inputs.push(F::from_canonical_usize(data.common.degree_bits()));
+    PoseidonHasher::from_hash_out(PoseidonHash::hash_no_pad(&inputs))
+}
+
+/// Serializes a built circuit as its digest followed by the circuit data.
+pub fn serialize_circuit(data: &CircuitData<F, C, D>) -> Result<Vec<u8>, SystemError> {
+    let body = data
+        .to_bytes(
+            &DefaultGateSerializer,
+            &DefaultGeneratorSerializer::<C, D>::default(),
+        )
+        .map_err(|e| {
+            SystemError::new(
+                SystemErrorType::InvalidOperation,
+                format!("Failed to serialize circuit: {:?}", e),
+            )
+        })?;
+    let mut bytes = Vec::with_capacity(32 + body.len());
+    bytes.extend_from_slice(&circuit_digest(data));
+    bytes.extend_from_slice(&body);
+    Ok(bytes)
+}
+
+/// Parses a serialized circuit whose recomputed digest must equal `expected` and the header.
+pub fn deserialize_circuit(
+    bytes: &[u8],
+    expected: &[u8; 32],
+) -> Result<CircuitData<F, C, D>, SystemError> {
+    if bytes.len() < 32 {
+        return Err(SystemError::new(
+            SystemErrorType::InvalidOperation,
+            "Serialized circuit is too short".to_string(),
+        ));
+    }
+    let (header, body) = bytes.split_at(32);
+    if expected[..] != *header {
+        return Err(SystemError::new(
+            SystemErrorType::InvalidHash,
+            "Serialized circuit has an unexpected digest".to_string(),
+        ));
+    }
+    let data = CircuitData::<F, C, D>::from_bytes(
+        body,
+        &DefaultGateSerializer,
+        &DefaultGeneratorSerializer::<C, D>::default(),
+    )
+    .map_err(|e| {
+        SystemError::new(
+            SystemErrorType::InvalidOperation,
+            format!("Failed to deserialize circuit: {:?}", e),
+        )
+    })?;
+    let digest = recompute_digest(&data);
+    if digest[..] != *header || digest != circuit_digest(&data) {
+        return Err(SystemError::new(
+            SystemErrorType::InvalidHash,
+            "Circuit digest does not match its header".to_string(),
+        ));
+    }
+    Ok(data)
+}
+
+type SharedCircuit = Arc<CircuitData<F, C, D>>;
+
+/// Built circuits, shared by name and by digest.
+#[derive(Default)]
+pub struct CircuitCache {
+    named: Mutex<HashMap<String, Arc<OnceLock<SharedCircuit>>>>,
+    by_digest: Mutex<HashMap<[u8; 32], SharedCircuit>>,
+}
+
+impl CircuitCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The process-wide cache.
+    pub fn global() -> &'static CircuitCache {
+        static CACHE: OnceLock<CircuitCache> = OnceLock::new();
+        CACHE.get_or_init(CircuitCache::new)
+    }
+
+    /// Returns the circuit cached under `name`, building it on first use. Concurrent callers
+    /// wait for a single build.
+    pub fn get_or_build<B>(&self, name: &str, build: B) -> SharedCircuit
+    where
+        B: FnOnce() -> CircuitData<F, C, D>,
+    {
+        let slot = self.slot(name);
+        slot.get_or_init(|| self.index(Arc::new(build()))).clone()
+    }
+
+    /// Returns the circuit cached under `name`, loading it from `path` or building and writing
+    /// it there when the file is missing, does not parse or is not the `expected` circuit. A
+    /// build that does not produce `expected` either is an error: the pin belongs to another
+    /// version of the circuit.
+    pub fn load_or_build<B>(
+        &self,
+        name: &str,
+        path: &Path,
+        expected: &[u8; 32],
+        build: B,
+    ) -> Result<SharedCircuit, SystemError>
+    where
+        B: FnOnce() -> CircuitData<F, C, D>,
+    {
+        let slot = self.slot(name);
+        if let Some(circuit) = slot.get() {
+            return check_cached(name, circuit, expected);
+        }
+
+        let loaded = std::fs::read(path)
+            .ok()
+            .and_then(|bytes| deserialize_circuit(&bytes, expected).ok());
+        let circuit = match loaded {
+            Some(data) => data,
+            None => {
+                let data = build();
+                if circuit_digest(&data) != *expected {
+                    return Err(SystemError::new(
+                        SystemErrorType::InvalidHash,
+                        format!("Circuit {} does not build to the pinned digest", name),
+                    ));
+                }
+                std::fs::write(path, serialize_circuit(&data)?).map_err(|e| {
+                    SystemError::new(
+                        SystemErrorType::StorageError,
+                        format!("Failed to write circuit to {}: {}", path.display(), e),
+                    )
+                })?;
+                data
+            }
+        };
+        Ok(slot.get_or_init(|| self.index(Arc::new(circuit))).clone())
+    }
+
+    /// Caches a serialized circuit under `name` and returns the cached circuit. The bytes must
+    /// hold the circuit with the `expected` digest, which must also be the digest of any
+    /// circuit `name` already holds.
+    pub fn insert_bytes(
+        &self,
+        name: &str,
+        bytes: &[u8],
+        expected: &[u8; 32],
+    ) -> Result<SharedCircuit, SystemError> {
+        let slot = self.slot(name);
+        if let Some(circuit) = slot.get() {
+            return check_cached(name, circuit, expected);
+        }
+        let data = deserialize_circuit(bytes, expected)?;
+        Ok(slot.get_or_init(|| self.index(Arc::new(data))).clone())
+    }
+
+    /// The circuit with this digest, if it has been built or loaded.
+    pub fn get(&self, digest: &[u8; 32]) -> Option<SharedCircuit> {
+        self.by_digest.lock().unwrap().get(digest).cloned()
+    }
+
+    fn slot(&self, name: &str) -> Arc<OnceLock<SharedCircuit>> {
+        self.named
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_default()
+            .clone()
+    }
+
+    fn index(&self, circuit: SharedCircuit) -> SharedCircuit {
+        self.by_digest
+            .lock()
+            .unwrap()
+            .insert(circuit_digest(&circuit), circuit.clone());
+        circuit
+    }
+}
+
+/// The circuit already cached under `name`, if it is the `expected` one.
+fn check_cached(
+    name: &str,
+    circuit: &SharedCircuit,
+    expected: &[u8; 32],
+) -> Result<SharedCircuit, SystemError> {
+    if circuit_digest(circuit) != *expected {
+        return Err(SystemError::new(
+            SystemErrorType::InvalidHash,
+            format!("Circuit {} is already cached with another digest", name),
+        ));
+    }
+    Ok(circuit.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use plonky2::plonk::circuit_builder::CircuitBuilder;
+    use plonky2::plonk::circuit_data::CircuitConfig;
+
+    fn build_square() -> CircuitData<F, C, D> {
+        let mut builder = CircuitBuilder::<F, D>::new(CircuitConfig::standard_recursion_config());
+        let x = builder.add_virtual_target();
+        let y = builder.mul(x, x);
+        builder.register_public_input(y);
+        builder.build::<C>()
+    }
+
+    #[test]
+    fn test_builds_once_per_name() {
+        let cache = CircuitCache::new();
+        let first = cache.get_or_build("square", build_square);
+        let second = cache.get_or_build("square", || panic!("circuit rebuilt"));
+        assert!(Arc::ptr_eq(&first, &second));
+        assert!(cache.get(&circuit_digest(&first)).is_some());
+    }
+
+    #[test]
+    fn test_round_trip_checks_digest() {
+        let data = build_square();
+        let digest = circuit_digest(&data);
+        let bytes = serialize_circuit(&data).unwrap();
+
+        let loaded = deserialize_circuit(&bytes, &digest).unwrap();
+        assert_eq!(circuit_digest(&loaded), digest);
+        assert!(deserialize_circuit(&bytes, &[1u8; 32]).is_err());
+
+        let mut tampered = bytes.clone();
+        tampered[0] ^= 1;
+        let mut tampered_digest = digest;
+        tampered_digest[0] ^= 1;
+        assert!(deserialize_circuit(&tampered, &tampered_digest).is_err());
+
+        let cache = CircuitCache::new();
+        let cached = cache.insert_bytes("square", &bytes, &digest).unwrap();
+        assert_eq!(circuit_digest(&cached), digest);
+        assert!(cache
+            .insert_bytes("square", &tampered, &tampered_digest)
+            .is_err());
+    }
+
+    #[test]
+    fn test_digest_is_recomputed_on_load() {
+        let data = build_square();
+        assert_eq!(recompute_digest(&data), circuit_digest(&data));
+
+        // A consistent header and stored digest that the cap does not produce
+        let mut forged = build_square();
+        forged.verifier_only.circuit_digest.elements[0] += F::ONE;
+        let forged_digest = circuit_digest(&forged);
+        let bytes = serialize_circuit(&forged).unwrap();
+        assert!(deserialize_circuit(&bytes, &forged_digest).is_err());
+        assert!(CircuitCache::new()
+            .insert_bytes("square", &bytes, &forged_digest)
+            .is_err());
+    }
+
+    #[test]
+    fn test_load_or_build_requires_pinned_digest() {
+        let digest = circuit_digest(&build_square());
+        let path = std::env::temp_dir().join(format!("square-{}.circuit", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let built = CircuitCache::new()
+            .load_or_build("square", &path, &digest, build_square)
+            .unwrap();
+        assert_eq!(circuit_digest(&built), digest);
+        let loaded = CircuitCache::new()
+            .load_or_build("square", &path, &digest, || panic!("circuit rebuilt"))
+            .unwrap();
+        assert_eq!(circuit_digest(&loaded), digest);
+        assert!(CircuitCache::new()
+            .load_or_build("square", &path, &[1u8; 32], build_square)
+            .is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,239 @@
+// ./src/core/zkps/compression.rs
+
+// Proof Compression
+// Shrinks proofs before they are stored or gossiped (blueprint 38.2). A proof is wrapped in a
+// chain of recursive layers, each verifying the previous proof and re-exposing its public
+// inputs unchanged, so the compressed proof proves the same statement with the same public
+// input layout. The first layer uses the standard recursion config, bringing the circuit
+// down to the size of a recursive verifier; the last uses `shrink_config`, which trades
+// prover time for a higher FRI rate and fewer queries and so a much smaller proof.
+//
+// Layers are built on first use for each inner circuit and cached by its digest. A
+// compressed proof's circuit digest is that of the last layer; registering that circuit with
+// `ProofVerifier` under the inner proof's type lets compressed proofs replace raw ones.
+
+use crate::core::error::errors::{SystemError, SystemErrorType};
+use crate::core::smt::hasher::PoseidonHasher;
+use plonky2::field::goldilocks_field::GoldilocksField;
+use plonky2::fri::reduction_strategies::FriReductionStrategy;
+use plonky2::fri::FriConfig;
+use plonky2::iop::witness::{PartialWitness, WitnessWrite};
+use plonky2::plonk::circuit_builder::CircuitBuilder;
+use plonky2::plonk::circuit_data::{CircuitConfig, CircuitData, VerifierCircuitData};
+use plonky2::plonk::config::PoseidonGoldilocksConfig;
+use plonky2::plonk::proof::{ProofWithPublicInputs, ProofWithPublicInputsTarget};
+use std::collections::HashMap;
+
+const D: usize = 2;
+type F = GoldilocksField;
+type C = PoseidonGoldilocksConfig;
+
+/// Recursion config for the outermost layer: rate 1/128 with 12 queries and 16 bits of
+/// grinding keeps about 100 bits of conjectured security with far fewer Merkle openings.
+pub fn shrink_config() -> CircuitConfig {
+    CircuitConfig {
+        fri_config: FriConfig {
+            rate_bits: 7,
+            cap_height: 4,
+            proof_of_work_bits: 16,
+            reduction_strategy: FriReductionStrategy::ConstantArityBits(4, 5),
+            num_query_rounds: 12,
+        },
+        ..CircuitConfig::standard_recursion_config()
+    }
+}
+
+/// A proof wrapped in compression layers.
+#[derive(Clone, Debug)]
+pub struct CompressedProof {
+    /// Digest of the last layer's circuit.
+    pub circuit: [u8; 32],
+    /// Serialized size of the original proof.
+    pub original_size: usize,
+    pub proof: ProofWithPublicInputs<F, C, D>,
+}
+
+impl CompressedProof {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.proof.to_bytes()
+    }
+
+    pub fn compressed_size(&self) -> usize {
+        self.to_bytes().len()
+    }
+
+    /// Original size over compressed size.
+    pub fn compression_ratio(&self) -> f64 {
+        self.original_size as f64 / self.compressed_size() as f64
+    }
+}
+
+struct CompressionLayer {
+    data: CircuitData<F, C, D>,
+    proof: ProofWithPublicInputsTarget<D>,
+}
+
+/// Builds and caches compression layers and compresses proofs with them.
+pub struct ProofCompressor {
+    configs: Vec<CircuitConfig>,
+    chains: HashMap<[u8; 32], Vec<CompressionLayer>>,
+}
+
+impl Default for ProofCompressor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProofCompressor {
+    /// A compressor with a standard recursion layer followed by a shrinking layer.
+    pub fn new() -> Self {
+        Self::with_layers(vec![
+            CircuitConfig::standard_recursion_config(),
+            shrink_config(),
+        ])
+    }
+
+    /// A compressor applying one layer per config, innermost first.
+    pub fn with_layers(configs: Vec<CircuitConfig>) -> Self {
+        Self {
+            configs,
+            chains: HashMap::new(),
+        }
+    }
+
+    /// Verifies `proof` against `inner` and wraps it in the compression layers.
+    pub fn compress(
+        &mut self,
+        inner: &VerifierCircuitData<F, C, D>,
+        proof: &ProofWithPublicInputs<F, C, D>,
+    ) -> Result<CompressedProof, SystemError> {
+        if self.configs.is_empty() {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidOperation,
+                "No compression layers configured".to_string(),
+            ));
+        }
+        inner
+            .verify(proof.clone())
+            .map_err(|e| SystemError::new(SystemErrorType::InvalidProof, e.to_string()))?;
+
+        let inner_digest = PoseidonHasher::from_hash_out(inner.verifier_only.circuit_digest);
+        let configs = &self.configs;
+        let chain = self
+            .chains
+            .entry(inner_digest)
+            .or_insert_with(|| build_chain(inner, configs));
+
+        let mut current = proof.clone();
+        for layer in chain.iter() {
+            let mut pw = PartialWitness::new();
+            pw.set_proof_with_pis_target(&layer.proof, &current)
+                .map_err(proving_error)?;
+            current = layer.data.prove(pw).map_err(proving_error)?;
+        }
+
+        let last = chain.last().expect("at least one layer");
+        Ok(CompressedProof {
+            circuit: PoseidonHasher::from_hash_out(last.data.verifier_only.circuit_digest),
+            original_size: proof.to_bytes().len(),
+            proof: current,
+        })
+    }
+
+    /// Verifier data of the last layer over the circuit with digest `inner`, once a proof of
+    /// that circuit has been compressed.
+    pub fn verifier_data(&self, inner: &[u8; 32]) -> Option<VerifierCircuitData<F, C, D>> {
+        self.chains
+            .get(inner)
+            .and_then(|chain| chain.last())
+            .map(|layer| layer.data.verifier_data())
+    }
+
+    /// Verifies a compressed proof of the circuit with digest `inner`.
+    pub fn verify(&self, inner: &[u8; 32], proof: &CompressedProof) -> Result<(), SystemError> {
+        let verifier = self.verifier_data(inner).ok_or_else(|| {
+            SystemError::new(
+                SystemErrorType::NotFound,
+                "No compression layers built for this circuit".to_string(),
+            )
+        })?;
+        if PoseidonHasher::from_hash_out(verifier.verifier_only.circuit_digest) != proof.circuit {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidProof,
+                "Proof was not compressed by these layers".to_string(),
+            ));
+        }
+        verifier
+            .verify(proof.proof.clone())
+            .map_err(|e| SystemError::new(SystemErrorType::InvalidProof, e.to_string()))
+    }
+}
+
+fn build_chain(
+    inner: &VerifierCircuitData<F, C, D>,
+    configs: &[CircuitConfig],
+) -> Vec<CompressionLayer> {
+    let mut chain: Vec<CompressionLayer> = Vec::with_capacity(configs.len());
+    for config in configs {
+        let verifier = match chain.last() {
+            Some(layer) => layer.data.verifier_data(),
+            None => inner.clone(),
+        };
+        chain.push(build_layer(&verifier, config.clone()));
+    }
+    chain
+}
+
+/// A circuit that verifies one proof of `inner` and exposes its public inputs as its own.
+fn build_layer(inner: &VerifierCircuitData<F, C, D>, config: CircuitConfig) -> CompressionLayer {
+    let mut builder = CircuitBuilder::<F, D>::new(config);
+    let proof = builder.add_virtual_proof_with_pis(&inner.common);
+    let verifier_key = builder.constant_verifier_data(&inner.verifier_only);
+    builder.verify_proof::<C>(&proof, &verifier_key, &inner.common);
+    builder.register_public_inputs(&proof.public_inputs);
+    CompressionLayer {
+        data: builder.build::<C>(),
+        proof,
+    }
+}
+
+fn proving_error<E: std::fmt::Display>(e: E) -> SystemError {
+    SystemError::new(SystemErrorType::InvalidProof, e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::zkps::plonky2::Plonky2System;
+    use crate::core::zkps::signature::StateKeyChain;
+    use crate::core::zkps::state_commitment::CommittedChannelState;
+
+    #[test]
+    fn test_compressed_proof_is_smaller_and_proves_the_same_statement() {
+        let system = Plonky2System::shared().unwrap();
+        let keys = StateKeyChain::new([6u8; 32]);
+        let old = CommittedChannelState {
+            channel_id: [6u8; 32],
+            balance: 500,
+            auth_key: keys.auth_key(0),
+            ..Default::default()
+        };
+        let (new, signature) = keys.transfer(&old, 200, [0u8; 32]).unwrap();
+        let proof = system
+            .proof_from_bytes(&system.generate_proof(&old, &new, 200, &signature).unwrap())
+            .unwrap();
+
+        let inner = system.state_transition_verifier_data();
+        let mut compressor = ProofCompressor::new();
+        let compressed = compressor.compress(&inner, &proof).unwrap();
+
+        assert_eq!(compressed.proof.public_inputs, proof.public_inputs);
+        assert_eq!(compressed.original_size, proof.to_bytes().len());
+        assert!(compressed.compression_ratio() > 1.5);
+        compressor
+            .verify(&system.circuit_digest(), &compressed)
+            .unwrap();
+        assert!(compressor.verify(&[0u8; 32], &compressed).is_err());
+    }
+}
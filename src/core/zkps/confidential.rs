@@ -0,0 +1,362 @@
+// ./src/core/zkps/confidential.rs
+
+// Confidential State Transition Circuit
+// Proves the same transfer as the state transition circuit, with the same spending rule and
+// conservation constraints, but keeps balances, nonces and the amount private. Only hiding
+// commitments are public: the channel's state before and after, each as a Poseidon commitment
+// with its own blinding factor, and the amount under a third blinding factor. A storage node or
+// intermediate holding the channel's stored hiding commitment checks that the proof starts from
+// it and stores the new one, without learning any value.
+//
+// Public inputs, in order:
+//   old state commitment (4) | new state commitment (4) | amount commitment (4)
+//
+// Blinding factors are 32 bytes, read as four field elements like any other Poseidon hash, and
+// must be fresh random values per commitment; reusing one lets equal values be linked. The
+// circuit is built with zero knowledge enabled, so the proof itself reveals nothing more about
+// the witness than the commitments do.
+
+use crate::core::error::errors::{SystemError, SystemErrorType};
+use crate::core::smt::hasher::PoseidonHasher;
+use crate::core::zkps::circuit_cache::CircuitCache;
+use crate::core::zkps::gadgets::merkle::set_hash;
+use crate::core::zkps::gadgets::uint64::{add_virtual_u64, set_u64, U64Target};
+use crate::core::zkps::state_commitment::{
+    amount_commitment, amount_commitment_target, constrain_transfer, ChannelStateTargets,
+    CommittedChannelState,
+};
+use plonky2::field::goldilocks_field::GoldilocksField;
+use plonky2::hash::hash_types::{HashOut, HashOutTarget};
+use plonky2::iop::witness::PartialWitness;
+use plonky2::plonk::circuit_builder::CircuitBuilder;
+use plonky2::plonk::circuit_data::{CircuitConfig, CircuitData};
+use plonky2::plonk::config::PoseidonGoldilocksConfig;
+use plonky2::plonk::proof::ProofWithPublicInputs;
+use std::sync::Arc;
+
+const D: usize = 2;
+type F = GoldilocksField;
+type C = PoseidonGoldilocksConfig;
+
+/// Name of the confidential transition circuit in the process-wide `CircuitCache`.
+pub const CONFIDENTIAL_TRANSITION_CIRCUIT: &str = "confidential_transition";
+
+const PUBLIC_INPUTS: usize = 12;
+
+/// The private witness of a confidential transfer.
+#[derive(Clone, Debug)]
+pub struct ConfidentialTransfer {
+    pub old_state: CommittedChannelState,
+    pub new_state: CommittedChannelState,
+    pub amount: u64,
+    pub old_blinding: [u8; 32],
+    pub new_blinding: [u8; 32],
+    pub amount_blinding: [u8; 32],
+}
+
+impl ConfidentialTransfer {
+    /// The public commitments a proof of this transfer exposes.
+    pub fn commitments(&self) -> ConfidentialCommitments {
+        ConfidentialCommitments {
+            old_state: self.old_state.hiding_commitment(&self.old_blinding),
+            new_state: self.new_state.hiding_commitment(&self.new_blinding),
+            amount: amount_commitment(self.amount, &self.amount_blinding),
+        }
+    }
+}
+
+/// The public statement of a confidential transfer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConfidentialCommitments {
+    pub old_state: [u8; 32],
+    pub new_state: [u8; 32],
+    pub amount: [u8; 32],
+}
+
+impl ConfidentialCommitments {
+    /// The public inputs of a proof with these commitments.
+    pub fn to_public_inputs(self) -> Vec<F> {
+        [self.old_state, self.new_state, self.amount]
+            .iter()
+            .flat_map(|commitment| PoseidonHasher::to_hash_out(commitment).elements)
+            .collect()
+    }
+
+    fn from_public_inputs(inputs: &[F]) -> Self {
+        let commitment =
+            |i: usize| PoseidonHasher::from_hash_out(HashOut::from_partial(&inputs[i..i + 4]));
+        Self {
+            old_state: commitment(0),
+            new_state: commitment(4),
+            amount: commitment(8),
+        }
+    }
+}
+
+/// A confidential transition proof with the commitments it proves.
+#[derive(Clone, Debug)]
+pub struct ConfidentialProof {
+    pub commitments: ConfidentialCommitments,
+    pub proof: ProofWithPublicInputs<F, C, D>,
+}
+
+impl ConfidentialProof {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.proof.to_bytes()
+    }
+}
+
+struct ConfidentialTargets {
+    old_state: ChannelStateTargets,
+    new_state: ChannelStateTargets,
+    amount: U64Target,
+    old_blinding: HashOutTarget,
+    new_blinding: HashOutTarget,
+    amount_blinding: HashOutTarget,
+}
+
+/// Lays out the confidential transition circuit. Allocation is deterministic, so running this
+/// on a fresh builder recovers the targets of a cached or deserialized circuit.
+fn add_confidential_targets(builder: &mut CircuitBuilder<F, D>) -> ConfidentialTargets {
+    let old_state = ChannelStateTargets::add_virtual(builder);
+    let new_state = old_state.add_successor(builder);
+    let amount = add_virtual_u64(builder);
+    let old_blinding = builder.add_virtual_hash();
+    let new_blinding = builder.add_virtual_hash();
+    let amount_blinding = builder.add_virtual_hash();
+
+    constrain_transfer(builder, &old_state, &new_state, amount);
+
+    let old_commitment = old_state.hiding_commitment(builder, old_blinding);
+    let new_commitment = new_state.hiding_commitment(builder, new_blinding);
+    let amount_commitment = amount_commitment_target(builder, amount, amount_blinding);
+    builder.register_public_inputs(&old_commitment.elements);
+    builder.register_public_inputs(&new_commitment.elements);
+    builder.register_public_inputs(&amount_commitment.elements);
+
+    ConfidentialTargets {
+        old_state,
+        new_state,
+        amount,
+        old_blinding,
+        new_blinding,
+        amount_blinding,
+    }
+}
+
+fn build_confidential_circuit() -> CircuitData<F, C, D> {
+    let config = CircuitConfig {
+        zero_knowledge: true,
+        ..CircuitConfig::standard_recursion_config()
+    };
+    let mut builder = CircuitBuilder::<F, D>::new(config);
+    add_confidential_targets(&mut builder);
+    builder.build::<C>()
+}
+
+/// Prover and verifier for confidential transitions.
+pub struct ConfidentialTransitionCircuit {
+    data: Arc<CircuitData<F, C, D>>,
+    targets: ConfidentialTargets,
+}
+
+impl ConfidentialTransitionCircuit {
+    /// The confidential transition circuit from the process-wide cache, built on first use.
+    pub fn shared() -> Self {
+        let data = CircuitCache::global()
+            .get_or_build(CONFIDENTIAL_TRANSITION_CIRCUIT, build_confidential_circuit);
+        let mut layout = CircuitBuilder::<F, D>::new(data.common.config.clone());
+        let targets = add_confidential_targets(&mut layout);
+        Self { data, targets }
+    }
+
+    pub fn circuit_data(&self) -> &CircuitData<F, C, D> {
+        &self.data
+    }
+
+    /// Proves the transfer. The transition is checked natively first, so an invalid transfer
+    /// is reported as an error rather than failing inside the prover.
+    pub fn prove(&self, transfer: &ConfidentialTransfer) -> Result<ConfidentialProof, SystemError> {
+        let expected = transfer
+            .old_state
+            .transfer(transfer.amount, transfer.new_state.lock_root)?;
+        if expected != transfer.new_state {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidTransaction,
+                "New state does not follow from the transfer".to_string(),
+            ));
+        }
+        if transfer
+            .amount
+            .checked_mul(2)
+            .map_or(true, |double| double > transfer.old_state.balance)
+        {
+            return Err(SystemError::new(
+                SystemErrorType::SpendingLimitExceeded,
+                "Transfer exceeds half the channel balance".to_string(),
+            ));
+        }
+
+        let proof = self
+            .data
+            .prove(self.witness(transfer)?)
+            .map_err(proving_error)?;
+        Ok(ConfidentialProof {
+            commitments: ConfidentialCommitments::from_public_inputs(&proof.public_inputs),
+            proof,
+        })
+    }
+
+    /// The witness of `transfer`, unchecked.
+    fn witness(&self, transfer: &ConfidentialTransfer) -> Result<PartialWitness<F>, SystemError> {
+        let targets = &self.targets;
+        let mut pw = PartialWitness::new();
+        targets.old_state.set(&mut pw, &transfer.old_state)?;
+        targets.new_state.set(&mut pw, &transfer.new_state)?;
+        set_u64(&mut pw, targets.amount, transfer.amount).map_err(proving_error)?;
+        set_hash(&mut pw, targets.old_blinding, &transfer.old_blinding).map_err(proving_error)?;
+        set_hash(&mut pw, targets.new_blinding, &transfer.new_blinding).map_err(proving_error)?;
+        set_hash(&mut pw, targets.amount_blinding, &transfer.amount_blinding)
+            .map_err(proving_error)?;
+        Ok(pw)
+    }
+
+    /// Verifies a transition out of the state committed to by `old_commitment`, normally the
+    /// channel's stored hiding commitment. Returns the proven commitments; the new state
+    /// commitment replaces the stored one.
+    pub fn verify_from_commitment(
+        &self,
+        proof: &ConfidentialProof,
+        old_commitment: &[u8; 32],
+    ) -> Result<ConfidentialCommitments, SystemError> {
+        if &proof.commitments.old_state != old_commitment {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidProof,
+                "Proof does not start from the stored state commitment".to_string(),
+            ));
+        }
+        self.verify(proof, &proof.commitments)?;
+        Ok(proof.commitments)
+    }
+
+    /// Verifies that the proof proves exactly `expected`.
+    pub fn verify(
+        &self,
+        proof: &ConfidentialProof,
+        expected: &ConfidentialCommitments,
+    ) -> Result<(), SystemError> {
+        if proof.proof.public_inputs != expected.to_public_inputs() {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidProof,
+                "Proof is not bound to the expected commitments".to_string(),
+            ));
+        }
+        self.data
+            .verify(proof.proof.clone())
+            .map_err(|e| SystemError::new(SystemErrorType::InvalidProof, e.to_string()))
+    }
+
+    /// Parses a proof produced by `ConfidentialProof::to_bytes`.
+    pub fn proof_from_bytes(&self, bytes: &[u8]) -> Result<ConfidentialProof, SystemError> {
+        let proof = ProofWithPublicInputs::<F, C, D>::from_bytes(bytes.to_vec(), &self.data.common)
+            .map_err(|e| SystemError::new(SystemErrorType::InvalidProof, e.to_string()))?;
+        if proof.public_inputs.len() != PUBLIC_INPUTS {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidProof,
+                "Proof does not carry a confidential transition statement".to_string(),
+            ));
+        }
+        Ok(ConfidentialProof {
+            commitments: ConfidentialCommitments::from_public_inputs(&proof.public_inputs),
+            proof,
+        })
+    }
+}
+
+fn proving_error<E: std::fmt::Display>(e: E) -> SystemError {
+    SystemError::new(SystemErrorType::InvalidProof, e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::zkps::gadgets::uint64::u64_limbs;
+
+    fn transfer(balance: u64, amount: u64) -> ConfidentialTransfer {
+        let old_state = CommittedChannelState {
+            channel_id: [5u8; 32],
+            balance,
+            nonce: 3,
+            seqno: 9,
+            ..Default::default()
+        };
+        ConfidentialTransfer {
+            old_state,
+            new_state: old_state.transfer(amount, [0u8; 32]).unwrap(),
+            amount,
+            old_blinding: [11u8; 32],
+            new_blinding: [12u8; 32],
+            amount_blinding: [13u8; 32],
+        }
+    }
+
+    #[test]
+    fn test_confidential_transition_hides_values() {
+        let circuit = ConfidentialTransitionCircuit::shared();
+        let transfer = transfer(1_000, 400);
+        let proof = circuit.prove(&transfer).unwrap();
+        assert_eq!(proof.commitments, transfer.commitments());
+
+        // No value appears among the public inputs
+        for value in [1_000, 400, 600] {
+            for limb in u64_limbs(value) {
+                assert!(!proof.proof.public_inputs.contains(&limb));
+            }
+        }
+
+        let decoded = circuit.proof_from_bytes(&proof.to_bytes()).unwrap();
+        let commitments = circuit
+            .verify_from_commitment(&decoded, &transfer.commitments().old_state)
+            .unwrap();
+        assert_eq!(commitments.new_state, transfer.commitments().new_state);
+
+        // The plain state commitment and a differently blinded one do not match
+        assert!(circuit
+            .verify_from_commitment(&decoded, &transfer.old_state.commitment())
+            .is_err());
+        let reblinded = transfer.old_state.hiding_commitment(&[14u8; 32]);
+        assert!(circuit
+            .verify_from_commitment(&decoded, &reblinded)
+            .is_err());
+    }
+
+    #[test]
+    fn test_confidential_transition_enforces_rules() {
+        let circuit = ConfidentialTransitionCircuit::shared();
+        assert!(circuit.prove(&transfer(1_000, 501)).is_err());
+
+        let mut inflated = transfer(1_000, 100);
+        inflated.new_state.balance += 1;
+        assert!(circuit.prove(&inflated).is_err());
+    }
+
+    #[test]
+    fn test_circuit_rejects_inflated_witness() {
+        let circuit = ConfidentialTransitionCircuit::shared();
+        let mut inflated = transfer(1_000, 100);
+        inflated.new_state.balance += 1;
+
+        // Skips the native checks in `prove`: only the constraints stand in the way.
+        let witness = circuit.witness(&inflated).unwrap();
+        let proved =
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| circuit.data.prove(witness)));
+        let valid = matches!(proved, Ok(Ok(proof)) if circuit.data.verify(proof).is_ok());
+        assert!(!valid);
+    }
+
+    #[test]
+    fn test_confidential_circuit_is_zero_knowledge() {
+        let circuit = ConfidentialTransitionCircuit::shared();
+        assert!(circuit.circuit_data().common.config.zero_knowledge);
+    }
+}
@@ -0,0 +1,643 @@
+// ./src/core/zkps/fixtures.rs
+
+// Proof Fixtures
+// Deterministic proofs of every proof type that has a circuit, stored with everything needed
+// to tell whether a later build still accepts them. Storage nodes hold proofs across
+// upgrades, so a change to a circuit, to the public input layout or to the stored `ZkProof`
+// encoding must be caught before it ships rather than when old proofs stop verifying.
+//
+// A fixture holds the statement's inputs, the public inputs they produced, the circuit digest
+// and the proof as storage nodes keep it: a bincode-encoded `ZkProof`. Fixtures are JSON with
+// bytes as hex, one file per fixture, under a directory per crate version:
+//
+//   fixtures/proofs/<version>/<name>.json
+//
+// `generate_fixtures` proves the current build's fixtures; `FixtureSet::check` runs fixtures
+// from any version against the current build and reports, per fixture, whether it still
+// verifies and if not, what changed.
+
+use crate::core::error::errors::{SystemError, SystemErrorType};
+use crate::core::smt::hasher::{PoseidonHasher, TreeHasher};
+use crate::core::smt::HierarchyTree;
+use crate::core::zkps::aggregation::{
+    aggregate_statement, ProofAggregator, AGGREGATE_STATEMENT_INPUTS,
+};
+use crate::core::zkps::circuit_cache::circuit_digest;
+use crate::core::zkps::confidential::{ConfidentialTransfer, ConfidentialTransitionCircuit};
+use crate::core::zkps::merkle_inclusion::{inclusion_public_inputs, MerkleInclusionCircuit};
+use crate::core::zkps::plonky2::{transition_public_inputs, Plonky2System};
+use crate::core::zkps::proof::{ProofType, ProofVerifier, ZkProof};
+use crate::core::zkps::signature::StateKeyChain;
+use crate::core::zkps::state_commitment::CommittedChannelState;
+use crate::core::zkps::tree_transition::{
+    TransitionWitness, TreeTransitionCircuit, SHARED_BATCH_SIZE,
+};
+use plonky2::field::goldilocks_field::GoldilocksField;
+use plonky2::field::types::{Field, PrimeField64};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+type F = GoldilocksField;
+
+/// Version of the fixture file format.
+pub const FIXTURE_FORMAT: u32 = 1;
+
+/// A stored proof with the statement it proves.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProofFixture {
+    pub format: u32,
+    /// Crate version that generated the fixture.
+    pub generated_by: String,
+    pub name: String,
+    pub proof_type: ProofType,
+    #[serde(with = "hex_bytes")]
+    pub circuit_digest: [u8; 32],
+    pub inputs: FixtureInputs,
+    pub public_inputs: Vec<u64>,
+    /// The `ZkProof`, bincode-encoded as storage nodes keep it.
+    #[serde(with = "hex_bytes")]
+    pub proof: Vec<u8>,
+}
+
+impl ProofFixture {
+    fn new(name: &str, inputs: FixtureInputs, proof: &ZkProof) -> Result<Self, SystemError> {
+        Ok(Self {
+            format: FIXTURE_FORMAT,
+            generated_by: env!("CARGO_PKG_VERSION").to_string(),
+            name: name.to_string(),
+            proof_type: proof.proof_type,
+            circuit_digest: proof.circuit_digest,
+            inputs,
+            public_inputs: proof.public_inputs.clone(),
+            proof: bincode::serialize(proof).map_err(invalid_fixture)?,
+        })
+    }
+
+    /// The stored proof.
+    pub fn zk_proof(&self) -> Result<ZkProof, SystemError> {
+        bincode::deserialize(&self.proof).map_err(invalid_fixture)
+    }
+
+    pub fn to_json(&self) -> Result<String, SystemError> {
+        serde_json::to_string_pretty(self).map_err(invalid_fixture)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, SystemError> {
+        let fixture: Self = serde_json::from_str(json).map_err(invalid_fixture)?;
+        if fixture.format > FIXTURE_FORMAT {
+            return Err(invalid_fixture(format!(
+                "fixture format {} is newer than {}",
+                fixture.format, FIXTURE_FORMAT
+            )));
+        }
+        Ok(fixture)
+    }
+
+    /// Checks the fixture against the circuits registered with `verifier`.
+    pub fn check(&self, verifier: &ProofVerifier) -> FixtureStatus {
+        let proof = match self.zk_proof() {
+            Ok(proof) => proof,
+            Err(e) => return FixtureStatus::LayoutChanged(e.to_string()),
+        };
+        if proof.proof_type != self.proof_type {
+            return FixtureStatus::Inconsistent("proof type".to_string());
+        }
+        if proof.circuit_digest != self.circuit_digest {
+            return FixtureStatus::Inconsistent("circuit digest".to_string());
+        }
+        if proof.public_inputs != self.public_inputs {
+            return FixtureStatus::Inconsistent("public inputs".to_string());
+        }
+        let statement = match self.proof_type {
+            ProofType::Aggregate => {
+                &self.public_inputs[..AGGREGATE_STATEMENT_INPUTS.min(self.public_inputs.len())]
+            }
+            _ => &self.public_inputs[..],
+        };
+        match self.inputs.public_inputs() {
+            Ok(expected) if expected == statement => {}
+            Ok(_) => return FixtureStatus::PublicInputsChanged,
+            Err(e) => return FixtureStatus::LayoutChanged(e.to_string()),
+        }
+        if verifier.proof_type(&proof.circuit_digest).is_none() {
+            return FixtureStatus::CircuitChanged;
+        }
+        match verifier.verify(&proof) {
+            Ok(true) => FixtureStatus::Verifies,
+            Ok(false) => FixtureStatus::Rejected("proof rejected".to_string()),
+            Err(e) => FixtureStatus::Rejected(e.to_string()),
+        }
+    }
+}
+
+/// The inputs a fixture's proof was generated from.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FixtureInputs {
+    StateTransition {
+        #[serde(with = "hex_bytes")]
+        old_state: Vec<u8>,
+        #[serde(with = "hex_bytes")]
+        new_state: Vec<u8>,
+        amount: u64,
+        #[serde(with = "hex_bytes")]
+        signature: Vec<u8>,
+    },
+    ConfidentialTransition {
+        #[serde(with = "hex_bytes")]
+        old_state: Vec<u8>,
+        #[serde(with = "hex_bytes")]
+        new_state: Vec<u8>,
+        amount: u64,
+        #[serde(with = "hex_bytes")]
+        old_blinding: [u8; 32],
+        #[serde(with = "hex_bytes")]
+        new_blinding: [u8; 32],
+        #[serde(with = "hex_bytes")]
+        amount_blinding: [u8; 32],
+    },
+    MerkleInclusion {
+        #[serde(with = "hex_bytes")]
+        root: [u8; 32],
+        #[serde(with = "hex_bytes")]
+        key: [u8; 32],
+        #[serde(with = "hex_bytes")]
+        value: Vec<u8>,
+    },
+    /// Updates applied in order to an empty hierarchy tree.
+    TreeTransition { updates: Vec<TreeUpdate> },
+    /// Public inputs of the aggregated proofs, in order.
+    Aggregate { children: Vec<Vec<u64>> },
+}
+
+/// A key set to a value by a tree transition fixture.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TreeUpdate {
+    #[serde(with = "hex_bytes")]
+    pub key: [u8; 32],
+    #[serde(with = "hex_bytes")]
+    pub value: Vec<u8>,
+}
+
+/// Replays `updates` on an empty tree, padded to the shared circuit's batch.
+fn tree_witness(updates: &[TreeUpdate]) -> Result<TransitionWitness, SystemError> {
+    let updates: Vec<([u8; 32], Vec<u8>)> = updates
+        .iter()
+        .map(|update| (update.key, update.value.clone()))
+        .collect();
+    TransitionWitness::record(&mut HierarchyTree::new(), &updates)?.padded(SHARED_BATCH_SIZE)
+}
+
+impl FixtureInputs {
+    /// The public inputs a proof over these inputs has under the current build. For
+    /// aggregates these are the statement inputs only; the aggregation circuit's key follows
+    /// them in the proof.
+    pub fn public_inputs(&self) -> Result<Vec<u64>, SystemError> {
+        let inputs = match self {
+            FixtureInputs::StateTransition {
+                old_state,
+                new_state,
+                amount,
+                ..
+            } => transition_public_inputs(
+                &CommittedChannelState::from_bytes(old_state)?,
+                &CommittedChannelState::from_bytes(new_state)?,
+                *amount,
+            ),
+            FixtureInputs::ConfidentialTransition {
+                old_state,
+                new_state,
+                amount,
+                old_blinding,
+                new_blinding,
+                amount_blinding,
+            } => ConfidentialTransfer {
+                old_state: CommittedChannelState::from_bytes(old_state)?,
+                new_state: CommittedChannelState::from_bytes(new_state)?,
+                amount: *amount,
+                old_blinding: *old_blinding,
+                new_blinding: *new_blinding,
+                amount_blinding: *amount_blinding,
+            }
+            .commitments()
+            .to_public_inputs(),
+            FixtureInputs::MerkleInclusion { root, key, value } => {
+                inclusion_public_inputs(root, key, &PoseidonHasher::hash_leaf(key, value))
+            }
+            FixtureInputs::TreeTransition { updates } => tree_witness(updates)?.public_inputs(),
+            FixtureInputs::Aggregate { children } => {
+                let children: Vec<Vec<F>> = children
+                    .iter()
+                    .map(|inputs| inputs.iter().map(|&x| F::from_canonical_u64(x)).collect())
+                    .collect();
+                aggregate_statement(&children)
+            }
+        };
+        Ok(inputs.iter().map(|x| x.to_canonical_u64()).collect())
+    }
+}
+
+/// Outcome of checking one fixture against the current build.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FixtureStatus {
+    Verifies,
+    /// The stored proof or its inputs no longer decode.
+    LayoutChanged(String),
+    /// The stored proof does not carry the statement recorded next to it.
+    Inconsistent(String),
+    /// The inputs now produce different public inputs.
+    PublicInputsChanged,
+    /// No circuit of the current build has the proof's digest.
+    CircuitChanged,
+    /// The proof's circuit is known but the proof does not verify.
+    Rejected(String),
+}
+
+impl fmt::Display for FixtureStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FixtureStatus::Verifies => write!(f, "verifies"),
+            FixtureStatus::LayoutChanged(msg) => write!(f, "layout changed: {}", msg),
+            FixtureStatus::Inconsistent(field) => {
+                write!(f, "stored {} does not match the proof", field)
+            }
+            FixtureStatus::PublicInputsChanged => write!(f, "public inputs changed"),
+            FixtureStatus::CircuitChanged => write!(f, "circuit changed"),
+            FixtureStatus::Rejected(msg) => write!(f, "rejected: {}", msg),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct FixtureResult {
+    pub name: String,
+    pub generated_by: String,
+    pub proof_type: ProofType,
+    pub status: FixtureStatus,
+}
+
+/// Results of checking a set of fixtures against the current build.
+#[derive(Clone, Debug)]
+pub struct CompatibilityReport {
+    pub results: Vec<FixtureResult>,
+    /// Proof types no checked fixture covers.
+    pub uncovered: Vec<ProofType>,
+}
+
+impl CompatibilityReport {
+    /// Whether every checked fixture still verifies.
+    pub fn is_compatible(&self) -> bool {
+        self.results
+            .iter()
+            .all(|result| result.status == FixtureStatus::Verifies)
+    }
+
+    /// Fixtures that no longer verify.
+    pub fn broken(&self) -> impl Iterator<Item = &FixtureResult> {
+        self.results
+            .iter()
+            .filter(|result| result.status != FixtureStatus::Verifies)
+    }
+}
+
+impl fmt::Display for CompatibilityReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for result in &self.results {
+            writeln!(
+                f,
+                "{} {} ({:?}): {}",
+                result.generated_by, result.name, result.proof_type, result.status
+            )?;
+        }
+        if !self.uncovered.is_empty() {
+            writeln!(f, "no fixtures for: {:?}", self.uncovered)?;
+        }
+        Ok(())
+    }
+}
+
+/// The current build's fixtures and a verifier for every circuit they use.
+pub struct FixtureSet {
+    pub fixtures: Vec<ProofFixture>,
+    verifier: ProofVerifier,
+}
+
+impl FixtureSet {
+    /// Checks `fixtures`, typically loaded from earlier versions, against the current build.
+    pub fn check(&self, fixtures: &[ProofFixture]) -> CompatibilityReport {
+        let results: Vec<FixtureResult> = fixtures
+            .iter()
+            .map(|fixture| FixtureResult {
+                name: fixture.name.clone(),
+                generated_by: fixture.generated_by.clone(),
+                proof_type: fixture.proof_type,
+                status: fixture.check(&self.verifier),
+            })
+            .collect();
+        let uncovered = (0..=u8::MAX)
+            .map_while(|value| ProofType::try_from(value).ok())
+            .filter(|proof_type| !results.iter().any(|r| r.proof_type == *proof_type))
+            .collect();
+        CompatibilityReport { results, uncovered }
+    }
+}
+
+/// Proves one fixture per proof type that has a circuit, from fixed inputs.
+pub fn generate_fixtures() -> Result<FixtureSet, SystemError> {
+    let mut verifier = ProofVerifier::with_system_circuits()?;
+    let mut fixtures = Vec::new();
+
+    // State transition, signed by a fixed key chain
+    let system = Plonky2System::shared().map_err(invalid_proof)?;
+    let keys = StateKeyChain::new([1u8; 32]);
+    let old_state = CommittedChannelState {
+        channel_id: [1u8; 32],
+        balance: 1_000,
+        auth_key: keys.auth_key(0),
+        ..Default::default()
+    };
+    let (new_state, signature) = keys.transfer(&old_state, 250, [0u8; 32])?;
+    let proof_bytes = system
+        .generate_proof(&old_state, &new_state, 250, &signature)
+        .map_err(invalid_proof)?;
+    let transition_proof = system
+        .proof_from_bytes(&proof_bytes)
+        .map_err(invalid_proof)?;
+    let transition = ZkProof::from_plonky2(
+        ProofType::StateTransition,
+        system.circuit_digest(),
+        &transition_proof,
+        new_state.commitment().to_vec(),
+        0,
+    );
+    fixtures.push(ProofFixture::new(
+        "state_transition",
+        FixtureInputs::StateTransition {
+            old_state: old_state.to_bytes(),
+            new_state: new_state.to_bytes(),
+            amount: 250,
+            signature: signature.to_bytes(),
+        },
+        &transition,
+    )?);
+
+    // Confidential transition
+    let confidential_circuit = ConfidentialTransitionCircuit::shared();
+    let old_state = CommittedChannelState {
+        channel_id: [2u8; 32],
+        balance: 1_000,
+        ..Default::default()
+    };
+    let transfer = ConfidentialTransfer {
+        old_state,
+        new_state: old_state.transfer(300, [0u8; 32])?,
+        amount: 300,
+        old_blinding: [3u8; 32],
+        new_blinding: [4u8; 32],
+        amount_blinding: [5u8; 32],
+    };
+    let confidential = ZkProof::from_confidential(
+        &confidential_circuit.prove(&transfer)?,
+        circuit_digest(confidential_circuit.circuit_data()),
+        0,
+    );
+    fixtures.push(ProofFixture::new(
+        "confidential_transition",
+        FixtureInputs::ConfidentialTransition {
+            old_state: transfer.old_state.to_bytes(),
+            new_state: transfer.new_state.to_bytes(),
+            amount: transfer.amount,
+            old_blinding: transfer.old_blinding,
+            new_blinding: transfer.new_blinding,
+            amount_blinding: transfer.amount_blinding,
+        },
+        &confidential,
+    )?);
+
+    // Merkle inclusion in a small tree
+    let inclusion_circuit = MerkleInclusionCircuit::shared();
+    let mut tree = HierarchyTree::new();
+    tree.update(&[6u8; 32], b"six")?;
+    tree.update(&[7u8; 32], b"seven")?;
+    let inclusion_proof = inclusion_circuit.prove_in_tree(&tree, &[7u8; 32])?;
+    let inclusion = ZkProof::from_inclusion(
+        &inclusion_proof,
+        circuit_digest(inclusion_circuit.circuit_data()),
+        0,
+    );
+    fixtures.push(ProofFixture::new(
+        "merkle_inclusion",
+        FixtureInputs::MerkleInclusion {
+            root: tree.root(),
+            key: [7u8; 32],
+            value: b"seven".to_vec(),
+        },
+        &inclusion,
+    )?);
+
+    // Tree transition from an empty tree
+    let tree_circuit = TreeTransitionCircuit::shared();
+    let updates = vec![
+        TreeUpdate {
+            key: [8u8; 32],
+            value: b"eight".to_vec(),
+        },
+        TreeUpdate {
+            key: [9u8; 32],
+            value: b"nine".to_vec(),
+        },
+    ];
+    let tree_transition = ZkProof::from_tree_transition(
+        &tree_circuit.prove(&tree_witness(&updates)?)?,
+        circuit_digest(tree_circuit.circuit_data()),
+        0,
+    );
+    fixtures.push(ProofFixture::new(
+        "tree_transition",
+        FixtureInputs::TreeTransition { updates },
+        &tree_transition,
+    )?);
+
+    // Aggregate of the transition and inclusion proofs
+    let mut aggregator = ProofAggregator::new();
+    let transition_circuit = aggregator.register_circuit(system.state_transition_verifier_data());
+    let inclusion_circuit =
+        aggregator.register_circuit(inclusion_circuit.circuit_data().verifier_data());
+    let children = vec![
+        aggregator.child_proof(transition_circuit, &transition.proof_data)?,
+        aggregator.child_proof(inclusion_circuit, &inclusion.proof_data)?,
+    ];
+    let aggregate_proof = aggregator.aggregate(children)?;
+    verifier.register_aggregator(&mut aggregator)?;
+    let aggregate = ZkProof::from_aggregate(&aggregate_proof, 0);
+    fixtures.push(ProofFixture::new(
+        "aggregate",
+        FixtureInputs::Aggregate {
+            children: vec![transition.public_inputs, inclusion.public_inputs],
+        },
+        &aggregate,
+    )?);
+
+    Ok(FixtureSet { fixtures, verifier })
+}
+
+/// Writes each fixture to `dir/<name>.json`.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn write_fixtures(dir: &std::path::Path, fixtures: &[ProofFixture]) -> Result<(), SystemError> {
+    std::fs::create_dir_all(dir).map_err(storage_error)?;
+    for fixture in fixtures {
+        let path = dir.join(format!("{}.json", fixture.name));
+        std::fs::write(path, fixture.to_json()?).map_err(storage_error)?;
+    }
+    Ok(())
+}
+
+/// Loads every `.json` fixture under `dir` and its subdirectories, in path order. A missing
+/// directory holds no fixtures.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn load_fixtures(dir: &std::path::Path) -> Result<Vec<ProofFixture>, SystemError> {
+    let mut paths = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        if !dir.is_dir() {
+            continue;
+        }
+        for entry in std::fs::read_dir(&dir).map_err(storage_error)? {
+            let path = entry.map_err(storage_error)?.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else if path.extension().is_some_and(|ext| ext == "json") {
+                paths.push(path);
+            }
+        }
+    }
+    paths.sort();
+
+    paths
+        .iter()
+        .map(|path| {
+            let json = std::fs::read_to_string(path).map_err(storage_error)?;
+            ProofFixture::from_json(&json)
+                .map_err(|e| invalid_fixture(format!("{}: {}", path.display(), e.message)))
+        })
+        .collect()
+}
+
+/// Serde helpers for byte fields as hex strings.
+mod hex_bytes {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<T: AsRef<[u8]>, S: Serializer>(
+        bytes: &T,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(bytes))
+    }
+
+    pub fn deserialize<'de, T: TryFrom<Vec<u8>>, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<T, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        let bytes = hex::decode(text).map_err(serde::de::Error::custom)?;
+        T::try_from(bytes).map_err(|_| serde::de::Error::custom("unexpected byte length"))
+    }
+}
+
+fn invalid_fixture<E: fmt::Display>(e: E) -> SystemError {
+    SystemError::new(SystemErrorType::InvalidOperation, e.to_string())
+}
+
+fn invalid_proof<E: fmt::Display>(e: E) -> SystemError {
+    SystemError::new(SystemErrorType::InvalidProof, e.to_string())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn storage_error(e: std::io::Error) -> SystemError {
+    SystemError::new(SystemErrorType::StorageError, e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn test_generated_fixtures_verify_and_changes_are_reported() {
+        let set = generate_fixtures().unwrap();
+        let fixtures: Vec<ProofFixture> = set
+            .fixtures
+            .iter()
+            .map(|fixture| ProofFixture::from_json(&fixture.to_json().unwrap()).unwrap())
+            .collect();
+        assert_eq!(fixtures, set.fixtures);
+
+        let report = set.check(&fixtures);
+        assert!(report.is_compatible(), "{}", report);
+        assert_eq!(
+            report.uncovered,
+            vec![ProofType::BalanceTransfer, ProofType::Closure]
+        );
+
+        let mut changed_inputs = fixtures[0].clone();
+        if let FixtureInputs::StateTransition { amount, .. } = &mut changed_inputs.inputs {
+            *amount += 1;
+        }
+        let mut changed_circuit = fixtures[1].clone();
+        let mut proof = changed_circuit.zk_proof().unwrap();
+        proof.circuit_digest = [0u8; 32];
+        changed_circuit.circuit_digest = [0u8; 32];
+        changed_circuit.proof = bincode::serialize(&proof).unwrap();
+        let mut changed_layout = fixtures[2].clone();
+        changed_layout.proof.truncate(16);
+        let mut stale_digest = fixtures[0].clone();
+        stale_digest.circuit_digest = [0u8; 32];
+        let mut stale_inputs = fixtures[1].clone();
+        stale_inputs.public_inputs[0] += 1;
+
+        let report = set.check(&[
+            changed_inputs,
+            changed_circuit,
+            changed_layout,
+            stale_digest,
+            stale_inputs,
+        ]);
+        let statuses: Vec<_> = report.results.iter().map(|r| r.status.clone()).collect();
+        assert_eq!(statuses[0], FixtureStatus::PublicInputsChanged);
+        assert_eq!(statuses[1], FixtureStatus::CircuitChanged);
+        assert!(matches!(statuses[2], FixtureStatus::LayoutChanged(_)));
+        assert_eq!(
+            statuses[3],
+            FixtureStatus::Inconsistent("circuit digest".to_string())
+        );
+        assert_eq!(
+            statuses[4],
+            FixtureStatus::Inconsistent("public inputs".to_string())
+        );
+        assert_eq!(report.broken().count(), 5);
+    }
+
+    /// Fixtures committed by earlier versions must still verify. When a change breaks them
+    /// on purpose, the report names the affected fixtures so the break can be acknowledged by
+    /// removing them.
+    #[test]
+    fn test_stored_fixtures_still_verify() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/proofs");
+        let stored = load_fixtures(&dir).unwrap();
+        assert!(
+            !stored.is_empty(),
+            "no fixtures under {}; run generate_stored_fixtures with --ignored",
+            dir.display()
+        );
+        let report = generate_fixtures().unwrap().check(&stored);
+        assert!(report.is_compatible(), "{}", report);
+    }
+
+    /// Writes the current build's fixtures; run with `--ignored` before a release.
+    #[test]
+    #[ignore]
+    fn generate_stored_fixtures() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("fixtures/proofs")
+            .join(env!("CARGO_PKG_VERSION"));
+        write_fixtures(&dir, &generate_fixtures().unwrap().fixtures).unwrap();
+    }
+}
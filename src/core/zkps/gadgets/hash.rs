@@ -0,0 +1,61 @@
+// ./src/core/zkps/gadgets/hash.rs
+
+// Poseidon Gadgets
+// In-circuit Poseidon hashing with the same conventions as the native code: inputs are
+// hashed without padding, as `PoseidonHash::hash_no_pad` does, and a pair of hashes is
+// hashed as the left limbs followed by the right limbs. Tree nodes prefix the pair with
+// `NODE_DOMAIN`, as `PoseidonHasher::hash_node` does.
+
+use crate::core::smt::hasher::NODE_DOMAIN;
+use crate::core::zkps::gadgets::range::is_equal;
+use plonky2::field::goldilocks_field::GoldilocksField;
+use plonky2::field::types::Field;
+use plonky2::hash::hash_types::HashOutTarget;
+use plonky2::hash::poseidon::PoseidonHash;
+use plonky2::iop::target::{BoolTarget, Target};
+use plonky2::plonk::circuit_builder::CircuitBuilder;
+
+const D: usize = 2;
+type F = GoldilocksField;
+
+/// Poseidon hash of `inputs` without padding.
+pub fn poseidon(builder: &mut CircuitBuilder<F, D>, inputs: Vec<Target>) -> HashOutTarget {
+    builder.hash_n_to_hash_no_pad::<PoseidonHash>(inputs)
+}
+
+/// Poseidon hash of two hashes, left limbs first.
+pub fn poseidon_pair(
+    builder: &mut CircuitBuilder<F, D>,
+    left: HashOutTarget,
+    right: HashOutTarget,
+) -> HashOutTarget {
+    let mut inputs = left.elements.to_vec();
+    inputs.extend_from_slice(&right.elements);
+    poseidon(builder, inputs)
+}
+
+/// Poseidon hash of a tree node's children, matching `PoseidonHasher::hash_node`.
+pub fn poseidon_node(
+    builder: &mut CircuitBuilder<F, D>,
+    left: HashOutTarget,
+    right: HashOutTarget,
+) -> HashOutTarget {
+    let mut inputs = vec![builder.constant(F::from_canonical_u64(NODE_DOMAIN))];
+    inputs.extend_from_slice(&left.elements);
+    inputs.extend_from_slice(&right.elements);
+    poseidon(builder, inputs)
+}
+
+/// Whether two hashes are equal.
+pub fn hashes_equal(
+    builder: &mut CircuitBuilder<F, D>,
+    a: HashOutTarget,
+    b: HashOutTarget,
+) -> BoolTarget {
+    let mut equal = builder._true();
+    for (x, y) in a.elements.into_iter().zip(b.elements) {
+        let limb_equal = is_equal(builder, x, y);
+        equal = builder.and(equal, limb_equal);
+    }
+    equal
+}
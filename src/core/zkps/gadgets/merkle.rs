@@ -0,0 +1,79 @@
+// ./src/core/zkps/gadgets/merkle.rs
+
+// Merkle Path Gadgets
+// In-circuit paths through the 256-level hierarchy tree. Keys enter the circuit as eight
+// big-endian 32-bit limbs and are split into path bits, most significant first, so `bits[d]`
+// selects the child at depth `d` exactly as `SparseMerkleTree` walks a key. Nodes hash both
+// children's limbs with `poseidon_node`, matching `PoseidonHasher::hash_node`.
+
+use crate::core::smt::hasher::PoseidonHasher;
+use crate::core::smt::tree::TREE_DEPTH;
+use crate::core::zkps::gadgets::hash::poseidon_node;
+use crate::core::zkps::gadgets::select::swap_hashes;
+use plonky2::field::goldilocks_field::GoldilocksField;
+use plonky2::field::types::Field;
+use plonky2::hash::hash_types::HashOutTarget;
+use plonky2::iop::target::{BoolTarget, Target};
+use plonky2::iop::witness::{PartialWitness, WitnessWrite};
+use plonky2::plonk::circuit_builder::CircuitBuilder;
+
+const D: usize = 2;
+type F = GoldilocksField;
+
+/// Number of 32-bit limbs a key is split into.
+pub const KEY_LIMBS: usize = 8;
+
+/// Splits the key limbs into path bits; `bits[d]` selects the child at depth `d`.
+pub fn key_bits(builder: &mut CircuitBuilder<F, D>, key: &[Target; KEY_LIMBS]) -> Vec<BoolTarget> {
+    let mut bits = Vec::with_capacity(TREE_DEPTH);
+    for limb in key {
+        let mut limb_bits = builder.split_le(*limb, 32);
+        limb_bits.reverse();
+        bits.extend(limb_bits);
+    }
+    bits
+}
+
+/// Hashes from the leaf up to the root, placing the running hash on the side the key selects.
+pub fn path_root(
+    builder: &mut CircuitBuilder<F, D>,
+    leaf: HashOutTarget,
+    siblings: &[HashOutTarget],
+    bits: &[BoolTarget],
+) -> HashOutTarget {
+    let mut current = leaf;
+    for depth in (0..TREE_DEPTH).rev() {
+        let (left, right) = swap_hashes(builder, bits[depth], current, siblings[depth]);
+        current = poseidon_node(builder, left, right);
+    }
+    current
+}
+
+/// Field limbs of a key, in the order `key_bits` expects.
+pub fn key_limbs(key: &[u8; 32]) -> Vec<F> {
+    key.chunks(4)
+        .map(|chunk| {
+            let limb = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+            F::from_canonical_u32(limb)
+        })
+        .collect()
+}
+
+pub fn set_key(
+    pw: &mut PartialWitness<F>,
+    targets: &[Target; KEY_LIMBS],
+    key: &[u8; 32],
+) -> anyhow::Result<()> {
+    for (target, limb) in targets.iter().zip(key_limbs(key)) {
+        pw.set_target(*target, limb)?;
+    }
+    Ok(())
+}
+
+pub fn set_hash(
+    pw: &mut PartialWitness<F>,
+    target: HashOutTarget,
+    hash: &[u8; 32],
+) -> anyhow::Result<()> {
+    pw.set_hash_target(target, PoseidonHasher::to_hash_out(hash))
+}
@@ -0,0 +1,12 @@
+// ./src/core/zkps/gadgets/mod.rs
+
+// Circuit gadgets shared by the Overpass circuits.
+// Every circuit is assembled from these on top of plonky2's `CircuitBuilder`: `range` for
+// bounds and comparisons of small values, `uint64` for exact u64 arithmetic, `hash` for
+// Poseidon, `select` for conditional choice and `merkle` for hierarchy tree paths.
+
+pub mod hash;
+pub mod merkle;
+pub mod range;
+pub mod select;
+pub mod uint64;
@@ -0,0 +1,126 @@
+// ./src/core/zkps/gadgets/range.rs
+
+// Range and Comparison Gadgets
+// Bounds and comparisons on field elements known to fit in a given number of bits. A
+// difference `x - y` of two `bits`-bit values lies in `[-2^bits, 2^bits)`; shifting it by
+// `2^bits` makes it non-negative and below `2^(bits + 1)`, so its top bit says whether the
+// difference was non-negative. The shifted value must stay below the field modulus, which
+// limits these gadgets to `bits <= 62`. Wider values, such as balances, go through `uint64`.
+
+use plonky2::field::goldilocks_field::GoldilocksField;
+use plonky2::field::types::Field;
+use plonky2::iop::target::{BoolTarget, Target};
+use plonky2::plonk::circuit_builder::CircuitBuilder;
+
+const D: usize = 2;
+type F = GoldilocksField;
+
+/// Widest operands the comparison gadgets accept.
+pub const MAX_COMPARISON_BITS: usize = 62;
+
+/// Constrains `x < 2^bits`.
+pub fn assert_bits(builder: &mut CircuitBuilder<F, D>, x: Target, bits: usize) {
+    builder.range_check(x, bits);
+}
+
+/// Whether `a == b`.
+pub fn is_equal(builder: &mut CircuitBuilder<F, D>, a: Target, b: Target) -> BoolTarget {
+    builder.is_equal(a, b)
+}
+
+/// `x - y` for `bits`-bit values as a `bits`-bit value plus a flag that is one when no borrow
+/// was needed.
+pub fn difference(
+    builder: &mut CircuitBuilder<F, D>,
+    x: Target,
+    y: Target,
+    bits: usize,
+) -> (Target, BoolTarget) {
+    let diff = builder.sub(x, y);
+    offset_difference(builder, diff, bits)
+}
+
+/// Splits `diff + 2^bits`, for `diff` in `[-2^bits, 2^bits)`, into the low `bits` bits and the
+/// bit that is set when `diff` is non-negative.
+pub fn offset_difference(
+    builder: &mut CircuitBuilder<F, D>,
+    diff: Target,
+    bits: usize,
+) -> (Target, BoolTarget) {
+    assert!(
+        bits <= MAX_COMPARISON_BITS,
+        "comparison too wide: {bits} bits"
+    );
+    let offset = builder.constant(F::from_canonical_u64(1 << bits));
+    let shifted = builder.add(diff, offset);
+    let (low, high) = builder.split_low_high(shifted, bits, bits + 1);
+    (low, BoolTarget::new_unsafe(high))
+}
+
+/// Whether `a <= b` for `bits`-bit values.
+pub fn less_than_or_equal(
+    builder: &mut CircuitBuilder<F, D>,
+    a: Target,
+    b: Target,
+    bits: usize,
+) -> BoolTarget {
+    difference(builder, b, a, bits).1
+}
+
+/// Whether `a < b` for `bits`-bit values.
+pub fn less_than(
+    builder: &mut CircuitBuilder<F, D>,
+    a: Target,
+    b: Target,
+    bits: usize,
+) -> BoolTarget {
+    let ge = less_than_or_equal(builder, b, a, bits);
+    builder.not(ge)
+}
+
+/// Constrains `a <= b` for `bits`-bit values.
+pub fn assert_less_than_or_equal(
+    builder: &mut CircuitBuilder<F, D>,
+    a: Target,
+    b: Target,
+    bits: usize,
+) {
+    let le = less_than_or_equal(builder, a, b, bits);
+    builder.assert_one(le.target);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use plonky2::iop::witness::{PartialWitness, WitnessWrite};
+    use plonky2::plonk::circuit_data::CircuitConfig;
+    use plonky2::plonk::config::PoseidonGoldilocksConfig;
+
+    type C = PoseidonGoldilocksConfig;
+
+    #[test]
+    fn test_comparisons_at_the_bit_boundary() {
+        const BITS: usize = 16;
+        let mut builder = CircuitBuilder::<F, D>::new(CircuitConfig::standard_recursion_config());
+        let a = builder.add_virtual_target();
+        let b = builder.add_virtual_target();
+        assert_bits(&mut builder, a, BITS);
+        assert_bits(&mut builder, b, BITS);
+        let le = less_than_or_equal(&mut builder, a, b, BITS);
+        let lt = less_than(&mut builder, a, b, BITS);
+        let eq = is_equal(&mut builder, a, b);
+        builder.register_public_inputs(&[le.target, lt.target, eq.target]);
+        let data = builder.build::<C>();
+
+        let max = (1u64 << BITS) - 1;
+        for (x, y) in [(0, max), (max, 0), (max, max), (7, 8), (8, 7)] {
+            let mut pw = PartialWitness::new();
+            pw.set_target(a, F::from_canonical_u64(x)).unwrap();
+            pw.set_target(b, F::from_canonical_u64(y)).unwrap();
+            let proof = data.prove(pw).unwrap();
+            let flags: Vec<bool> = proof.public_inputs.iter().map(|f| *f == F::ONE).collect();
+            assert_eq!(flags, vec![x <= y, x < y, x == y], "{x} vs {y}");
+            data.verify(proof).unwrap();
+        }
+    }
+}
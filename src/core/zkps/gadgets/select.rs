@@ -0,0 +1,62 @@
+// ./src/core/zkps/gadgets/select.rs
+
+// Conditional Select Gadgets
+// Branch-free choice between values. Every select takes a `BoolTarget`, so the condition
+// must already be constrained to 0 or 1, and yields the first value when it is one.
+
+use crate::core::zkps::gadgets::uint64::U64Target;
+use plonky2::field::goldilocks_field::GoldilocksField;
+use plonky2::hash::hash_types::HashOutTarget;
+use plonky2::iop::target::{BoolTarget, Target};
+use plonky2::plonk::circuit_builder::CircuitBuilder;
+
+const D: usize = 2;
+type F = GoldilocksField;
+
+/// `a` if `condition`, otherwise `b`.
+pub fn select(
+    builder: &mut CircuitBuilder<F, D>,
+    condition: BoolTarget,
+    a: Target,
+    b: Target,
+) -> Target {
+    builder.select(condition, a, b)
+}
+
+/// `a` if `condition`, otherwise `b`, limb by limb.
+pub fn select_hash(
+    builder: &mut CircuitBuilder<F, D>,
+    condition: BoolTarget,
+    a: HashOutTarget,
+    b: HashOutTarget,
+) -> HashOutTarget {
+    HashOutTarget {
+        elements: std::array::from_fn(|i| builder.select(condition, a.elements[i], b.elements[i])),
+    }
+}
+
+/// `a` if `condition`, otherwise `b`. Both limbs are already range-checked, so the result is.
+pub fn select_u64(
+    builder: &mut CircuitBuilder<F, D>,
+    condition: BoolTarget,
+    a: U64Target,
+    b: U64Target,
+) -> U64Target {
+    U64Target {
+        lo: builder.select(condition, a.lo, b.lo),
+        hi: builder.select(condition, a.hi, b.hi),
+    }
+}
+
+/// `(b, a)` if `condition`, otherwise `(a, b)`.
+pub fn swap_hashes(
+    builder: &mut CircuitBuilder<F, D>,
+    condition: BoolTarget,
+    a: HashOutTarget,
+    b: HashOutTarget,
+) -> (HashOutTarget, HashOutTarget) {
+    (
+        select_hash(builder, condition, b, a),
+        select_hash(builder, condition, a, b),
+    )
+}
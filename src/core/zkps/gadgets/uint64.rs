@@ -0,0 +1,197 @@
+// ./src/core/zkps/gadgets/uint64.rs
+
+// u64 Arithmetic Gadgets
+// The Goldilocks field is smaller than 2^64, so a u64 does not fit in one field element and
+// field arithmetic on balances wraps. A `U64Target` holds a value as two 32-bit limbs, each
+// range-checked. Limb sums and differences stay below 2^34, far from the field modulus, so
+// carries and borrows can be extracted exactly and every operation has integer semantics:
+// `checked_add` rejects results of 2^64 or more, `checked_sub` rejects negative results, and
+// comparisons are exact over the whole u64 range.
+
+use crate::core::zkps::gadgets::range;
+use plonky2::field::goldilocks_field::GoldilocksField;
+use plonky2::field::types::Field;
+use plonky2::iop::target::{BoolTarget, Target};
+use plonky2::iop::witness::{PartialWitness, WitnessWrite};
+use plonky2::plonk::circuit_builder::CircuitBuilder;
+
+const D: usize = 2;
+type F = GoldilocksField;
+
+const LIMB_BITS: usize = 32;
+
+/// A u64 in the circuit as range-checked 32-bit limbs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct U64Target {
+    pub lo: Target,
+    pub hi: Target,
+}
+
+impl U64Target {
+    /// Limbs in the order they appear in public inputs and commitments.
+    pub fn limbs(&self) -> [Target; 2] {
+        [self.lo, self.hi]
+    }
+}
+
+/// Field limbs of a u64, low limb first.
+pub fn u64_limbs(value: u64) -> [F; 2] {
+    [
+        F::from_canonical_u64(value & 0xffff_ffff),
+        F::from_canonical_u64(value >> LIMB_BITS),
+    ]
+}
+
+/// A new u64 witness with both limbs range-checked.
+pub fn add_virtual_u64(builder: &mut CircuitBuilder<F, D>) -> U64Target {
+    let lo = builder.add_virtual_target();
+    let hi = builder.add_virtual_target();
+    builder.range_check(lo, LIMB_BITS);
+    builder.range_check(hi, LIMB_BITS);
+    U64Target { lo, hi }
+}
+
+pub fn constant_u64(builder: &mut CircuitBuilder<F, D>, value: u64) -> U64Target {
+    let [lo, hi] = u64_limbs(value);
+    U64Target {
+        lo: builder.constant(lo),
+        hi: builder.constant(hi),
+    }
+}
+
+pub fn set_u64(pw: &mut PartialWitness<F>, target: U64Target, value: u64) -> anyhow::Result<()> {
+    let [lo, hi] = u64_limbs(value);
+    pw.set_target(target.lo, lo)?;
+    pw.set_target(target.hi, hi)
+}
+
+pub fn connect_u64(builder: &mut CircuitBuilder<F, D>, a: U64Target, b: U64Target) {
+    builder.connect(a.lo, b.lo);
+    builder.connect(a.hi, b.hi);
+}
+
+/// `a + b`, constrained not to overflow.
+pub fn checked_add(builder: &mut CircuitBuilder<F, D>, a: U64Target, b: U64Target) -> U64Target {
+    let lo_sum = builder.add(a.lo, b.lo);
+    let (lo, carry) = builder.split_low_high(lo_sum, LIMB_BITS, LIMB_BITS + 1);
+    let hi_sum = builder.add_many([a.hi, b.hi, carry]);
+    builder.range_check(hi_sum, LIMB_BITS);
+    U64Target { lo, hi: hi_sum }
+}
+
+/// `a - b`, constrained not to underflow.
+pub fn checked_sub(builder: &mut CircuitBuilder<F, D>, a: U64Target, b: U64Target) -> U64Target {
+    let (lo, no_borrow) = range::difference(builder, a.lo, b.lo, LIMB_BITS);
+    let borrow = builder.not(no_borrow);
+    // For a < b this is negative and wraps to a field element far above 2^32.
+    let hi_diff = builder.sub(a.hi, b.hi);
+    let hi = builder.sub(hi_diff, borrow.target);
+    builder.range_check(hi, LIMB_BITS);
+    U64Target { lo, hi }
+}
+
+/// Whether `a <= b`.
+pub fn less_than_or_equal(
+    builder: &mut CircuitBuilder<F, D>,
+    a: U64Target,
+    b: U64Target,
+) -> BoolTarget {
+    let (_, no_borrow) = range::difference(builder, b.lo, a.lo, LIMB_BITS);
+    let borrow = builder.not(no_borrow);
+    let hi_diff = builder.sub(b.hi, a.hi);
+    let hi_diff = builder.sub(hi_diff, borrow.target);
+    range::offset_difference(builder, hi_diff, LIMB_BITS).1
+}
+
+/// Constrains `a <= b`.
+pub fn assert_less_than_or_equal(builder: &mut CircuitBuilder<F, D>, a: U64Target, b: U64Target) {
+    let le = less_than_or_equal(builder, a, b);
+    builder.assert_one(le.target);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use plonky2::plonk::circuit_data::{CircuitConfig, CircuitData};
+    use plonky2::plonk::config::PoseidonGoldilocksConfig;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    type C = PoseidonGoldilocksConfig;
+
+    const MAX: u64 = u64::MAX;
+    const HALF: u64 = 1 << 63;
+
+    /// A circuit over two u64 inputs; `gadget` returns the targets to expose as public inputs.
+    struct Harness {
+        data: CircuitData<F, C, D>,
+        a: U64Target,
+        b: U64Target,
+    }
+
+    impl Harness {
+        fn new<G>(gadget: G) -> Self
+        where
+            G: FnOnce(&mut CircuitBuilder<F, D>, U64Target, U64Target) -> Vec<Target>,
+        {
+            let mut builder =
+                CircuitBuilder::<F, D>::new(CircuitConfig::standard_recursion_config());
+            let a = add_virtual_u64(&mut builder);
+            let b = add_virtual_u64(&mut builder);
+            let outputs = gadget(&mut builder, a, b);
+            builder.register_public_inputs(&outputs);
+            Self {
+                data: builder.build::<C>(),
+                a,
+                b,
+            }
+        }
+
+        /// Public outputs if the constraints hold for `(a, b)`, `None` otherwise.
+        fn run(&self, a: u64, b: u64) -> Option<Vec<F>> {
+            let mut pw = PartialWitness::new();
+            set_u64(&mut pw, self.a, a).unwrap();
+            set_u64(&mut pw, self.b, b).unwrap();
+            let proof = catch_unwind(AssertUnwindSafe(|| self.data.prove(pw)))
+                .ok()?
+                .ok()?;
+            let outputs = proof.public_inputs.clone();
+            self.data.verify(proof).ok().map(|_| outputs)
+        }
+    }
+
+    fn value(limbs: &[F]) -> u64 {
+        use plonky2::field::types::PrimeField64;
+        limbs[0].to_canonical_u64() | (limbs[1].to_canonical_u64() << LIMB_BITS)
+    }
+
+    #[test]
+    fn test_checked_add_at_the_2_64_boundary() {
+        let harness = Harness::new(|builder, a, b| checked_add(builder, a, b).limbs().to_vec());
+        assert_eq!(value(&harness.run(HALF, HALF - 1).unwrap()), MAX);
+        assert_eq!(value(&harness.run(MAX - 1, 1).unwrap()), MAX);
+        assert!(harness.run(HALF, HALF).is_none());
+        assert!(harness.run(MAX, 1).is_none());
+    }
+
+    #[test]
+    fn test_checked_sub_rejects_underflow() {
+        let harness = Harness::new(|builder, a, b| checked_sub(builder, a, b).limbs().to_vec());
+        assert_eq!(value(&harness.run(MAX, HALF).unwrap()), HALF - 1);
+        assert_eq!(value(&harness.run(HALF, HALF).unwrap()), 0);
+        assert_eq!(value(&harness.run(1 << 32, 1).unwrap()), (1 << 32) - 1);
+        assert!(harness.run(HALF - 1, HALF).is_none());
+        assert!(harness.run(0, 1).is_none());
+    }
+
+    #[test]
+    fn test_comparison_is_exact() {
+        let harness = Harness::new(|builder, a, b| vec![less_than_or_equal(builder, a, b).target]);
+        let le = |a, b| harness.run(a, b).unwrap()[0] == F::ONE;
+        assert!(le(MAX, MAX));
+        assert!(le(HALF - 1, HALF));
+        assert!(!le(HALF, HALF - 1));
+        assert!(!le(MAX, 0));
+        assert!(le(0, MAX));
+        assert!(!le(1 << 32, (1 << 32) - 1));
+    }
+}
@@ -0,0 +1,288 @@
+// ./src/core/zkps/merkle_inclusion.rs
+
+// Merkle Inclusion Circuit
+// Proves that a leaf is stored under a key in a hierarchy tree with a given root, without
+// revealing the siblings on its path. The siblings are private witnesses; the circuit hashes
+// the leaf up the key's path and connects the result to the public root. A light client or a
+// storage-node challenger checks the root, key and leaf hash it expects against the public
+// inputs and verifies one proof instead of 256 sibling hashes.
+//
+// Public inputs, in order:
+//   root (4) | key (8 x 32-bit limbs, big-endian) | leaf hash (4)
+//
+// The serialized proof is the plonky2 proof with its public inputs; root, key and leaf are
+// read back from those on load. The circuit is built with zero knowledge enabled, since
+// without it the proof's openings leak information about the private siblings.
+
+use crate::core::error::errors::{SystemError, SystemErrorType};
+use crate::core::smt::hasher::{PoseidonHasher, TreeHasher};
+use crate::core::smt::store::NodeStore;
+use crate::core::smt::tree::{EMPTY_LEAF, TREE_DEPTH};
+use crate::core::smt::HierarchyTree;
+use crate::core::zkps::circuit_cache::CircuitCache;
+use crate::core::zkps::gadgets::merkle::{
+    key_bits, key_limbs, path_root, set_hash, set_key, KEY_LIMBS,
+};
+use plonky2::field::goldilocks_field::GoldilocksField;
+use plonky2::field::types::PrimeField64;
+use plonky2::hash::hash_types::{HashOut, HashOutTarget};
+use plonky2::iop::target::Target;
+use plonky2::iop::witness::PartialWitness;
+use plonky2::plonk::circuit_builder::CircuitBuilder;
+use plonky2::plonk::circuit_data::{CircuitConfig, CircuitData};
+use plonky2::plonk::config::PoseidonGoldilocksConfig;
+use plonky2::plonk::proof::ProofWithPublicInputs;
+use std::sync::Arc;
+
+const D: usize = 2;
+type F = GoldilocksField;
+type C = PoseidonGoldilocksConfig;
+
+/// Name of the inclusion circuit in the process-wide `CircuitCache`.
+pub const MERKLE_INCLUSION_CIRCUIT: &str = "merkle_inclusion";
+
+const PUBLIC_INPUTS: usize = 4 + KEY_LIMBS + 4;
+
+/// A proof that `leaf` is stored under `key` in the tree with root `root`.
+#[derive(Clone, Debug)]
+pub struct InclusionProof {
+    pub root: [u8; 32],
+    pub key: [u8; 32],
+    pub leaf: [u8; 32],
+    pub proof: ProofWithPublicInputs<F, C, D>,
+}
+
+impl InclusionProof {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.proof.to_bytes()
+    }
+}
+
+struct InclusionTargets {
+    root: HashOutTarget,
+    key: [Target; KEY_LIMBS],
+    leaf: HashOutTarget,
+    siblings: Vec<HashOutTarget>,
+}
+
+/// Lays out the inclusion circuit. Allocation is deterministic, so running this on a fresh
+/// builder recovers the targets of a cached or deserialized circuit.
+fn add_inclusion_targets(builder: &mut CircuitBuilder<F, D>) -> InclusionTargets {
+    let root = builder.add_virtual_hash();
+    let key: [Target; KEY_LIMBS] = builder.add_virtual_target_arr();
+    let leaf = builder.add_virtual_hash();
+    builder.register_public_inputs(&root.elements);
+    builder.register_public_inputs(&key);
+    builder.register_public_inputs(&leaf.elements);
+
+    let bits = key_bits(builder, &key);
+    let siblings = builder.add_virtual_hashes(TREE_DEPTH);
+    let computed = path_root(builder, leaf, &siblings, &bits);
+    builder.connect_hashes(computed, root);
+
+    InclusionTargets {
+        root,
+        key,
+        leaf,
+        siblings,
+    }
+}
+
+/// The public inputs of a proof that the leaf hash `leaf` sits under `key` below `root`.
+pub fn inclusion_public_inputs(root: &[u8; 32], key: &[u8; 32], leaf: &[u8; 32]) -> Vec<F> {
+    let mut inputs = PoseidonHasher::to_hash_out(root).elements.to_vec();
+    inputs.extend(key_limbs(key));
+    inputs.extend(PoseidonHasher::to_hash_out(leaf).elements);
+    inputs
+}
+
+fn build_inclusion_circuit() -> CircuitData<F, C, D> {
+    let config = CircuitConfig {
+        zero_knowledge: true,
+        ..CircuitConfig::standard_recursion_config()
+    };
+    let mut builder = CircuitBuilder::<F, D>::new(config);
+    add_inclusion_targets(&mut builder);
+    builder.build::<C>()
+}
+
+/// Prover and verifier for hierarchy tree inclusion.
+pub struct MerkleInclusionCircuit {
+    data: Arc<CircuitData<F, C, D>>,
+    targets: InclusionTargets,
+}
+
+impl MerkleInclusionCircuit {
+    /// The inclusion circuit from the process-wide cache, built on first use.
+    pub fn shared() -> Self {
+        let data =
+            CircuitCache::global().get_or_build(MERKLE_INCLUSION_CIRCUIT, build_inclusion_circuit);
+        let mut layout = CircuitBuilder::<F, D>::new(data.common.config.clone());
+        let targets = add_inclusion_targets(&mut layout);
+        Self { data, targets }
+    }
+
+    pub fn circuit_data(&self) -> &CircuitData<F, C, D> {
+        &self.data
+    }
+
+    /// Proves that `leaf` sits under `key` given the path's siblings, root side first.
+    pub fn prove(
+        &self,
+        root: &[u8; 32],
+        key: &[u8; 32],
+        leaf: &[u8; 32],
+        siblings: &[[u8; 32]],
+    ) -> Result<InclusionProof, SystemError> {
+        if siblings.len() != TREE_DEPTH {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidProof,
+                "Inclusion witness must carry one sibling per level".to_string(),
+            ));
+        }
+        if leaf == &EMPTY_LEAF {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidProof,
+                "An empty leaf is not included".to_string(),
+            ));
+        }
+
+        let mut pw = PartialWitness::new();
+        set_hash(&mut pw, self.targets.root, root).map_err(proving_error)?;
+        set_key(&mut pw, &self.targets.key, key).map_err(proving_error)?;
+        set_hash(&mut pw, self.targets.leaf, leaf).map_err(proving_error)?;
+        for (target, sibling) in self.targets.siblings.iter().zip(siblings) {
+            set_hash(&mut pw, *target, sibling).map_err(proving_error)?;
+        }
+
+        let proof = self.data.prove(pw).map_err(proving_error)?;
+        Ok(InclusionProof {
+            root: *root,
+            key: *key,
+            leaf: *leaf,
+            proof,
+        })
+    }
+
+    /// Proves that `key` is set in `tree` at its current root.
+    pub fn prove_in_tree<S: NodeStore>(
+        &self,
+        tree: &HierarchyTree<S>,
+        key: &[u8; 32],
+    ) -> Result<InclusionProof, SystemError> {
+        let value = tree.get(key)?.ok_or_else(|| {
+            SystemError::new(
+                SystemErrorType::NotFound,
+                "Key is not set in the tree".to_string(),
+            )
+        })?;
+        let siblings = tree.prove(key)?.siblings;
+        self.prove(
+            &tree.root(),
+            key,
+            &PoseidonHasher::hash_leaf(key, &value),
+            &siblings,
+        )
+    }
+
+    /// Verifies that the proof shows `value` stored under `key` in the tree with `root`.
+    pub fn verify(
+        &self,
+        proof: &InclusionProof,
+        root: &[u8; 32],
+        key: &[u8; 32],
+        value: &[u8],
+    ) -> Result<(), SystemError> {
+        self.verify_leaf(proof, root, key, &PoseidonHasher::hash_leaf(key, value))
+    }
+
+    /// Verifies that the proof shows the leaf hash `leaf` under `key` in the tree with `root`.
+    pub fn verify_leaf(
+        &self,
+        proof: &InclusionProof,
+        root: &[u8; 32],
+        key: &[u8; 32],
+        leaf: &[u8; 32],
+    ) -> Result<(), SystemError> {
+        if proof.proof.public_inputs != inclusion_public_inputs(root, key, leaf) {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidProof,
+                "Proof is not bound to the expected root, key and leaf".to_string(),
+            ));
+        }
+        self.data
+            .verify(proof.proof.clone())
+            .map_err(|e| SystemError::new(SystemErrorType::InvalidProof, e.to_string()))
+    }
+
+    /// Parses a proof produced by `InclusionProof::to_bytes`.
+    pub fn proof_from_bytes(&self, bytes: &[u8]) -> Result<InclusionProof, SystemError> {
+        let proof = ProofWithPublicInputs::<F, C, D>::from_bytes(bytes.to_vec(), &self.data.common)
+            .map_err(|e| SystemError::new(SystemErrorType::InvalidProof, e.to_string()))?;
+        let inputs = &proof.public_inputs;
+        if inputs.len() != PUBLIC_INPUTS {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidProof,
+                "Proof does not carry an inclusion statement".to_string(),
+            ));
+        }
+        let mut key = [0u8; 32];
+        for (chunk, limb) in key.chunks_mut(4).zip(&inputs[4..4 + KEY_LIMBS]) {
+            let limb = u32::try_from(limb.to_canonical_u64()).map_err(|_| {
+                SystemError::new(
+                    SystemErrorType::InvalidProof,
+                    "Key limb exceeds 32 bits".to_string(),
+                )
+            })?;
+            chunk.copy_from_slice(&limb.to_be_bytes());
+        }
+        Ok(InclusionProof {
+            root: PoseidonHasher::from_hash_out(HashOut::from_partial(&inputs[0..4])),
+            key,
+            leaf: PoseidonHasher::from_hash_out(HashOut::from_partial(&inputs[4 + KEY_LIMBS..])),
+            proof,
+        })
+    }
+}
+
+fn proving_error<E: std::fmt::Display>(e: E) -> SystemError {
+    SystemError::new(SystemErrorType::InvalidProof, e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(byte: u8) -> [u8; 32] {
+        let mut key = [0u8; 32];
+        key[0] = byte;
+        key[31] = byte.wrapping_mul(7);
+        key
+    }
+
+    #[test]
+    fn test_inclusion_round_trip() {
+        let mut tree = HierarchyTree::new();
+        tree.update(&key(1), b"one").unwrap();
+        tree.update(&key(2), b"two").unwrap();
+        let root = tree.root();
+
+        let circuit = MerkleInclusionCircuit::shared();
+        let proof = circuit.prove_in_tree(&tree, &key(2)).unwrap();
+        circuit.verify(&proof, &root, &key(2), b"two").unwrap();
+
+        let decoded = circuit.proof_from_bytes(&proof.to_bytes()).unwrap();
+        assert_eq!(decoded.root, root);
+        assert_eq!(decoded.key, key(2));
+        circuit.verify(&decoded, &root, &key(2), b"two").unwrap();
+
+        assert!(circuit.verify(&proof, &root, &key(2), b"one").is_err());
+        assert!(circuit.verify(&proof, &root, &key(1), b"two").is_err());
+        tree.update(&key(3), b"three").unwrap();
+        assert!(circuit
+            .verify(&proof, &tree.root(), &key(2), b"two")
+            .is_err());
+        assert!(circuit.prove_in_tree(&tree, &key(9)).is_err());
+        assert!(circuit.circuit_data().common.config.zero_knowledge);
+    }
+}
@@ -2,8 +2,18 @@
 
 // src/core/zkp/mod.rs
 
-pub mod circuit_builder;
+pub mod aggregation;
+pub mod circuit_cache;
+pub mod compression;
+pub mod confidential;
+pub mod fixtures;
+pub mod gadgets;
+pub mod merkle_inclusion;
 pub mod plonky2;
 pub mod proof;
-pub mod zkp;
+pub mod prover_queue;
+pub mod signature;
+pub mod state_commitment;
+pub mod tree_transition;
+pub mod verifier_export;
 pub mod zkp_interface;
@@ -1,195 +1,478 @@
+use crate::core::smt::hasher::PoseidonHasher;
+use crate::core::zkps::circuit_cache::{circuit_digest, serialize_circuit, CircuitCache};
+use crate::core::zkps::gadgets::hash::hashes_equal;
+use crate::core::zkps::gadgets::uint64::{add_virtual_u64, set_u64, u64_limbs, U64Target};
+use crate::core::zkps::signature::{StateSignature, StateSignatureTargets};
+use crate::core::zkps::state_commitment::{
+    constrain_transfer, ChannelStateTargets, CommittedChannelState,
+};
 use plonky2::{
     field::goldilocks_field::GoldilocksField,
-    iop::{
-        target::Target,
-        witness::{PartialWitness, WitnessWrite},
-    },
+    hash::hash_types::HashOut,
+    iop::witness::PartialWitness,
     plonk::{
         circuit_builder::CircuitBuilder,
-        circuit_data::{CircuitConfig, CircuitData},
+        circuit_data::{CircuitConfig, CircuitData, VerifierCircuitData},
         config::PoseidonGoldilocksConfig,
         proof::ProofWithPublicInputs,
     },
 };
-use plonky2_field::types::Field;
-use std::rc::Rc;
+use plonky2_field::types::{Field, Field64};
+use std::path::Path;
+use std::sync::Arc;
 use wasm_bindgen::prelude::*;
 
 const D: usize = 2;
 type F = GoldilocksField;
 type C = PoseidonGoldilocksConfig;
 
+/// Name of the state transition circuit in the process-wide `CircuitCache`.
+pub const STATE_TRANSITION_CIRCUIT: &str = "state_transition";
+
 #[wasm_bindgen]
 
 pub struct Plonky2System {
-    circuit_config: CircuitConfig,
     state_transition_circuit: StateTransitionCircuitData,
 }
 
 #[wasm_bindgen]
-pub struct Plonky2SystemHandle(Rc<Plonky2System>);
+pub struct Plonky2SystemHandle(Arc<Plonky2System>);
 
 #[wasm_bindgen]
 impl Plonky2SystemHandle {
     #[wasm_bindgen(constructor)]
     pub fn new() -> Result<Plonky2SystemHandle, JsValue> {
-        let circuit_config = CircuitConfig::standard_recursion_config();
-        let builder = CircuitBuilder::<F, D>::new(circuit_config.clone());
-        let state_transition_circuit = build_state_transition_circuit(builder)
-            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let system = Plonky2System::shared().map_err(|e| JsValue::from_str(&e.to_string()))?;
+        Ok(Plonky2SystemHandle(Arc::new(system)))
+    }
 
-        let system = Plonky2System {
-            circuit_config,
-            state_transition_circuit,
-        };
+    /// Loads the state transition circuit from bytes produced by `circuit_bytes`, so the
+    /// browser does not have to build it. `circuit_digest` pins the circuit the bytes must
+    /// hold and has to come from a trusted source, not from the bytes' own header.
+    pub fn from_circuit_bytes(
+        bytes: &[u8],
+        circuit_digest: &[u8],
+    ) -> Result<Plonky2SystemHandle, JsValue> {
+        let circuit_digest: [u8; 32] = circuit_digest
+            .try_into()
+            .map_err(|_| JsValue::from_str("Circuit digest must be 32 bytes"))?;
+        let system = Plonky2System::from_circuit_bytes(bytes, &circuit_digest)
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+        Ok(Plonky2SystemHandle(Arc::new(system)))
+    }
 
-        Ok(Plonky2SystemHandle(Rc::new(system)))
+    pub fn circuit_bytes(&self) -> Result<Vec<u8>, JsValue> {
+        self.0
+            .circuit_bytes()
+            .map_err(|e| JsValue::from_str(&e.to_string()))
     }
 
+    /// Proves a transfer of `transfer_amount` between two serialized `CommittedChannelState`s,
+    /// authorized by a serialized `StateSignature` over the new state's commitment.
     pub fn generate_proof_js(
         &self,
+        old_state: &[u8],
+        new_state: &[u8],
+        transfer_amount: u64,
+        signature: &[u8],
+    ) -> Result<Vec<u8>, JsValue> {
+        let (old_state, new_state) = decode_states(old_state, new_state)?;
+        let signature =
+            StateSignature::from_bytes(signature).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        self.0
+            .generate_proof(&old_state, &new_state, transfer_amount, &signature)
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    pub fn verify_transition_js(
+        &self,
+        proof_bytes: &[u8],
+        old_state: &[u8],
+        new_state: &[u8],
+        transfer_amount: u64,
+    ) -> Result<bool, JsValue> {
+        let (old_state, new_state) = decode_states(old_state, new_state)?;
+        self.0
+            .verify_transition(proof_bytes, &old_state, &new_state, transfer_amount)
+            .map(|_| true)
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// Verifies a transition out of the state with commitment `old_commitment` and returns the
+    /// commitment of the new state.
+    #[allow(clippy::too_many_arguments)]
+    pub fn verify_from_commitment_js(
+        &self,
+        proof_bytes: &[u8],
+        old_commitment: &[u8],
         old_balance: u64,
         old_nonce: u64,
         new_balance: u64,
         new_nonce: u64,
         transfer_amount: u64,
     ) -> Result<Vec<u8>, JsValue> {
+        let old_commitment: [u8; 32] = old_commitment
+            .try_into()
+            .map_err(|_| JsValue::from_str("State commitment must be 32 bytes"))?;
         self.0
-            .generate_proof(
-                old_balance,
-                old_nonce,
-                new_balance,
-                new_nonce,
-                transfer_amount,
+            .verify_from_commitment(
+                proof_bytes,
+                &old_commitment,
+                [
+                    old_balance,
+                    old_nonce,
+                    new_balance,
+                    new_nonce,
+                    transfer_amount,
+                ],
             )
+            .map(|commitment| commitment.to_vec())
             .map_err(|e| JsValue::from_str(&e.to_string()))
     }
+}
 
-    pub fn verify_proof_js(&self, proof_bytes: &[u8]) -> Result<bool, JsValue> {
-        self.0
-            .verify_proof(proof_bytes)
-            .map(|_| true)
-            .map_err(|e| JsValue::from_str(&e.to_string()))
+impl Plonky2SystemHandle {
+    pub fn system(&self) -> &Plonky2System {
+        &self.0
+    }
+
+    /// The system itself, for jobs that outlive the borrow of this handle.
+    pub fn shared_system(&self) -> Arc<Plonky2System> {
+        Arc::clone(&self.0)
     }
 }
 
 impl Plonky2System {
+    /// The state transition system backed by the process-wide circuit cache; the circuit is
+    /// built on first use only.
+    pub fn shared() -> Result<Self, PlonkyError> {
+        let circuit_data = CircuitCache::global()
+            .get_or_build(STATE_TRANSITION_CIRCUIT, build_state_transition_circuit);
+        Self::from_circuit_data(circuit_data)
+    }
+
+    /// Loads the state transition circuit with the pinned `circuit_digest` from serialized
+    /// bytes into the process-wide cache.
+    pub fn from_circuit_bytes(
+        bytes: &[u8],
+        circuit_digest: &[u8; 32],
+    ) -> Result<Self, PlonkyError> {
+        let circuit_data = CircuitCache::global()
+            .insert_bytes(STATE_TRANSITION_CIRCUIT, bytes, circuit_digest)
+            .map_err(|e| PlonkyError::InvalidInput(e.to_string()))?;
+        Self::from_circuit_data(circuit_data)
+    }
+
+    /// Loads the state transition circuit with the pinned `circuit_digest` from `path`,
+    /// building and writing it there if the file is missing, unreadable or holds another
+    /// circuit.
+    pub fn load_or_build(path: &Path, circuit_digest: &[u8; 32]) -> Result<Self, PlonkyError> {
+        let circuit_data = CircuitCache::global()
+            .load_or_build(
+                STATE_TRANSITION_CIRCUIT,
+                path,
+                circuit_digest,
+                build_state_transition_circuit,
+            )
+            .map_err(|e| PlonkyError::InvalidInput(e.to_string()))?;
+        Self::from_circuit_data(circuit_data)
+    }
+
+    fn from_circuit_data(circuit_data: Arc<CircuitData<F, C, D>>) -> Result<Self, PlonkyError> {
+        Ok(Self {
+            state_transition_circuit: StateTransitionCircuitData::from_circuit_data(circuit_data)?,
+        })
+    }
+
+    pub fn circuit_bytes(&self) -> Result<Vec<u8>, PlonkyError> {
+        serialize_circuit(&self.state_transition_circuit.circuit_data)
+            .map_err(|e| PlonkyError::InvalidInput(e.to_string()))
+    }
+
+    /// Proves that `new_state` follows `old_state` by a transfer of `transfer_amount` out of
+    /// the same channel, authorized by `signature` under the old state's `auth_key`. The
+    /// transition is checked natively first, so an invalid transfer is reported as an error
+    /// rather than failing inside the prover.
     pub fn generate_proof(
         &self,
-        old_balance: u64,
-        old_nonce: u64,
-        new_balance: u64,
-        new_nonce: u64,
+        old_state: &CommittedChannelState,
+        new_state: &CommittedChannelState,
         transfer_amount: u64,
+        signature: &StateSignature,
     ) -> Result<Vec<u8>, PlonkyError> {
-        let circuit_data = &self.state_transition_circuit.circuit_data;
+        if old_state.channel_id != new_state.channel_id {
+            return Err(PlonkyError::InvalidInput(
+                "States belong to different channels".to_string(),
+            ));
+        }
+        if old_state.auth_key == new_state.auth_key
+            || signature
+                .verify(&old_state.auth_key, &new_state.commitment())
+                .is_err()
+        {
+            return Err(PlonkyError::Unauthorized);
+        }
+        check_transfer(old_state, transfer_amount)?;
+        let expected = old_state
+            .transfer(transfer_amount, new_state.lock_root)
+            .map_err(|e| PlonkyError::InvalidInput(e.to_string()))?;
+        if expected.with_auth_key(new_state.auth_key) != *new_state {
+            return Err(PlonkyError::InvalidInput(
+                "New state does not follow from the transfer".to_string(),
+            ));
+        }
+
+        let circuit = &self.state_transition_circuit;
         let mut pw = PartialWitness::new();
+        circuit
+            .targets
+            .old_state
+            .set(&mut pw, old_state)
+            .map_err(|e| PlonkyError::ProofGenerationError(e.to_string()))?;
+        circuit
+            .targets
+            .new_state
+            .set(&mut pw, new_state)
+            .map_err(|e| PlonkyError::ProofGenerationError(e.to_string()))?;
+        set_u64(&mut pw, circuit.targets.transfer_amount, transfer_amount)
+            .map_err(|e| PlonkyError::ProofGenerationError(e.to_string()))?;
+        circuit
+            .targets
+            .signature
+            .set(&mut pw, signature)
+            .map_err(|e| PlonkyError::ProofGenerationError(e.to_string()))?;
 
-        fill_state_transition_witness(
-            &mut pw,
-            &self.state_transition_circuit,
-            old_balance,
-            old_nonce,
-            new_balance,
-            new_nonce,
-            transfer_amount,
-        )?;
-
-        let proof = circuit_data
+        let proof = circuit
+            .circuit_data
             .prove(pw)
             .map_err(|e| PlonkyError::ProofGenerationError(e.to_string()))?;
 
         Ok(proof.to_bytes())
     }
 
-    pub fn verify_proof(&self, proof_bytes: &[u8]) -> Result<(), PlonkyError> {
-        let circuit_data = &self.state_transition_circuit.circuit_data;
-        let common_data = &circuit_data.common;
+    /// Parses a serialized state transition proof.
+    pub fn proof_from_bytes(
+        &self,
+        proof_bytes: &[u8],
+    ) -> Result<ProofWithPublicInputs<F, C, D>, PlonkyError> {
+        ProofWithPublicInputs::<F, C, D>::from_bytes(
+            proof_bytes.to_vec(),
+            &self.state_transition_circuit.circuit_data.common,
+        )
+        .map_err(|e| PlonkyError::InvalidInput(e.to_string()))
+    }
 
-        let proof = ProofWithPublicInputs::<F, C, D>::from_bytes(proof_bytes.to_vec(), common_data)
-            .map_err(|e| PlonkyError::InvalidInput(e.to_string()))?;
+    /// Verifies a proof of exactly this transition. A valid proof made for any other channel,
+    /// prior state, balances, nonces or amount is rejected.
+    pub fn verify_transition(
+        &self,
+        proof_bytes: &[u8],
+        old_state: &CommittedChannelState,
+        new_state: &CommittedChannelState,
+        transfer_amount: u64,
+    ) -> Result<(), PlonkyError> {
+        let expected = transition_public_inputs(old_state, new_state, transfer_amount);
+        self.verify_public_inputs(proof_bytes, &expected)?;
+        Ok(())
+    }
+
+    /// Verifies a transition out of the state committed to by `old_commitment`, normally the
+    /// channel's stored state hash, with the given `[old_balance, old_nonce, new_balance,
+    /// new_nonce, transfer_amount]`. Returns the commitment to the new state.
+    pub fn verify_from_commitment(
+        &self,
+        proof_bytes: &[u8],
+        old_commitment: &[u8; 32],
+        values: [u64; 5],
+    ) -> Result<[u8; 32], PlonkyError> {
+        let mut expected = value_inputs(values);
+        expected.extend(PoseidonHasher::to_hash_out(old_commitment).elements);
+        let public_inputs = self.verify_public_inputs(proof_bytes, &expected)?;
+        Ok(PoseidonHasher::from_hash_out(HashOut::from_partial(
+            &public_inputs[NEW_COMMITMENT_OFFSET..],
+        )))
+    }
 
-        circuit_data
+    /// Checks that the proof's public inputs start with `expected`, then verifies it.
+    fn verify_public_inputs(
+        &self,
+        proof_bytes: &[u8],
+        expected: &[F],
+    ) -> Result<Vec<F>, PlonkyError> {
+        let proof = self.proof_from_bytes(proof_bytes)?;
+        if !proof.public_inputs.starts_with(expected) {
+            return Err(PlonkyError::PublicInputMismatch);
+        }
+
+        let public_inputs = proof.public_inputs.clone();
+        self.state_transition_circuit
+            .circuit_data
             .verify(proof)
-            .map_err(|e| PlonkyError::InvalidInput(e.to_string()))
+            .map_err(|e| PlonkyError::InvalidInput(e.to_string()))?;
+        Ok(public_inputs)
+    }
+
+    /// Digest of the state transition circuit.
+    pub fn circuit_digest(&self) -> [u8; 32] {
+        circuit_digest(&self.state_transition_circuit.circuit_data)
+    }
+
+    /// Verifier data of the state transition circuit, for registering it with a
+    /// `ProofAggregator`.
+    pub fn state_transition_verifier_data(&self) -> VerifierCircuitData<F, C, D> {
+        self.state_transition_circuit.circuit_data.verifier_data()
     }
 }
 
+/// Offset of the new state commitment in the public inputs.
+const NEW_COMMITMENT_OFFSET: usize = 12;
+
 struct StateTransitionCircuitData {
-    circuit_data: CircuitData<F, C, D>,
-    old_balance_target: Target,
-    old_nonce_target: Target,
-    new_balance_target: Target,
-    new_nonce_target: Target,
-    transfer_amount_target: Target,
-}
-
-fn build_state_transition_circuit(
-    mut builder: CircuitBuilder<F, D>,
-) -> Result<StateTransitionCircuitData, PlonkyError> {
-    let old_balance_target = builder.add_virtual_public_input();
-    let old_nonce_target = builder.add_virtual_public_input();
-    let new_balance_target = builder.add_virtual_public_input();
-    let new_nonce_target = builder.add_virtual_public_input();
-    let transfer_amount_target = builder.add_virtual_public_input();
-
-    let one = builder.one();
-    let old_nonce_plus_one = builder.add(old_nonce_target, one);
-    builder.connect(old_nonce_plus_one, new_nonce_target);
-
-    let old_balance_minus_amount = builder.sub(old_balance_target, transfer_amount_target);
-    builder.connect(old_balance_minus_amount, new_balance_target);
-
-    let two = builder.constant(F::from_canonical_u64(2));
-    let double_transfer = builder.mul(transfer_amount_target, two);
-    let difference = builder.sub(old_balance_target, double_transfer);
-    builder.range_check(difference, 64);
-
-    let circuit_data = builder.build::<C>();
-
-    Ok(StateTransitionCircuitData {
-        circuit_data,
-        old_balance_target,
-        old_nonce_target,
-        new_balance_target,
-        new_nonce_target,
-        transfer_amount_target,
-    })
-}
-
-fn fill_state_transition_witness(
-    pw: &mut PartialWitness<F>,
-    circuit: &StateTransitionCircuitData,
-    old_balance: u64,
-    old_nonce: u64,
-    new_balance: u64,
-    new_nonce: u64,
+    circuit_data: Arc<CircuitData<F, C, D>>,
+    targets: StateTransitionTargets,
+}
+
+impl StateTransitionCircuitData {
+    /// Recovers the targets of a built or deserialized circuit. Target allocation is
+    /// deterministic, so laying the circuit out again on a fresh builder yields the same
+    /// targets; the public inputs are compared to make sure the layouts agree.
+    fn from_circuit_data(circuit_data: Arc<CircuitData<F, C, D>>) -> Result<Self, PlonkyError> {
+        let mut builder = CircuitBuilder::<F, D>::new(circuit_data.common.config.clone());
+        let targets = add_state_transition_targets(&mut builder);
+        if builder.num_public_inputs() != circuit_data.common.num_public_inputs {
+            return Err(PlonkyError::InvalidInput(
+                "Circuit is not the state transition circuit".to_string(),
+            ));
+        }
+        Ok(Self {
+            circuit_data,
+            targets,
+        })
+    }
+}
+
+struct StateTransitionTargets {
+    old_state: ChannelStateTargets,
+    new_state: ChannelStateTargets,
+    transfer_amount: U64Target,
+    signature: StateSignatureTargets,
+}
+
+/// Lays out the state transition circuit.
+///
+/// Public inputs: old balance (2 limbs), old nonce, new balance (2 limbs), new nonce, transfer
+/// amount (2 limbs), old state commitment (4), new state commitment (4). Both states share the
+/// channel id targets. Balance arithmetic uses the u64 gadgets, so it cannot wrap in the field.
+///
+/// The new state commitment must be signed under the old state's `auth_key`, and the new state
+/// must move to a different key, so a proof also attests that the channel owner authorized
+/// the update and no one-time key signs twice.
+fn add_state_transition_targets(builder: &mut CircuitBuilder<F, D>) -> StateTransitionTargets {
+    let old_state = ChannelStateTargets::add_virtual(builder);
+    let new_state = old_state.add_successor(builder);
+    let transfer_amount = add_virtual_u64(builder);
+    builder.register_public_inputs(&old_state.balance.limbs());
+    builder.register_public_input(old_state.nonce);
+    builder.register_public_inputs(&new_state.balance.limbs());
+    builder.register_public_input(new_state.nonce);
+    builder.register_public_inputs(&transfer_amount.limbs());
+
+    constrain_transfer(builder, &old_state, &new_state, transfer_amount);
+
+    let old_commitment = old_state.commitment(builder);
+    let new_commitment = new_state.commitment(builder);
+    builder.register_public_inputs(&old_commitment.elements);
+    builder.register_public_inputs(&new_commitment.elements);
+
+    let signature = StateSignatureTargets::add_virtual(builder);
+    let signer = signature.signer(builder, new_commitment);
+    builder.connect_hashes(signer, old_state.auth_key);
+    let same_key = hashes_equal(builder, old_state.auth_key, new_state.auth_key);
+    builder.assert_zero(same_key.target);
+
+    StateTransitionTargets {
+        old_state,
+        new_state,
+        transfer_amount,
+        signature,
+    }
+}
+
+fn build_state_transition_circuit() -> CircuitData<F, C, D> {
+    let mut builder = CircuitBuilder::<F, D>::new(CircuitConfig::standard_recursion_config());
+    add_state_transition_targets(&mut builder);
+    builder.build::<C>()
+}
+
+/// The rules the state transition circuit puts on a transfer out of `old_state`, checked
+/// natively: the amount is at most half the balance and the next nonce is still a field
+/// element. Signers run this before using a one-time key, so they never sign a state that
+/// cannot be proven.
+pub fn check_transfer(
+    old_state: &CommittedChannelState,
     transfer_amount: u64,
 ) -> Result<(), PlonkyError> {
-    let old_balance_f = F::from_canonical_u64(old_balance);
-    let old_nonce_f = F::from_canonical_u64(old_nonce);
-    let new_balance_f = F::from_canonical_u64(new_balance);
-    let new_nonce_f = F::from_canonical_u64(new_nonce);
-    let transfer_amount_f = F::from_canonical_u64(transfer_amount);
-
-    pw.set_target(circuit.old_balance_target, old_balance_f)
-        .map_err(|e| PlonkyError::ProofGenerationError(e.to_string()))?;
-    pw.set_target(circuit.old_nonce_target, old_nonce_f)
-        .map_err(|e| PlonkyError::ProofGenerationError(e.to_string()))?;
-    pw.set_target(circuit.new_balance_target, new_balance_f)
-        .map_err(|e| PlonkyError::ProofGenerationError(e.to_string()))?;
-    pw.set_target(circuit.new_nonce_target, new_nonce_f)
-        .map_err(|e| PlonkyError::ProofGenerationError(e.to_string()))?;
-    pw.set_target(circuit.transfer_amount_target, transfer_amount_f)
-        .map_err(|e| PlonkyError::ProofGenerationError(e.to_string()))?;
-
+    if transfer_amount
+        .checked_mul(2)
+        .map_or(true, |double| double > old_state.balance)
+    {
+        return Err(PlonkyError::InvalidInput(
+            "Transfer exceeds half the channel balance".to_string(),
+        ));
+    }
+    if old_state.nonce >= F::ORDER - 1 {
+        return Err(PlonkyError::InvalidInput(
+            "Channel nonce is exhausted".to_string(),
+        ));
+    }
     Ok(())
 }
 
+/// The public inputs of a proof of this transition.
+pub fn transition_public_inputs(
+    old_state: &CommittedChannelState,
+    new_state: &CommittedChannelState,
+    transfer_amount: u64,
+) -> Vec<F> {
+    let mut inputs = value_inputs([
+        old_state.balance,
+        old_state.nonce,
+        new_state.balance,
+        new_state.nonce,
+        transfer_amount,
+    ]);
+    inputs.extend(PoseidonHasher::to_hash_out(&old_state.commitment()).elements);
+    inputs.extend(PoseidonHasher::to_hash_out(&new_state.commitment()).elements);
+    inputs
+}
+
+/// Public inputs for `[old_balance, old_nonce, new_balance, new_nonce, transfer_amount]`.
+fn value_inputs(values: [u64; 5]) -> Vec<F> {
+    let [old_balance, old_nonce, new_balance, new_nonce, transfer_amount] = values;
+    let mut inputs = u64_limbs(old_balance).to_vec();
+    inputs.push(F::from_canonical_u64(old_nonce));
+    inputs.extend(u64_limbs(new_balance));
+    inputs.push(F::from_canonical_u64(new_nonce));
+    inputs.extend(u64_limbs(transfer_amount));
+    inputs
+}
+
+fn decode_states(
+    old_state: &[u8],
+    new_state: &[u8],
+) -> Result<(CommittedChannelState, CommittedChannelState), JsValue> {
+    let decode = |bytes: &[u8]| {
+        CommittedChannelState::from_bytes(bytes).map_err(|e| JsValue::from_str(&e.to_string()))
+    };
+    Ok((decode(old_state)?, decode(new_state)?))
+}
+
 #[derive(Debug)]
 pub enum PlonkyError {
     InvalidInput(String),
     ProofGenerationError(String),
+    PublicInputMismatch,
+    Unauthorized,
 }
 
 impl std::fmt::Display for PlonkyError {
@@ -197,8 +480,210 @@ impl std::fmt::Display for PlonkyError {
         match self {
             PlonkyError::InvalidInput(msg) => write!(f, "Invalid input: {}", msg),
             PlonkyError::ProofGenerationError(msg) => write!(f, "Proof generation error: {}", msg),
+            PlonkyError::PublicInputMismatch => {
+                write!(f, "Proof public inputs do not match the claimed transition")
+            }
+            PlonkyError::Unauthorized => {
+                write!(
+                    f,
+                    "State update is not signed by the channel's authorization key"
+                )
+            }
         }
     }
 }
 
 impl std::error::Error for PlonkyError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::zkps::signature::StateKeyChain;
+
+    fn keys() -> StateKeyChain {
+        StateKeyChain::new([21u8; 32])
+    }
+
+    fn channel_state(channel: u8) -> CommittedChannelState {
+        CommittedChannelState {
+            channel_id: [channel; 32],
+            balance: 100,
+            nonce: 4,
+            seqno: 9,
+            lock_root: [0u8; 32],
+            auth_key: keys().auth_key(4),
+        }
+    }
+
+    #[test]
+    fn test_verification_is_bound_to_claimed_transition() {
+        let system = Plonky2System::shared().unwrap();
+        let old_state = channel_state(1);
+        let (new_state, signature) = keys().transfer(&old_state, 30, [5u8; 32]).unwrap();
+        let proof = system
+            .generate_proof(&old_state, &new_state, 30, &signature)
+            .unwrap();
+
+        system
+            .verify_transition(&proof, &old_state, &new_state, 30)
+            .unwrap();
+        let other_amount = old_state.transfer(40, [5u8; 32]).unwrap();
+        assert!(matches!(
+            system.verify_transition(&proof, &old_state, &other_amount, 40),
+            Err(PlonkyError::PublicInputMismatch)
+        ));
+
+        let new_commitment = system
+            .verify_from_commitment(&proof, &old_state.commitment(), [100, 4, 70, 5, 30])
+            .unwrap();
+        assert_eq!(new_commitment, new_state.commitment());
+    }
+
+    #[test]
+    fn test_proof_does_not_replay_across_channels() {
+        let system = Plonky2System::shared().unwrap();
+        let old_state = channel_state(1);
+        let (new_state, signature) = keys().transfer(&old_state, 30, [0u8; 32]).unwrap();
+        let proof = system
+            .generate_proof(&old_state, &new_state, 30, &signature)
+            .unwrap();
+
+        let other_old = channel_state(2);
+        let other_new = other_old.transfer(30, [0u8; 32]).unwrap();
+        assert!(matches!(
+            system.verify_transition(&proof, &other_old, &other_new, 30),
+            Err(PlonkyError::PublicInputMismatch)
+        ));
+        assert!(system
+            .verify_from_commitment(&proof, &other_old.commitment(), [100, 4, 70, 5, 30])
+            .is_err());
+        assert!(system
+            .generate_proof(&old_state, &other_new, 30, &signature)
+            .is_err());
+    }
+
+    #[test]
+    fn test_balances_above_the_field_modulus() {
+        let system = Plonky2System::shared().unwrap();
+        let old_state = CommittedChannelState {
+            balance: u64::MAX,
+            ..channel_state(1)
+        };
+
+        let amount = (1 << 63) - 1;
+        let (new_state, signature) = keys().transfer(&old_state, amount, [0u8; 32]).unwrap();
+        let proof = system
+            .generate_proof(&old_state, &new_state, amount, &signature)
+            .unwrap();
+        system
+            .verify_transition(&proof, &old_state, &new_state, amount)
+            .unwrap();
+
+        // Twice this amount is 2^64, which must not wrap into a passing spending check.
+        let amount = 1 << 63;
+        let (new_state, signature) = keys().transfer(&old_state, amount, [0u8; 32]).unwrap();
+        assert!(matches!(
+            system.generate_proof(&old_state, &new_state, amount, &signature),
+            Err(PlonkyError::InvalidInput(_))
+        ));
+
+        // Nor does the circuit accept it when the native check is bypassed
+        let circuit = &system.state_transition_circuit;
+        let mut pw = PartialWitness::new();
+        circuit.targets.old_state.set(&mut pw, &old_state).unwrap();
+        circuit.targets.new_state.set(&mut pw, &new_state).unwrap();
+        set_u64(&mut pw, circuit.targets.transfer_amount, amount).unwrap();
+        circuit.targets.signature.set(&mut pw, &signature).unwrap();
+        let proved = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            circuit.circuit_data.prove(pw)
+        }));
+        assert!(!matches!(proved, Ok(Ok(_))));
+    }
+
+    #[test]
+    fn test_invalid_transitions_are_rejected_before_proving() {
+        let system = Plonky2System::shared().unwrap();
+        let old_state = channel_state(1);
+        let signed = |state: CommittedChannelState| {
+            let state = state.with_auth_key(keys().auth_key(5));
+            (state, keys().key(4).sign(&state.commitment()))
+        };
+        let valid = old_state.transfer(30, [0u8; 32]).unwrap();
+
+        let skipped_nonce = CommittedChannelState { nonce: 6, ..valid };
+        let stale_seqno = CommittedChannelState { seqno: 9, ..valid };
+        let inflated = CommittedChannelState {
+            balance: 90,
+            ..valid
+        };
+        let over_half = old_state.transfer(60, [0u8; 32]).unwrap();
+        for (new_state, amount) in [
+            (skipped_nonce, 30),
+            (stale_seqno, 30),
+            (inflated, 30),
+            (over_half, 60),
+            (valid, 200),
+        ] {
+            let (new_state, signature) = signed(new_state);
+            assert!(matches!(
+                system.generate_proof(&old_state, &new_state, amount, &signature),
+                Err(PlonkyError::InvalidInput(_))
+            ));
+        }
+
+        let (new_state, signature) = signed(valid);
+        system
+            .generate_proof(&old_state, &new_state, 30, &signature)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_transition_requires_signature_under_committed_key() {
+        let system = Plonky2System::shared().unwrap();
+        let old_state = channel_state(1);
+        let (new_state, signature) = keys().transfer(&old_state, 30, [0u8; 32]).unwrap();
+
+        // Signed by a key the old state does not commit to
+        let forged = StateKeyChain::new([22u8; 32])
+            .key(4)
+            .sign(&new_state.commitment());
+        assert!(matches!(
+            system.generate_proof(&old_state, &new_state, 30, &forged),
+            Err(PlonkyError::Unauthorized)
+        ));
+
+        // Signed by the right key but without rotating to a new one
+        let same_key = new_state.with_auth_key(old_state.auth_key);
+        let reused = keys().key(4).sign(&same_key.commitment());
+        assert!(matches!(
+            system.generate_proof(&old_state, &same_key, 30, &reused),
+            Err(PlonkyError::Unauthorized)
+        ));
+
+        // The circuit itself rejects the forged signature
+        let circuit = &system.state_transition_circuit;
+        let mut pw = PartialWitness::new();
+        circuit.targets.old_state.set(&mut pw, &old_state).unwrap();
+        circuit.targets.new_state.set(&mut pw, &new_state).unwrap();
+        set_u64(&mut pw, circuit.targets.transfer_amount, 30).unwrap();
+        circuit.targets.signature.set(&mut pw, &forged).unwrap();
+        let proved = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            circuit.circuit_data.prove(pw)
+        }));
+        assert!(!matches!(proved, Ok(Ok(_))));
+
+        system
+            .generate_proof(&old_state, &new_state, 30, &signature)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_loads_circuit_bytes_only_under_pinned_digest() {
+        let system = Plonky2System::shared().unwrap();
+        let bytes = system.circuit_bytes().unwrap();
+
+        let loaded = Plonky2System::from_circuit_bytes(&bytes, &system.circuit_digest()).unwrap();
+        assert_eq!(loaded.circuit_digest(), system.circuit_digest());
+        assert!(Plonky2System::from_circuit_bytes(&bytes, &[0u8; 32]).is_err());
+    }
+}
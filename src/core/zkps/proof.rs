@@ -1,23 +1,101 @@
+// ./src/core/zkps/proof.rs
+
+// Proof Envelope and Verifier Registry
+// A `ZkProof` carries the type of statement it proves and the digest of the circuit that
+// produced it. `ProofVerifier` maps circuit digests to the proof type they are registered for
+// and their verifier data, and verifies a proof only against the circuit its digest names:
+// unknown digests and digests registered for another proof type are rejected before any
+// verification work is done.
+//
+// Balance transfers and closures have proof types but no circuit yet: they cannot be
+// registered, and proofs claiming them are rejected with `UnsupportedProofType`.
+//
+// `merkle_root` is the root the proof commits to and must match the corresponding public
+// inputs: the new state commitment for (confidential) state transitions, the tree root for
+// Merkle inclusion, the new root for tree transitions, and the covered digest for
+// aggregates. Aggregates must also carry the key of the aggregation circuit they are
+// verified against.
+
 use crate::core::error::errors::{SystemError, SystemErrorType};
-use crate::core::zkps::plonky2::Plonky2SystemHandle;
-use plonky2::hash::hash_types::RichField;
-use plonky2::plonk::circuit_data::CircuitConfig;
+use crate::core::smt::hasher::PoseidonHasher;
+use crate::core::zkps::aggregation::{check_aggregate_key, AggregateProof, ProofAggregator};
+use crate::core::zkps::confidential::{ConfidentialProof, ConfidentialTransitionCircuit};
+use crate::core::zkps::merkle_inclusion::{InclusionProof, MerkleInclusionCircuit};
+use crate::core::zkps::plonky2::{Plonky2System, Plonky2SystemHandle};
+use crate::core::zkps::state_commitment::CommittedChannelState;
+use crate::core::zkps::tree_transition::{TransitionProof, TreeTransitionCircuit};
+use plonky2::field::goldilocks_field::GoldilocksField;
+use plonky2::field::types::PrimeField64;
+use plonky2::hash::hash_types::HashOut;
+use plonky2::plonk::circuit_data::VerifierCircuitData;
+use plonky2::plonk::config::PoseidonGoldilocksConfig;
+use plonky2::plonk::proof::ProofWithPublicInputs;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use wasm_bindgen::prelude::*;
 
+const D: usize = 2;
+type F = GoldilocksField;
+type C = PoseidonGoldilocksConfig;
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ZkProof {
+    pub proof_type: ProofType,
+    /// Digest of the circuit that produced `proof_data`.
+    pub circuit_digest: [u8; 32],
     pub proof_data: Vec<u8>,
     pub public_inputs: Vec<u64>,
     pub merkle_root: Vec<u8>,
     pub timestamp: u64,
 }
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[repr(u8)]
 pub enum ProofType {
     StateTransition = 0,
+    /// Reserved: no circuit proves balance transfers.
     BalanceTransfer = 1,
     MerkleInclusion = 2,
+    /// Reserved: no circuit proves channel closures.
+    Closure = 3,
+    Aggregate = 4,
+    ConfidentialTransition = 5,
+    /// A hierarchy tree moving from one root to another, such as a wallet's channel tree.
+    TreeTransition = 6,
+}
+
+impl TryFrom<u8> for ProofType {
+    type Error = &'static str;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(ProofType::StateTransition),
+            1 => Ok(ProofType::BalanceTransfer),
+            2 => Ok(ProofType::MerkleInclusion),
+            3 => Ok(ProofType::Closure),
+            4 => Ok(ProofType::Aggregate),
+            5 => Ok(ProofType::ConfidentialTransition),
+            6 => Ok(ProofType::TreeTransition),
+            _ => Err("Invalid proof type"),
+        }
+    }
+}
+
+impl ProofType {
+    /// Whether a circuit exists for this proof type.
+    pub fn has_circuit(&self) -> bool {
+        !matches!(self, ProofType::BalanceTransfer | ProofType::Closure)
+    }
+
+    /// Position of the four public inputs `merkle_root` must match, for types that have one.
+    fn root_offset(&self) -> Option<usize> {
+        match self {
+            ProofType::StateTransition => Some(12),
+            ProofType::ConfidentialTransition | ProofType::TreeTransition => Some(4),
+            ProofType::MerkleInclusion | ProofType::Aggregate => Some(0),
+            ProofType::BalanceTransfer | ProofType::Closure => None,
+        }
+    }
 }
 
 // Proof metadata for tracking context
@@ -36,98 +114,172 @@ pub struct ProofBundle {
     pub metadata: ProofMetadata,
 }
 
-// Circuit verifier
-pub struct ProofVerifier<F: RichField> {
-    config: CircuitConfig,
-    _marker: std::marker::PhantomData<F>,
+struct RegisteredCircuit {
+    proof_type: ProofType,
+    verifier: VerifierCircuitData<F, C, D>,
 }
 
-impl<F: RichField> ProofVerifier<F> {
-    pub fn new(config: CircuitConfig) -> Self {
-        Self {
-            config,
-            _marker: std::marker::PhantomData,
-        }
+/// Registry of the circuits proofs are verified against, keyed by circuit digest.
+#[derive(Default)]
+pub struct ProofVerifier {
+    circuits: HashMap<[u8; 32], RegisteredCircuit>,
+}
+
+impl ProofVerifier {
+    /// A verifier with no circuits registered.
+    pub fn new() -> Self {
+        Self::default()
     }
 
-    pub fn verify(&self, proof: &ZkProof) -> Result<bool, SystemError> {
-        // Basic proof validation
-        proof.verify_internally()?;
+    /// A verifier for the circuits built into the system: state transition, confidential
+    /// transition, Merkle inclusion and the shared tree transition. Aggregation circuits
+    /// depend on the circuits they fold and are added with `register_aggregator`.
+    pub fn with_system_circuits() -> Result<Self, SystemError> {
+        let state_transition = Plonky2System::shared()
+            .map_err(|e| SystemError::new(SystemErrorType::InvalidProof, e.to_string()))?;
+        let mut verifier = Self::new();
+        verifier.register(
+            ProofType::StateTransition,
+            state_transition.state_transition_verifier_data(),
+        )?;
+        verifier.register(
+            ProofType::ConfidentialTransition,
+            ConfidentialTransitionCircuit::shared()
+                .circuit_data()
+                .verifier_data(),
+        )?;
+        verifier.register(
+            ProofType::MerkleInclusion,
+            MerkleInclusionCircuit::shared()
+                .circuit_data()
+                .verifier_data(),
+        )?;
+        verifier.register(
+            ProofType::TreeTransition,
+            TreeTransitionCircuit::shared()
+                .circuit_data()
+                .verifier_data(),
+        )?;
+        Ok(verifier)
+    }
 
-        // Type-specific verification
-        match ProofType::StateTransition {
-            ProofType::StateTransition => self.verify_state_transition(proof),
-            ProofType::BalanceTransfer => self.verify_balance_transfer(proof),
-            ProofType::MerkleInclusion => self.verify_merkle_inclusion(proof),
+    /// Registers a circuit for proofs of `proof_type` and returns its digest. A digest can only
+    /// be registered for one proof type.
+    pub fn register(
+        &mut self,
+        proof_type: ProofType,
+        verifier: VerifierCircuitData<F, C, D>,
+    ) -> Result<[u8; 32], SystemError> {
+        check_supported(proof_type)?;
+        let digest = PoseidonHasher::from_hash_out(verifier.verifier_only.circuit_digest);
+        if let Some(existing) = self.circuits.get(&digest) {
+            if existing.proof_type != proof_type {
+                return Err(SystemError::new(
+                    SystemErrorType::InvalidOperation,
+                    format!(
+                        "Circuit {} is already registered for {:?} proofs",
+                        hex::encode(digest),
+                        existing.proof_type
+                    ),
+                ));
+            }
+            return Ok(digest);
         }
+        self.circuits.insert(
+            digest,
+            RegisteredCircuit {
+                proof_type,
+                verifier,
+            },
+        );
+        Ok(digest)
     }
 
-    fn verify_state_transition(&self, proof: &ZkProof) -> Result<bool, SystemError> {
-        // Validate state transition constraints
-        if proof.public_inputs.len() < 3 {
-            return Err(SystemError::new(
-                SystemErrorType::InvalidTransaction,
-                "State transition proof requires at least 3 public inputs".to_string(),
-            ));
-        }
+    /// Registers the aggregation circuit of `aggregator`, building it if needed, and returns
+    /// its digest.
+    pub fn register_aggregator(
+        &mut self,
+        aggregator: &mut ProofAggregator,
+    ) -> Result<[u8; 32], SystemError> {
+        self.register(
+            ProofType::Aggregate,
+            aggregator.aggregation_verifier_data()?,
+        )
+    }
 
-        // Extract values
-        let old_balance = proof.public_inputs[0];
-        let new_balance = proof.public_inputs[1];
-        let amount = proof.public_inputs[2];
+    /// The proof type a circuit digest is registered for.
+    pub fn proof_type(&self, circuit_digest: &[u8; 32]) -> Option<ProofType> {
+        self.circuits
+            .get(circuit_digest)
+            .map(|circuit| circuit.proof_type)
+    }
 
-        // Verify basic constraints
-        if new_balance > old_balance {
-            return Err(SystemError::new(
-                SystemErrorType::InvalidAmount,
-                "New balance cannot exceed old balance".to_string(),
-            ));
-        }
+    /// Verifies the proof against the circuit named by its digest. The circuit must be
+    /// registered for the proof's type, the proof's public inputs must be the ones it claims,
+    /// and `merkle_root` must be the root those inputs commit to.
+    pub fn verify(&self, proof: &ZkProof) -> Result<bool, SystemError> {
+        check_supported(proof.proof_type)?;
+        proof.verify_internally()?;
 
-        if old_balance - new_balance != amount {
+        let circuit = self.circuits.get(&proof.circuit_digest).ok_or_else(|| {
+            SystemError::new(
+                SystemErrorType::NotFound,
+                format!(
+                    "No circuit registered with digest {} for {:?} proofs",
+                    hex::encode(proof.circuit_digest),
+                    proof.proof_type
+                ),
+            )
+        })?;
+        if circuit.proof_type != proof.proof_type {
             return Err(SystemError::new(
-                SystemErrorType::InvalidAmount,
-                "Balance difference must equal transfer amount".to_string(),
+                SystemErrorType::InvalidProof,
+                format!(
+                    "Circuit {} is registered for {:?} proofs, not {:?}",
+                    hex::encode(proof.circuit_digest),
+                    circuit.proof_type,
+                    proof.proof_type
+                ),
             ));
         }
 
-        Ok(true)
-    }
-
-    fn verify_balance_transfer(&self, proof: &ZkProof) -> Result<bool, SystemError> {
-        // Validate balance transfer constraints
-        if proof.public_inputs.len() < 4 {
-            return Err(SystemError::new(
-                SystemErrorType::InvalidTransaction,
-                "Balance transfer proof requires at least 4 public inputs".to_string(),
-            ));
+        let plonky2_proof = ProofWithPublicInputs::<F, C, D>::from_bytes(
+            proof.proof_data.clone(),
+            &circuit.verifier.common,
+        )
+        .map_err(|e| SystemError::new(SystemErrorType::InvalidProof, e.to_string()))?;
+        if proof.proof_type == ProofType::Aggregate {
+            check_aggregate_key(
+                &plonky2_proof.public_inputs,
+                &circuit.verifier.verifier_only,
+            )?;
         }
-
-        // Extract values
-        let sender_balance = proof.public_inputs[0];
-        let amount = proof.public_inputs[2];
-        let fee = proof.public_inputs[3];
-
-        // Verify transfer constraints
-        if amount + fee > sender_balance {
+        if canonical_inputs(&plonky2_proof) != proof.public_inputs {
             return Err(SystemError::new(
-                SystemErrorType::InsufficientBalance,
-                "Insufficient balance for transfer and fee".to_string(),
+                SystemErrorType::InvalidProof,
+                "Public inputs do not match the proof".to_string(),
             ));
         }
-
-        Ok(true)
-    }
-
-    fn verify_merkle_inclusion(&self, proof: &ZkProof) -> Result<bool, SystemError> {
-        // Validate Merkle inclusion proof
-        if proof.public_inputs.is_empty() || proof.merkle_root.len() != 32 {
-            return Err(SystemError::new(
-                SystemErrorType::InvalidTransaction,
-                "Invalid Merkle inclusion proof structure".to_string(),
-            ));
+        if let Some(offset) = proof.proof_type.root_offset() {
+            let root = plonky2_proof
+                .public_inputs
+                .get(offset..offset + 4)
+                .map(|inputs| PoseidonHasher::from_hash_out(HashOut::from_partial(inputs)));
+            if root.as_ref().map(|root| root.as_slice()) != Some(proof.merkle_root.as_slice()) {
+                return Err(SystemError::new(
+                    SystemErrorType::InvalidProof,
+                    format!(
+                        "Merkle root does not match the {:?} proof",
+                        proof.proof_type
+                    ),
+                ));
+            }
         }
 
+        circuit
+            .verifier
+            .verify(plonky2_proof)
+            .map_err(|e| SystemError::new(SystemErrorType::InvalidProof, e.to_string()))?;
         Ok(true)
     }
 }
@@ -145,40 +297,41 @@ impl ProofGenerator {
         Ok(ProofGenerator { plonky2_system })
     }
 
+    /// Proves a transfer between two serialized `CommittedChannelState`s of one channel,
+    /// authorized by a serialized `StateSignature` over the new state's commitment.
     pub fn generate_state_transition_proof(
         &self,
-        old_balance: u64,
-        new_balance: u64,
+        old_state: &[u8],
+        new_state: &[u8],
         amount: u64,
-        channel_id: Option<Box<[u8]>>,
+        signature: &[u8],
     ) -> Result<JsValue, JsValue> {
-        // Generate proof using Plonky2
-        let proof_bytes = self.plonky2_system.generate_proof_js(
-            old_balance,
-            0, // nonce
-            new_balance,
-            1, // new nonce
-            amount,
-        )?;
+        let decode = |bytes: &[u8]| {
+            CommittedChannelState::from_bytes(bytes).map_err(|e| JsValue::from_str(&e.to_string()))
+        };
+        let (old, new) = (decode(old_state)?, decode(new_state)?);
 
-        // Convert channel_id if provided
-        let channel_id_array = channel_id.map(|bytes| {
-            let mut array = [0u8; 32];
-            array.copy_from_slice(&bytes[..32]);
-            array
-        });
+        // Generate proof using Plonky2
+        let proof_bytes = self
+            .plonky2_system
+            .generate_proof_js(old_state, new_state, amount, signature)?;
+        let system = self.plonky2_system.system();
+        let proof = system
+            .proof_from_bytes(&proof_bytes)
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
 
         // Create proof bundle
         let bundle = ProofBundle {
-            proof: ZkProof {
-                proof_data: proof_bytes,
-                public_inputs: vec![old_balance, new_balance, amount],
-                merkle_root: vec![0; 32],
-                timestamp: current_timestamp(),
-            },
+            proof: ZkProof::from_plonky2(
+                ProofType::StateTransition,
+                system.circuit_digest(),
+                &proof,
+                new.commitment().to_vec(),
+                current_timestamp(),
+            ),
             metadata: ProofMetadata {
                 proof_type: ProofType::StateTransition,
-                channel_id: channel_id_array,
+                channel_id: Some(old.channel_id),
                 created_at: current_timestamp(),
                 verified_at: None,
             },
@@ -191,8 +344,8 @@ impl ProofGenerator {
     pub fn verify_state_transition(
         &self,
         bundle_js: &JsValue,
-        old_balance: u64,
-        new_balance: u64,
+        old_state: &[u8],
+        new_state: &[u8],
         amount: u64,
     ) -> Result<bool, JsValue> {
         // Deserialize proof bundle
@@ -206,26 +359,114 @@ impl ProofGenerator {
             return Ok(false);
         }
 
-        // Verify the inputs match
-        let claimed_inputs = vec![old_balance, new_balance, amount];
-        if bundle.proof.public_inputs != claimed_inputs {
+        // Verify the proof is for the state transition circuit
+        if bundle.proof.circuit_digest != self.plonky2_system.system().circuit_digest() {
             return Ok(false);
         }
 
-        // Verify the proof itself
-        self.plonky2_system
-            .verify_proof_js(&bundle.proof.proof_data)
+        // Verify the proof was made for exactly these states
+        let decode = |bytes: &[u8]| {
+            CommittedChannelState::from_bytes(bytes).map_err(|e| JsValue::from_str(&e.to_string()))
+        };
+        let (old, new) = (decode(old_state)?, decode(new_state)?);
+        Ok(self
+            .plonky2_system
+            .system()
+            .verify_transition(&bundle.proof.proof_data, &old, &new, amount)
+            .is_ok())
     }
 }
 
 impl ZkProof {
+    /// Wraps a plonky2 proof of the circuit with `circuit_digest`; the public inputs are the
+    /// proof's own.
+    pub fn from_plonky2(
+        proof_type: ProofType,
+        circuit_digest: [u8; 32],
+        proof: &ProofWithPublicInputs<F, C, D>,
+        merkle_root: Vec<u8>,
+        timestamp: u64,
+    ) -> Self {
+        Self {
+            proof_type,
+            circuit_digest,
+            proof_data: proof.to_bytes(),
+            public_inputs: canonical_inputs(proof),
+            merkle_root,
+            timestamp,
+        }
+    }
+
+    /// Wraps an inclusion proof; the root is its Merkle root.
+    pub fn from_inclusion(
+        proof: &InclusionProof,
+        circuit_digest: [u8; 32],
+        timestamp: u64,
+    ) -> Self {
+        Self::from_plonky2(
+            ProofType::MerkleInclusion,
+            circuit_digest,
+            &proof.proof,
+            proof.root.to_vec(),
+            timestamp,
+        )
+    }
+
+    /// Wraps a confidential transition proof; the root is the new state commitment.
+    pub fn from_confidential(
+        proof: &ConfidentialProof,
+        circuit_digest: [u8; 32],
+        timestamp: u64,
+    ) -> Self {
+        Self::from_plonky2(
+            ProofType::ConfidentialTransition,
+            circuit_digest,
+            &proof.proof,
+            proof.commitments.new_state.to_vec(),
+            timestamp,
+        )
+    }
+
+    /// Wraps a tree transition proof; the root is the tree's new root.
+    pub fn from_tree_transition(
+        proof: &TransitionProof,
+        circuit_digest: [u8; 32],
+        timestamp: u64,
+    ) -> Self {
+        Self::from_plonky2(
+            ProofType::TreeTransition,
+            circuit_digest,
+            &proof.proof,
+            proof.new_root.to_vec(),
+            timestamp,
+        )
+    }
+
+    /// Wraps an aggregate of two or more proofs; the root is the digest of the public inputs
+    /// it covers.
+    pub fn from_aggregate(proof: &AggregateProof, timestamp: u64) -> Self {
+        let digest =
+            PoseidonHasher::from_hash_out(HashOut::from_partial(&proof.proof.public_inputs[..4]));
+        Self::from_plonky2(
+            ProofType::Aggregate,
+            proof.circuit,
+            &proof.proof,
+            digest.to_vec(),
+            timestamp,
+        )
+    }
+
     pub fn new(
+        proof_type: ProofType,
+        circuit_digest: [u8; 32],
         proof_data: Vec<u8>,
         public_inputs: Vec<u64>,
         merkle_root: Vec<u8>,
         timestamp: u64,
     ) -> Self {
         Self {
+            proof_type,
+            circuit_digest,
             proof_data,
             public_inputs,
             merkle_root,
@@ -262,6 +503,24 @@ impl ZkProof {
     }
 }
 
+fn check_supported(proof_type: ProofType) -> Result<(), SystemError> {
+    if !proof_type.has_circuit() {
+        return Err(SystemError::new(
+            SystemErrorType::UnsupportedProofType,
+            format!("No circuit exists for {:?} proofs", proof_type),
+        ));
+    }
+    Ok(())
+}
+
+fn canonical_inputs(proof: &ProofWithPublicInputs<F, C, D>) -> Vec<u64> {
+    proof
+        .public_inputs
+        .iter()
+        .map(|input| input.to_canonical_u64())
+        .collect()
+}
+
 // Helper function for timestamp
 fn current_timestamp() -> u64 {
     use std::time::{SystemTime, UNIX_EPOCH};
@@ -274,23 +533,34 @@ fn current_timestamp() -> u64 {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::core::zkps::signature::{StateKeyChain, StateSignature};
+
+    /// Serialized old and new states and the signature authorizing the transfer.
+    fn states(old_balance: u64, amount: u64) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+        let keys = StateKeyChain::new([3u8; 32]);
+        let old = CommittedChannelState {
+            channel_id: [3u8; 32],
+            balance: old_balance,
+            auth_key: keys.auth_key(0),
+            ..Default::default()
+        };
+        let (new, signature) = keys.transfer(&old, amount, [0u8; 32]).unwrap();
+        (old.to_bytes(), new.to_bytes(), signature.to_bytes())
+    }
 
     #[test]
     fn test_proof_generation_and_verification() {
         let generator = ProofGenerator::try_new().unwrap();
-
-        let old_balance = 1000;
-        let amount = 100;
-        let new_balance = 900;
+        let (old_state, new_state, signature) = states(1000, 100);
 
         // Generate proof
         let bundle_js = generator
-            .generate_state_transition_proof(old_balance, new_balance, amount, None)
+            .generate_state_transition_proof(&old_state, &new_state, 100, &signature)
             .unwrap();
 
         // Verify proof
         let is_valid = generator
-            .verify_state_transition(&bundle_js, old_balance, new_balance, amount)
+            .verify_state_transition(&bundle_js, &old_state, &new_state, 100)
             .unwrap();
 
         assert!(is_valid);
@@ -299,21 +569,100 @@ mod tests {
     #[test]
     fn test_proof_verification_constraints() {
         let generator = ProofGenerator::try_new().unwrap();
-
-        // Test invalid balance transition
+        let (old_state, new_state, signature) = states(1000, 100);
         let bundle_js = generator
-            .generate_state_transition_proof(
-                1000, // old_balance
-                950,  // new_balance
-                100,  // amount (doesn't match balance difference)
-                None,
-            )
+            .generate_state_transition_proof(&old_state, &new_state, 100, &signature)
             .unwrap();
 
+        // A valid proof does not verify a different transition
+        let (other_old, other_new, _) = states(1000, 50);
         let is_valid = generator
-            .verify_state_transition(&bundle_js, 1000, 950, 100)
+            .verify_state_transition(&bundle_js, &other_old, &other_new, 50)
             .unwrap();
 
         assert!(!is_valid);
+
+        // An inconsistent transition: 1000 -> 950 is not a transfer of 100
+        let old = CommittedChannelState::from_bytes(&old_state).unwrap();
+        let inconsistent = CommittedChannelState {
+            balance: 950,
+            ..CommittedChannelState::from_bytes(&new_state).unwrap()
+        };
+        let signature = StateKeyChain::new([3u8; 32])
+            .key(old.nonce)
+            .sign(&inconsistent.commitment());
+        assert!(generator
+            .plonky2_system
+            .system()
+            .generate_proof(&old, &inconsistent, 100, &signature)
+            .is_err());
+    }
+
+    #[test]
+    fn test_registry_checks_type_and_digest() {
+        let verifier = ProofVerifier::with_system_circuits().unwrap();
+        let system = Plonky2System::shared().unwrap();
+        let (old_state, new_state, signature) = states(1000, 100);
+        let old = CommittedChannelState::from_bytes(&old_state).unwrap();
+        let new = CommittedChannelState::from_bytes(&new_state).unwrap();
+        let signature = StateSignature::from_bytes(&signature).unwrap();
+        let proof_bytes = system.generate_proof(&old, &new, 100, &signature).unwrap();
+        let proof = ZkProof::from_plonky2(
+            ProofType::StateTransition,
+            system.circuit_digest(),
+            &system.proof_from_bytes(&proof_bytes).unwrap(),
+            new.commitment().to_vec(),
+            42,
+        );
+        assert!(verifier.verify(&proof).unwrap());
+        assert_eq!(
+            verifier.proof_type(&system.circuit_digest()),
+            Some(ProofType::StateTransition)
+        );
+
+        let mut mistyped = proof.clone();
+        mistyped.proof_type = ProofType::MerkleInclusion;
+        assert!(verifier.verify(&mistyped).is_err());
+
+        let mut unknown = proof.clone();
+        unknown.circuit_digest = [0u8; 32];
+        assert!(verifier.verify(&unknown).is_err());
+
+        let mut rerooted = proof.clone();
+        rerooted.merkle_root = old.commitment().to_vec();
+        assert!(verifier.verify(&rerooted).is_err());
+
+        let mut relabelled = proof.clone();
+        relabelled.public_inputs[0] += 1;
+        assert!(verifier.verify(&relabelled).is_err());
+
+        assert!(ProofVerifier::new().verify(&proof).is_err());
+    }
+
+    #[test]
+    fn test_rejects_proof_types_without_circuit() {
+        let mut verifier = ProofVerifier::with_system_circuits().unwrap();
+        let system = Plonky2System::shared().unwrap();
+        for proof_type in [ProofType::BalanceTransfer, ProofType::Closure] {
+            assert!(!proof_type.has_circuit());
+            let registered = verifier.register(proof_type, system.state_transition_verifier_data());
+            assert_eq!(
+                registered.unwrap_err().error_type(),
+                SystemErrorType::UnsupportedProofType
+            );
+
+            let proof = ZkProof::new(
+                proof_type,
+                system.circuit_digest(),
+                vec![1u8; 32],
+                vec![1],
+                vec![0u8; 32],
+                42,
+            );
+            assert_eq!(
+                verifier.verify(&proof).unwrap_err().error_type(),
+                SystemErrorType::UnsupportedProofType
+            );
+        }
     }
 }
@@ -0,0 +1,698 @@
+// ./src/core/zkps/prover_queue.rs
+
+// Prover Queue
+// Runs proving jobs off the caller's path. Jobs wait in a bounded queue split into priority
+// lanes; disputes are always taken before settlements, and settlements before routine
+// transfers, with submission order kept within a lane. Submitting returns a `JobHandle` that
+// can be waited on, awaited as a future, polled for its status, or cancelled.
+//
+// Native builds run a pool of worker threads. With zero workers, which is always the case on
+// wasm, jobs run on the caller's thread when a handle is waited on or polled, or when
+// `run_pending` is called, still in priority order; the API is the same either way.
+//
+// A job's timeout counts from submission. A job still queued at its deadline is never run; a
+// running job cannot be interrupted, so one that finishes past its deadline resolves as timed
+// out and its proof is discarded. Cancelling a running job works the same way.
+
+use crate::core::error::errors::{SystemError, SystemErrorType};
+use crate::core::zkps::plonky2::Plonky2System;
+use crate::core::zkps::signature::StateSignature;
+use crate::core::zkps::state_commitment::CommittedChannelState;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+/// Priority lanes, highest first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum JobPriority {
+    Dispute = 0,
+    Settlement = 1,
+    Normal = 2,
+}
+
+const LANES: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+    TimedOut,
+}
+
+impl JobStatus {
+    fn is_finished(&self) -> bool {
+        !matches!(self, JobStatus::Queued | JobStatus::Running)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum ProverError {
+    QueueFull,
+    ShutDown,
+    Cancelled,
+    TimedOut,
+    Failed(SystemError),
+}
+
+impl std::fmt::Display for ProverError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ProverError::QueueFull => write!(f, "Prover queue is full"),
+            ProverError::ShutDown => write!(f, "Prover queue has shut down"),
+            ProverError::Cancelled => write!(f, "Proving job was cancelled"),
+            ProverError::TimedOut => write!(f, "Proving job timed out"),
+            ProverError::Failed(e) => write!(f, "Proving failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ProverError {}
+
+pub type JobResult = Result<Vec<u8>, ProverError>;
+
+#[derive(Clone, Debug)]
+pub struct ProverQueueConfig {
+    /// Jobs that may wait across all lanes.
+    pub capacity: usize,
+    /// Worker threads; zero runs jobs on the caller's thread. Ignored on wasm.
+    pub workers: usize,
+    /// Timeout for jobs submitted without one.
+    pub default_timeout: Option<Duration>,
+}
+
+impl Default for ProverQueueConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 64,
+            workers: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            default_timeout: None,
+        }
+    }
+}
+
+/// A snapshot of the queue's counters.
+#[derive(Clone, Debug, Default)]
+pub struct ProverMetrics {
+    /// Jobs waiting in each lane, highest priority first.
+    pub queued: [usize; LANES],
+    pub running: usize,
+    pub completed: u64,
+    pub failed: u64,
+    pub cancelled: u64,
+    pub timed_out: u64,
+    /// Time spent proving jobs that completed.
+    pub proving_time: Duration,
+}
+
+impl ProverMetrics {
+    pub fn average_proving_time(&self) -> Option<Duration> {
+        (self.completed > 0).then(|| self.proving_time / self.completed as u32)
+    }
+}
+
+type ProveFn = Box<dyn FnOnce() -> Result<Vec<u8>, SystemError> + Send>;
+
+struct JobSlot {
+    status: JobStatus,
+    outcome: Option<JobResult>,
+    waker: Option<Waker>,
+}
+
+struct JobState {
+    id: u64,
+    deadline: Option<Duration>,
+    slot: Mutex<JobSlot>,
+    finished: Condvar,
+}
+
+impl JobState {
+    fn status(&self) -> JobStatus {
+        self.slot.lock().unwrap().status
+    }
+
+    fn outcome(&self) -> Option<JobResult> {
+        self.slot.lock().unwrap().outcome.clone()
+    }
+
+    fn is_expired(&self) -> bool {
+        self.deadline.is_some_and(|deadline| now() >= deadline)
+    }
+
+    /// Moves a queued job to running; false if it already finished.
+    fn start(&self) -> bool {
+        let mut slot = self.slot.lock().unwrap();
+        if slot.status.is_finished() {
+            return false;
+        }
+        slot.status = JobStatus::Running;
+        true
+    }
+
+    /// Records the job's outcome unless it already has one. Returns whether it was recorded.
+    fn finish(&self, status: JobStatus, outcome: JobResult) -> bool {
+        let mut slot = self.slot.lock().unwrap();
+        if slot.status.is_finished() {
+            return false;
+        }
+        slot.status = status;
+        slot.outcome = Some(outcome);
+        if let Some(waker) = slot.waker.take() {
+            waker.wake();
+        }
+        self.finished.notify_all();
+        true
+    }
+}
+
+struct Job {
+    state: Arc<JobState>,
+    prove: ProveFn,
+}
+
+struct Lanes {
+    lanes: [VecDeque<Job>; LANES],
+    shut_down: bool,
+}
+
+impl Lanes {
+    fn len(&self) -> usize {
+        self.lanes.iter().map(VecDeque::len).sum()
+    }
+
+    fn pop(&mut self) -> Option<Job> {
+        self.lanes.iter_mut().find_map(VecDeque::pop_front)
+    }
+
+    fn remove(&mut self, id: u64) -> bool {
+        for lane in &mut self.lanes {
+            if let Some(index) = lane.iter().position(|job| job.state.id == id) {
+                lane.remove(index);
+                return true;
+            }
+        }
+        false
+    }
+}
+
+struct Shared {
+    capacity: usize,
+    inline: bool,
+    lanes: Mutex<Lanes>,
+    available: Condvar,
+    metrics: Mutex<ProverMetrics>,
+    next_id: AtomicU64,
+}
+
+impl Shared {
+    /// Takes the next job, waiting for one; `None` once the queue shuts down.
+    fn next_job_blocking(&self) -> Option<Job> {
+        let mut lanes = self.lanes.lock().unwrap();
+        loop {
+            if let Some(job) = lanes.pop() {
+                return Some(job);
+            }
+            if lanes.shut_down {
+                return None;
+            }
+            lanes = self.available.wait(lanes).unwrap();
+        }
+    }
+
+    /// Runs the next queued job on this thread. Returns false if the queue is empty.
+    fn run_next(&self) -> bool {
+        let job = self.lanes.lock().unwrap().pop();
+        match job {
+            Some(job) => {
+                self.run(job);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn run(&self, job: Job) {
+        let state = job.state;
+        if state.is_expired() {
+            self.finish(&state, JobStatus::TimedOut, Err(ProverError::TimedOut));
+            return;
+        }
+        if !state.start() {
+            return;
+        }
+
+        self.metrics.lock().unwrap().running += 1;
+        let started = now();
+        let result = catch_unwind(AssertUnwindSafe(job.prove));
+        let elapsed = now().saturating_sub(started);
+        self.metrics.lock().unwrap().running -= 1;
+
+        let (status, outcome) = match result {
+            _ if state.is_expired() => (JobStatus::TimedOut, Err(ProverError::TimedOut)),
+            Ok(Ok(proof)) => (JobStatus::Completed, Ok(proof)),
+            Ok(Err(e)) => (JobStatus::Failed, Err(ProverError::Failed(e))),
+            Err(_) => (
+                JobStatus::Failed,
+                Err(ProverError::Failed(SystemError::new(
+                    SystemErrorType::InvalidProof,
+                    "Prover panicked; the witness does not satisfy the circuit".to_string(),
+                ))),
+            ),
+        };
+        if self.finish(&state, status, outcome) && status == JobStatus::Completed {
+            self.metrics.lock().unwrap().proving_time += elapsed;
+        }
+    }
+
+    fn finish(&self, state: &JobState, status: JobStatus, outcome: JobResult) -> bool {
+        if !state.finish(status, outcome) {
+            return false;
+        }
+        let mut metrics = self.metrics.lock().unwrap();
+        match status {
+            JobStatus::Completed => metrics.completed += 1,
+            JobStatus::Failed => metrics.failed += 1,
+            JobStatus::Cancelled => metrics.cancelled += 1,
+            JobStatus::TimedOut => metrics.timed_out += 1,
+            JobStatus::Queued | JobStatus::Running => {}
+        }
+        true
+    }
+
+    fn cancel(&self, state: &JobState) -> bool {
+        self.lanes.lock().unwrap().remove(state.id);
+        self.finish(state, JobStatus::Cancelled, Err(ProverError::Cancelled))
+    }
+}
+
+/// A bounded, prioritized queue of proving jobs.
+pub struct ProverQueue {
+    shared: Arc<Shared>,
+    workers: Vec<std::thread::JoinHandle<()>>,
+    default_timeout: Option<Duration>,
+}
+
+impl ProverQueue {
+    pub fn new(config: ProverQueueConfig) -> Self {
+        let workers = if cfg!(target_arch = "wasm32") {
+            0
+        } else {
+            config.workers
+        };
+        let shared = Arc::new(Shared {
+            capacity: config.capacity,
+            inline: workers == 0,
+            lanes: Mutex::new(Lanes {
+                lanes: Default::default(),
+                shut_down: false,
+            }),
+            available: Condvar::new(),
+            metrics: Mutex::new(ProverMetrics::default()),
+            next_id: AtomicU64::new(0),
+        });
+        let workers = (0..workers)
+            .map(|_| {
+                let shared = Arc::clone(&shared);
+                std::thread::spawn(move || {
+                    while let Some(job) = shared.next_job_blocking() {
+                        shared.run(job);
+                    }
+                })
+            })
+            .collect();
+        Self {
+            shared,
+            workers,
+            default_timeout: config.default_timeout,
+        }
+    }
+
+    /// The process-wide queue with the default configuration, for callers that do not bring
+    /// their own.
+    pub fn shared() -> Arc<ProverQueue> {
+        static QUEUE: OnceLock<Arc<ProverQueue>> = OnceLock::new();
+        Arc::clone(QUEUE.get_or_init(|| Arc::new(ProverQueue::new(ProverQueueConfig::default()))))
+    }
+
+    /// Queues a proving job. `timeout` falls back to the configured default.
+    pub fn submit<P>(
+        &self,
+        priority: JobPriority,
+        timeout: Option<Duration>,
+        prove: P,
+    ) -> Result<JobHandle, ProverError>
+    where
+        P: FnOnce() -> Result<Vec<u8>, SystemError> + Send + 'static,
+    {
+        let timeout = timeout.or(self.default_timeout);
+        let state = Arc::new(JobState {
+            id: self.shared.next_id.fetch_add(1, Ordering::Relaxed),
+            deadline: timeout.map(|timeout| now() + timeout),
+            slot: Mutex::new(JobSlot {
+                status: JobStatus::Queued,
+                outcome: None,
+                waker: None,
+            }),
+            finished: Condvar::new(),
+        });
+
+        let mut lanes = self.shared.lanes.lock().unwrap();
+        if lanes.shut_down {
+            return Err(ProverError::ShutDown);
+        }
+        if lanes.len() >= self.shared.capacity {
+            return Err(ProverError::QueueFull);
+        }
+        lanes.lanes[priority as usize].push_back(Job {
+            state: Arc::clone(&state),
+            prove: Box::new(prove),
+        });
+        drop(lanes);
+        self.shared.available.notify_one();
+
+        Ok(JobHandle {
+            state,
+            shared: Arc::clone(&self.shared),
+        })
+    }
+
+    /// Queues a state transition proof; the job's output is the serialized proof.
+    pub fn submit_state_transition(
+        &self,
+        system: Arc<Plonky2System>,
+        old_state: CommittedChannelState,
+        new_state: CommittedChannelState,
+        transfer_amount: u64,
+        signature: StateSignature,
+        priority: JobPriority,
+    ) -> Result<JobHandle, ProverError> {
+        self.submit(priority, None, move || {
+            system
+                .generate_proof(&old_state, &new_state, transfer_amount, &signature)
+                .map_err(|e| SystemError::new(SystemErrorType::InvalidProof, e.to_string()))
+        })
+    }
+
+    /// Runs queued jobs on this thread until none are left, returning how many ran. This is
+    /// how jobs make progress without workers.
+    pub fn run_pending(&self) -> usize {
+        let mut ran = 0;
+        while self.shared.run_next() {
+            ran += 1;
+        }
+        ran
+    }
+
+    pub fn metrics(&self) -> ProverMetrics {
+        let mut metrics = self.shared.metrics.lock().unwrap().clone();
+        let lanes = self.shared.lanes.lock().unwrap();
+        for (queued, lane) in metrics.queued.iter_mut().zip(&lanes.lanes) {
+            *queued = lane.len();
+        }
+        metrics
+    }
+}
+
+impl Drop for ProverQueue {
+    /// Stops taking jobs, cancels those still queued and waits for running ones.
+    fn drop(&mut self) {
+        let pending: Vec<Job> = {
+            let mut lanes = self.shared.lanes.lock().unwrap();
+            lanes.shut_down = true;
+            lanes
+                .lanes
+                .iter_mut()
+                .flat_map(|lane| lane.drain(..))
+                .collect()
+        };
+        for job in pending {
+            self.shared.finish(
+                &job.state,
+                JobStatus::Cancelled,
+                Err(ProverError::Cancelled),
+            );
+        }
+        self.shared.available.notify_all();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// A submitted job. Await it, or call `wait`, for the proof.
+pub struct JobHandle {
+    state: Arc<JobState>,
+    shared: Arc<Shared>,
+}
+
+impl JobHandle {
+    pub fn id(&self) -> u64 {
+        self.state.id
+    }
+
+    pub fn status(&self) -> JobStatus {
+        self.state.status()
+    }
+
+    /// The outcome, if the job has finished.
+    pub fn try_result(&self) -> Option<JobResult> {
+        self.state.outcome()
+    }
+
+    /// Cancels the job. Returns false if it had already finished.
+    pub fn cancel(&self) -> bool {
+        self.shared.cancel(&self.state)
+    }
+
+    /// Blocks until the job finishes or its deadline passes.
+    pub fn wait(self) -> JobResult {
+        loop {
+            if let Some(outcome) = self.poll_outcome() {
+                return outcome;
+            }
+            let slot = self.state.slot.lock().unwrap();
+            if slot.status.is_finished() {
+                continue;
+            }
+            // Past the deadline a running job still has to finish before it can time out.
+            match self
+                .state
+                .deadline
+                .map(|deadline| deadline.saturating_sub(now()))
+            {
+                Some(remaining) if !remaining.is_zero() => {
+                    drop(self.state.finished.wait_timeout(slot, remaining).unwrap());
+                }
+                _ => drop(self.state.finished.wait(slot).unwrap()),
+            }
+        }
+    }
+
+    /// The outcome if the job has finished or expired. Without workers, runs queued jobs
+    /// ahead of and including this one first.
+    fn poll_outcome(&self) -> Option<JobResult> {
+        if self.shared.inline {
+            while self.state.outcome().is_none() && self.shared.run_next() {}
+        }
+        if let Some(outcome) = self.state.outcome() {
+            return Some(outcome);
+        }
+        if self.state.is_expired() && self.state.status() == JobStatus::Queued {
+            self.shared.lanes.lock().unwrap().remove(self.state.id);
+            self.shared
+                .finish(&self.state, JobStatus::TimedOut, Err(ProverError::TimedOut));
+            return self.state.outcome();
+        }
+        // Still running, possibly on another thread draining an inline queue: its outcome is
+        // recorded when it finishes, so the caller waits for it.
+        None
+    }
+}
+
+impl Future for JobHandle {
+    type Output = JobResult;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<JobResult> {
+        if let Some(outcome) = self.poll_outcome() {
+            return Poll::Ready(outcome);
+        }
+        let mut slot = self.state.slot.lock().unwrap();
+        match &slot.outcome {
+            Some(outcome) => Poll::Ready(outcome.clone()),
+            None => {
+                slot.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Time since a fixed origin, for deadlines.
+#[cfg(not(target_arch = "wasm32"))]
+fn now() -> Duration {
+    static ORIGIN: std::sync::OnceLock<std::time::Instant> = std::sync::OnceLock::new();
+    ORIGIN.get_or_init(std::time::Instant::now).elapsed()
+}
+
+/// Time since a fixed origin, for deadlines. `Instant` is unavailable on wasm.
+#[cfg(target_arch = "wasm32")]
+fn now() -> Duration {
+    Duration::from_secs_f64(js_sys::Date::now() / 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::zkps::signature::StateKeyChain;
+    use std::sync::mpsc;
+
+    fn queue(workers: usize, capacity: usize) -> ProverQueue {
+        ProverQueue::new(ProverQueueConfig {
+            capacity,
+            workers,
+            default_timeout: None,
+        })
+    }
+
+    /// Occupies the queue's single worker until the returned sender is dropped or sent to.
+    fn block_worker(queue: &ProverQueue) -> (mpsc::Sender<()>, JobHandle) {
+        let (release, gate) = mpsc::channel::<()>();
+        let (started_tx, started) = mpsc::channel();
+        let handle = queue
+            .submit(JobPriority::Normal, None, move || {
+                started_tx.send(()).unwrap();
+                let _ = gate.recv();
+                Ok(Vec::new())
+            })
+            .unwrap();
+        started.recv().unwrap();
+        (release, handle)
+    }
+
+    #[test]
+    fn test_disputes_run_first() {
+        let queue = queue(1, 8);
+        let (release, blocker) = block_worker(&queue);
+
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let job = |priority: JobPriority, tag: u8| {
+            let order = Arc::clone(&order);
+            queue
+                .submit(priority, None, move || {
+                    order.lock().unwrap().push(tag);
+                    Ok(vec![tag])
+                })
+                .unwrap()
+        };
+        let normal = job(JobPriority::Normal, 1);
+        let settlement = job(JobPriority::Settlement, 2);
+        let dispute = job(JobPriority::Dispute, 3);
+        assert_eq!(queue.metrics().queued, [1, 1, 1]);
+
+        release.send(()).unwrap();
+        blocker.wait().unwrap();
+        assert_eq!(normal.wait().unwrap(), vec![1]);
+        assert_eq!(futures::executor::block_on(dispute).unwrap(), vec![3]);
+        assert_eq!(settlement.wait().unwrap(), vec![2]);
+        assert_eq!(*order.lock().unwrap(), vec![3, 2, 1]);
+        assert_eq!(queue.metrics().completed, 4);
+    }
+
+    #[test]
+    fn test_capacity_cancellation_and_timeouts() {
+        let queue = queue(1, 2);
+        let (release, _blocker) = block_worker(&queue);
+
+        let cancelled = queue
+            .submit(JobPriority::Normal, None, || Ok(Vec::new()))
+            .unwrap();
+        let expiring = queue
+            .submit(JobPriority::Normal, Some(Duration::from_millis(1)), || {
+                Ok(Vec::new())
+            })
+            .unwrap();
+        assert!(matches!(
+            queue.submit(JobPriority::Dispute, None, || Ok(Vec::new())),
+            Err(ProverError::QueueFull)
+        ));
+
+        assert!(cancelled.cancel());
+        assert_eq!(cancelled.status(), JobStatus::Cancelled);
+        assert!(matches!(cancelled.wait(), Err(ProverError::Cancelled)));
+
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(matches!(expiring.wait(), Err(ProverError::TimedOut)));
+
+        drop(release);
+        let failing = queue
+            .submit(JobPriority::Normal, None, || panic!("unsatisfied witness"))
+            .unwrap();
+        assert!(matches!(failing.wait(), Err(ProverError::Failed(_))));
+
+        let metrics = queue.metrics();
+        assert_eq!(metrics.cancelled, 1);
+        assert_eq!(metrics.timed_out, 1);
+        assert_eq!(metrics.failed, 1);
+    }
+
+    #[test]
+    fn test_inline_wait_on_job_running_elsewhere() {
+        let queue = queue(0, 4);
+        let (release, gate) = mpsc::channel::<()>();
+        let (started_tx, started) = mpsc::channel();
+        let handle = queue
+            .submit(JobPriority::Normal, None, move || {
+                started_tx.send(()).unwrap();
+                let _ = gate.recv();
+                Ok(vec![7])
+            })
+            .unwrap();
+
+        std::thread::scope(|scope| {
+            scope.spawn(|| queue.run_pending());
+            started.recv().unwrap();
+            assert_eq!(handle.status(), JobStatus::Running);
+            scope.spawn(move || {
+                std::thread::sleep(Duration::from_millis(20));
+                release.send(()).unwrap();
+            });
+            assert_eq!(handle.wait().unwrap(), vec![7]);
+        });
+    }
+
+    #[test]
+    fn test_inline_queue_proves_state_transitions() {
+        let queue = queue(0, 4);
+        let system = Arc::new(Plonky2System::shared().unwrap());
+        let keys = StateKeyChain::new([2u8; 32]);
+        let old = CommittedChannelState {
+            channel_id: [2u8; 32],
+            balance: 300,
+            auth_key: keys.auth_key(0),
+            ..Default::default()
+        };
+        let (new, signature) = keys.transfer(&old, 100, [0u8; 32]).unwrap();
+
+        let handle = queue
+            .submit_state_transition(
+                Arc::clone(&system),
+                old,
+                new,
+                100,
+                signature,
+                JobPriority::Dispute,
+            )
+            .unwrap();
+        assert_eq!(handle.status(), JobStatus::Queued);
+        let proof = futures::executor::block_on(handle).unwrap();
+        system.verify_transition(&proof, &old, &new, 100).unwrap();
+        assert_eq!(queue.metrics().completed, 1);
+    }
+}
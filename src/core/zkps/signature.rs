@@ -0,0 +1,323 @@
+// ./src/core/zkps/signature.rs
+
+// State Signatures
+// Hash-based one-time signatures over state commitments. They use nothing but Poseidon, so
+// verifying one in-circuit costs a few hundred Poseidon permutations and no field emulation.
+//
+// A signing key is 256 pairs of secret preimages. Its public key is the Poseidon hash of the
+// hashes of all 512 preimages, in order (bit 0 zero, bit 0 one, bit 1 zero, ...). A message is
+// a 4-element hash, read as 256 bits: element by element, least significant bit first. Signing
+// reveals, for each bit, the preimage the bit selects together with the hash of the other
+// preimage, which is all a verifier needs to rebuild the public key.
+//
+// A key must sign only once: two signatures reveal both preimages for every bit on which the
+// messages differ. Channel states therefore commit to the key that authorizes their
+// successor, and each transition moves to a fresh key. `StateKeyChain` derives the key for
+// each nonce from a wallet seed, so the wallet keeps one secret.
+//
+// The key of a state must be the one derived from its nonce, never a key picked by the caller:
+// a channel moving through keys A -> B -> A would sign twice with A and leak it. Nonces only
+// increase, so nonce-derived keys are never reused within a channel, and `for_channel` gives
+// every channel its own chain so that two channels never share the key of a nonce.
+
+use crate::core::error::errors::{SystemError, SystemErrorType};
+use crate::core::smt::hasher::PoseidonHasher;
+use crate::core::zkps::gadgets::hash::poseidon;
+use crate::core::zkps::gadgets::select::swap_hashes;
+use crate::core::zkps::state_commitment::CommittedChannelState;
+use plonky2::field::goldilocks_field::GoldilocksField;
+use plonky2::field::types::{Field, PrimeField64};
+use plonky2::hash::hash_types::{HashOut, HashOutTarget};
+use plonky2::hash::poseidon::PoseidonHash;
+use plonky2::iop::witness::{PartialWitness, WitnessWrite};
+use plonky2::plonk::circuit_builder::CircuitBuilder;
+use plonky2::plonk::config::Hasher;
+
+const D: usize = 2;
+type F = GoldilocksField;
+
+/// Number of message bits a signature covers.
+pub const MESSAGE_BITS: usize = 256;
+
+/// Length of `StateSignature::to_bytes`.
+pub const SIGNATURE_BYTES: usize = MESSAGE_BITS * 64;
+
+/// A one-time signing key.
+pub struct StateSigningKey {
+    preimages: Vec<[HashOut<F>; 2]>,
+    hashes: Vec<[HashOut<F>; 2]>,
+}
+
+impl StateSigningKey {
+    /// The key with the given index derived from `seed`.
+    pub fn from_seed(seed: &[u8; 32], index: u64) -> Self {
+        let seed = PoseidonHasher::to_hash_out(seed);
+        let preimages: Vec<[HashOut<F>; 2]> = (0..MESSAGE_BITS)
+            .map(|bit| {
+                [0u64, 1].map(|value| {
+                    let mut inputs = seed.elements.to_vec();
+                    inputs.extend([
+                        F::from_noncanonical_u64(index),
+                        F::from_canonical_usize(bit),
+                        F::from_canonical_u64(value),
+                    ]);
+                    PoseidonHash::hash_no_pad(&inputs)
+                })
+            })
+            .collect();
+        let hashes = preimages
+            .iter()
+            .map(|pair| pair.map(|preimage| PoseidonHash::hash_no_pad(&preimage.elements)))
+            .collect();
+        Self { preimages, hashes }
+    }
+
+    /// The public key committed in channel states as their `auth_key`.
+    pub fn public_key(&self) -> [u8; 32] {
+        let inputs: Vec<F> = self
+            .hashes
+            .iter()
+            .flat_map(|pair| pair.iter().flat_map(|hash| hash.elements))
+            .collect();
+        PoseidonHasher::from_hash_out(PoseidonHash::hash_no_pad(&inputs))
+    }
+
+    pub fn sign(&self, message: &[u8; 32]) -> StateSignature {
+        let entries = message_bits(message)
+            .into_iter()
+            .enumerate()
+            .map(|(i, bit)| {
+                let bit = bit as usize;
+                (self.preimages[i][bit], self.hashes[i][1 - bit])
+            })
+            .collect();
+        StateSignature { entries }
+    }
+}
+
+/// A one-time signature: per message bit, the revealed preimage and the hash of the other.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StateSignature {
+    entries: Vec<(HashOut<F>, HashOut<F>)>,
+}
+
+impl StateSignature {
+    /// The public key under which this is a signature of `message`.
+    pub fn signer(&self, message: &[u8; 32]) -> [u8; 32] {
+        let mut inputs = Vec::with_capacity(MESSAGE_BITS * 8);
+        for (bit, (preimage, other)) in message_bits(message).into_iter().zip(&self.entries) {
+            let revealed = PoseidonHash::hash_no_pad(&preimage.elements);
+            let (zero, one) = if bit {
+                (other, &revealed)
+            } else {
+                (&revealed, other)
+            };
+            inputs.extend(zero.elements);
+            inputs.extend(one.elements);
+        }
+        PoseidonHasher::from_hash_out(PoseidonHash::hash_no_pad(&inputs))
+    }
+
+    pub fn verify(&self, public_key: &[u8; 32], message: &[u8; 32]) -> Result<(), SystemError> {
+        if self.signer(message) != *public_key {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidSignature,
+                "State signature does not match the authorization key".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(SIGNATURE_BYTES);
+        for (preimage, other) in &self.entries {
+            bytes.extend_from_slice(&PoseidonHasher::from_hash_out(*preimage));
+            bytes.extend_from_slice(&PoseidonHasher::from_hash_out(*other));
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SystemError> {
+        if bytes.len() != SIGNATURE_BYTES {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidSignature,
+                format!("State signature must be {} bytes", SIGNATURE_BYTES),
+            ));
+        }
+        let hash_at = |offset: usize| {
+            PoseidonHasher::to_hash_out(&bytes[offset..offset + 32].try_into().expect("32 bytes"))
+        };
+        let entries = (0..MESSAGE_BITS)
+            .map(|i| (hash_at(i * 64), hash_at(i * 64 + 32)))
+            .collect();
+        Ok(Self { entries })
+    }
+}
+
+/// Per-nonce signing keys derived from a wallet seed. The state with nonce `n` commits to
+/// key `n`, which signs the state with nonce `n + 1`.
+pub struct StateKeyChain {
+    seed: [u8; 32],
+}
+
+impl StateKeyChain {
+    pub fn new(seed: [u8; 32]) -> Self {
+        Self { seed }
+    }
+
+    /// The chain of one channel. Channels sharing a wallet seed must each use their own.
+    pub fn for_channel(&self, channel_id: &[u8; 32]) -> StateKeyChain {
+        let mut inputs = PoseidonHasher::to_hash_out(&self.seed).elements.to_vec();
+        inputs.extend(PoseidonHasher::to_hash_out(channel_id).elements);
+        Self::new(PoseidonHasher::from_hash_out(PoseidonHash::hash_no_pad(
+            &inputs,
+        )))
+    }
+
+    pub fn key(&self, nonce: u64) -> StateSigningKey {
+        StateSigningKey::from_seed(&self.seed, nonce)
+    }
+
+    /// The `auth_key` of a state with this nonce.
+    pub fn auth_key(&self, nonce: u64) -> [u8; 32] {
+        self.key(nonce).public_key()
+    }
+
+    /// The state after a transfer of `amount` out of `state`, moved to the next key and
+    /// signed by the key `state` commits to.
+    pub fn transfer(
+        &self,
+        state: &CommittedChannelState,
+        amount: u64,
+        lock_root: [u8; 32],
+    ) -> Result<(CommittedChannelState, StateSignature), SystemError> {
+        let key = self.key(state.nonce);
+        if key.public_key() != state.auth_key {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidPublicKey,
+                "State is not authorized by this key chain".to_string(),
+            ));
+        }
+        let next = state
+            .transfer(amount, lock_root)?
+            .with_auth_key(self.auth_key(state.nonce + 1));
+        let signature = key.sign(&next.commitment());
+        Ok((next, signature))
+    }
+}
+
+/// Circuit targets of one state signature.
+#[derive(Clone, Debug)]
+pub struct StateSignatureTargets {
+    entries: Vec<(HashOutTarget, HashOutTarget)>,
+}
+
+impl StateSignatureTargets {
+    pub fn add_virtual(builder: &mut CircuitBuilder<F, D>) -> Self {
+        let entries = (0..MESSAGE_BITS)
+            .map(|_| (builder.add_virtual_hash(), builder.add_virtual_hash()))
+            .collect();
+        Self { entries }
+    }
+
+    /// In-circuit `StateSignature::signer`: the public key under which the witnessed
+    /// signature signs `message`. Callers connect it to the expected key.
+    pub fn signer(
+        &self,
+        builder: &mut CircuitBuilder<F, D>,
+        message: HashOutTarget,
+    ) -> HashOutTarget {
+        // Small elements also split as themselves plus the modulus, but those bits belong to
+        // no canonical message, so no honest signature reveals the preimages they select.
+        let mut bits = Vec::with_capacity(MESSAGE_BITS);
+        for element in message.elements {
+            bits.extend(builder.split_le(element, 64));
+        }
+        let mut inputs = Vec::with_capacity(MESSAGE_BITS * 8);
+        for (bit, (preimage, other)) in bits.into_iter().zip(&self.entries) {
+            let revealed = poseidon(builder, preimage.elements.to_vec());
+            let (zero, one) = swap_hashes(builder, bit, revealed, *other);
+            inputs.extend(zero.elements);
+            inputs.extend(one.elements);
+        }
+        poseidon(builder, inputs)
+    }
+
+    pub fn set(
+        &self,
+        pw: &mut PartialWitness<F>,
+        signature: &StateSignature,
+    ) -> Result<(), SystemError> {
+        for ((preimage_target, other_target), (preimage, other)) in
+            self.entries.iter().zip(&signature.entries)
+        {
+            pw.set_hash_target(*preimage_target, *preimage)
+                .and_then(|_| pw.set_hash_target(*other_target, *other))
+                .map_err(|e| SystemError::new(SystemErrorType::InvalidProof, e.to_string()))?;
+        }
+        Ok(())
+    }
+}
+
+/// The 256 bits a signature covers, matching the circuit's decomposition of the message.
+fn message_bits(message: &[u8; 32]) -> Vec<bool> {
+    PoseidonHasher::to_hash_out(message)
+        .elements
+        .iter()
+        .flat_map(|element| {
+            let value = element.to_canonical_u64();
+            (0..64).map(move |i| (value >> i) & 1 == 1)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signature_verifies_only_its_message_and_key() {
+        let keys = StateKeyChain::new([4u8; 32]);
+        let key = keys.key(0);
+        let message = [9u8; 32];
+        let signature = key.sign(&message);
+
+        signature.verify(&key.public_key(), &message).unwrap();
+        assert!(signature.verify(&key.public_key(), &[8u8; 32]).is_err());
+        assert!(signature.verify(&keys.auth_key(1), &message).is_err());
+        assert_ne!(keys.auth_key(0), StateKeyChain::new([5u8; 32]).auth_key(0));
+
+        let decoded = StateSignature::from_bytes(&signature.to_bytes()).unwrap();
+        assert_eq!(decoded, signature);
+        assert!(StateSignature::from_bytes(&[0u8; 64]).is_err());
+    }
+
+    #[test]
+    fn test_key_chain_rotates_keys_on_transfer() {
+        let keys = StateKeyChain::new([4u8; 32]);
+        let old = CommittedChannelState {
+            balance: 100,
+            nonce: 2,
+            auth_key: keys.auth_key(2),
+            ..Default::default()
+        };
+        let (new, signature) = keys.transfer(&old, 10, [0u8; 32]).unwrap();
+
+        assert_eq!(new.auth_key, keys.auth_key(3));
+        signature.verify(&old.auth_key, &new.commitment()).unwrap();
+        assert!(keys
+            .transfer(&new.with_auth_key([1u8; 32]), 10, [0u8; 32])
+            .is_err());
+    }
+
+    #[test]
+    fn test_channels_get_their_own_key_chains() {
+        let keys = StateKeyChain::new([4u8; 32]);
+        let first = keys.for_channel(&[1u8; 32]);
+        let second = keys.for_channel(&[2u8; 32]);
+
+        assert_ne!(first.auth_key(0), second.auth_key(0));
+        assert_ne!(first.auth_key(0), keys.auth_key(0));
+        assert_eq!(first.auth_key(5), keys.for_channel(&[1u8; 32]).auth_key(5));
+    }
+}
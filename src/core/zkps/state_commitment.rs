@@ -0,0 +1,253 @@
+// ./src/core/zkps/state_commitment.rs
+
+// Channel State Commitment
+// Poseidon commitment to a channel's state: its id, balance, nonce, sequence number, the
+// root of its pending locks and the key that must authorize its successor. The commitment is the channel's state hash and is what the state
+// transition circuit exposes for the states before and after a transition, so a proof is tied
+// to one channel and one prior state and cannot be replayed elsewhere.
+//
+// Field layout (24 elements, hashed with Poseidon without padding):
+// channel_id as 8 big-endian u32 limbs | balance as low, high u32 limbs | nonce | seqno |
+// lock_root as 8 u32 limbs | auth_key as 4 hash elements
+//
+// The plain commitment can be opened by guessing balances, so confidential transitions use a
+// hiding commitment instead: the same 24 fields followed by a 4-element blinding factor. Amounts
+// are committed the same way, as their two limbs followed by a blinding factor.
+
+use crate::core::error::errors::{SystemError, SystemErrorType};
+use crate::core::smt::hasher::PoseidonHasher;
+use crate::core::zkps::gadgets::hash::poseidon;
+use crate::core::zkps::gadgets::uint64::{
+    add_virtual_u64, assert_less_than_or_equal, checked_add, checked_sub, connect_u64, u64_limbs,
+    U64Target,
+};
+use plonky2::field::goldilocks_field::GoldilocksField;
+use plonky2::field::types::Field;
+use plonky2::hash::hash_types::HashOutTarget;
+use plonky2::hash::poseidon::PoseidonHash;
+use plonky2::iop::target::Target;
+use plonky2::iop::witness::{PartialWitness, WitnessWrite};
+use plonky2::plonk::circuit_builder::CircuitBuilder;
+use plonky2::plonk::config::Hasher;
+
+const D: usize = 2;
+type F = GoldilocksField;
+
+/// Length of `CommittedChannelState::to_bytes`.
+pub const CHANNEL_STATE_BYTES: usize = 32 + 8 + 8 + 8 + 32 + 32;
+
+/// The channel state covered by a state commitment.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CommittedChannelState {
+    pub channel_id: [u8; 32],
+    pub balance: u64,
+    pub nonce: u64,
+    pub seqno: u64,
+    /// Root of the channel's pending locks; all zeroes when there are none.
+    pub lock_root: [u8; 32],
+    /// Public key of the one-time `StateSigningKey` that signs the next state.
+    pub auth_key: [u8; 32],
+}
+
+impl CommittedChannelState {
+    /// The Poseidon state commitment.
+    pub fn commitment(&self) -> [u8; 32] {
+        PoseidonHasher::from_hash_out(PoseidonHash::hash_no_pad(&self.to_fields()))
+    }
+
+    /// The hiding state commitment under `blinding`.
+    pub fn hiding_commitment(&self, blinding: &[u8; 32]) -> [u8; 32] {
+        let mut fields = self.to_fields();
+        fields.extend(PoseidonHasher::to_hash_out(blinding).elements);
+        PoseidonHasher::from_hash_out(PoseidonHash::hash_no_pad(&fields))
+    }
+
+    /// The state after a transfer of `amount` out of the channel, with the lock root replaced.
+    pub fn transfer(&self, amount: u64, lock_root: [u8; 32]) -> Result<Self, SystemError> {
+        let balance = self.balance.checked_sub(amount).ok_or_else(|| {
+            SystemError::new(
+                SystemErrorType::InsufficientBalance,
+                "Transfer exceeds channel balance".to_string(),
+            )
+        })?;
+        Ok(Self {
+            channel_id: self.channel_id,
+            balance,
+            nonce: self.nonce + 1,
+            seqno: self.seqno + 1,
+            lock_root,
+            auth_key: self.auth_key,
+        })
+    }
+
+    /// The same state, authorizing its successor with `auth_key`.
+    pub fn with_auth_key(self, auth_key: [u8; 32]) -> Self {
+        Self { auth_key, ..self }
+    }
+
+    pub fn to_fields(&self) -> Vec<F> {
+        let mut fields = bytes_to_limbs(&self.channel_id);
+        fields.extend(u64_limbs(self.balance));
+        fields.push(F::from_canonical_u64(self.nonce));
+        fields.push(F::from_canonical_u64(self.seqno));
+        fields.extend(bytes_to_limbs(&self.lock_root));
+        fields.extend(PoseidonHasher::to_hash_out(&self.auth_key).elements);
+        fields
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(CHANNEL_STATE_BYTES);
+        bytes.extend_from_slice(&self.channel_id);
+        bytes.extend_from_slice(&self.balance.to_le_bytes());
+        bytes.extend_from_slice(&self.nonce.to_le_bytes());
+        bytes.extend_from_slice(&self.seqno.to_le_bytes());
+        bytes.extend_from_slice(&self.lock_root);
+        bytes.extend_from_slice(&self.auth_key);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SystemError> {
+        if bytes.len() != CHANNEL_STATE_BYTES {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidOperation,
+                format!("Channel state must be {} bytes", CHANNEL_STATE_BYTES),
+            ));
+        }
+        let u64_at = |offset: usize| {
+            u64::from_le_bytes(bytes[offset..offset + 8].try_into().expect("8 bytes"))
+        };
+        Ok(Self {
+            channel_id: bytes[..32].try_into().expect("32 bytes"),
+            balance: u64_at(32),
+            nonce: u64_at(40),
+            seqno: u64_at(48),
+            lock_root: bytes[56..88].try_into().expect("32 bytes"),
+            auth_key: bytes[88..].try_into().expect("32 bytes"),
+        })
+    }
+}
+
+/// Circuit targets of one committed channel state.
+#[derive(Clone, Debug)]
+pub struct ChannelStateTargets {
+    pub channel_id: [Target; 8],
+    pub balance: U64Target,
+    pub nonce: Target,
+    pub seqno: Target,
+    pub lock_root: [Target; 8],
+    pub auth_key: HashOutTarget,
+}
+
+impl ChannelStateTargets {
+    pub fn add_virtual(builder: &mut CircuitBuilder<F, D>) -> Self {
+        Self {
+            channel_id: builder.add_virtual_target_arr(),
+            balance: add_virtual_u64(builder),
+            nonce: builder.add_virtual_target(),
+            seqno: builder.add_virtual_target(),
+            lock_root: builder.add_virtual_target_arr(),
+            auth_key: builder.add_virtual_hash(),
+        }
+    }
+
+    /// Targets for the next state of the same channel: the channel id targets are shared, the
+    /// rest are fresh.
+    pub fn add_successor(&self, builder: &mut CircuitBuilder<F, D>) -> Self {
+        Self {
+            channel_id: self.channel_id,
+            balance: add_virtual_u64(builder),
+            nonce: builder.add_virtual_target(),
+            seqno: builder.add_virtual_target(),
+            lock_root: builder.add_virtual_target_arr(),
+            auth_key: builder.add_virtual_hash(),
+        }
+    }
+
+    /// Poseidon commitment over the targets, in the same layout as
+    /// `CommittedChannelState::to_fields`.
+    pub fn commitment(&self, builder: &mut CircuitBuilder<F, D>) -> HashOutTarget {
+        poseidon(builder, self.fields())
+    }
+
+    /// Hiding commitment over the targets, matching `CommittedChannelState::hiding_commitment`.
+    pub fn hiding_commitment(
+        &self,
+        builder: &mut CircuitBuilder<F, D>,
+        blinding: HashOutTarget,
+    ) -> HashOutTarget {
+        let mut inputs = self.fields();
+        inputs.extend(blinding.elements);
+        poseidon(builder, inputs)
+    }
+
+    pub fn set(
+        &self,
+        pw: &mut PartialWitness<F>,
+        state: &CommittedChannelState,
+    ) -> Result<(), SystemError> {
+        for (target, value) in self.fields().into_iter().zip(state.to_fields()) {
+            pw.set_target(target, value)
+                .map_err(|e| SystemError::new(SystemErrorType::InvalidProof, e.to_string()))?;
+        }
+        Ok(())
+    }
+
+    /// The targets in `CommittedChannelState::to_fields` order.
+    fn fields(&self) -> Vec<Target> {
+        let mut targets = self.channel_id.to_vec();
+        targets.extend(self.balance.limbs());
+        targets.extend([self.nonce, self.seqno]);
+        targets.extend_from_slice(&self.lock_root);
+        targets.extend(self.auth_key.elements);
+        targets
+    }
+}
+
+/// Hiding commitment to a transfer amount under `blinding`.
+pub fn amount_commitment(amount: u64, blinding: &[u8; 32]) -> [u8; 32] {
+    let mut fields = u64_limbs(amount).to_vec();
+    fields.extend(PoseidonHasher::to_hash_out(blinding).elements);
+    PoseidonHasher::from_hash_out(PoseidonHash::hash_no_pad(&fields))
+}
+
+/// In-circuit `amount_commitment`.
+pub fn amount_commitment_target(
+    builder: &mut CircuitBuilder<F, D>,
+    amount: U64Target,
+    blinding: HashOutTarget,
+) -> HashOutTarget {
+    let mut inputs = amount.limbs().to_vec();
+    inputs.extend(blinding.elements);
+    poseidon(builder, inputs)
+}
+
+/// Constrains `new` to follow `old` by a transfer of `amount` out of the channel: nonce and
+/// sequence number advance by one, the balance drops by exactly `amount` without underflow,
+/// and at most half the balance moves in one transition.
+pub fn constrain_transfer(
+    builder: &mut CircuitBuilder<F, D>,
+    old: &ChannelStateTargets,
+    new: &ChannelStateTargets,
+    amount: U64Target,
+) {
+    let one = builder.one();
+    let old_nonce_plus_one = builder.add(old.nonce, one);
+    builder.connect(old_nonce_plus_one, new.nonce);
+    let old_seqno_plus_one = builder.add(old.seqno, one);
+    builder.connect(old_seqno_plus_one, new.seqno);
+
+    // Conservation: new balance = old balance - amount, with no underflow.
+    let remaining = checked_sub(builder, old.balance, amount);
+    connect_u64(builder, remaining, new.balance);
+
+    // Spending rule: at most half the balance moves in one transition.
+    let double_amount = checked_add(builder, amount, amount);
+    assert_less_than_or_equal(builder, double_amount, old.balance);
+}
+
+fn bytes_to_limbs(bytes: &[u8; 32]) -> Vec<F> {
+    bytes
+        .chunks(4)
+        .map(|chunk| F::from_canonical_u32(u32::from_be_bytes(chunk.try_into().expect("4 bytes"))))
+        .collect()
+}
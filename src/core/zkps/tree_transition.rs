@@ -0,0 +1,472 @@
+// ./src/core/zkps/tree_transition.rs
+
+// Tree Transition Circuit
+// Proves that a hierarchy tree moved from an old root to a new root by a given list of leaf
+// updates. For every update the circuit takes the key, the old and new leaf hashes and the
+// 256 Poseidon siblings of the key's path, recomputes the path root once from the old leaf
+// and once from the new leaf, and chains the updates: the first old path must end at the
+// public old root, each new path must end where the next old path does, and the last new
+// path must end at the public new root.
+//
+// Hashing matches `PoseidonHasher::hash_node` limb for limb, so the roots are the ones the
+// wallet, intermediate and root trees report.
+//
+// Public inputs, in order:
+//   old root (4) | new root (4) | per update: key (8 x 32-bit limbs, big-endian) |
+//   old leaf hash (4) | new leaf hash (4)
+//
+// The system registers one circuit of `SHARED_BATCH_SIZE` updates; shorter transitions are
+// padded with updates that leave the root unchanged. Wallets prove their root submissions
+// with it, so the circuit is built with zero knowledge: the siblings are the leaf hashes of
+// the wallet's other channels.
+
+use crate::core::error::errors::{SystemError, SystemErrorType};
+use crate::core::smt::hasher::{PoseidonHasher, TreeHasher};
+use crate::core::smt::store::NodeStore;
+use crate::core::smt::tree::{EMPTY_LEAF, TREE_DEPTH};
+use crate::core::smt::HierarchyTree;
+use crate::core::zkps::circuit_cache::CircuitCache;
+use crate::core::zkps::gadgets::merkle::{
+    key_bits, key_limbs, path_root, set_hash, set_key, KEY_LIMBS,
+};
+use plonky2::field::goldilocks_field::GoldilocksField;
+use plonky2::field::types::{Field, PrimeField64};
+use plonky2::hash::hash_types::HashOutTarget;
+use plonky2::iop::target::Target;
+use plonky2::iop::witness::PartialWitness;
+use plonky2::plonk::circuit_builder::CircuitBuilder;
+use plonky2::plonk::circuit_data::{CircuitConfig, CircuitData};
+use plonky2::plonk::config::PoseidonGoldilocksConfig;
+use plonky2::plonk::proof::ProofWithPublicInputs;
+use std::sync::Arc;
+
+const D: usize = 2;
+type F = GoldilocksField;
+type C = PoseidonGoldilocksConfig;
+
+/// Name prefix of the tree transition circuits in the process-wide `CircuitCache`.
+pub const TREE_TRANSITION_CIRCUIT: &str = "tree_transition";
+
+/// Updates per proof of the circuit returned by `TreeTransitionCircuit::shared`.
+pub const SHARED_BATCH_SIZE: usize = 4;
+
+/// Public inputs taken by each update.
+const UPDATE_PUBLIC_INPUTS: usize = KEY_LIMBS + 8;
+
+/// Everything the prover needs for one leaf update.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UpdateWitness {
+    pub key: [u8; 32],
+    pub old_leaf: [u8; 32],
+    pub new_leaf: [u8; 32],
+    /// Siblings along the key's path, root side first. Unchanged by the update itself.
+    pub siblings: Vec<[u8; 32]>,
+}
+
+/// A sequence of updates taking a tree from `old_root` to `new_root`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TransitionWitness {
+    pub old_root: [u8; 32],
+    pub new_root: [u8; 32],
+    pub updates: Vec<UpdateWitness>,
+}
+
+impl TransitionWitness {
+    /// Applies `updates` to `tree` in order, recording the witness for each.
+    /// An empty value removes the key.
+    pub fn record<S: NodeStore>(
+        tree: &mut HierarchyTree<S>,
+        updates: &[([u8; 32], Vec<u8>)],
+    ) -> Result<Self, SystemError> {
+        let old_root = tree.root();
+        let mut witnesses = Vec::with_capacity(updates.len());
+        for (key, value) in updates {
+            let siblings = tree.prove(key)?.siblings;
+            let old_leaf = match tree.get(key)? {
+                Some(old_value) => PoseidonHasher::hash_leaf(key, &old_value),
+                None => EMPTY_LEAF,
+            };
+            let new_leaf = if value.is_empty() {
+                EMPTY_LEAF
+            } else {
+                PoseidonHasher::hash_leaf(key, value)
+            };
+            tree.update(key, value)?;
+            witnesses.push(UpdateWitness {
+                key: *key,
+                old_leaf,
+                new_leaf,
+                siblings,
+            });
+        }
+        Ok(Self {
+            old_root,
+            new_root: tree.root(),
+            updates: witnesses,
+        })
+    }
+
+    /// Pads the transition to `batch_size` updates by repeating its last update's new leaf,
+    /// which leaves the root unchanged.
+    pub fn padded(mut self, batch_size: usize) -> Result<Self, SystemError> {
+        let last = self.updates.last().cloned().ok_or_else(|| {
+            SystemError::new(
+                SystemErrorType::InvalidOperation,
+                "A tree transition needs at least one update".to_string(),
+            )
+        })?;
+        if self.updates.len() > batch_size {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidOperation,
+                format!(
+                    "Transition has {} updates, more than the batch of {}",
+                    self.updates.len(),
+                    batch_size
+                ),
+            ));
+        }
+        let unchanged = UpdateWitness {
+            old_leaf: last.new_leaf,
+            ..last
+        };
+        self.updates.resize(batch_size, unchanged);
+        Ok(self)
+    }
+
+    /// The public inputs of a proof of this transition.
+    pub fn public_inputs(&self) -> Vec<F> {
+        let mut inputs = PoseidonHasher::to_hash_out(&self.old_root)
+            .elements
+            .to_vec();
+        inputs.extend(PoseidonHasher::to_hash_out(&self.new_root).elements);
+        for update in &self.updates {
+            inputs.extend(key_limbs(&update.key));
+            inputs.extend(PoseidonHasher::to_hash_out(&update.old_leaf).elements);
+            inputs.extend(PoseidonHasher::to_hash_out(&update.new_leaf).elements);
+        }
+        inputs
+    }
+}
+
+/// The old and new roots committed by a tree transition proof's public inputs.
+pub fn tree_transition_roots(public_inputs: &[u64]) -> Option<([u8; 32], [u8; 32])> {
+    if public_inputs.len() < 8 {
+        return None;
+    }
+    let root = |inputs: &[u64]| {
+        let elements: Vec<F> = inputs
+            .iter()
+            .map(|&x| F::from_noncanonical_u64(x))
+            .collect();
+        field_hash(&elements)
+    };
+    Some((root(&public_inputs[0..4]), root(&public_inputs[4..8])))
+}
+
+/// A proof that a tree moved from `old_root` to `new_root`.
+pub struct TransitionProof {
+    pub old_root: [u8; 32],
+    pub new_root: [u8; 32],
+    pub proof: ProofWithPublicInputs<F, C, D>,
+}
+
+impl TransitionProof {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.proof.to_bytes()
+    }
+}
+
+struct UpdateTargets {
+    key: [Target; KEY_LIMBS],
+    old_leaf: HashOutTarget,
+    new_leaf: HashOutTarget,
+    siblings: Vec<HashOutTarget>,
+}
+
+struct TransitionTargets {
+    old_root: HashOutTarget,
+    new_root: HashOutTarget,
+    updates: Vec<UpdateTargets>,
+}
+
+/// Lays out the circuit for `batch_size` chained updates. Allocation is deterministic, so
+/// running this on a fresh builder recovers the targets of a cached circuit.
+fn add_transition_targets(
+    builder: &mut CircuitBuilder<F, D>,
+    batch_size: usize,
+) -> TransitionTargets {
+    let old_root = builder.add_virtual_hash();
+    let new_root = builder.add_virtual_hash();
+    builder.register_public_inputs(&old_root.elements);
+    builder.register_public_inputs(&new_root.elements);
+
+    let mut current = old_root;
+    let mut updates = Vec::with_capacity(batch_size);
+    for _ in 0..batch_size {
+        let key: [Target; KEY_LIMBS] = builder.add_virtual_target_arr();
+        let old_leaf = builder.add_virtual_hash();
+        let new_leaf = builder.add_virtual_hash();
+        builder.register_public_inputs(&key);
+        builder.register_public_inputs(&old_leaf.elements);
+        builder.register_public_inputs(&new_leaf.elements);
+
+        let bits = key_bits(builder, &key);
+        let siblings = builder.add_virtual_hashes(TREE_DEPTH);
+        let computed_old = path_root(builder, old_leaf, &siblings, &bits);
+        let computed_new = path_root(builder, new_leaf, &siblings, &bits);
+        builder.connect_hashes(computed_old, current);
+        current = computed_new;
+
+        updates.push(UpdateTargets {
+            key,
+            old_leaf,
+            new_leaf,
+            siblings,
+        });
+    }
+    builder.connect_hashes(current, new_root);
+
+    TransitionTargets {
+        old_root,
+        new_root,
+        updates,
+    }
+}
+
+fn transition_config() -> CircuitConfig {
+    CircuitConfig {
+        zero_knowledge: true,
+        ..CircuitConfig::standard_recursion_config()
+    }
+}
+
+/// Circuit for a fixed number of chained leaf updates.
+pub struct TreeTransitionCircuit {
+    data: Arc<CircuitData<F, C, D>>,
+    old_root: HashOutTarget,
+    new_root: HashOutTarget,
+    updates: Vec<UpdateTargets>,
+}
+
+impl TreeTransitionCircuit {
+    /// Builds the circuit for exactly `batch_size` updates.
+    pub fn new(batch_size: usize) -> Result<Self, SystemError> {
+        if batch_size == 0 {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidOperation,
+                "A tree transition needs at least one update".to_string(),
+            ));
+        }
+        Ok(Self::cached(batch_size))
+    }
+
+    /// The circuit for `SHARED_BATCH_SIZE` updates that the system registers.
+    pub fn shared() -> Self {
+        Self::cached(SHARED_BATCH_SIZE)
+    }
+
+    fn cached(batch_size: usize) -> Self {
+        let name = format!("{}_{}", TREE_TRANSITION_CIRCUIT, batch_size);
+        let data = CircuitCache::global().get_or_build(&name, || {
+            let mut builder = CircuitBuilder::<F, D>::new(transition_config());
+            add_transition_targets(&mut builder, batch_size);
+            builder.build::<C>()
+        });
+        let mut layout = CircuitBuilder::<F, D>::new(data.common.config.clone());
+        let targets = add_transition_targets(&mut layout, batch_size);
+        Self {
+            data,
+            old_root: targets.old_root,
+            new_root: targets.new_root,
+            updates: targets.updates,
+        }
+    }
+
+    pub fn batch_size(&self) -> usize {
+        self.updates.len()
+    }
+
+    pub fn circuit_data(&self) -> &CircuitData<F, C, D> {
+        &self.data
+    }
+
+    /// Proves a transition with exactly `batch_size` updates.
+    pub fn prove(&self, witness: &TransitionWitness) -> Result<TransitionProof, SystemError> {
+        if witness.updates.len() != self.updates.len() {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidOperation,
+                format!(
+                    "Circuit takes {} updates, got {}",
+                    self.updates.len(),
+                    witness.updates.len()
+                ),
+            ));
+        }
+
+        let mut pw = PartialWitness::new();
+        set_hash(&mut pw, self.old_root, &witness.old_root).map_err(proving_error)?;
+        set_hash(&mut pw, self.new_root, &witness.new_root).map_err(proving_error)?;
+        for (targets, update) in self.updates.iter().zip(&witness.updates) {
+            if update.siblings.len() != TREE_DEPTH {
+                return Err(SystemError::new(
+                    SystemErrorType::InvalidProof,
+                    "Update witness must carry one sibling per level".to_string(),
+                ));
+            }
+            set_key(&mut pw, &targets.key, &update.key).map_err(proving_error)?;
+            set_hash(&mut pw, targets.old_leaf, &update.old_leaf).map_err(proving_error)?;
+            set_hash(&mut pw, targets.new_leaf, &update.new_leaf).map_err(proving_error)?;
+            for (target, sibling) in targets.siblings.iter().zip(&update.siblings) {
+                set_hash(&mut pw, *target, sibling).map_err(proving_error)?;
+            }
+        }
+
+        let proof = self.data.prove(pw).map_err(proving_error)?;
+        Ok(TransitionProof {
+            old_root: witness.old_root,
+            new_root: witness.new_root,
+            proof,
+        })
+    }
+
+    /// Verifies a transition proof and that it moves `old_root` to `new_root`.
+    pub fn verify(
+        &self,
+        proof: &TransitionProof,
+        old_root: &[u8; 32],
+        new_root: &[u8; 32],
+    ) -> Result<(), SystemError> {
+        let public_inputs = &proof.proof.public_inputs;
+        if public_inputs.len() != 8 + self.updates.len() * UPDATE_PUBLIC_INPUTS
+            || public_inputs[0..4] != PoseidonHasher::to_hash_out(old_root).elements
+            || public_inputs[4..8] != PoseidonHasher::to_hash_out(new_root).elements
+        {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidProof,
+                "Proof is not bound to the expected roots".to_string(),
+            ));
+        }
+        self.data
+            .verify(proof.proof.clone())
+            .map_err(|e| SystemError::new(SystemErrorType::InvalidProof, e.to_string()))
+    }
+
+    /// Parses a proof produced by `TransitionProof::to_bytes` for this circuit.
+    pub fn proof_from_bytes(&self, bytes: &[u8]) -> Result<TransitionProof, SystemError> {
+        let proof = ProofWithPublicInputs::<F, C, D>::from_bytes(bytes.to_vec(), &self.data.common)
+            .map_err(|e| SystemError::new(SystemErrorType::InvalidProof, e.to_string()))?;
+        if proof.public_inputs.len() < 8 {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidProof,
+                "Proof is missing its public roots".to_string(),
+            ));
+        }
+        Ok(TransitionProof {
+            old_root: field_hash(&proof.public_inputs[0..4]),
+            new_root: field_hash(&proof.public_inputs[4..8]),
+            proof,
+        })
+    }
+}
+
+fn field_hash(elements: &[F]) -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    for (chunk, element) in bytes.chunks_mut(8).zip(elements) {
+        chunk.copy_from_slice(&element.to_canonical_u64().to_le_bytes());
+    }
+    bytes
+}
+
+fn proving_error<E: std::fmt::Display>(e: E) -> SystemError {
+    SystemError::new(SystemErrorType::InvalidProof, e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(byte: u8) -> [u8; 32] {
+        let mut key = [0u8; 32];
+        key[0] = byte;
+        key[31] = byte.wrapping_mul(3);
+        key
+    }
+
+    #[test]
+    fn test_single_update_transition() {
+        let mut tree = HierarchyTree::new();
+        tree.update(&key(1), b"one").unwrap();
+        let witness = TransitionWitness::record(&mut tree, &[(key(9), b"nine".to_vec())]).unwrap();
+
+        let circuit = TreeTransitionCircuit::new(1).unwrap();
+        let proof = circuit.prove(&witness).unwrap();
+        assert_eq!(proof.new_root, tree.root());
+        circuit
+            .verify(&proof, &witness.old_root, &witness.new_root)
+            .unwrap();
+        assert!(circuit
+            .verify(&proof, &witness.new_root, &witness.old_root)
+            .is_err());
+
+        let decoded = circuit.proof_from_bytes(&proof.to_bytes()).unwrap();
+        assert_eq!(decoded.old_root, witness.old_root);
+        assert_eq!(decoded.new_root, witness.new_root);
+    }
+
+    #[test]
+    fn test_batched_transition_chains_roots() {
+        let mut tree = HierarchyTree::new();
+        tree.update(&key(1), b"one").unwrap();
+        let witness = TransitionWitness::record(
+            &mut tree,
+            &[(key(1), Vec::new()), (key(2), b"two".to_vec())],
+        )
+        .unwrap();
+        assert_eq!(witness.updates[0].new_leaf, EMPTY_LEAF);
+
+        let circuit = TreeTransitionCircuit::new(2).unwrap();
+        let proof = circuit.prove(&witness).unwrap();
+        circuit
+            .verify(&proof, &witness.old_root, &tree.root())
+            .unwrap();
+
+        let mut single = witness.clone();
+        single.updates.pop();
+        assert!(circuit.prove(&single).is_err());
+    }
+
+    #[test]
+    fn test_padded_transition_keeps_its_roots() {
+        let mut tree = HierarchyTree::new();
+        let witness = TransitionWitness::record(&mut tree, &[(key(3), b"three".to_vec())])
+            .unwrap()
+            .padded(SHARED_BATCH_SIZE)
+            .unwrap();
+        assert_eq!(witness.updates.len(), SHARED_BATCH_SIZE);
+        assert!(witness.clone().padded(SHARED_BATCH_SIZE - 1).is_err());
+
+        let circuit = TreeTransitionCircuit::shared();
+        let proof = circuit.prove(&witness).unwrap();
+        circuit
+            .verify(&proof, &witness.old_root, &tree.root())
+            .unwrap();
+        let inputs: Vec<u64> = proof
+            .proof
+            .public_inputs
+            .iter()
+            .map(|x| x.to_canonical_u64())
+            .collect();
+        assert_eq!(
+            inputs,
+            witness
+                .public_inputs()
+                .iter()
+                .map(|x| x.to_canonical_u64())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            tree_transition_roots(&inputs),
+            Some((witness.old_root, tree.root()))
+        );
+    }
+}
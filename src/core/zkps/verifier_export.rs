@@ -0,0 +1,354 @@
+// ./src/core/zkps/verifier_export.rs
+
+// Verifier Key and Proof Export
+// Settlement is checked by the root contract, which holds only a verifier-key hash and
+// receives keys and proofs as bytes or BOCs. This module fixes those byte layouts. All
+// integers are little-endian; field elements are written as canonical u64s.
+//
+// Verifier key:
+//   magic "OVPK" (4) | version (1) | circuit digest (32) | common_len (4) | common data
+//   (common_len) | verifier-only data
+//
+// The common data uses plonky2's default gate serialization; the verifier-only data is the
+// constants/sigmas Merkle cap followed by the circuit digest. The verifier-key hash is the
+// SHA-256 of the whole encoding and is what `RootContract` and `RootSubmission` reference.
+//
+// Proof:
+//   magic "OVPP" (4) | version (1) | verifier-key hash (32) | input_count (4) |
+//   public inputs (8 each) | plonky2 proof with public inputs
+//
+// The public inputs are repeated ahead of the proof so a contract can read the statement
+// without parsing the proof; import rejects a proof whose inputs disagree with the header.
+//
+// BOC wrappers hold each encoding in a single root cell whose merkle hash is the SHA-256 of
+// its data, so a verifier key's cell hash is its verifier-key hash.
+
+use crate::core::error::errors::{SystemError, SystemErrorType};
+use crate::core::smt::hasher::PoseidonHasher;
+use crate::core::types::boc::{Cell, BOC};
+use plonky2::field::goldilocks_field::GoldilocksField;
+use plonky2::field::types::{Field64, PrimeField64};
+use plonky2::plonk::circuit_data::{
+    CommonCircuitData, VerifierCircuitData, VerifierOnlyCircuitData,
+};
+use plonky2::plonk::config::PoseidonGoldilocksConfig;
+use plonky2::plonk::proof::ProofWithPublicInputs;
+use plonky2::util::serialization::DefaultGateSerializer;
+use sha2::{Digest, Sha256};
+
+const D: usize = 2;
+type F = GoldilocksField;
+type C = PoseidonGoldilocksConfig;
+
+/// Version of both export layouts.
+pub const EXPORT_VERSION: u8 = 1;
+
+const KEY_MAGIC: &[u8; 4] = b"OVPK";
+const PROOF_MAGIC: &[u8; 4] = b"OVPP";
+const KEY_HEADER_LEN: usize = 4 + 1 + 32 + 4;
+const PROOF_HEADER_LEN: usize = 4 + 1 + 32 + 4;
+
+/// A verifier key in the export layout.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExportedVerifierKey {
+    bytes: Vec<u8>,
+}
+
+impl ExportedVerifierKey {
+    pub fn from_verifier_data(
+        verifier: &VerifierCircuitData<F, C, D>,
+    ) -> Result<Self, SystemError> {
+        let common = verifier
+            .common
+            .to_bytes(&DefaultGateSerializer)
+            .map_err(|e| export_error(format!("Failed to serialize common data: {:?}", e)))?;
+        let verifier_only = verifier
+            .verifier_only
+            .to_bytes()
+            .map_err(|e| export_error(format!("Failed to serialize verifier data: {:?}", e)))?;
+
+        let mut bytes = Vec::with_capacity(KEY_HEADER_LEN + common.len() + verifier_only.len());
+        bytes.extend_from_slice(KEY_MAGIC);
+        bytes.push(EXPORT_VERSION);
+        bytes.extend_from_slice(&PoseidonHasher::from_hash_out(
+            verifier.verifier_only.circuit_digest,
+        ));
+        bytes.extend_from_slice(&(common.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&common);
+        bytes.extend_from_slice(&verifier_only);
+        Ok(Self { bytes })
+    }
+
+    /// Parses an exported key. The encoding must parse completely and its circuit digest must
+    /// match the one in the verifier-only data.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SystemError> {
+        let key = Self {
+            bytes: bytes.to_vec(),
+        };
+        key.verifier_data()?;
+        Ok(key)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// SHA-256 of the encoding.
+    pub fn hash(&self) -> [u8; 32] {
+        Sha256::digest(&self.bytes).into()
+    }
+
+    /// Digest of the exported circuit.
+    pub fn circuit_digest(&self) -> [u8; 32] {
+        self.bytes[5..37].try_into().expect("32 bytes")
+    }
+
+    /// The plonky2 verifier data the key encodes.
+    pub fn verifier_data(&self) -> Result<VerifierCircuitData<F, C, D>, SystemError> {
+        let bytes = &self.bytes;
+        if bytes.len() < KEY_HEADER_LEN || &bytes[..4] != KEY_MAGIC {
+            return Err(invalid_format("Not an exported verifier key"));
+        }
+        if bytes[4] != EXPORT_VERSION {
+            return Err(invalid_format("Unsupported verifier key version"));
+        }
+        let common_len = u32::from_le_bytes(bytes[37..41].try_into().expect("4 bytes")) as usize;
+        if bytes.len() - KEY_HEADER_LEN < common_len {
+            return Err(invalid_format("Verifier key truncated in common data"));
+        }
+        let (common, verifier_only) = bytes[KEY_HEADER_LEN..].split_at(common_len);
+
+        let common = CommonCircuitData::<F, D>::from_bytes(common.to_vec(), &DefaultGateSerializer)
+            .map_err(|e| invalid_format(&format!("Invalid common data: {:?}", e)))?;
+        let verifier_only = VerifierOnlyCircuitData::<C, D>::from_bytes(verifier_only.to_vec())
+            .map_err(|e| invalid_format(&format!("Invalid verifier data: {:?}", e)))?;
+        if PoseidonHasher::from_hash_out(verifier_only.circuit_digest) != self.circuit_digest() {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidHash,
+                "Verifier key header does not match its circuit digest".to_string(),
+            ));
+        }
+        Ok(VerifierCircuitData {
+            verifier_only,
+            common,
+        })
+    }
+
+    /// Verifies an exported proof using this key alone.
+    pub fn verify(&self, proof: &ExportedProof) -> Result<(), SystemError> {
+        if proof.verifier_key_hash != self.hash() {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidProof,
+                "Proof was exported for a different verifier key".to_string(),
+            ));
+        }
+        let verifier = self.verifier_data()?;
+        let plonky2_proof =
+            ProofWithPublicInputs::<F, C, D>::from_bytes(proof.proof.clone(), &verifier.common)
+                .map_err(|e| SystemError::new(SystemErrorType::InvalidProof, e.to_string()))?;
+        let inputs: Vec<u64> = plonky2_proof
+            .public_inputs
+            .iter()
+            .map(|input| input.to_canonical_u64())
+            .collect();
+        if inputs != proof.public_inputs {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidProof,
+                "Public inputs do not match the proof".to_string(),
+            ));
+        }
+        verifier
+            .verify(plonky2_proof)
+            .map_err(|e| SystemError::new(SystemErrorType::InvalidProof, e.to_string()))
+    }
+
+    pub fn to_boc(&self) -> BOC {
+        single_cell_boc(self.bytes.clone())
+    }
+
+    pub fn from_boc(boc: &BOC) -> Result<Self, SystemError> {
+        Self::from_bytes(single_cell_data(boc)?)
+    }
+}
+
+/// A proof in the export layout.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExportedProof {
+    pub verifier_key_hash: [u8; 32],
+    pub public_inputs: Vec<u64>,
+    /// The plonky2 proof with public inputs.
+    pub proof: Vec<u8>,
+}
+
+impl ExportedProof {
+    /// Exports `proof` for verification with `key`.
+    pub fn new(key: &ExportedVerifierKey, proof: &ProofWithPublicInputs<F, C, D>) -> Self {
+        Self {
+            verifier_key_hash: key.hash(),
+            public_inputs: proof
+                .public_inputs
+                .iter()
+                .map(|input| input.to_canonical_u64())
+                .collect(),
+            proof: proof.to_bytes(),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes =
+            Vec::with_capacity(PROOF_HEADER_LEN + self.public_inputs.len() * 8 + self.proof.len());
+        bytes.extend_from_slice(PROOF_MAGIC);
+        bytes.push(EXPORT_VERSION);
+        bytes.extend_from_slice(&self.verifier_key_hash);
+        bytes.extend_from_slice(&(self.public_inputs.len() as u32).to_le_bytes());
+        for input in &self.public_inputs {
+            bytes.extend_from_slice(&input.to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.proof);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SystemError> {
+        if bytes.len() < PROOF_HEADER_LEN || &bytes[..4] != PROOF_MAGIC {
+            return Err(invalid_format("Not an exported proof"));
+        }
+        if bytes[4] != EXPORT_VERSION {
+            return Err(invalid_format("Unsupported proof version"));
+        }
+        let verifier_key_hash = bytes[5..37].try_into().expect("32 bytes");
+        let input_count = u32::from_le_bytes(bytes[37..41].try_into().expect("4 bytes")) as usize;
+        let body = &bytes[PROOF_HEADER_LEN..];
+        if body.len() / 8 < input_count {
+            return Err(invalid_format("Proof truncated in public inputs"));
+        }
+        let (inputs, proof) = body.split_at(input_count * 8);
+        let public_inputs = inputs
+            .chunks(8)
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().expect("8 bytes")))
+            .collect::<Vec<_>>();
+        if public_inputs.iter().any(|input| *input >= F::ORDER) {
+            return Err(invalid_format(
+                "Public input is not a canonical field element",
+            ));
+        }
+        Ok(Self {
+            verifier_key_hash,
+            public_inputs,
+            proof: proof.to_vec(),
+        })
+    }
+
+    pub fn to_boc(&self) -> BOC {
+        single_cell_boc(self.to_bytes())
+    }
+
+    pub fn from_boc(boc: &BOC) -> Result<Self, SystemError> {
+        Self::from_bytes(single_cell_data(boc)?)
+    }
+}
+
+fn single_cell_boc(data: Vec<u8>) -> BOC {
+    let mut cell = Cell::with_data(data);
+    cell.merkle_hash = Sha256::digest(&cell.data).into();
+    let mut boc = BOC::new();
+    let root = boc.add_cell(cell);
+    boc.add_root(root);
+    boc
+}
+
+fn single_cell_data(boc: &BOC) -> Result<&[u8], SystemError> {
+    let cell = boc.get_root_cell().ok_or_else(|| {
+        SystemError::new(
+            SystemErrorType::NoRootCell,
+            "Export BOC has no root cell".to_string(),
+        )
+    })?;
+    let hash: [u8; 32] = Sha256::digest(&cell.data).into();
+    if cell.merkle_hash != hash {
+        return Err(SystemError::new(
+            SystemErrorType::InvalidHash,
+            "Export cell hash does not match its data".to_string(),
+        ));
+    }
+    Ok(&cell.data)
+}
+
+fn export_error(message: String) -> SystemError {
+    SystemError::new(SystemErrorType::InvalidOperation, message)
+}
+
+fn invalid_format(message: &str) -> SystemError {
+    SystemError::new(SystemErrorType::InvalidOperation, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::hierarchy::root::root_contract::RootContract;
+    use crate::core::zkps::plonky2::Plonky2System;
+    use crate::core::zkps::signature::StateKeyChain;
+    use crate::core::zkps::state_commitment::CommittedChannelState;
+
+    #[test]
+    fn test_exported_proof_verifies_from_exported_key() {
+        let system = Plonky2System::shared().unwrap();
+        let keys = StateKeyChain::new([8u8; 32]);
+        let old = CommittedChannelState {
+            channel_id: [8u8; 32],
+            balance: 1_000,
+            auth_key: keys.auth_key(0),
+            ..Default::default()
+        };
+        let (new, signature) = keys.transfer(&old, 250, [0u8; 32]).unwrap();
+        let proof = system
+            .proof_from_bytes(&system.generate_proof(&old, &new, 250, &signature).unwrap())
+            .unwrap();
+
+        let key = ExportedVerifierKey::from_verifier_data(&system.state_transition_verifier_data())
+            .unwrap();
+        let exported = ExportedProof::new(&key, &proof);
+
+        // Only the exported bytes and BOCs cross the boundary
+        let key_boc = key.to_boc();
+        assert_eq!(key_boc.get_root_cell().unwrap().merkle_hash, key.hash());
+        let imported_key = ExportedVerifierKey::from_boc(&key_boc).unwrap();
+        let imported_proof = ExportedProof::from_boc(&exported.to_boc()).unwrap();
+        assert_eq!(imported_key, key);
+        assert_eq!(imported_key.circuit_digest(), system.circuit_digest());
+        assert_eq!(imported_proof, exported);
+        imported_key.verify(&imported_proof).unwrap();
+        let from_bytes = ExportedProof::from_bytes(&exported.to_bytes()).unwrap();
+        imported_key.verify(&from_bytes).unwrap();
+
+        let mut contract = RootContract::new(60);
+        contract.set_settlement_verifier_key(imported_key.clone());
+        assert_eq!(contract.settlement_verifier_key_hash(), Some(key.hash()));
+        contract
+            .verify_settlement_proof(&key.hash(), &exported.to_bytes())
+            .unwrap();
+        assert!(contract
+            .verify_settlement_proof(&[0u8; 32], &exported.to_bytes())
+            .is_err());
+
+        // Submissions are settled only with a proof under the referenced key.
+        assert!(contract
+            .process_settlement_submission([1u8; 32], [2u8; 32], &[0u8; 32], &exported.to_bytes())
+            .is_err());
+        assert!(contract
+            .process_settlement_submission([1u8; 32], [2u8; 32], &key.hash(), &[0u8; 16])
+            .is_err());
+        let before = contract.global_tree().root();
+        contract
+            .process_settlement_submission([1u8; 32], [2u8; 32], &key.hash(), &exported.to_bytes())
+            .unwrap();
+        assert_ne!(contract.global_tree().root(), before);
+
+        let mut relabelled = imported_proof.clone();
+        relabelled.public_inputs[0] += 1;
+        assert!(imported_key.verify(&relabelled).is_err());
+
+        let mut rekeyed = imported_proof;
+        rekeyed.verifier_key_hash = [0u8; 32];
+        assert!(imported_key.verify(&rekeyed).is_err());
+    }
+}
@@ -1,5 +1,7 @@
 use crate::core::zkps::plonky2::Plonky2SystemHandle;
 use crate::core::zkps::proof::{ProofType, ZkProof};
+use crate::core::zkps::signature::StateKeyChain;
+use crate::core::zkps::state_commitment::CommittedChannelState;
 use js_sys::Uint8Array;
 use serde::{Deserialize, Serialize};
 use wasm_bindgen::prelude::*;
@@ -83,33 +85,96 @@ impl ProofWithMetadataJS {
     }
 }
 
+/// Proves a transfer between two serialized `CommittedChannelState`s of one channel,
+/// authorized by a serialized `StateSignature` over the new state's commitment.
 #[wasm_bindgen]
 pub fn generate_proof(
-    old_balance: u64,
-    old_nonce: u64,
-    new_balance: u64,
-    new_nonce: u64,
+    old_state: &[u8],
+    new_state: &[u8],
     transfer_amount: u64,
+    signature: &[u8],
 ) -> Result<Uint8Array, JsValue> {
     let plonky2_system_handle = Plonky2SystemHandle::new()?;
 
     let proof_bytes = plonky2_system_handle.generate_proof_js(
-        old_balance,
-        old_nonce,
-        new_balance,
-        new_nonce,
+        old_state,
+        new_state,
         transfer_amount,
+        signature,
     )?;
 
     Ok(Uint8Array::from(&proof_bytes[..]))
 }
 
+/// The `auth_key` committed by a state of `channel_id` with `nonce`, for the channel's key
+/// chain derived from the 32-byte wallet `seed`.
 #[wasm_bindgen]
-pub fn verify_proof(proof_bytes: &Uint8Array) -> Result<bool, JsValue> {
+pub fn state_auth_key(seed: &[u8], channel_id: &[u8], nonce: u64) -> Result<Uint8Array, JsValue> {
+    let channel_id: [u8; 32] = channel_id
+        .try_into()
+        .map_err(|_| JsValue::from_str("Channel id must be 32 bytes"))?;
+    let keys = StateKeyChain::new(seed_array(seed)?).for_channel(&channel_id);
+    Ok(Uint8Array::from(&keys.auth_key(nonce)[..]))
+}
+
+/// Signs the new state's commitment with the key the serialized old state commits to, taken
+/// from the old state's channel chain.
+#[wasm_bindgen]
+pub fn sign_state_transition(
+    seed: &[u8],
+    old_state: &[u8],
+    new_state: &[u8],
+) -> Result<Uint8Array, JsValue> {
+    let old_state = CommittedChannelState::from_bytes(old_state).map_err(to_js_error)?;
+    let new_state = CommittedChannelState::from_bytes(new_state).map_err(to_js_error)?;
+    let key = StateKeyChain::new(seed_array(seed)?)
+        .for_channel(&old_state.channel_id)
+        .key(old_state.nonce);
+    if key.public_key() != old_state.auth_key {
+        return Err(JsValue::from_str("Seed does not hold the old state's key"));
+    }
+    let signature = key.sign(&new_state.commitment());
+    Ok(Uint8Array::from(&signature.to_bytes()[..]))
+}
+
+/// Verifies a proof of exactly the transition between two serialized
+/// `CommittedChannelState`s. A proof checked without its states binds nothing, so there is no
+/// state-free verification entry point.
+#[wasm_bindgen]
+pub fn verify_transition(
+    proof_bytes: &[u8],
+    old_state: &[u8],
+    new_state: &[u8],
+    transfer_amount: u64,
+) -> Result<bool, JsValue> {
     let plonky2_system_handle = Plonky2SystemHandle::new()?;
-    let proof_vec = proof_bytes.to_vec();
-    let result = plonky2_system_handle.verify_proof_js(&proof_vec)?;
-    Ok(result)
+    plonky2_system_handle.verify_transition_js(proof_bytes, old_state, new_state, transfer_amount)
+}
+
+/// Verifies a transition out of the state with commitment `old_commitment` and returns the
+/// commitment of the new state.
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn verify_from_commitment(
+    proof_bytes: &[u8],
+    old_commitment: &[u8],
+    old_balance: u64,
+    old_nonce: u64,
+    new_balance: u64,
+    new_nonce: u64,
+    transfer_amount: u64,
+) -> Result<Uint8Array, JsValue> {
+    let plonky2_system_handle = Plonky2SystemHandle::new()?;
+    let new_commitment = plonky2_system_handle.verify_from_commitment_js(
+        proof_bytes,
+        old_commitment,
+        old_balance,
+        old_nonce,
+        new_balance,
+        new_nonce,
+        transfer_amount,
+    )?;
+    Ok(Uint8Array::from(&new_commitment[..]))
 }
 
 #[wasm_bindgen]
@@ -137,6 +202,8 @@ pub fn create_proof_with_metadata(
         .collect();
 
     let zk_proof = ZkProof {
+        proof_type: ProofType::StateTransition,
+        circuit_digest: Plonky2SystemHandle::new()?.system().circuit_digest(),
         proof_data: proof_vec,
         merkle_root: merkle_root_vec,
         public_inputs: public_inputs_u64,
@@ -153,6 +220,11 @@ pub fn create_proof_with_metadata(
         .map_err(|e| JsValue::from_str(&format!("Failed to serialize bundle: {}", e)))
 }
 
+fn seed_array(seed: &[u8]) -> Result<[u8; 32], JsValue> {
+    seed.try_into()
+        .map_err(|_| JsValue::from_str("Seed must be 32 bytes"))
+}
+
 fn to_js_error<E: std::fmt::Display>(error: E) -> JsValue {
     JsValue::from_str(&format!("Error: {}", error))
 }
@@ -175,6 +247,8 @@ mod tests {
     fn test_proof_with_metadata() {
         let timestamp = 12345;
         let zk_proof = ZkProof {
+            proof_type: ProofType::StateTransition,
+            circuit_digest: [0u8; 32],
             proof_data: vec![1, 2, 3],
             public_inputs: vec![100, 200],
             merkle_root: vec![0; 32],